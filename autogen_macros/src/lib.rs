@@ -0,0 +1,320 @@
+//! Procedural macros of the `autogen` crate. Use them through the reexports, `autogen::tool` and
+//! `autogen::ToolSchema` - the generated code refers to the `autogen` crate.
+
+mod serde_attributes;
+
+use proc_macro::TokenStream;
+
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::ext::IdentExt;
+use syn::{
+    parse_macro_input, Attribute, Data, DeriveInput, Error, Expr, Fields, FnArg, ItemFn, Lit, Pat,
+    ReturnType, Type,
+};
+
+use serde_attributes::{Place, SerdeAttributes};
+
+/// Turns an async function into a tool. Next to the function, `{name}_tool()` returning
+/// `autogen::text_chat::tool::Tool` is generated.
+///
+/// The doc comment of the function becomes the description of the tool and doc comments of the
+/// parameters become descriptions of the properties. Types of the parameters have to implement
+/// `Deserialize` and `autogen::text_chat::tool_schema::ToolSchema`, the returned type `Serialize`.
+/// Functions returning `Result` report their errors to the agent.
+///
+/// ```ignore
+/// /// Adds two integers.
+/// #[autogen::tool]
+/// async fn add(
+///     /// First summand.
+///     a: i64,
+///     /// Second summand.
+///     b: i64,
+/// ) -> i64 {
+///     a + b
+/// }
+///
+/// let tools = ToolRegistry::new().with_tool(add_tool());
+/// ```
+#[proc_macro_attribute]
+pub fn tool(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let function = parse_macro_input!(item as ItemFn);
+
+    match attr.is_empty() {
+        true => expand_tool(function).unwrap_or_else(Error::into_compile_error),
+        false => Error::new_spanned(
+            TokenStream2::from(attr),
+            "#[tool] does not take any arguments",
+        )
+        .into_compile_error(),
+    }
+    .into()
+}
+
+/// Derives `autogen::text_chat::tool_schema::ToolSchema` for structs with named fields and enums
+/// with unit variants, so they can be used as parameters of tools. Doc comments become the
+/// descriptions.
+///
+/// Serde attributes renaming, skipping or defaulting fields and variants are taken into account.
+/// Other serde attributes changing the shape of the data are rejected.
+#[proc_macro_derive(ToolSchema)]
+pub fn derive_tool_schema(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    expand_tool_schema(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand_tool(mut function: ItemFn) -> Result<TokenStream2, Error> {
+    let signature = &function.sig;
+
+    if signature.asyncness.is_none() {
+        return Err(Error::new_spanned(
+            signature.fn_token,
+            "#[tool] requires an async function",
+        ));
+    }
+
+    if !signature.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &signature.generics,
+            "#[tool] does not support generic functions",
+        ));
+    }
+
+    let mut names = Vec::new();
+    let mut types = Vec::new();
+    let mut descriptions = Vec::new();
+
+    for input in function.sig.inputs.iter_mut() {
+        let input = match input {
+            FnArg::Typed(input) => input,
+            FnArg::Receiver(receiver) => {
+                return Err(Error::new_spanned(
+                    receiver,
+                    "#[tool] does not support methods",
+                ))
+            }
+        };
+
+        let name = match &*input.pat {
+            Pat::Ident(pat) => pat.ident.clone(),
+            pat => {
+                return Err(Error::new_spanned(
+                    pat,
+                    "parameters of a tool have to be plain identifiers",
+                ))
+            }
+        };
+
+        // Doc comments are not allowed on parameters, so they are taken away.
+        descriptions.push(documentation(&input.attrs));
+        input.attrs.retain(|attr| !attr.path().is_ident("doc"));
+
+        names.push(name);
+        types.push((*input.ty).clone());
+    }
+
+    let function_name = &function.sig.ident;
+    let tool_name = function_name.unraw().to_string();
+    let constructor = format_ident!("{}_tool", function_name.unraw());
+    let description = documentation(&function.attrs);
+    let visibility = &function.vis;
+    let property_names: Vec<String> = names.iter().map(|name| name.unraw().to_string()).collect();
+
+    let call = quote! { #function_name(#(arguments.#names),*).await };
+    let call = match returns_result(&function.sig.output) {
+        true => call,
+        false => quote! { Ok::<_, ::std::convert::Infallible>(#call) },
+    };
+
+    let constructor_doc = format!("Tool calling [{}].", tool_name);
+
+    Ok(quote! {
+        #function
+
+        #[doc = #constructor_doc]
+        #visibility fn #constructor() -> ::autogen::text_chat::tool::Tool {
+            #[derive(::autogen::__private::serde::Deserialize)]
+            #[serde(crate = "::autogen::__private::serde", deny_unknown_fields)]
+            struct Arguments {
+                #(#names: #types,)*
+            }
+
+            let parameters = ::autogen::text_chat::tool_schema::object(vec![
+                #((
+                    #property_names,
+                    ::autogen::text_chat::tool_schema::describe(
+                        <#types as ::autogen::text_chat::tool_schema::ToolSchema>::schema(),
+                        #descriptions,
+                    ),
+                    <#types as ::autogen::text_chat::tool_schema::ToolSchema>::optional(),
+                ),)*
+            ]);
+
+            ::autogen::text_chat::tool::Tool::new(
+                ::autogen::text_chat::tool::ToolDefinition::new(#tool_name, #description, parameters),
+                |arguments: Arguments| async move { #call },
+            )
+        }
+    })
+}
+
+fn expand_tool_schema(input: DeriveInput) -> Result<TokenStream2, Error> {
+    let name = &input.ident;
+    let description = documentation(&input.attrs);
+    let container = SerdeAttributes::parse(&input.attrs, Place::Container)?;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+
+    let schema = match &input.data {
+        Data::Struct(data) => {
+            let fields = match &data.fields {
+                Fields::Named(fields) => &fields.named,
+                fields => {
+                    return Err(Error::new_spanned(
+                        fields,
+                        "ToolSchema can only be derived for structs with named fields",
+                    ))
+                }
+            };
+
+            let fields: Vec<_> = fields
+                .iter()
+                .map(|field| Ok((field, SerdeAttributes::parse(&field.attrs, Place::Field)?)))
+                .collect::<Result<_, Error>>()?;
+            let fields = fields.iter().filter(|(_, attributes)| !attributes.skip);
+
+            let names = fields.clone().map(|(field, attributes)| {
+                let name = field
+                    .ident
+                    .as_ref()
+                    .map(|ident| ident.unraw().to_string())
+                    .unwrap_or_default();
+
+                match (&attributes.rename, container.rename_all) {
+                    (Some(rename), _) => rename.clone(),
+                    (None, Some(rule)) => rule.apply_to_field(&name),
+                    (None, None) => name,
+                }
+            });
+            let types: Vec<&Type> = fields.clone().map(|(field, _)| &field.ty).collect();
+            let descriptions = fields.clone().map(|(field, _)| documentation(&field.attrs));
+            let optional = fields.zip(&types).map(|((_, attributes), ty)| {
+                match container.default || attributes.default {
+                    true => quote! { true },
+                    false => quote! { <#ty as ::autogen::text_chat::tool_schema::ToolSchema>::optional() },
+                }
+            });
+
+            quote! {
+                ::autogen::text_chat::tool_schema::object(vec![
+                    #((
+                        #names,
+                        ::autogen::text_chat::tool_schema::describe(
+                            <#types as ::autogen::text_chat::tool_schema::ToolSchema>::schema(),
+                            #descriptions,
+                        ),
+                        #optional,
+                    ),)*
+                ])
+            }
+        }
+        Data::Enum(data) => {
+            if let Some(variant) = data
+                .variants
+                .iter()
+                .find(|variant| !matches!(variant.fields, Fields::Unit))
+            {
+                return Err(Error::new_spanned(
+                    variant,
+                    "ToolSchema can only be derived for enums with unit variants",
+                ));
+            }
+
+            let variants = data
+                .variants
+                .iter()
+                .map(|variant| {
+                    Ok((
+                        variant,
+                        SerdeAttributes::parse(&variant.attrs, Place::Variant)?,
+                    ))
+                })
+                .collect::<Result<Vec<_>, Error>>()?
+                .into_iter()
+                .filter(|(_, attributes)| !attributes.skip)
+                .map(|(variant, attributes)| {
+                    let name = variant.ident.unraw().to_string();
+
+                    match (attributes.rename, container.rename_all) {
+                        (Some(rename), _) => rename,
+                        (None, Some(rule)) => rule.apply_to_variant(&name),
+                        (None, None) => name,
+                    }
+                });
+
+            quote! {
+                ::autogen::__private::serde_json::json!({
+                    "type": "string",
+                    "enum": [#(#variants),*],
+                })
+            }
+        }
+        Data::Union(data) => {
+            return Err(Error::new_spanned(
+                data.union_token,
+                "ToolSchema cannot be derived for unions",
+            ))
+        }
+    };
+
+    Ok(quote! {
+        impl #impl_generics ::autogen::text_chat::tool_schema::ToolSchema for #name #type_generics
+        #where_clause
+        {
+            fn schema() -> ::autogen::__private::serde_json::Value {
+                ::autogen::text_chat::tool_schema::describe(#schema, #description)
+            }
+        }
+    })
+}
+
+/// Doc comments joined into a single string, without the leading spaces.
+fn documentation(attrs: &[Attribute]) -> String {
+    let lines: Vec<String> = attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("doc"))
+        .filter_map(|attr| match &attr.meta.require_name_value().ok()?.value {
+            Expr::Lit(expr) => match &expr.lit {
+                Lit::Str(lit) => Some(lit.value()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .map(|line| {
+            line.strip_prefix(' ')
+                .unwrap_or(&line)
+                .trim_end()
+                .to_string()
+        })
+        .collect();
+
+    lines.join("\n").trim().to_string()
+}
+
+/// Whether the function returns a `Result`, judging by the name of the type.
+fn returns_result(output: &ReturnType) -> bool {
+    match output {
+        ReturnType::Type(_, ty) => match &**ty {
+            Type::Path(path) => path
+                .path
+                .segments
+                .last()
+                .is_some_and(|segment| segment.ident == "Result"),
+            _ => false,
+        },
+        ReturnType::Default => false,
+    }
+}
@@ -0,0 +1,199 @@
+//! The part of the serde attributes which changes what the deserializer accepts: renamed and
+//! skipped fields or variants and defaults. Attributes changing the shape of the data in other
+//! ways are rejected, so the derived schema never disagrees with the deserializer.
+
+use syn::meta::ParseNestedMeta;
+use syn::{Attribute, Error, LitStr, Token};
+
+/// Where the attributes are placed. Each place accepts different attributes.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Place {
+    Container,
+    Field,
+    Variant,
+}
+
+#[derive(Default)]
+pub struct SerdeAttributes {
+    /// Name used by the deserializer.
+    pub rename: Option<String>,
+    pub rename_all: Option<RenameRule>,
+    /// The field may be missing. On a container, this applies to all the fields.
+    pub default: bool,
+    /// Never deserialized.
+    pub skip: bool,
+}
+
+impl SerdeAttributes {
+    pub fn parse(attrs: &[Attribute], place: Place) -> Result<Self, Error> {
+        let mut attributes = Self::default();
+
+        for attr in attrs.iter().filter(|attr| attr.path().is_ident("serde")) {
+            attr.parse_nested_meta(|meta| {
+                let name = meta
+                    .path
+                    .get_ident()
+                    .map(|ident| ident.to_string())
+                    .unwrap_or_default();
+
+                match (name.as_str(), place) {
+                    ("rename", Place::Container) => {
+                        // Only names the type, which is not a part of the schema.
+                        deserialize_name(&meta)?;
+                    }
+                    ("rename", Place::Field | Place::Variant) => {
+                        attributes.rename = deserialize_name(&meta)?.map(|name| name.value());
+                    }
+                    ("rename_all", Place::Container) => {
+                        attributes.rename_all = match deserialize_name(&meta)? {
+                            Some(rule) => Some(RenameRule::parse(&rule)?),
+                            None => None,
+                        };
+                    }
+                    ("default", Place::Container | Place::Field) => {
+                        // Optionally followed by the function providing the default.
+                        if meta.input.peek(Token![=]) {
+                            meta.value()?.parse::<LitStr>()?;
+                        }
+                        attributes.default = true;
+                    }
+                    ("skip" | "skip_deserializing", Place::Field | Place::Variant) => {
+                        attributes.skip = true;
+                    }
+                    ("alias" | "skip_serializing_if", Place::Field)
+                    | ("alias", Place::Variant)
+                    | ("crate", Place::Container) => {
+                        meta.value()?.parse::<LitStr>()?;
+                    }
+                    ("skip_serializing", Place::Field | Place::Variant)
+                    | ("deny_unknown_fields", Place::Container) => {}
+                    _ => {
+                        return Err(meta.error(format!(
+                            "serde attribute `{}` is not supported by ToolSchema",
+                            name
+                        )))
+                    }
+                }
+
+                Ok(())
+            })?;
+        }
+
+        Ok(attributes)
+    }
+}
+
+/// Name from `name = "..."` or `name(serialize = "...", deserialize = "...")`. None if only the
+/// serialized one is set.
+fn deserialize_name(meta: &ParseNestedMeta) -> Result<Option<LitStr>, Error> {
+    if meta.input.peek(Token![=]) {
+        return meta.value()?.parse().map(Some);
+    }
+
+    let mut name = None;
+    meta.parse_nested_meta(|meta| {
+        let value = meta.value()?.parse::<LitStr>()?;
+
+        match meta.path.is_ident("deserialize") {
+            true => name = Some(value),
+            false if meta.path.is_ident("serialize") => {}
+            false => return Err(meta.error("expected `serialize` or `deserialize`")),
+        }
+
+        Ok(())
+    })?;
+
+    Ok(name)
+}
+
+/// Case conventions of `rename_all`, converting the same way as serde.
+#[derive(Clone, Copy)]
+pub enum RenameRule {
+    Lower,
+    Upper,
+    Pascal,
+    Camel,
+    Snake,
+    ScreamingSnake,
+    Kebab,
+    ScreamingKebab,
+}
+
+impl RenameRule {
+    fn parse(rule: &LitStr) -> Result<Self, Error> {
+        match rule.value().as_str() {
+            "lowercase" => Ok(RenameRule::Lower),
+            "UPPERCASE" => Ok(RenameRule::Upper),
+            "PascalCase" => Ok(RenameRule::Pascal),
+            "camelCase" => Ok(RenameRule::Camel),
+            "snake_case" => Ok(RenameRule::Snake),
+            "SCREAMING_SNAKE_CASE" => Ok(RenameRule::ScreamingSnake),
+            "kebab-case" => Ok(RenameRule::Kebab),
+            "SCREAMING-KEBAB-CASE" => Ok(RenameRule::ScreamingKebab),
+            _ => Err(Error::new_spanned(rule, "unknown rename rule")),
+        }
+    }
+
+    /// Field names are expected in snake_case.
+    pub fn apply_to_field(self, field: &str) -> String {
+        match self {
+            RenameRule::Lower | RenameRule::Snake => field.to_string(),
+            RenameRule::Upper | RenameRule::ScreamingSnake => field.to_ascii_uppercase(),
+            RenameRule::Pascal => field
+                .split('_')
+                .map(|word| {
+                    let mut chars = word.chars();
+                    match chars.next() {
+                        Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+                        None => String::new(),
+                    }
+                })
+                .collect(),
+            RenameRule::Camel => {
+                let pascal = RenameRule::Pascal.apply_to_field(field);
+                let mut chars = pascal.chars();
+                match chars.next() {
+                    Some(first) => first.to_ascii_lowercase().to_string() + chars.as_str(),
+                    None => String::new(),
+                }
+            }
+            RenameRule::Kebab => field.replace('_', "-"),
+            RenameRule::ScreamingKebab => field.to_ascii_uppercase().replace('_', "-"),
+        }
+    }
+
+    /// Variant names are expected in PascalCase.
+    pub fn apply_to_variant(self, variant: &str) -> String {
+        match self {
+            RenameRule::Pascal => variant.to_string(),
+            RenameRule::Lower => variant.to_ascii_lowercase(),
+            RenameRule::Upper => variant.to_ascii_uppercase(),
+            RenameRule::Camel => {
+                let mut chars = variant.chars();
+                match chars.next() {
+                    Some(first) => first.to_ascii_lowercase().to_string() + chars.as_str(),
+                    None => String::new(),
+                }
+            }
+            RenameRule::Snake => {
+                let mut snake = String::new();
+                for (i, c) in variant.char_indices() {
+                    if i > 0 && c.is_uppercase() {
+                        snake.push('_');
+                    }
+                    snake.push(c.to_ascii_lowercase());
+                }
+                snake
+            }
+            RenameRule::ScreamingSnake => RenameRule::Snake
+                .apply_to_variant(variant)
+                .to_ascii_uppercase(),
+            RenameRule::Kebab => RenameRule::Snake
+                .apply_to_variant(variant)
+                .replace('_', "-"),
+            RenameRule::ScreamingKebab => RenameRule::ScreamingSnake
+                .apply_to_variant(variant)
+                .replace('_', "-"),
+        }
+    }
+}
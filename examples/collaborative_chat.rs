@@ -1,5 +1,7 @@
+#![allow(clippy::from_over_into, clippy::derivable_impls)]
+
 use autogen::agent_traits::{ConsumerAgent, NamedAgent, ProducerAgent};
-use autogen::text_chat::code::{CodeBlock, CodeBlockExecutionResult, CodeExecutor};
+use autogen::text_chat::code::CodeBlockExecutionResult;
 use autogen::text_chat::collaborative_agent::{CollaborativeAgentResponse, CommentedCodeBlock};
 
 use autogen::text_chat::chat_user_agent::CodeBlockFeedback;
@@ -9,6 +11,7 @@ use async_std::io;
 use tracing::{debug, info};
 
 #[derive(Debug)]
+#[allow(dead_code)]
 enum Error {
     Io(io::Error),
 }
@@ -98,25 +101,58 @@ impl From<ChatUserAgentMessage> for LocalMessage {
                         sender, request_execution, comment, code_block.code
                     ),
                 },
+                response @ (CollaborativeAgentResponse::Segments(_)
+                | CollaborativeAgentResponse::ToolCalls { .. }) => Self {
+                    message: format!("{}:\n{}", sender, response.to_markdown()),
+                },
             },
             ChatUserAgentMessage::CodeBlockFeedback {
                 sender,
                 comment,
                 code_block,
+                risk_report,
+            } => Self {
+                message: format!(
+                    "You are asked for feedback on the code:\nsender: {},\ncomment: {},\ncode_block: {}\n{}\n\nIf you want to allow execution, type \"allow\". Otherwise, type the reason.",
+                    sender, comment, code_block.code, risk_report
+                ),
+            },
+            ChatUserAgentMessage::CodeBlocksFeedback {
+                sender,
+                segments,
+                risk_reports,
             } => Self {
                 message: format!(
-                    "You are asked for feedback on the code:\nsender: {},\ncomment: {},\ncode_block: {}\n\nIf you want to allow execution, type \"allow\". Otherwise, type the reason.",
-                    sender, comment, code_block.code
+                    "You are asked for feedback on all the code blocks:\nsender: {},\n{}\n{}\n\nIf you want to allow execution, type \"allow\". Otherwise, type the reason.",
+                    sender,
+                    CollaborativeAgentResponse::Segments(segments).to_markdown(),
+                    risk_reports
+                        .iter()
+                        .enumerate()
+                        .map(|(index, risk_report)| format!("code block {}: {}", index + 1, risk_report))
+                        .collect::<Vec<String>>()
+                        .join("\n")
+                ),
+            },
+            ChatUserAgentMessage::ToolCallFeedback { sender, tool_call } => Self {
+                message: format!(
+                    "You are asked for feedback on the tool call:\nsender: {},\ntool: {},\narguments: {}\n\nIf you want to allow the call, type \"allow\". Otherwise, type the reason.",
+                    sender, tool_call.name, tool_call.arguments
                 ),
             },
             ChatUserAgentMessage::CodeBlockExecutionResult(code_block_execution_result) => {
-                match code_block_execution_result {
-                    CodeBlockExecutionResult::Success(info) => Self {
-                        message: format!("code_block_execution_result: {:?}", info),
-                    },
-                    CodeBlockExecutionResult::Failure(info) => Self {
-                        message: format!("code_block_execution_result: {:?}", info),
-                    },
+                let status = match code_block_execution_result {
+                    CodeBlockExecutionResult::Success(_) => "success".to_string(),
+                    CodeBlockExecutionResult::Failure(_) => "failure".to_string(),
+                    CodeBlockExecutionResult::LimitExceeded { limit, .. } => limit.to_string(),
+                };
+                let output = code_block_execution_result.output();
+
+                Self {
+                    message: format!(
+                        "code_block_execution_result: {} (exit code: {:?}, took {:?})\nstdout: {}\nstderr: {}",
+                        status, output.exit_code, output.duration, output.stdout, output.stderr
+                    ),
                 }
             }
         };
@@ -159,7 +195,9 @@ impl ConsumerAgent for LlmMock {
                 self.denied_execution = Some(())
             }
             CollaborativeAgentMessage::Text { .. }
-            | CollaborativeAgentMessage::CodeExecutionResult { .. } => {}
+            | CollaborativeAgentMessage::CodeExecutionResult { .. }
+            | CollaborativeAgentMessage::CodeExecutionResults { .. }
+            | CollaborativeAgentMessage::ToolCallResults { .. } => {}
         }
 
         Ok(())
@@ -177,19 +215,10 @@ impl ProducerAgent for LlmMock {
             )),
             None => {
                 let response = match self.request_index {
-                    0 => CollaborativeAgentResponse::CommentedCodeBlock(CommentedCodeBlock {
-                        code_block: CodeBlock {
-                            code: r#"
-                                fn main() {
-                                    println!("Hello, world!");
-                                }
-                            "#
-                            .to_string(),
-                            language: "rust".to_string(),
-                        },
-                        comment: "I hate python. Hope you like Rust!".to_string(),
-                        request_execution: true,
-                    }),
+                    // Replies of real LLMs are markdown, parsed with the default MarkdownResponseParser.
+                    0 => "I hate python. Hope you like Rust!\n```rust execute\nfn main() {\n    println!(\"Hello, world!\");\n}\n```"
+                        .to_string()
+                        .into(),
                     1 => CollaborativeAgentResponse::Text("Glad you liked it!".to_string()),
                     _ => CollaborativeAgentResponse::Text("sorry, im out of ideas..".to_string()),
                 };
@@ -201,23 +230,17 @@ impl ProducerAgent for LlmMock {
     }
 }
 
-use autogen::text_chat::collaborative_chat::collaborative_chat;
-
-struct LocalCodeExecutor;
+use autogen::text_chat::collaborative_chat::{
+    collaborative_chat_with_options, CollaborativeChatOptions,
+};
+use autogen::text_chat::termination::{IdleTimeout, TerminationCondition, UserExit};
 
-impl CodeExecutor for LocalCodeExecutor {
-    type Error = Error;
+use std::sync::Arc;
 
-    async fn execute_code_block(
-        &self,
-        code_block: &CodeBlock,
-    ) -> Result<CodeBlockExecutionResult, Self::Error> {
-        debug!("Enter execute_code_block");
-        debug!("code_block: {:?}", code_block);
+use autogen::text_chat::code::execution_limits::ExecutionLimits;
+use autogen::text_chat::code::local_code_executor::LocalCodeExecutor;
 
-        Ok(CodeBlockExecutionResult::Success("".to_string()))
-    }
-}
+use std::time::Duration;
 
 use autogen::text_chat::collaborative_chat::SystemAgent;
 
@@ -238,16 +261,32 @@ async fn main() {
     let llm_mock = LlmMock::default();
     let system_agent = LocalSystemAgent;
 
-    let executor = LocalCodeExecutor;
+    let executor = LocalCodeExecutor::new(std::env::temp_dir().join("autogen_collaborative_chat"))
+        .with_limits(ExecutionLimits {
+            timeout: Some(Duration::from_secs(30)),
+            max_output_bytes: Some(64 * 1024),
+            ..Default::default()
+        });
     let cancellation_token = CancellationToken::new();
 
-    collaborative_chat(
+    let options = CollaborativeChatOptions {
+        termination: Some(Arc::new(UserExit.or(IdleTimeout(Duration::from_secs(300))))),
+        ..Default::default()
+    };
+
+    let result = collaborative_chat_with_options(
         user_agent,
         llm_mock,
         system_agent,
         executor,
+        options,
         cancellation_token,
     )
     .await
     .unwrap();
+
+    info!(
+        "chat ended after {} turns and {:?}: {:?}",
+        result.outcome.turns, result.duration, result.outcome.reason
+    );
 }
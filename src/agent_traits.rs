@@ -1,4 +1,8 @@
-/// Very general traits for agents.
+//! Very general traits for agents.
+
+use futures::Stream;
+
+use serde::Serialize;
 
 /// One may imagine a situation, when we do not have text as our communication format.
 /// Both ConsumerAgent and ProducerAgent may be implemented to create an agent that accepts and
@@ -30,7 +34,6 @@
 /// }
 ///
 /// ```
-
 pub trait ConsumerAgent {
     type Mrx;
     type Error;
@@ -44,6 +47,57 @@ pub trait ProducerAgent {
 
     /// Based on the current state of Agent, reply with a message.
     async fn send_message(&mut self) -> Result<Self::Mtx, Self::Error>;
+
+    /// Tokens used by all the messages sent so far. Agents which are not backed by a model, or do
+    /// not know, return [None].
+    fn usage(&self) -> Option<TokenUsage> {
+        None
+    }
+}
+
+/// Tokens processed by a model, as reported by its API.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct TokenUsage {
+    /// Everything sent to the model - with a history, the same message is counted on every request.
+    pub prompt_tokens: u64,
+    /// Generated by the model.
+    pub completion_tokens: u64,
+}
+
+impl TokenUsage {
+    pub fn total(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+}
+
+impl std::ops::Add for TokenUsage {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            prompt_tokens: self.prompt_tokens + other.prompt_tokens,
+            completion_tokens: self.completion_tokens + other.completion_tokens,
+        }
+    }
+}
+
+impl std::ops::AddAssign for TokenUsage {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
+
+/// [ProducerAgent] which can hand out the message in pieces while it is being produced, e.g. an LLM
+/// generating its reply token by token.
+pub trait StreamingProducerAgent: ProducerAgent {
+    type Delta;
+
+    /// Same as [ProducerAgent::send_message], but the message comes as a stream of deltas.
+    fn send_message_stream(&mut self) -> impl Stream<Item = Result<Self::Delta, Self::Error>> + '_;
+
+    /// Once the stream ends, all the deltas it yielded are passed here in order to get the whole
+    /// message - the one [ProducerAgent::send_message] would have returned.
+    fn assemble_message(&mut self, deltas: Vec<Self::Delta>) -> Result<Self::Mtx, Self::Error>;
 }
 
 pub trait NamedAgent {
@@ -2,3 +2,12 @@
 
 pub mod agent_traits;
 pub mod text_chat;
+
+pub use autogen_macros::{tool, ToolSchema};
+
+/// Used by the code generated with the macros.
+#[doc(hidden)]
+pub mod __private {
+    pub use serde;
+    pub use serde_json;
+}
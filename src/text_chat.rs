@@ -1,3 +1,6 @@
+pub mod approval_policy;
+pub mod budget;
+pub mod chat_result;
 pub mod chat_user_agent;
 pub mod chat_user_agent_error;
 pub mod code;
@@ -5,3 +8,15 @@ pub mod collaborative_agent;
 pub mod collaborative_agent_error;
 pub mod collaborative_chat;
 pub mod collaborative_chat_error;
+pub mod conversation_history;
+pub mod group_chat;
+pub mod group_chat_error;
+pub mod human_input;
+#[cfg(any(feature = "openai", feature = "ollama", feature = "anthropic"))]
+pub mod llm;
+pub mod markdown;
+pub mod termination;
+pub mod tokenizer;
+pub mod tool;
+pub mod tool_error;
+pub mod tool_schema;
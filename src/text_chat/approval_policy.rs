@@ -0,0 +1,245 @@
+//! Approving the execution of code blocks without asking the user every time, see
+//! [super::collaborative_chat::CollaborativeChatOptions::approval_policy].
+//!
+//! Rules are checked in order and the first one with a decision wins. Code blocks no rule decides
+//! about get the default decision of the policy.
+
+use super::code::risk_analysis::{RiskKind, RiskReport};
+use super::code::CodeBlock;
+
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use regex::Regex;
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum ApprovalDecision {
+    Allow,
+    /// The reason is passed to the collaborative agent.
+    Deny {
+        reason: String,
+    },
+    /// The user agent is asked for the feedback.
+    Escalate,
+}
+
+pub trait ApprovalRule: Debug + Send + Sync {
+    /// [None] if the rule has nothing to say about the code block.
+    fn check(&self, code_block: &CodeBlock, risk_report: &RiskReport) -> Option<ApprovalDecision>;
+}
+
+/// Escalates everything unless there are rules.
+#[derive(Debug, Clone)]
+pub struct ApprovalPolicy {
+    rules: Vec<Arc<dyn ApprovalRule>>,
+    default: ApprovalDecision,
+}
+
+impl ApprovalPolicy {
+    pub fn new() -> Self {
+        Self {
+            rules: Vec::new(),
+            default: ApprovalDecision::Escalate,
+        }
+    }
+
+    /// Checked after the rules added before.
+    pub fn with_rule(mut self, rule: impl ApprovalRule + 'static) -> Self {
+        self.rules.push(Arc::new(rule));
+        self
+    }
+
+    /// For the code blocks no rule decides about, e.g. [ApprovalDecision::Allow] to ask only about
+    /// the risky ones.
+    pub fn with_default(mut self, default: ApprovalDecision) -> Self {
+        self.default = default;
+        self
+    }
+
+    /// The risk report is made by the [super::code::risk_analysis::RiskAnalyzer] of the chat.
+    pub fn decide(&self, code_block: &CodeBlock, risk_report: &RiskReport) -> ApprovalDecision {
+        self.rules
+            .iter()
+            .find_map(|rule| rule.check(code_block, risk_report))
+            .unwrap_or_else(|| self.default.clone())
+    }
+
+    /// Single decision for several code blocks - denied if any of them is, escalated if any of them
+    /// is, allowed otherwise.
+    pub fn decide_all<'a>(
+        &self,
+        code_blocks: impl IntoIterator<Item = (&'a CodeBlock, &'a RiskReport)>,
+    ) -> ApprovalDecision {
+        let mut decision = ApprovalDecision::Allow;
+
+        for (code_block, risk_report) in code_blocks {
+            match self.decide(code_block, risk_report) {
+                ApprovalDecision::Deny { reason } => return ApprovalDecision::Deny { reason },
+                ApprovalDecision::Escalate => decision = ApprovalDecision::Escalate,
+                ApprovalDecision::Allow => {}
+            }
+        }
+
+        decision
+    }
+}
+
+impl Default for ApprovalPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Denies code in any other language. Languages are compared with [CodeBlock::is_in_language], e.g.
+/// allowing `python` allows `py` and `python3` as well.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AllowedLanguages(pub Vec<String>);
+
+impl AllowedLanguages {
+    pub fn new<L: Into<String>>(languages: impl IntoIterator<Item = L>) -> Self {
+        Self(languages.into_iter().map(Into::into).collect())
+    }
+}
+
+impl ApprovalRule for AllowedLanguages {
+    fn check(&self, code_block: &CodeBlock, _: &RiskReport) -> Option<ApprovalDecision> {
+        let allowed = self
+            .0
+            .iter()
+            .any(|language| code_block.is_in_language(language));
+
+        match allowed {
+            true => None,
+            false => Some(ApprovalDecision::Deny {
+                reason: format!("{} code is not allowed", code_block.language),
+            }),
+        }
+    }
+}
+
+/// Denies code longer than the given number of bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaxCodeSize(pub usize);
+
+impl ApprovalRule for MaxCodeSize {
+    fn check(&self, code_block: &CodeBlock, _: &RiskReport) -> Option<ApprovalDecision> {
+        match code_block.code.len() > self.0 {
+            true => Some(ApprovalDecision::Deny {
+                reason: format!("code is longer than {} bytes", self.0),
+            }),
+            false => None,
+        }
+    }
+}
+
+/// Decides about the code matching the regex, e.g. denies `rm -rf` or escalates `subprocess`.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    regex: Regex,
+    decision: ApprovalDecision,
+    /// Checked only for code in these languages, all of them if empty.
+    languages: Vec<String>,
+}
+
+impl Pattern {
+    pub fn new(regex: Regex, decision: ApprovalDecision) -> Self {
+        Self {
+            regex,
+            decision,
+            languages: Vec::new(),
+        }
+    }
+
+    pub fn deny(regex: Regex, reason: impl Into<String>) -> Self {
+        Self::new(
+            regex,
+            ApprovalDecision::Deny {
+                reason: reason.into(),
+            },
+        )
+    }
+
+    pub fn escalate(regex: Regex) -> Self {
+        Self::new(regex, ApprovalDecision::Escalate)
+    }
+
+    pub fn allow(regex: Regex) -> Self {
+        Self::new(regex, ApprovalDecision::Allow)
+    }
+
+    pub fn with_languages<L: Into<String>>(
+        mut self,
+        languages: impl IntoIterator<Item = L>,
+    ) -> Self {
+        self.languages = languages.into_iter().map(Into::into).collect();
+        self
+    }
+}
+
+impl ApprovalRule for Pattern {
+    fn check(&self, code_block: &CodeBlock, _: &RiskReport) -> Option<ApprovalDecision> {
+        let language = self.languages.is_empty()
+            || self
+                .languages
+                .iter()
+                .any(|language| code_block.is_in_language(language));
+
+        match language && self.regex.is_match(&code_block.code) {
+            true => Some(self.decision.clone()),
+            false => None,
+        }
+    }
+}
+
+/// Escalates or denies the code the risk analysis found risky. With [ApprovalDecision::Allow] as
+/// the default of the policy, the user is asked only about the risky code. Code in languages which
+/// are not analyzed counts as risky.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RiskyCode {
+    deny: bool,
+    /// All of them if empty.
+    kinds: Vec<RiskKind>,
+}
+
+impl RiskyCode {
+    pub fn escalate() -> Self {
+        Self {
+            deny: false,
+            kinds: Vec::new(),
+        }
+    }
+
+    /// The reason lists the risks found.
+    pub fn deny() -> Self {
+        Self {
+            deny: true,
+            kinds: Vec::new(),
+        }
+    }
+
+    /// Only these risks count.
+    pub fn with_kinds(mut self, kinds: impl IntoIterator<Item = RiskKind>) -> Self {
+        self.kinds = kinds.into_iter().collect();
+        self
+    }
+}
+
+impl ApprovalRule for RiskyCode {
+    fn check(&self, _: &CodeBlock, risk_report: &RiskReport) -> Option<ApprovalDecision> {
+        let risky = !risk_report.analyzed
+            || risk_report
+                .risks
+                .iter()
+                .any(|risk| self.kinds.is_empty() || self.kinds.contains(&risk.kind));
+
+        match (risky, self.deny) {
+            (false, _) => None,
+            (true, true) => Some(ApprovalDecision::Deny {
+                reason: format!("the code is risky. {}", risk_report),
+            }),
+            (true, false) => Some(ApprovalDecision::Escalate),
+        }
+    }
+}
@@ -0,0 +1,45 @@
+//! Limits of the tokens and money a chat may spend.
+
+use crate::agent_traits::TokenUsage;
+
+/// Prices per million tokens, in whatever currency [Budget::max_cost] is given in.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Pricing {
+    pub prompt: f64,
+    pub completion: f64,
+}
+
+impl Pricing {
+    pub fn cost(&self, usage: &TokenUsage) -> f64 {
+        (usage.prompt_tokens as f64 * self.prompt
+            + usage.completion_tokens as f64 * self.completion)
+            / 1_000_000.0
+    }
+}
+
+/// Checked against the usage of the collaborative agent after each of its replies, see
+/// [super::collaborative_agent::CollaborativeAgent::token_usage]. Agents which do not report their
+/// usage are never over the budget.
+///
+/// Unlimited by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Budget {
+    pub max_tokens: Option<u64>,
+    /// Requires the [Pricing].
+    pub max_cost: Option<f64>,
+    pub pricing: Pricing,
+}
+
+impl Budget {
+    pub fn is_exceeded(&self, usage: &TokenUsage) -> bool {
+        let tokens = self
+            .max_tokens
+            .is_some_and(|max_tokens| usage.total() > max_tokens);
+
+        let cost = self
+            .max_cost
+            .is_some_and(|max_cost| self.pricing.cost(usage) > max_cost);
+
+        tokens || cost
+    }
+}
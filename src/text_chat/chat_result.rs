@@ -0,0 +1,114 @@
+//! What is left after the collaborative chat - the transcript and how it ended.
+
+use super::approval_policy::ApprovalDecision;
+use super::chat_user_agent::CodeBlockFeedback;
+use super::code::{CodeBlock, CodeBlockExecutionResult};
+use super::collaborative_agent::{CollaborativeAgentResponse, Message};
+use super::collaborative_chat::{ChatOutcome, TerminationReason};
+use super::tool::ToolCall;
+
+use std::time::{Duration, Instant, SystemTime};
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChatResult {
+    pub outcome: ChatOutcome,
+    /// Everything that happened, in order.
+    pub transcript: Vec<TranscriptEntry>,
+    pub started_at: SystemTime,
+    pub duration: Duration,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TranscriptEntry {
+    /// Since the start of the chat.
+    pub elapsed: Duration,
+    pub event: TranscriptEvent,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub enum TranscriptEvent {
+    /// Initial message of the system agent.
+    Welcome { sender: String, message: String },
+    /// Passed to the collaborative agent. Recorded even if it ended the chat and was not passed in
+    /// the end, e.g. [super::termination::UserExit].
+    Message(Message),
+    Reply {
+        sender: String,
+        response: CollaborativeAgentResponse,
+    },
+    /// Made by the [super::approval_policy::ApprovalPolicy], a single one for all the code blocks
+    /// in [super::collaborative_chat::ExecutionMode::Batch].
+    ApprovalDecision {
+        code_blocks: Vec<CodeBlock>,
+        decision: ApprovalDecision,
+    },
+    /// Given by the user agent.
+    CodeBlockFeedback {
+        code_block: CodeBlock,
+        feedback: CodeBlockFeedback,
+    },
+    /// Single decision for all the code blocks, see
+    /// [super::collaborative_chat::ExecutionMode::Batch].
+    CodeBlocksFeedback {
+        code_blocks: Vec<CodeBlock>,
+        feedback: CodeBlockFeedback,
+    },
+    ToolCallFeedback {
+        tool_call: ToolCall,
+        feedback: CodeBlockFeedback,
+    },
+    Execution {
+        code_block: CodeBlock,
+        result: CodeBlockExecutionResult,
+    },
+}
+
+/// Collects the transcript while the chat goes on.
+#[derive(Debug)]
+pub(crate) struct Transcript {
+    started_at: SystemTime,
+    start: Instant,
+    entries: Vec<TranscriptEntry>,
+}
+
+impl Transcript {
+    pub(crate) fn new() -> Self {
+        Self {
+            started_at: SystemTime::now(),
+            start: Instant::now(),
+            entries: Vec::new(),
+        }
+    }
+
+    pub(crate) fn record(&mut self, event: TranscriptEvent) {
+        self.entries.push(TranscriptEntry {
+            elapsed: self.start.elapsed(),
+            event,
+        });
+    }
+
+    /// Replies recorded so far are the turns, as they are counted by the chat.
+    pub(crate) fn fail(self) -> ChatResult {
+        let turns = self
+            .entries
+            .iter()
+            .filter(|entry| matches!(entry.event, TranscriptEvent::Reply { .. }))
+            .count();
+
+        self.finish(ChatOutcome {
+            reason: TerminationReason::Failed,
+            turns,
+        })
+    }
+
+    pub(crate) fn finish(self, outcome: ChatOutcome) -> ChatResult {
+        ChatResult {
+            outcome,
+            duration: self.start.elapsed(),
+            transcript: self.entries,
+            started_at: self.started_at,
+        }
+    }
+}
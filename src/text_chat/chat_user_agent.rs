@@ -1,13 +1,19 @@
-use super::collaborative_agent::CollaborativeAgentResponse;
+use super::collaborative_agent::{CollaborativeAgentResponse, ResponseSegment};
 
+use super::code::risk_analysis::RiskReport;
 use super::code::CodeBlock;
 
 use super::code::CodeBlockExecutionResult;
 
+use super::tool::ToolCall;
+
 use crate::agent_traits::{ConsumerAgent, ProducerAgent};
 
 use super::chat_user_agent_error::ChatUserAgentError;
 
+use serde::Serialize;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub enum CodeBlockFeedback {
     AllowExecution,
     DenyExecution { reason: String },
@@ -19,7 +25,6 @@ pub enum CodeBlockFeedback {
 /// Consumer/Producer force user to dynamically distiguish between the types of queries.
 /// If used with stdin/stdout Consumer/Producer apprach will probably be better (unless we want
 /// custom formatting for different inputs), but in the case of tests or other implementations it might be easier and more explicit to use this trait
-
 pub trait ChatUserAgent {
     type Error;
 
@@ -42,10 +47,91 @@ pub trait ChatUserAgent {
         code_block: CodeBlock,
     ) -> Result<CodeBlockFeedback, Self::Error>;
 
+    /// This is what the chat actually calls. The risk report is meant to be shown along with the
+    /// code, by default it is ignored.
+    async fn request_code_block_feedback_with_risks(
+        &mut self,
+        sender: String,
+        comment: String,
+        code_block: CodeBlock,
+        _risk_report: RiskReport,
+    ) -> Result<CodeBlockFeedback, Self::Error> {
+        self.request_code_block_feedback(sender, comment, code_block)
+            .await
+    }
+
+    /// Used in [super::collaborative_chat::ExecutionMode::Batch] - a single decision for all the
+    /// code blocks of the response which request execution. There is a risk report for each of
+    /// them, in order.
+    ///
+    /// By default every code block is asked for separately, with the text before it as the
+    /// comment. The first denial is the decision for all of them.
+    async fn request_code_blocks_feedback(
+        &mut self,
+        sender: String,
+        segments: Vec<ResponseSegment>,
+        risk_reports: Vec<RiskReport>,
+    ) -> Result<CodeBlockFeedback, Self::Error> {
+        let mut risk_reports = risk_reports.into_iter();
+        let mut comment = String::new();
+
+        for segment in segments {
+            match segment {
+                ResponseSegment::Text(text) => comment = text,
+                ResponseSegment::CodeBlock {
+                    code_block,
+                    request_execution: true,
+                } => {
+                    let feedback = self
+                        .request_code_block_feedback_with_risks(
+                            sender.clone(),
+                            std::mem::take(&mut comment),
+                            code_block,
+                            risk_reports.next().unwrap_or_default(),
+                        )
+                        .await?;
+
+                    if let CodeBlockFeedback::DenyExecution { .. } = feedback {
+                        return Ok(feedback);
+                    }
+                }
+                ResponseSegment::CodeBlock { .. } => comment.clear(),
+            }
+        }
+
+        Ok(CodeBlockFeedback::AllowExecution)
+    }
+
     async fn receive_code_execution_result(
         &mut self,
         result: CodeBlockExecutionResult,
     ) -> Result<(), Self::Error>;
+
+    /// Asked before every tool call, unless approval is disabled with
+    /// [super::collaborative_chat::ToolCallApproval::Automatic]. Denied by default.
+    async fn request_tool_call_feedback(
+        &mut self,
+        _sender: String,
+        tool_call: ToolCall,
+    ) -> Result<CodeBlockFeedback, Self::Error> {
+        Ok(CodeBlockFeedback::DenyExecution {
+            reason: format!(
+                "The user cannot approve tool calls, `{}` was not called.",
+                tool_call.name
+            ),
+        })
+    }
+
+    /// Used in [super::collaborative_chat::streaming_collaborative_chat] - a piece of the reply
+    /// as soon as the collaborative agent produces it. The whole reply is passed to the other
+    /// methods afterwards as usual, so the partial text may simply be ignored.
+    async fn receive_partial_text(
+        &mut self,
+        _sender: String,
+        _delta: String,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
 }
 
 pub enum Message {
@@ -61,8 +147,19 @@ pub enum Message {
         sender: String,
         comment: String,
         code_block: CodeBlock,
+        risk_report: RiskReport,
+    },
+    CodeBlocksFeedback {
+        sender: String,
+        segments: Vec<ResponseSegment>,
+        /// One for each code block requesting execution, in order.
+        risk_reports: Vec<RiskReport>,
     },
     CodeBlockExecutionResult(CodeBlockExecutionResult),
+    ToolCallFeedback {
+        sender: String,
+        tool_call: ToolCall,
+    },
 }
 
 /// This is a convenience implementation of ChatUserAgent for any Agent that implements
@@ -124,11 +221,58 @@ where
         sender: String,
         comment: String,
         code_block: CodeBlock,
+    ) -> Result<CodeBlockFeedback, Self::Error> {
+        self.request_code_block_feedback_with_risks(
+            sender,
+            comment,
+            code_block,
+            RiskReport::default(),
+        )
+        .await
+    }
+
+    async fn request_code_block_feedback_with_risks(
+        &mut self,
+        sender: String,
+        comment: String,
+        code_block: CodeBlock,
+        risk_report: RiskReport,
     ) -> Result<CodeBlockFeedback, Self::Error> {
         let message = Message::CodeBlockFeedback {
             sender,
             comment,
             code_block,
+            risk_report,
+        };
+
+        let message = Mrx::try_from(message).map_err(|_| ChatUserAgentError::TryFromMessage)?;
+
+        self.receive_message(message)
+            .await
+            .map_err(ChatUserAgentError::Receiving)?;
+
+        let response = self
+            .send_message()
+            .await
+            .map_err(ChatUserAgentError::Sending)?;
+
+        let response = response
+            .try_into()
+            .map_err(|_| ChatUserAgentError::TryIntoString)?;
+
+        Ok(response)
+    }
+
+    async fn request_code_blocks_feedback(
+        &mut self,
+        sender: String,
+        segments: Vec<ResponseSegment>,
+        risk_reports: Vec<RiskReport>,
+    ) -> Result<CodeBlockFeedback, Self::Error> {
+        let message = Message::CodeBlocksFeedback {
+            sender,
+            segments,
+            risk_reports,
         };
 
         let message = Mrx::try_from(message).map_err(|_| ChatUserAgentError::TryFromMessage)?;
@@ -163,4 +307,29 @@ where
 
         Ok(())
     }
+
+    async fn request_tool_call_feedback(
+        &mut self,
+        sender: String,
+        tool_call: ToolCall,
+    ) -> Result<CodeBlockFeedback, Self::Error> {
+        let message = Message::ToolCallFeedback { sender, tool_call };
+
+        let message = Mrx::try_from(message).map_err(|_| ChatUserAgentError::TryFromMessage)?;
+
+        self.receive_message(message)
+            .await
+            .map_err(ChatUserAgentError::Receiving)?;
+
+        let response = self
+            .send_message()
+            .await
+            .map_err(ChatUserAgentError::Sending)?;
+
+        let response = response
+            .try_into()
+            .map_err(|_| ChatUserAgentError::TryIntoString)?;
+
+        Ok(response)
+    }
 }
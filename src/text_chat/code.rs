@@ -1,14 +1,178 @@
+pub mod execution_limits;
+#[cfg(feature = "jupyter")]
+pub mod jupyter_code_executor;
+#[cfg(feature = "jupyter")]
+pub mod jupyter_code_executor_error;
+pub mod local_code_executor;
+pub mod local_code_executor_error;
+pub mod repl_code_executor;
+pub mod repl_code_executor_error;
+pub mod risk_analysis;
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+pub mod sandboxed_code_executor;
+#[cfg(feature = "wasm")]
+pub mod wasm_code_executor;
+#[cfg(feature = "wasm")]
+pub mod wasm_code_executor_error;
+
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+mod sandbox;
+
+#[cfg(feature = "jupyter")]
+mod jupyter_kernel;
+
+use execution_limits::ExecutionLimit;
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::Serialize;
+
 /// Language is kept as a string for now.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct CodeBlock {
     pub language: String,
     pub code: String,
 }
 
-#[derive(Debug, Clone)]
+impl CodeBlock {
+    /// The known language of the code block, see [Language::from_name].
+    pub fn known_language(&self) -> Option<Language> {
+        Language::from_name(&self.language)
+    }
+
+    /// Whether the code block is in the language of the given name. Known languages are compared by
+    /// their aliases, the others ignoring case.
+    pub fn is_in_language(&self, name: &str) -> bool {
+        match (self.known_language(), Language::from_name(name)) {
+            (Some(language), Some(other)) => language == other,
+            _ => self.language.trim().eq_ignore_ascii_case(name.trim()),
+        }
+    }
+}
+
+/// Languages known to the executors, each with the aliases used for it in code blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Language {
+    /// `python`, `python3` or `py`.
+    Python,
+    /// `bash`, `shell` or `console`.
+    Bash,
+    /// `sh` - the POSIX shell, not necessarily bash.
+    Sh,
+    /// `rust` or `rs`.
+    Rust,
+}
+
+impl Language {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "python" | "python3" | "py" => Some(Language::Python),
+            "bash" | "shell" | "console" => Some(Language::Bash),
+            "sh" => Some(Language::Sh),
+            "rust" | "rs" => Some(Language::Rust),
+            _ => None,
+        }
+    }
+
+    /// Extension of source files in the language.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Language::Python => "py",
+            Language::Bash | Language::Sh => "sh",
+            Language::Rust => "rs",
+        }
+    }
+}
+
+/// Everything that is known about a finished execution of a [CodeBlock].
+///
+/// Executors that do not track some of the details (e.g. remote ones) may simply leave them at
+/// their defaults.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct CodeBlockExecutionOutput {
+    /// None if the process was terminated by a signal or the executor has no notion of exit codes.
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    /// Wall time of the execution.
+    pub duration: Duration,
+    /// Set when the executor did not capture the whole output.
+    pub truncated: bool,
+    /// Files created or modified by the code.
+    pub produced_files: Vec<PathBuf>,
+}
+
+impl CodeBlockExecutionOutput {
+    /// Stdout followed by stderr.
+    pub fn combined_output(&self) -> String {
+        let mut output = self.stdout.clone();
+        output.push_str(&self.stderr);
+
+        output
+    }
+}
+
+/// Results used to be plain strings. The string is stored as stdout, so
+/// [CodeBlockExecutionOutput::combined_output] gives it back unchanged.
+impl From<String> for CodeBlockExecutionOutput {
+    fn from(output: String) -> Self {
+        Self {
+            stdout: output,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<&str> for CodeBlockExecutionOutput {
+    fn from(output: &str) -> Self {
+        output.to_string().into()
+    }
+}
+
+impl From<CodeBlockExecutionOutput> for String {
+    fn from(output: CodeBlockExecutionOutput) -> Self {
+        output.combined_output()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub enum CodeBlockExecutionResult {
-    Success(String),
-    Failure(String),
+    Success(CodeBlockExecutionOutput),
+    Failure(CodeBlockExecutionOutput),
+    /// Execution was terminated by the executor. Output contains whatever was captured until then.
+    LimitExceeded {
+        limit: ExecutionLimit,
+        output: CodeBlockExecutionOutput,
+    },
+}
+
+impl CodeBlockExecutionResult {
+    pub fn is_success(&self) -> bool {
+        matches!(self, CodeBlockExecutionResult::Success(_))
+    }
+
+    pub fn output(&self) -> &CodeBlockExecutionOutput {
+        match self {
+            CodeBlockExecutionResult::Success(output)
+            | CodeBlockExecutionResult::Failure(output)
+            | CodeBlockExecutionResult::LimitExceeded { output, .. } => output,
+        }
+    }
+
+    pub fn into_output(self) -> CodeBlockExecutionOutput {
+        match self {
+            CodeBlockExecutionResult::Success(output)
+            | CodeBlockExecutionResult::Failure(output)
+            | CodeBlockExecutionResult::LimitExceeded { output, .. } => output,
+        }
+    }
 }
 
 /// One may define a custom executor for a specific platform.
@@ -17,7 +181,6 @@ pub enum CodeBlockExecutionResult {
 ///
 /// This is very similar to the UserProxyAgent, however since its a trait, it does not care about
 /// any specific implementation and configs as the Python version does.
-
 pub trait CodeExecutor {
     type Error;
 
@@ -26,3 +189,16 @@ pub trait CodeExecutor {
         code_block: &CodeBlock,
     ) -> Result<CodeBlockExecutionResult, Self::Error>;
 }
+
+/// Allows passing an executor to the chat by reference, so that it can still be used afterwards,
+/// e.g. to reset a stateful executor between chats.
+impl<E: CodeExecutor> CodeExecutor for &E {
+    type Error = E::Error;
+
+    async fn execute_code_block(
+        &self,
+        code_block: &CodeBlock,
+    ) -> Result<CodeBlockExecutionResult, Self::Error> {
+        (**self).execute_code_block(code_block).await
+    }
+}
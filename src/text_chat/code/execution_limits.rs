@@ -0,0 +1,310 @@
+use std::io;
+use std::process::{ExitStatus, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::process::Command;
+
+use tokio_util::sync::CancellationToken;
+
+use tracing::debug;
+
+use serde::Serialize;
+
+/// Limits enforced on a single code block execution. Every limit is optional and none of them is set
+/// by default.
+///
+/// Resource limits (memory, cpu time and processes) are applied with `setrlimit` and are only
+/// available on unix.
+#[derive(Debug, Clone, Default)]
+pub struct ExecutionLimits {
+    /// Wall-clock time after which the process is killed.
+    pub timeout: Option<Duration>,
+    /// Maximum number of bytes captured from stdout and stderr together. The process is killed once
+    /// it writes more than that.
+    pub max_output_bytes: Option<usize>,
+    /// Maximum size of the address space (RLIMIT_AS).
+    pub max_memory_bytes: Option<u64>,
+    /// Maximum cpu time (RLIMIT_CPU). Precision is in seconds.
+    pub max_cpu_time: Option<Duration>,
+    /// Maximum number of processes of the user (RLIMIT_NPROC). Note that this counts all the processes
+    /// of the user, not only the ones spawned by the code.
+    pub max_processes: Option<u64>,
+}
+
+/// The limit which caused the execution to be terminated.
+///
+/// Exceeding the memory limit usually surfaces as an allocation error inside of the program, thus
+/// it is reported as an ordinary failure rather than here.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ExecutionLimit {
+    Timeout(Duration),
+    OutputSize(usize),
+    CpuTime(Duration),
+    /// Fuel given to a wasm module was used up.
+    Fuel(u64),
+}
+
+impl std::fmt::Display for ExecutionLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExecutionLimit::Timeout(timeout) => {
+                write!(f, "execution timed out after {:?}", timeout)
+            }
+            ExecutionLimit::OutputSize(bytes) => {
+                write!(f, "output exceeded the limit of {} bytes", bytes)
+            }
+            ExecutionLimit::CpuTime(cpu_time) => {
+                write!(f, "cpu time exceeded the limit of {:?}", cpu_time)
+            }
+            ExecutionLimit::Fuel(fuel) => write!(f, "used up all {} units of fuel", fuel),
+        }
+    }
+}
+
+/// Output of a process run under [ExecutionLimits].
+#[derive(Debug)]
+pub(crate) struct LimitedOutput {
+    /// None if the process had to be killed and its status could not be collected.
+    pub status: Option<ExitStatus>,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub limit_exceeded: Option<ExecutionLimit>,
+}
+
+impl ExecutionLimits {
+    /// Spawns the command, captures its output and enforces the limits.
+    pub(crate) async fn run(&self, command: &mut Command) -> io::Result<LimitedOutput> {
+        self.apply_resource_limits(command);
+
+        // Own process group, so that the whole group can be killed - not only the direct child.
+        #[cfg(unix)]
+        command.process_group(0);
+
+        let start = Instant::now();
+
+        let mut child = command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+
+        // Kept aside, as the id of the child is gone once it is waited for, while its process group
+        // may still be alive.
+        let pid = child.id();
+
+        let stdout_pipe = child.stdout.take();
+        let stderr_pipe = child.stderr.take();
+
+        let mut stdout = Vec::new();
+        let mut stderr = Vec::new();
+
+        let captured_bytes = AtomicUsize::new(0);
+        let output_exceeded = CancellationToken::new();
+
+        let execution = async {
+            let streams = async {
+                tokio::try_join!(
+                    self.read_capped(stdout_pipe, &mut stdout, &captured_bytes, &output_exceeded),
+                    self.read_capped(stderr_pipe, &mut stderr, &captured_bytes, &output_exceeded),
+                )
+            };
+
+            let exit = async {
+                let status = child.wait().await?;
+                // Whatever the code left running in the background is killed with it, otherwise it
+                // could keep the pipes open and the streams would never end.
+                kill_group(pid);
+                Ok(status)
+            };
+
+            tokio::select! {
+                result = async { tokio::try_join!(streams, exit) } => {
+                    result.map(|(_, status)| Some(status))
+                }
+                _ = output_exceeded.cancelled() => Ok(None),
+            }
+        };
+
+        let finished = match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, execution).await.ok(),
+            None => Some(execution.await),
+        };
+
+        let (status, limit_exceeded) = match finished {
+            Some(Ok(Some(status))) => (
+                Some(status),
+                self.cpu_time_exceeded(&status, start.elapsed()),
+            ),
+            Some(Ok(None)) => {
+                debug!("output limit exceeded, killing the process..");
+                kill_group(pid);
+                let max_output_bytes = self.max_output_bytes.unwrap_or_default();
+                (
+                    kill(&mut child).await,
+                    Some(ExecutionLimit::OutputSize(max_output_bytes)),
+                )
+            }
+            Some(Err(e)) => return Err(e),
+            None => {
+                debug!("timeout exceeded, killing the process..");
+                kill_group(pid);
+                let timeout = self.timeout.unwrap_or_default();
+                (
+                    kill(&mut child).await,
+                    Some(ExecutionLimit::Timeout(timeout)),
+                )
+            }
+        };
+
+        Ok(LimitedOutput {
+            status,
+            stdout,
+            stderr,
+            limit_exceeded,
+        })
+    }
+
+    /// Reads the pipe until it is closed or the output limit is exceeded, in which case
+    /// `output_exceeded` is cancelled.
+    async fn read_capped(
+        &self,
+        pipe: Option<impl AsyncRead + Unpin>,
+        buffer: &mut Vec<u8>,
+        captured_bytes: &AtomicUsize,
+        output_exceeded: &CancellationToken,
+    ) -> io::Result<()> {
+        let mut pipe = match pipe {
+            Some(pipe) => pipe,
+            None => return Ok(()),
+        };
+
+        let mut chunk = [0u8; 8192];
+
+        loop {
+            let read = pipe.read(&mut chunk).await?;
+            if read == 0 {
+                return Ok(());
+            }
+
+            let captured_before = captured_bytes.fetch_add(read, Ordering::SeqCst);
+
+            match self.max_output_bytes {
+                Some(max_output_bytes) if captured_before + read > max_output_bytes => {
+                    let allowed = max_output_bytes.saturating_sub(captured_before).min(read);
+                    buffer.extend_from_slice(&chunk[..allowed]);
+
+                    output_exceeded.cancel();
+                    // The process is going to be killed, so there is nothing more to read.
+                    std::future::pending::<()>().await;
+                }
+                _ => buffer.extend_from_slice(&chunk[..read]),
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    pub(crate) fn apply_resource_limits(&self, command: &mut Command) {
+        let limits = [
+            (libc::RLIMIT_AS, self.max_memory_bytes),
+            // The hard limit is one second above the soft limit, so the process gets SIGXCPU
+            // before it is killed.
+            (
+                libc::RLIMIT_CPU,
+                self.max_cpu_time.map(|t| t.as_secs().max(1)),
+            ),
+            (libc::RLIMIT_NPROC, self.max_processes),
+        ];
+
+        let limits: Vec<_> = limits
+            .into_iter()
+            .filter_map(|(resource, limit)| limit.map(|limit| (resource, limit)))
+            .collect();
+
+        if limits.is_empty() {
+            return;
+        }
+
+        // SAFETY: the closure only calls setrlimit, which is async-signal-safe, and does not
+        // allocate.
+        unsafe {
+            command.pre_exec(move || {
+                for (resource, limit) in &limits {
+                    let hard_limit = match *resource == libc::RLIMIT_CPU {
+                        true => limit + 1,
+                        false => *limit,
+                    };
+
+                    let rlimit = libc::rlimit {
+                        rlim_cur: *limit as libc::rlim_t,
+                        rlim_max: hard_limit as libc::rlim_t,
+                    };
+
+                    if libc::setrlimit(*resource, &rlimit) != 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+
+                Ok(())
+            });
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub(crate) fn apply_resource_limits(&self, _command: &mut Command) {}
+
+    /// SIGXCPU is sent on the soft limit. Processes ignoring it (e.g. init of a pid namespace) are
+    /// killed on the hard limit - SIGKILL is attributed to the limit only if the process ran at least
+    /// as long as the limit, as cpu time cannot exceed the wall time.
+    #[cfg(unix)]
+    fn cpu_time_exceeded(&self, status: &ExitStatus, elapsed: Duration) -> Option<ExecutionLimit> {
+        use std::os::unix::process::ExitStatusExt;
+
+        match (self.max_cpu_time, status.signal()) {
+            (Some(max_cpu_time), Some(libc::SIGXCPU)) => {
+                Some(ExecutionLimit::CpuTime(max_cpu_time))
+            }
+            (Some(max_cpu_time), Some(libc::SIGKILL)) if elapsed >= max_cpu_time => {
+                Some(ExecutionLimit::CpuTime(max_cpu_time))
+            }
+            _ => None,
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn cpu_time_exceeded(
+        &self,
+        _status: &ExitStatus,
+        _elapsed: Duration,
+    ) -> Option<ExecutionLimit> {
+        None
+    }
+}
+
+/// Kills the process group of the child and collects its status.
+pub(crate) async fn kill(child: &mut tokio::process::Child) -> Option<ExitStatus> {
+    kill_group(child.id());
+
+    if let Err(e) = child.kill().await {
+        debug!("failed to kill the process: {:?}", e);
+    }
+
+    child.try_wait().ok().flatten()
+}
+
+/// Kills the process group led by the process, which stays around as long as any of its members
+/// does.
+fn kill_group(pid: Option<u32>) {
+    #[cfg(unix)]
+    if let Some(pid) = pid {
+        // SAFETY: killpg has no memory safety preconditions.
+        unsafe {
+            libc::killpg(pid as libc::pid_t, libc::SIGKILL);
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = pid;
+}
@@ -0,0 +1,686 @@
+use super::{CodeBlock, CodeBlockExecutionOutput, CodeBlockExecutionResult, CodeExecutor};
+
+use super::execution_limits::{kill, ExecutionLimit, ExecutionLimits};
+use super::jupyter_code_executor_error::JupyterCodeExecutorError;
+use super::jupyter_kernel::{ConnectionInfo, KernelConnection, KernelMessage};
+use super::local_code_executor::{source_file_stem, FilesSnapshot};
+
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
+use base64::Engine;
+use serde_json::{json, Map, Value};
+use tokio::io::AsyncWriteExt;
+use tokio::process::{Child, Command};
+use tokio::sync::Mutex;
+
+use tracing::debug;
+
+/// Placeholder in [JupyterKernel::argv] replaced with the path to the connection file. Same as in
+/// the `kernel.json` of kernelspecs.
+pub const CONNECTION_FILE_PLACEHOLDER: &str = "{connection_file}";
+
+const DEFAULT_STARTUP_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long the kernel has to exit on its own after a shutdown request, before it is killed.
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(1);
+
+/// Rich outputs saved to files, with the extension used for them. Textual formats are stored as
+/// they are, binary ones are base64 encoded in the messages.
+const SAVED_MIME_TYPES: &[(&str, &str, bool)] = &[
+    ("image/png", "png", true),
+    ("image/jpeg", "jpg", true),
+    ("image/svg+xml", "svg", false),
+    ("text/html", "html", false),
+];
+
+/// How to launch a kernel.
+#[derive(Debug, Clone)]
+pub struct JupyterKernel {
+    /// Command starting the kernel. [CONNECTION_FILE_PLACEHOLDER] is replaced with the path to the
+    /// connection file.
+    pub argv: Vec<String>,
+    /// Languages of code blocks sent to this kernel, compared with [CodeBlock::is_in_language].
+    pub languages: Vec<String>,
+}
+
+impl JupyterKernel {
+    /// Reads `argv` and `language` from the `kernel.json` of a kernelspec.
+    pub fn from_kernel_spec(path: impl AsRef<Path>) -> Result<Self, JupyterCodeExecutorError> {
+        let spec = std::fs::read(path).map_err(JupyterCodeExecutorError::Io)?;
+        let spec: Value = serde_json::from_slice(&spec)
+            .map_err(|e| JupyterCodeExecutorError::InvalidMessage(e.to_string()))?;
+
+        let argv = spec["argv"]
+            .as_array()
+            .map(|argv| {
+                argv.iter()
+                    .filter_map(|arg| arg.as_str().map(str::to_string))
+                    .collect()
+            })
+            .ok_or_else(|| {
+                JupyterCodeExecutorError::InvalidMessage("kernel spec without argv".to_string())
+            })?;
+
+        let languages = spec["language"]
+            .as_str()
+            .map(|language| vec![language.to_lowercase()])
+            .unwrap_or_default();
+
+        Ok(Self { argv, languages })
+    }
+}
+
+/// ipykernel, started with the `python3` found in PATH.
+impl Default for JupyterKernel {
+    fn default() -> Self {
+        Self {
+            argv: [
+                "python3",
+                "-m",
+                "ipykernel_launcher",
+                "-f",
+                CONNECTION_FILE_PLACEHOLDER,
+            ]
+            .map(str::to_string)
+            .to_vec(),
+            languages: vec!["python".to_string()],
+        }
+    }
+}
+
+struct KernelSession {
+    child: Child,
+    connection: KernelConnection,
+    connection_file: PathBuf,
+}
+
+impl Drop for KernelSession {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.connection_file) {
+            debug!("failed to remove the connection file: {:?}", e);
+        }
+    }
+}
+
+/// How waiting for the messages of a request ended.
+enum ExecutionEnd {
+    /// The kernel became idle. Contains the status from the execute reply.
+    Idle(String),
+    KernelDied(Option<i32>),
+    OutputExceeded,
+}
+
+/// Runs code blocks in a Jupyter kernel (ipykernel or any other compliant one), so the state is
+/// kept between code blocks and rich outputs are available.
+///
+/// The kernel is launched lazily in `work_dir` on the first code block and lives until
+/// [JupyterCodeExecutor::reset] or until the executor is dropped. Outputs of the execution are
+/// mapped as follows:
+/// - `stream` messages go to stdout and stderr.
+/// - `execute_result` and `display_data` put their `text/plain` representation into stdout. Images
+///   and html are saved in `work_dir` and reported as produced files.
+/// - `error` puts the traceback into stderr and makes the result a failure.
+///
+/// Kernels have no exit codes, so `exit_code` is only set if the kernel died.
+///
+/// [ExecutionLimits] apply as in the [super::repl_code_executor::ReplCodeExecutor] - hitting the
+/// timeout or the output limit kills the kernel.
+pub struct JupyterCodeExecutor {
+    work_dir: PathBuf,
+    kernel: JupyterKernel,
+    limits: ExecutionLimits,
+    startup_timeout: Duration,
+    session: Mutex<Option<KernelSession>>,
+}
+
+impl JupyterCodeExecutor {
+    pub fn new(work_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            work_dir: work_dir.into(),
+            kernel: JupyterKernel::default(),
+            limits: ExecutionLimits::default(),
+            startup_timeout: DEFAULT_STARTUP_TIMEOUT,
+            session: Mutex::new(None),
+        }
+    }
+
+    pub fn with_kernel(mut self, kernel: JupyterKernel) -> Self {
+        self.kernel = kernel;
+        self
+    }
+
+    pub fn with_limits(mut self, limits: ExecutionLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// How long to wait for the kernel to start, 60 seconds by default.
+    pub fn with_startup_timeout(mut self, startup_timeout: Duration) -> Self {
+        self.startup_timeout = startup_timeout;
+        self
+    }
+
+    pub fn work_dir(&self) -> &Path {
+        &self.work_dir
+    }
+
+    /// Shuts the kernel down. The next code block starts a new one with a clean state.
+    pub async fn reset(&self) {
+        if let Some(mut session) = self.session.lock().await.take() {
+            debug!("shutting the kernel down..");
+
+            let requested = session
+                .connection
+                .send_control("shutdown_request", json!({ "restart": false }))
+                .await;
+
+            let exited = match requested {
+                Ok(_) => tokio::time::timeout(SHUTDOWN_GRACE_PERIOD, session.child.wait())
+                    .await
+                    .is_ok(),
+                Err(_) => false,
+            };
+
+            if !exited {
+                kill(&mut session.child).await;
+            }
+        }
+    }
+
+    async fn start_session(&self) -> Result<KernelSession, JupyterCodeExecutorError> {
+        debug!("starting the kernel..");
+
+        let info = ConnectionInfo::new().map_err(JupyterCodeExecutorError::Io)?;
+
+        let connection_file =
+            std::env::temp_dir().join(format!("autogen_kernel_{}.json", source_file_stem()));
+
+        write_connection_file(&connection_file, &info)
+            .await
+            .map_err(JupyterCodeExecutorError::Io)?;
+
+        match self.launch_kernel(&info, &connection_file).await {
+            Ok((child, connection)) => Ok(KernelSession {
+                child,
+                connection,
+                connection_file,
+            }),
+            Err(e) => {
+                if let Err(e) = tokio::fs::remove_file(&connection_file).await {
+                    debug!("failed to remove the connection file: {:?}", e);
+                }
+
+                Err(e)
+            }
+        }
+    }
+
+    async fn launch_kernel(
+        &self,
+        info: &ConnectionInfo,
+        connection_file: &Path,
+    ) -> Result<(Child, KernelConnection), JupyterCodeExecutorError> {
+        let connection_file = connection_file.display().to_string();
+        let argv: Vec<String> = self
+            .kernel
+            .argv
+            .iter()
+            .map(|arg| arg.replace(CONNECTION_FILE_PLACEHOLDER, &connection_file))
+            .collect();
+
+        let (program, args) = argv.split_first().ok_or_else(|| {
+            JupyterCodeExecutorError::KernelStartup("empty kernel argv".to_string())
+        })?;
+
+        let mut command = Command::new(program);
+        command.args(args);
+        self.limits.apply_resource_limits(&mut command);
+
+        #[cfg(unix)]
+        command.process_group(0);
+
+        let mut child = command
+            .current_dir(&self.work_dir)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|source| JupyterCodeExecutorError::Spawn {
+                program: program.clone(),
+                source,
+            })?;
+
+        let ready = async {
+            let mut connection = KernelConnection::connect(info).await?;
+            wait_until_ready(&mut connection).await?;
+
+            Ok::<_, JupyterCodeExecutorError>(connection)
+        };
+
+        let started = tokio::select! {
+            ready = tokio::time::timeout(self.startup_timeout, ready) => ready,
+            status = child.wait() => {
+                let status = status.map_err(JupyterCodeExecutorError::Io)?;
+
+                return Err(JupyterCodeExecutorError::KernelStartup(format!(
+                    "kernel exited during the startup with {}",
+                    status
+                )));
+            }
+        };
+
+        match started {
+            Ok(Ok(connection)) => Ok((child, connection)),
+            Ok(Err(e)) => {
+                kill(&mut child).await;
+                Err(e)
+            }
+            Err(_) => {
+                kill(&mut child).await;
+                Err(JupyterCodeExecutorError::KernelStartup(format!(
+                    "kernel did not start in {:?}",
+                    self.startup_timeout
+                )))
+            }
+        }
+    }
+
+    /// Collects the outputs of the request until the kernel becomes idle.
+    async fn collect_outputs(
+        &self,
+        session: &mut KernelSession,
+        msg_id: &str,
+        stem: &str,
+        stdout: &mut String,
+        stderr: &mut String,
+    ) -> Result<ExecutionEnd, JupyterCodeExecutorError> {
+        let mut saved_outputs = 0;
+
+        loop {
+            let message = tokio::select! {
+                message = session.connection.recv_iopub() => message,
+                status = session.child.wait() => {
+                    let status = status.map_err(JupyterCodeExecutorError::Io)?;
+                    return Ok(ExecutionEnd::KernelDied(status.code()));
+                }
+            };
+
+            let message = match message {
+                Ok(message) if message.parent_id.as_deref() == Some(msg_id) => message,
+                Ok(_) => continue,
+                Err(JupyterCodeExecutorError::InvalidMessage(e)) => {
+                    debug!("skipping invalid message: {}", e);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+
+            let content = &message.content;
+
+            match message.msg_type.as_str() {
+                "stream" => {
+                    let text = content["text"].as_str().unwrap_or_default();
+                    match content["name"].as_str() {
+                        Some("stderr") => stderr.push_str(text),
+                        _ => stdout.push_str(text),
+                    }
+                }
+                "execute_result" | "display_data" => {
+                    let data = content["data"].as_object().cloned().unwrap_or_default();
+
+                    if let Some(text) = data.get("text/plain").and_then(Value::as_str) {
+                        stdout.push_str(text);
+                        stdout.push('\n');
+                    }
+
+                    let name = format!("{}_output_{}", stem, saved_outputs);
+                    saved_outputs += 1;
+
+                    save_rich_outputs(&self.work_dir, &name, &data).await?;
+                }
+                "error" => {
+                    let traceback: Vec<&str> = content["traceback"]
+                        .as_array()
+                        .map(|lines| lines.iter().filter_map(Value::as_str).collect())
+                        .unwrap_or_default();
+
+                    match traceback.is_empty() {
+                        true => stderr.push_str(&format!(
+                            "{}: {}",
+                            content["ename"].as_str().unwrap_or_default(),
+                            content["evalue"].as_str().unwrap_or_default()
+                        )),
+                        false => stderr.push_str(&strip_ansi_escapes(&traceback.join("\n"))),
+                    }
+                    stderr.push('\n');
+                }
+                "status" if content["execution_state"] == "idle" => {
+                    let reply = wait_for_reply(&mut session.connection, msg_id).await?;
+                    let status = reply.content["status"].as_str().unwrap_or_default();
+
+                    return Ok(ExecutionEnd::Idle(status.to_string()));
+                }
+                _ => {}
+            }
+
+            if let Some(max_output_bytes) = self.limits.max_output_bytes {
+                if stdout.len() + stderr.len() > max_output_bytes {
+                    truncate(stderr, max_output_bytes.saturating_sub(stdout.len()));
+                    truncate(stdout, max_output_bytes);
+
+                    return Ok(ExecutionEnd::OutputExceeded);
+                }
+            }
+        }
+    }
+}
+
+impl CodeExecutor for JupyterCodeExecutor {
+    type Error = JupyterCodeExecutorError;
+
+    async fn execute_code_block(
+        &self,
+        code_block: &CodeBlock,
+    ) -> Result<CodeBlockExecutionResult, Self::Error> {
+        let supported = self
+            .kernel
+            .languages
+            .iter()
+            .any(|language| code_block.is_in_language(language));
+
+        if !supported {
+            debug!("unsupported language: {}", code_block.language);
+
+            return Ok(CodeBlockExecutionResult::Failure(
+                CodeBlockExecutionOutput {
+                    stderr: format!("unsupported language: {}", code_block.language),
+                    ..Default::default()
+                },
+            ));
+        }
+
+        tokio::fs::create_dir_all(&self.work_dir)
+            .await
+            .map_err(JupyterCodeExecutorError::Io)?;
+
+        let mut guard = self.session.lock().await;
+
+        let mut session = match guard.take() {
+            Some(session) => session,
+            None => self.start_session().await?,
+        };
+
+        let files_before = FilesSnapshot::take(self.work_dir.clone())
+            .await
+            .map_err(JupyterCodeExecutorError::Io)?;
+        let start = Instant::now();
+
+        let msg_id = session
+            .connection
+            .send_shell(
+                "execute_request",
+                json!({
+                    "code": code_block.code,
+                    "silent": false,
+                    "store_history": true,
+                    "user_expressions": {},
+                    "allow_stdin": false,
+                    "stop_on_error": true,
+                }),
+            )
+            .await?;
+
+        let stem = source_file_stem();
+        let mut stdout = String::new();
+        let mut stderr = String::new();
+
+        let outputs = self.collect_outputs(&mut session, &msg_id, &stem, &mut stdout, &mut stderr);
+
+        let finished = match self.limits.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, outputs).await.ok(),
+            None => Some(outputs.await),
+        };
+
+        let (exit_code, succeeded, limit_exceeded, keep_session) = match finished {
+            Some(Ok(ExecutionEnd::Idle(status))) => (None, status == "ok", None, true),
+            Some(Ok(ExecutionEnd::KernelDied(exit_code))) => {
+                debug!("kernel died");
+                stderr.push_str("kernel died\n");
+
+                (exit_code, false, None, false)
+            }
+            Some(Ok(ExecutionEnd::OutputExceeded)) => {
+                debug!("output limit exceeded, killing the kernel..");
+                let max_output_bytes = self.limits.max_output_bytes.unwrap_or_default();
+
+                (
+                    None,
+                    false,
+                    Some(ExecutionLimit::OutputSize(max_output_bytes)),
+                    false,
+                )
+            }
+            Some(Err(e)) => {
+                kill(&mut session.child).await;
+                return Err(e);
+            }
+            None => {
+                debug!("timeout exceeded, killing the kernel..");
+                let timeout = self.limits.timeout.unwrap_or_default();
+
+                (None, false, Some(ExecutionLimit::Timeout(timeout)), false)
+            }
+        };
+
+        match keep_session {
+            true => *guard = Some(session),
+            false => {
+                kill(&mut session.child).await;
+            }
+        }
+
+        drop(guard);
+
+        let duration = start.elapsed();
+
+        let produced_files = FilesSnapshot::take(self.work_dir.clone())
+            .await
+            .map_err(JupyterCodeExecutorError::Io)?
+            .changed_since(&files_before, &[]);
+
+        let output = CodeBlockExecutionOutput {
+            exit_code,
+            stdout,
+            stderr,
+            duration,
+            truncated: matches!(limit_exceeded, Some(ExecutionLimit::OutputSize(_))),
+            produced_files,
+        };
+
+        match (limit_exceeded, succeeded) {
+            (Some(limit), _) => Ok(CodeBlockExecutionResult::LimitExceeded { limit, output }),
+            (None, true) => Ok(CodeBlockExecutionResult::Success(output)),
+            (None, false) => Ok(CodeBlockExecutionResult::Failure(output)),
+        }
+    }
+}
+
+/// The file holds the key used to sign the messages, so only the current user may read it. It
+/// must not exist yet, otherwise someone else could have prepared it.
+async fn write_connection_file(path: &Path, info: &ConnectionInfo) -> std::io::Result<()> {
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+
+    #[cfg(unix)]
+    options.mode(0o600);
+
+    let mut file = options.open(path).await?;
+    file.write_all(info.to_json().as_bytes()).await?;
+    file.flush().await
+}
+
+/// Iopub is a pub-sub channel, so messages published before the subscription reaches the kernel
+/// are lost. Kernel info is requested until a status message for it arrives on iopub, which means
+/// that both channels work.
+async fn wait_until_ready(
+    connection: &mut KernelConnection,
+) -> Result<(), JupyterCodeExecutorError> {
+    loop {
+        let msg_id = connection
+            .send_shell("kernel_info_request", json!({}))
+            .await?;
+        wait_for_reply(connection, &msg_id).await?;
+
+        let status = async {
+            loop {
+                match connection.recv_iopub().await {
+                    Ok(message)
+                        if message.msg_type == "status"
+                            && message.parent_id.as_deref() == Some(msg_id.as_str()) =>
+                    {
+                        return Ok(())
+                    }
+                    Ok(_) | Err(JupyterCodeExecutorError::InvalidMessage(_)) => continue,
+                    Err(e) => return Err(e),
+                }
+            }
+        };
+
+        if let Ok(status) = tokio::time::timeout(Duration::from_millis(500), status).await {
+            return status;
+        }
+    }
+}
+
+/// Waits for the reply to the request on the shell channel, skipping replies to earlier requests.
+async fn wait_for_reply(
+    connection: &mut KernelConnection,
+    msg_id: &str,
+) -> Result<KernelMessage, JupyterCodeExecutorError> {
+    loop {
+        match connection.recv_shell().await {
+            Ok(message) if message.parent_id.as_deref() == Some(msg_id) => return Ok(message),
+            Ok(_) | Err(JupyterCodeExecutorError::InvalidMessage(_)) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Saves images and html from the data of `execute_result` or `display_data` as `name.extension`.
+async fn save_rich_outputs(
+    work_dir: &Path,
+    name: &str,
+    data: &Map<String, Value>,
+) -> Result<(), JupyterCodeExecutorError> {
+    for (mime_type, extension, binary) in SAVED_MIME_TYPES {
+        let content = match data.get(*mime_type).and_then(Value::as_str) {
+            Some(content) => content,
+            None => continue,
+        };
+
+        let content = match binary {
+            true => match base64::engine::general_purpose::STANDARD
+                .decode(content.split_whitespace().collect::<String>())
+            {
+                Ok(content) => content,
+                Err(e) => {
+                    debug!("skipping invalid {} output: {:?}", mime_type, e);
+                    continue;
+                }
+            },
+            false => content.as_bytes().to_vec(),
+        };
+
+        tokio::fs::write(work_dir.join(format!("{}.{}", name, extension)), content)
+            .await
+            .map_err(JupyterCodeExecutorError::Io)?;
+    }
+
+    Ok(())
+}
+
+/// Tracebacks of ipykernel are colored.
+fn strip_ansi_escapes(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('\u{1b}', Some('[')) => {
+                chars.next();
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            _ => stripped.push(c),
+        }
+    }
+
+    stripped
+}
+
+fn truncate(text: &mut String, max_bytes: usize) {
+    if text.len() <= max_bytes {
+        return;
+    }
+
+    let boundary = (0..=max_bytes)
+        .rev()
+        .find(|index| text.is_char_boundary(*index))
+        .unwrap_or_default();
+
+    text.truncate(boundary);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ansi_escapes_are_stripped() {
+        assert_eq!(
+            strip_ansi_escapes("\u{1b}[0;31mNameError\u{1b}[0m: name 'x' is not defined"),
+            "NameError: name 'x' is not defined"
+        );
+        assert_eq!(
+            strip_ansi_escapes("\u{1b}[1;32m----> 1\u{1b}[39;49m x"),
+            "----> 1 x"
+        );
+        // Only control sequences are escapes.
+        assert_eq!(strip_ansi_escapes("a\u{1b}b [c]"), "a\u{1b}b [c]");
+        // Unterminated sequence swallows the rest.
+        assert_eq!(strip_ansi_escapes("text\u{1b}[31"), "text");
+    }
+
+    #[test]
+    fn text_is_truncated_to_the_byte_limit() {
+        let mut text = "hello world".to_string();
+        truncate(&mut text, 5);
+        assert_eq!(text, "hello");
+
+        let mut text = "hello".to_string();
+        truncate(&mut text, 10);
+        assert_eq!(text, "hello");
+
+        let mut text = "hello".to_string();
+        truncate(&mut text, 0);
+        assert_eq!(text, "");
+    }
+
+    #[test]
+    fn text_is_truncated_at_a_char_boundary() {
+        // Each of these takes two bytes.
+        let mut text = "ééé".to_string();
+        truncate(&mut text, 3);
+        assert_eq!(text, "é");
+
+        let mut text = "aéé".to_string();
+        truncate(&mut text, 3);
+        assert_eq!(text, "aé");
+    }
+}
@@ -0,0 +1,16 @@
+#[derive(Debug)]
+pub enum JupyterCodeExecutorError {
+    /// Preparing the working directory or writing the connection file failed.
+    Io(std::io::Error),
+    /// The kernel could not be started, most likely because it is not installed.
+    Spawn {
+        program: String,
+        source: std::io::Error,
+    },
+    /// The kernel did not become ready in time or exited during the startup.
+    KernelStartup(String),
+    /// Communication with the kernel failed.
+    Zmq(zeromq::ZmqError),
+    /// The kernel sent a message which does not follow the protocol.
+    InvalidMessage(String),
+}
@@ -0,0 +1,551 @@
+//! Client side of the Jupyter messaging protocol (version 5.3), limited to what the
+//! [super::jupyter_code_executor::JupyterCodeExecutor] needs: the shell, iopub and control
+//! channels over tcp.
+//!
+//! See <https://jupyter-client.readthedocs.io/en/stable/messaging.html>.
+
+use super::jupyter_code_executor_error::JupyterCodeExecutorError;
+
+use std::net::{Ipv4Addr, TcpListener};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use hmac::{Hmac, Mac};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use zeromq::{DealerSocket, Socket, SocketRecv, SocketSend, SubSocket, ZmqMessage};
+
+const PROTOCOL_VERSION: &str = "5.3";
+
+/// Separates the routing identities from the message itself.
+const DELIMITER: &[u8] = b"<IDS|MSG>";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Everything written to the connection file of the kernel.
+pub(super) struct ConnectionInfo {
+    pub ip: String,
+    pub shell_port: u16,
+    pub iopub_port: u16,
+    pub stdin_port: u16,
+    pub control_port: u16,
+    pub hb_port: u16,
+    pub key: String,
+}
+
+impl ConnectionInfo {
+    /// Picks free ports on the loopback interface and generates a new signing key.
+    ///
+    /// The ports are released before the kernel binds them, so in theory something else could take
+    /// them in the meantime. jupyter_client does the same.
+    pub fn new() -> std::io::Result<Self> {
+        let listeners = (0..5)
+            .map(|_| TcpListener::bind((Ipv4Addr::LOCALHOST, 0)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let ports = listeners
+            .iter()
+            .map(|listener| listener.local_addr().map(|address| address.port()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            ip: Ipv4Addr::LOCALHOST.to_string(),
+            shell_port: ports[0],
+            iopub_port: ports[1],
+            stdin_port: ports[2],
+            control_port: ports[3],
+            hb_port: ports[4],
+            key: uuid::Uuid::new_v4().to_string(),
+        })
+    }
+
+    pub fn to_json(&self) -> String {
+        json!({
+            "ip": self.ip,
+            "transport": "tcp",
+            "shell_port": self.shell_port,
+            "iopub_port": self.iopub_port,
+            "stdin_port": self.stdin_port,
+            "control_port": self.control_port,
+            "hb_port": self.hb_port,
+            "key": self.key,
+            "signature_scheme": "hmac-sha256",
+        })
+        .to_string()
+    }
+
+    fn endpoint(&self, port: u16) -> String {
+        format!("tcp://{}:{}", self.ip, port)
+    }
+}
+
+/// A message received from the kernel.
+#[derive(Debug)]
+pub(super) struct KernelMessage {
+    pub msg_type: String,
+    /// Id of the request which caused this message, if any.
+    pub parent_id: Option<String>,
+    pub content: Value,
+}
+
+pub(super) struct KernelConnection {
+    key: Vec<u8>,
+    session: String,
+    shell: DealerSocket,
+    iopub: SubSocket,
+    control: DealerSocket,
+}
+
+impl KernelConnection {
+    /// Connects to the channels of the kernel. Connecting is retried until the kernel binds them,
+    /// so the caller should put a timeout on it.
+    pub async fn connect(info: &ConnectionInfo) -> Result<Self, JupyterCodeExecutorError> {
+        let mut shell = DealerSocket::new();
+        let mut iopub = SubSocket::new();
+        let mut control = DealerSocket::new();
+
+        iopub
+            .subscribe("")
+            .await
+            .map_err(JupyterCodeExecutorError::Zmq)?;
+
+        iopub
+            .connect(&info.endpoint(info.iopub_port))
+            .await
+            .map_err(JupyterCodeExecutorError::Zmq)?;
+        shell
+            .connect(&info.endpoint(info.shell_port))
+            .await
+            .map_err(JupyterCodeExecutorError::Zmq)?;
+        control
+            .connect(&info.endpoint(info.control_port))
+            .await
+            .map_err(JupyterCodeExecutorError::Zmq)?;
+
+        Ok(Self {
+            key: info.key.as_bytes().to_vec(),
+            session: uuid::Uuid::new_v4().to_string(),
+            shell,
+            iopub,
+            control,
+        })
+    }
+
+    /// Sends a request on the shell channel and returns its id.
+    pub async fn send_shell(
+        &mut self,
+        msg_type: &str,
+        content: Value,
+    ) -> Result<String, JupyterCodeExecutorError> {
+        let (msg_id, message) = self.message(msg_type, content)?;
+
+        self.shell
+            .send(message)
+            .await
+            .map_err(JupyterCodeExecutorError::Zmq)?;
+
+        Ok(msg_id)
+    }
+
+    /// Sends a request on the control channel and returns its id.
+    pub async fn send_control(
+        &mut self,
+        msg_type: &str,
+        content: Value,
+    ) -> Result<String, JupyterCodeExecutorError> {
+        let (msg_id, message) = self.message(msg_type, content)?;
+
+        self.control
+            .send(message)
+            .await
+            .map_err(JupyterCodeExecutorError::Zmq)?;
+
+        Ok(msg_id)
+    }
+
+    pub async fn recv_shell(&mut self) -> Result<KernelMessage, JupyterCodeExecutorError> {
+        let message = self
+            .shell
+            .recv()
+            .await
+            .map_err(JupyterCodeExecutorError::Zmq)?;
+
+        self.parse(message)
+    }
+
+    pub async fn recv_iopub(&mut self) -> Result<KernelMessage, JupyterCodeExecutorError> {
+        let message = self
+            .iopub
+            .recv()
+            .await
+            .map_err(JupyterCodeExecutorError::Zmq)?;
+
+        self.parse(message)
+    }
+
+    fn message(
+        &self,
+        msg_type: &str,
+        content: Value,
+    ) -> Result<(String, ZmqMessage), JupyterCodeExecutorError> {
+        let msg_id = uuid::Uuid::new_v4().to_string();
+
+        let header = json!({
+            "msg_id": msg_id,
+            "session": self.session,
+            "username": "autogen",
+            "date": iso8601_now(),
+            "msg_type": msg_type,
+            "version": PROTOCOL_VERSION,
+        })
+        .to_string();
+
+        let parts = [
+            header.into_bytes(),
+            b"{}".to_vec(),
+            b"{}".to_vec(),
+            content.to_string().into_bytes(),
+        ];
+
+        let signature = self.sign(&parts);
+
+        let frames: Vec<Bytes> = [DELIMITER.to_vec(), signature.into_bytes()]
+            .into_iter()
+            .chain(parts)
+            .map(Bytes::from)
+            .collect();
+
+        let message = ZmqMessage::try_from(frames)
+            .map_err(|e| JupyterCodeExecutorError::InvalidMessage(e.to_string()))?;
+
+        Ok((msg_id, message))
+    }
+
+    fn parse(&self, message: ZmqMessage) -> Result<KernelMessage, JupyterCodeExecutorError> {
+        let frames = message.into_vec();
+
+        let delimiter = frames
+            .iter()
+            .position(|frame| frame.as_ref() == DELIMITER)
+            .ok_or_else(|| {
+                JupyterCodeExecutorError::InvalidMessage("missing delimiter".to_string())
+            })?;
+
+        let (signature, parts) = match &frames[delimiter + 1..] {
+            [signature, header, parent_header, metadata, content, ..] => (
+                signature,
+                [header, parent_header, metadata, content].map(|part| part.to_vec()),
+            ),
+            _ => {
+                return Err(JupyterCodeExecutorError::InvalidMessage(
+                    "missing message parts".to_string(),
+                ))
+            }
+        };
+
+        if !self.verify(signature, &parts) {
+            return Err(JupyterCodeExecutorError::InvalidMessage(
+                "invalid signature".to_string(),
+            ));
+        }
+
+        let [header, parent_header, _, content] = parts.map(|part| {
+            serde_json::from_slice::<Value>(&part)
+                .map_err(|e| JupyterCodeExecutorError::InvalidMessage(e.to_string()))
+        });
+
+        let header = header?;
+        let parent_header = parent_header?;
+
+        Ok(KernelMessage {
+            msg_type: header["msg_type"].as_str().unwrap_or_default().to_string(),
+            parent_id: parent_header["msg_id"].as_str().map(str::to_string),
+            content: content?,
+        })
+    }
+
+    /// Hex encoded hmac of the message parts. Empty key disables signing.
+    fn sign(&self, parts: &[Vec<u8>]) -> String {
+        if self.key.is_empty() {
+            return String::new();
+        }
+
+        self.mac(parts)
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    /// Compares the hex encoded signature in constant time, so it cannot be guessed byte by byte
+    /// from the timing.
+    fn verify(&self, signature: &[u8], parts: &[Vec<u8>]) -> bool {
+        if self.key.is_empty() {
+            return signature.is_empty();
+        }
+
+        match decode_hex(signature) {
+            Some(signature) => self.mac(parts).verify_slice(&signature).is_ok(),
+            None => false,
+        }
+    }
+
+    fn mac(&self, parts: &[Vec<u8>]) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("hmac accepts keys of any size");
+        for part in parts {
+            mac.update(part);
+        }
+
+        mac
+    }
+}
+
+fn decode_hex(hex: &[u8]) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    hex.chunks(2)
+        .map(|pair| {
+            let high = (pair[0] as char).to_digit(16)?;
+            let low = (pair[1] as char).to_digit(16)?;
+            Some((high * 16 + low) as u8)
+        })
+        .collect()
+}
+
+/// Current time in the format required by message headers, e.g. `2024-01-01T12:00:00.000000Z`.
+fn iso8601_now() -> String {
+    iso8601(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default(),
+    )
+}
+
+/// Formats the time since the epoch.
+fn iso8601(now: Duration) -> String {
+    let seconds = now.as_secs();
+    let (days, seconds_of_day) = (seconds / 86400, seconds % 86400);
+
+    // Converts days since the epoch to a civil date, see
+    // <https://howardhinnant.github.io/date_algorithms.html#civil_from_days>.
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = match mp < 10 {
+        true => mp + 3,
+        false => mp - 9,
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+        year,
+        month,
+        day,
+        seconds_of_day / 3600,
+        seconds_of_day % 3600 / 60,
+        seconds_of_day % 60,
+        now.subsec_micros()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_key(key: &str) -> KernelConnection {
+        KernelConnection {
+            key: key.as_bytes().to_vec(),
+            session: "session".to_string(),
+            shell: DealerSocket::new(),
+            iopub: SubSocket::new(),
+            control: DealerSocket::new(),
+        }
+    }
+
+    fn parts() -> Vec<Vec<u8>> {
+        [
+            r#"{"msg_type":"stream"}"#,
+            r#"{"msg_id":"request"}"#,
+            "{}",
+            r#"{"name":"stdout","text":"hello"}"#,
+        ]
+        .map(|part| part.as_bytes().to_vec())
+        .to_vec()
+    }
+
+    /// Frames as sent by the kernel, prefixed by a routing identity.
+    fn frames(signature: &str, parts: Vec<Vec<u8>>) -> ZmqMessage {
+        let frames: Vec<Bytes> = [
+            b"identity".to_vec(),
+            DELIMITER.to_vec(),
+            signature.as_bytes().to_vec(),
+        ]
+        .into_iter()
+        .chain(parts)
+        .map(Bytes::from)
+        .collect();
+
+        ZmqMessage::try_from(frames).unwrap()
+    }
+
+    #[test]
+    fn signature_is_the_hex_encoded_hmac_of_the_parts() {
+        let connection = with_key("key");
+
+        // hmac-sha256 of "abc" with the key "key".
+        assert_eq!(
+            connection.sign(&[b"a".to_vec(), b"bc".to_vec()]),
+            "9c196e32dc0175f86f4b1cb89289d6619de6bee699e4c378e68309ed97a1a6ab"
+        );
+    }
+
+    #[test]
+    fn valid_signature_is_verified() {
+        let connection = with_key("key");
+        let signature = connection.sign(&parts());
+
+        assert!(connection.verify(signature.as_bytes(), &parts()));
+    }
+
+    #[test]
+    fn bad_signature_is_rejected() {
+        let connection = with_key("key");
+        let signature = connection.sign(&parts());
+
+        let mut tampered = parts();
+        tampered[3] = br#"{"name":"stdout","text":"bye"}"#.to_vec();
+
+        assert!(!connection.verify(signature.as_bytes(), &tampered));
+        assert!(!connection.verify(b"0123", &parts()));
+        assert!(!connection.verify(b"not hex", &parts()));
+        assert!(!connection.verify(b"", &parts()));
+        assert!(!connection.verify(with_key("other key").sign(&parts()).as_bytes(), &parts()));
+    }
+
+    #[test]
+    fn empty_key_disables_signing() {
+        let connection = with_key("");
+
+        assert_eq!(connection.sign(&parts()), "");
+        assert!(connection.verify(b"", &parts()));
+        assert!(!connection.verify(b"00", &parts()));
+    }
+
+    #[test]
+    fn message_is_parsed_after_the_routing_identities() {
+        let connection = with_key("key");
+        let signature = connection.sign(&parts());
+
+        let message = connection.parse(frames(&signature, parts())).unwrap();
+
+        assert_eq!(message.msg_type, "stream");
+        assert_eq!(message.parent_id.as_deref(), Some("request"));
+        assert_eq!(
+            message.content,
+            json!({ "name": "stdout", "text": "hello" })
+        );
+    }
+
+    #[test]
+    fn message_without_a_parent_has_no_parent_id() {
+        let connection = with_key("key");
+
+        let mut parts = parts();
+        parts[1] = b"{}".to_vec();
+        let signature = connection.sign(&parts);
+
+        let message = connection.parse(frames(&signature, parts)).unwrap();
+
+        assert_eq!(message.parent_id, None);
+    }
+
+    #[test]
+    fn invalid_messages_are_rejected() {
+        let connection = with_key("key");
+        let signature = connection.sign(&parts());
+
+        let without_delimiter =
+            ZmqMessage::try_from(parts().into_iter().map(Bytes::from).collect::<Vec<_>>()).unwrap();
+        let without_content = frames(&signature, parts()[..3].to_vec());
+        let badly_signed = frames(&with_key("other key").sign(&parts()), parts());
+
+        let mut not_json = parts();
+        not_json[3] = b"hello".to_vec();
+        let not_json = frames(&connection.sign(&not_json), not_json);
+
+        for message in [without_delimiter, without_content, badly_signed, not_json] {
+            assert!(matches!(
+                connection.parse(message),
+                Err(JupyterCodeExecutorError::InvalidMessage(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn sent_messages_can_be_parsed_back() {
+        let connection = with_key("key");
+
+        let (msg_id, message) = connection
+            .message("execute_request", json!({ "code": "1 + 1" }))
+            .unwrap();
+
+        let frames = message.into_vec();
+        assert_eq!(frames[0].as_ref(), DELIMITER);
+
+        let header: Value = serde_json::from_slice(&frames[2]).unwrap();
+        assert_eq!(header["msg_id"], msg_id);
+        assert_eq!(header["msg_type"], "execute_request");
+        assert_eq!(header["session"], "session");
+        assert_eq!(header["version"], PROTOCOL_VERSION);
+
+        let parsed = connection
+            .parse(ZmqMessage::try_from(frames).unwrap())
+            .unwrap();
+        assert_eq!(parsed.msg_type, "execute_request");
+        assert_eq!(parsed.content, json!({ "code": "1 + 1" }));
+    }
+
+    #[test]
+    fn hex_is_decoded() {
+        assert_eq!(decode_hex(b"00ff7A"), Some(vec![0x00, 0xff, 0x7a]));
+        assert_eq!(decode_hex(b""), Some(vec![]));
+        assert_eq!(decode_hex(b"abc"), None);
+        assert_eq!(decode_hex(b"zz"), None);
+    }
+
+    #[test]
+    fn times_are_formatted_as_iso8601() {
+        assert_eq!(iso8601(Duration::ZERO), "1970-01-01T00:00:00.000000Z");
+        // Leap day.
+        assert_eq!(
+            iso8601(Duration::from_secs(951_825_599)),
+            "2000-02-29T11:59:59.000000Z"
+        );
+        assert_eq!(
+            iso8601(Duration::from_micros(1_704_110_400_123_456)),
+            "2024-01-01T12:00:00.123456Z"
+        );
+        assert_eq!(
+            iso8601(Duration::from_secs(1_735_689_599)),
+            "2024-12-31T23:59:59.000000Z"
+        );
+    }
+
+    #[test]
+    fn current_time_is_formatted_as_iso8601() {
+        let now = iso8601_now();
+
+        assert_eq!(now.len(), "2024-01-01T12:00:00.000000Z".len());
+        assert!(now.ends_with('Z'));
+        assert!(now.as_str() > "2024");
+    }
+}
@@ -0,0 +1,275 @@
+use super::{
+    CodeBlock, CodeBlockExecutionOutput, CodeBlockExecutionResult, CodeExecutor, Language,
+};
+
+use super::local_code_executor_error::LocalCodeExecutorError;
+
+use super::execution_limits::{ExecutionLimit, ExecutionLimits, LimitedOutput};
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Instant, SystemTime};
+
+use tokio::process::Command;
+
+use tracing::debug;
+
+/// Used to give every written source file a unique name, even if multiple executors share the
+/// same working directory.
+static SOURCE_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Executes code blocks as subprocesses on the local machine.
+///
+/// Every code block is written to a file inside of `work_dir` and handed to the matching local
+/// interpreter (`python3`, `bash`, `sh`) or compiled with `rustc` and then run. Both the source
+/// files and compiled binaries are left in `work_dir`, so files created by the code are there as well.
+/// Files created or modified by the code are reported in
+/// [CodeBlockExecutionOutput::produced_files].
+///
+/// [ExecutionLimits] are enforced on the program itself. Compilation is only subject to the
+/// timeout and the output limit, since `rustc` would not fit into limits meant for a small script.
+///
+/// Nothing is isolated here - the code runs with the permissions of the current user, so
+/// [super::super::chat_user_agent::CodeBlockFeedback::AllowExecution] should be given carefully.
+///
+/// See [super::sandboxed_code_executor::SandboxedCodeExecutor] for an isolated version.
+pub struct LocalCodeExecutor {
+    work_dir: PathBuf,
+    limits: ExecutionLimits,
+    command_hook: Option<Box<CommandHook>>,
+}
+
+/// Called with every command before it is spawned and the working directory, which exists by
+/// then.
+pub type CommandHook = dyn Fn(&mut Command, &Path) -> std::io::Result<()> + Send + Sync;
+
+impl LocalCodeExecutor {
+    pub fn new(work_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            work_dir: work_dir.into(),
+            limits: ExecutionLimits::default(),
+            command_hook: None,
+        }
+    }
+
+    /// Lets the caller adjust every spawned process (including the compiler), e.g. to set
+    /// environment variables or a `pre_exec` closure. If the hook fails, the code is not run and
+    /// the error is returned as [LocalCodeExecutorError::Io].
+    pub fn with_command_hook(
+        mut self,
+        hook: impl Fn(&mut Command, &Path) -> std::io::Result<()> + Send + Sync + 'static,
+    ) -> Self {
+        self.command_hook = Some(Box::new(hook));
+        self
+    }
+
+    pub fn with_limits(mut self, limits: ExecutionLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn work_dir(&self) -> &Path {
+        &self.work_dir
+    }
+
+    pub fn limits(&self) -> &ExecutionLimits {
+        &self.limits
+    }
+
+    async fn run(
+        &self,
+        program: &str,
+        command: &mut Command,
+        limits: &ExecutionLimits,
+    ) -> Result<LimitedOutput, LocalCodeExecutorError> {
+        debug!("running {:?}", command);
+
+        if let Some(hook) = &self.command_hook {
+            hook(command, &self.work_dir).map_err(LocalCodeExecutorError::Io)?;
+        }
+
+        limits
+            .run(command.current_dir(&self.work_dir))
+            .await
+            .map_err(|source| LocalCodeExecutorError::Spawn {
+                program: program.to_string(),
+                source,
+            })
+    }
+}
+
+impl CodeExecutor for LocalCodeExecutor {
+    type Error = LocalCodeExecutorError;
+
+    async fn execute_code_block(
+        &self,
+        code_block: &CodeBlock,
+    ) -> Result<CodeBlockExecutionResult, Self::Error> {
+        let language = match code_block.known_language() {
+            Some(language) => language,
+            None => {
+                debug!("unsupported language: {}", code_block.language);
+
+                return Ok(CodeBlockExecutionResult::Failure(
+                    CodeBlockExecutionOutput {
+                        stderr: format!("unsupported language: {}", code_block.language),
+                        ..Default::default()
+                    },
+                ));
+            }
+        };
+
+        tokio::fs::create_dir_all(&self.work_dir)
+            .await
+            .map_err(LocalCodeExecutorError::Io)?;
+
+        let stem = source_file_stem();
+
+        let source_path = self
+            .work_dir
+            .join(format!("{}.{}", stem, language.extension()));
+
+        tokio::fs::write(&source_path, &code_block.code)
+            .await
+            .map_err(LocalCodeExecutorError::Io)?;
+
+        let binary_path = self.work_dir.join(&stem);
+
+        let files_before = FilesSnapshot::take(self.work_dir.clone())
+            .await
+            .map_err(LocalCodeExecutorError::Io)?;
+        let start = Instant::now();
+
+        let limits = &self.limits;
+
+        let output = match language {
+            Language::Python => {
+                let mut command = Command::new("python3");
+                self.run("python3", command.arg(&source_path), limits)
+                    .await?
+            }
+            Language::Bash => {
+                let mut command = Command::new("bash");
+                self.run("bash", command.arg(&source_path), limits).await?
+            }
+            Language::Sh => {
+                let mut command = Command::new("sh");
+                self.run("sh", command.arg(&source_path), limits).await?
+            }
+            Language::Rust => {
+                let compilation_limits = ExecutionLimits {
+                    timeout: limits.timeout,
+                    max_output_bytes: limits.max_output_bytes,
+                    ..Default::default()
+                };
+
+                let mut command = Command::new("rustc");
+                command
+                    .arg("--edition=2021")
+                    .arg("-o")
+                    .arg(&binary_path)
+                    .arg(&source_path);
+
+                let compilation = self.run("rustc", &mut command, &compilation_limits).await?;
+
+                let compiled = compilation.limit_exceeded.is_none()
+                    && compilation.status.is_some_and(|status| status.success());
+
+                match compiled {
+                    true => {
+                        let program = binary_path.to_string_lossy().into_owned();
+                        self.run(&program, &mut Command::new(&binary_path), limits)
+                            .await?
+                    }
+                    false => {
+                        debug!("compilation failed");
+                        compilation
+                    }
+                }
+            }
+        };
+
+        let duration = start.elapsed();
+
+        let produced_files = FilesSnapshot::take(self.work_dir.clone())
+            .await
+            .map_err(LocalCodeExecutorError::Io)?
+            .changed_since(&files_before, &[&source_path, &binary_path]);
+
+        let output_details = CodeBlockExecutionOutput {
+            exit_code: output.status.and_then(|status| status.code()),
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            duration,
+            truncated: matches!(output.limit_exceeded, Some(ExecutionLimit::OutputSize(_))),
+            produced_files,
+        };
+
+        match (output.limit_exceeded, output.status) {
+            (Some(limit), _) => Ok(CodeBlockExecutionResult::LimitExceeded {
+                limit,
+                output: output_details,
+            }),
+            (None, Some(status)) if status.success() => {
+                Ok(CodeBlockExecutionResult::Success(output_details))
+            }
+            (None, _) => Ok(CodeBlockExecutionResult::Failure(output_details)),
+        }
+    }
+}
+
+/// Unique name for the source file of a code block.
+pub(crate) fn source_file_stem() -> String {
+    format!(
+        "code_block_{}_{}",
+        std::process::id(),
+        SOURCE_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// Modification times of all the files in the directory tree. Used to find files produced by the
+/// code.
+pub(crate) struct FilesSnapshot(HashMap<PathBuf, Option<SystemTime>>);
+
+impl FilesSnapshot {
+    pub(crate) async fn take(dir: PathBuf) -> std::io::Result<Self> {
+        tokio::task::spawn_blocking(move || {
+            let mut files = HashMap::new();
+            let mut pending = vec![dir];
+
+            while let Some(dir) = pending.pop() {
+                for entry in std::fs::read_dir(&dir)? {
+                    let entry = entry?;
+                    let metadata = entry.metadata()?;
+
+                    match metadata.is_dir() {
+                        true => pending.push(entry.path()),
+                        false => {
+                            files.insert(entry.path(), metadata.modified().ok());
+                        }
+                    }
+                }
+            }
+
+            Ok(Self(files))
+        })
+        .await?
+    }
+
+    /// Files created or modified since `before`, sorted.
+    pub(crate) fn changed_since(self, before: &FilesSnapshot, excluded: &[&Path]) -> Vec<PathBuf> {
+        let mut changed: Vec<PathBuf> = self
+            .0
+            .into_iter()
+            .filter(|(path, modified)| {
+                !excluded.contains(&path.as_path()) && before.0.get(path) != Some(modified)
+            })
+            .map(|(path, _)| path)
+            .collect();
+        changed.sort();
+
+        changed
+    }
+}
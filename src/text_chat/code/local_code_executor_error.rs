@@ -0,0 +1,10 @@
+#[derive(Debug)]
+pub enum LocalCodeExecutorError {
+    /// Preparing the working directory or writing the source file failed.
+    Io(std::io::Error),
+    /// The interpreter or compiler could not be started, most likely because it is not installed.
+    Spawn {
+        program: String,
+        source: std::io::Error,
+    },
+}
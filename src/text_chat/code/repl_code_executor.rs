@@ -0,0 +1,440 @@
+use super::{
+    CodeBlock, CodeBlockExecutionOutput, CodeBlockExecutionResult, CodeExecutor, Language,
+};
+
+use super::execution_limits::{kill, ExecutionLimit, ExecutionLimits};
+use super::local_code_executor::{source_file_stem, FilesSnapshot};
+use super::repl_code_executor_error::ReplCodeExecutorError;
+
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStderr, ChildStdin, ChildStdout, Command};
+use tokio::sync::Mutex;
+
+use tracing::debug;
+
+/// Runs every request in a persistent namespace. The value of a trailing expression is printed,
+/// as in a notebook. Stdin of the code is detached from the requests.
+const PYTHON_DRIVER: &str = r#"
+import ast, os, sys, traceback
+
+namespace = {"__name__": "__main__"}
+requests = sys.stdin
+sys.stdin = open(os.devnull)
+
+for request in requests:
+    marker, path = request.rstrip("\n").split(" ", 1)
+    status = 0
+    try:
+        with open(path) as source:
+            tree = ast.parse(source.read(), path)
+        last = None
+        if tree.body and isinstance(tree.body[-1], ast.Expr):
+            last = ast.Expression(tree.body.pop().value)
+        exec(compile(tree, path, "exec"), namespace)
+        if last is not None:
+            value = eval(compile(last, path, "eval"), namespace)
+            if value is not None:
+                print(repr(value))
+    except SystemExit as e:
+        status = e.code if isinstance(e.code, int) else (0 if e.code is None else 1)
+    except BaseException:
+        error_type, error, error_traceback = sys.exc_info()
+        traceback.print_exception(error_type, error, error_traceback.tb_next)
+        status = 1
+    sys.stdout.flush()
+    sys.stderr.write("\n" + marker + "\n")
+    sys.stderr.flush()
+    sys.stdout.write("\n" + marker + " " + str(status) + "\n")
+    sys.stdout.flush()
+"#;
+
+/// Languages with a REPL session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ReplLanguage {
+    Python,
+    Bash,
+}
+
+impl ReplLanguage {
+    /// Sh code runs in the bash session.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match Language::from_name(name)? {
+            Language::Python => Some(ReplLanguage::Python),
+            Language::Bash | Language::Sh => Some(ReplLanguage::Bash),
+            Language::Rust => None,
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            ReplLanguage::Python => "py",
+            ReplLanguage::Bash => "sh",
+        }
+    }
+
+    fn command(&self) -> Command {
+        match self {
+            ReplLanguage::Python => {
+                let mut command = Command::new("python3");
+                command.arg("-u").arg("-c").arg(PYTHON_DRIVER);
+                command
+            }
+            ReplLanguage::Bash => {
+                let mut command = Command::new("bash");
+                command.arg("--noprofile").arg("--norc");
+                command
+            }
+        }
+    }
+
+    fn program(&self) -> &'static str {
+        match self {
+            ReplLanguage::Python => "python3",
+            ReplLanguage::Bash => "bash",
+        }
+    }
+
+    /// Line sent to the session to run the source file and print the markers afterwards.
+    fn request(&self, marker: &str, source_path: &Path) -> String {
+        match self {
+            ReplLanguage::Python => format!("{} {}\n", marker, source_path.display()),
+            ReplLanguage::Bash => {
+                let source_path = source_path.display().to_string().replace('\'', r"'\''");
+
+                format!(
+                    "source '{path}' < /dev/null; __autogen_status=$?; printf '\\n%s\\n' '{marker}' >&2; printf '\\n%s %s\\n' '{marker}' \"$__autogen_status\"\n",
+                    path = source_path,
+                    marker = marker
+                )
+            }
+        }
+    }
+}
+
+struct ReplSession {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    stderr: BufReader<ChildStderr>,
+}
+
+/// How reading of a single stream ended.
+enum StreamEnd {
+    /// The marker was found. Contains the rest of the marker line.
+    Marker(String),
+    /// The session exited.
+    Eof,
+}
+
+enum ReadError {
+    Io(io::Error),
+    OutputExceeded,
+}
+
+/// Keeps a long-lived interpreter process per language, so state (variables, functions, imports,
+/// current directory of the shell) is kept between code blocks - as in a notebook.
+///
+/// Sessions are started lazily, in `work_dir`, on the first code block in the given language and
+/// live until [ReplCodeExecutor::reset] or until the executor is dropped. The executor may be
+/// passed to the chat by reference, so it can be reset or reused afterwards.
+///
+/// [ExecutionLimits] apply to every code block, except for the resource limits which apply to the
+/// whole session. Hitting the timeout or the output limit kills the session, so the state is lost
+/// and the next code block starts from scratch. The same happens when the code exits the
+/// interpreter.
+pub struct ReplCodeExecutor {
+    work_dir: PathBuf,
+    limits: ExecutionLimits,
+    sessions: Mutex<HashMap<ReplLanguage, ReplSession>>,
+}
+
+impl ReplCodeExecutor {
+    pub fn new(work_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            work_dir: work_dir.into(),
+            limits: ExecutionLimits::default(),
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_limits(mut self, limits: ExecutionLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn work_dir(&self) -> &Path {
+        &self.work_dir
+    }
+
+    /// Kills the session of the language. The next code block starts with a clean state.
+    pub async fn reset(&self, language: ReplLanguage) {
+        if let Some(mut session) = self.sessions.lock().await.remove(&language) {
+            debug!("resetting {:?} session..", language);
+            kill(&mut session.child).await;
+        }
+    }
+
+    pub async fn reset_all(&self) {
+        let sessions: Vec<_> = self.sessions.lock().await.drain().collect();
+
+        for (language, mut session) in sessions {
+            debug!("resetting {:?} session..", language);
+            kill(&mut session.child).await;
+        }
+    }
+
+    fn start_session(&self, language: ReplLanguage) -> Result<ReplSession, ReplCodeExecutorError> {
+        debug!("starting {:?} session..", language);
+
+        let mut command = language.command();
+        self.limits.apply_resource_limits(&mut command);
+
+        #[cfg(unix)]
+        command.process_group(0);
+
+        let mut child = command
+            .current_dir(&self.work_dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|source| ReplCodeExecutorError::Spawn {
+                program: language.program().to_string(),
+                source,
+            })?;
+
+        let (stdin, stdout, stderr) =
+            match (child.stdin.take(), child.stdout.take(), child.stderr.take()) {
+                (Some(stdin), Some(stdout), Some(stderr)) => (stdin, stdout, stderr),
+                _ => {
+                    return Err(ReplCodeExecutorError::Io(io::Error::other(
+                        "session pipes are missing",
+                    )))
+                }
+            };
+
+        Ok(ReplSession {
+            child,
+            stdin,
+            stdout: BufReader::new(stdout),
+            stderr: BufReader::new(stderr),
+        })
+    }
+}
+
+impl CodeExecutor for ReplCodeExecutor {
+    type Error = ReplCodeExecutorError;
+
+    async fn execute_code_block(
+        &self,
+        code_block: &CodeBlock,
+    ) -> Result<CodeBlockExecutionResult, Self::Error> {
+        let language = match ReplLanguage::from_name(&code_block.language) {
+            Some(language) => language,
+            None => {
+                debug!("unsupported language: {}", code_block.language);
+
+                return Ok(CodeBlockExecutionResult::Failure(
+                    CodeBlockExecutionOutput {
+                        stderr: format!("unsupported language: {}", code_block.language),
+                        ..Default::default()
+                    },
+                ));
+            }
+        };
+
+        tokio::fs::create_dir_all(&self.work_dir)
+            .await
+            .map_err(ReplCodeExecutorError::Io)?;
+
+        let stem = source_file_stem();
+        let source_path = self
+            .work_dir
+            .canonicalize()
+            .map_err(ReplCodeExecutorError::Io)?
+            .join(format!("{}.{}", stem, language.extension()));
+
+        tokio::fs::write(&source_path, &code_block.code)
+            .await
+            .map_err(ReplCodeExecutorError::Io)?;
+
+        let mut sessions = self.sessions.lock().await;
+
+        let mut session = match sessions.remove(&language) {
+            Some(session) => session,
+            None => self.start_session(language)?,
+        };
+
+        let files_before = FilesSnapshot::take(self.work_dir.clone())
+            .await
+            .map_err(ReplCodeExecutorError::Io)?;
+        let start = Instant::now();
+
+        let marker = format!("__autogen_repl_{}__", stem);
+
+        session
+            .stdin
+            .write_all(language.request(&marker, &source_path).as_bytes())
+            .await
+            .map_err(ReplCodeExecutorError::Io)?;
+        session
+            .stdin
+            .flush()
+            .await
+            .map_err(ReplCodeExecutorError::Io)?;
+
+        let mut stdout = Vec::new();
+        let mut stderr = Vec::new();
+        let captured_bytes = AtomicUsize::new(0);
+
+        let streams = async {
+            tokio::try_join!(
+                read_until_marker(
+                    &mut session.stdout,
+                    &mut stdout,
+                    &marker,
+                    &captured_bytes,
+                    self.limits.max_output_bytes
+                ),
+                read_until_marker(
+                    &mut session.stderr,
+                    &mut stderr,
+                    &marker,
+                    &captured_bytes,
+                    self.limits.max_output_bytes
+                ),
+            )
+        };
+
+        let finished = match self.limits.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, streams).await.ok(),
+            None => Some(streams.await),
+        };
+
+        let (exit_code, limit_exceeded, keep_session) = match finished {
+            Some(Ok((StreamEnd::Marker(status), StreamEnd::Marker(_)))) => {
+                (status.trim().parse().ok(), None, true)
+            }
+            Some(Ok(_)) => {
+                debug!("{:?} session exited", language);
+                let status = session.child.wait().await.ok();
+
+                (status.and_then(|status| status.code()), None, false)
+            }
+            Some(Err(ReadError::Io(e))) => return Err(ReplCodeExecutorError::Io(e)),
+            Some(Err(ReadError::OutputExceeded)) => {
+                debug!("output limit exceeded, killing the session..");
+                let max_output_bytes = self.limits.max_output_bytes.unwrap_or_default();
+
+                (
+                    None,
+                    Some(ExecutionLimit::OutputSize(max_output_bytes)),
+                    false,
+                )
+            }
+            None => {
+                debug!("timeout exceeded, killing the session..");
+                let timeout = self.limits.timeout.unwrap_or_default();
+
+                (None, Some(ExecutionLimit::Timeout(timeout)), false)
+            }
+        };
+
+        match keep_session {
+            true => {
+                sessions.insert(language, session);
+            }
+            false => {
+                kill(&mut session.child).await;
+            }
+        }
+
+        drop(sessions);
+
+        let duration = start.elapsed();
+
+        let produced_files = FilesSnapshot::take(self.work_dir.clone())
+            .await
+            .map_err(ReplCodeExecutorError::Io)?
+            .changed_since(&files_before, &[&source_path]);
+
+        let output = CodeBlockExecutionOutput {
+            exit_code,
+            stdout: String::from_utf8_lossy(&stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&stderr).into_owned(),
+            duration,
+            truncated: matches!(limit_exceeded, Some(ExecutionLimit::OutputSize(_))),
+            produced_files,
+        };
+
+        match (limit_exceeded, exit_code) {
+            (Some(limit), _) => Ok(CodeBlockExecutionResult::LimitExceeded { limit, output }),
+            (None, Some(0)) => Ok(CodeBlockExecutionResult::Success(output)),
+            (None, _) => Ok(CodeBlockExecutionResult::Failure(output)),
+        }
+    }
+}
+
+/// Lines are read in chunks of at most this size, so a program printing without newlines cannot
+/// exhaust the memory before the output limit is checked.
+const MAX_LINE_CHUNK: u64 = 64 * 1024;
+
+/// Reads lines into the buffer until the marker line. The newline printed before the marker is
+/// not a part of the output, thus it is removed.
+async fn read_until_marker(
+    reader: &mut BufReader<impl AsyncRead + Unpin>,
+    buffer: &mut Vec<u8>,
+    marker: &str,
+    captured_bytes: &AtomicUsize,
+    max_output_bytes: Option<usize>,
+) -> Result<StreamEnd, ReadError> {
+    let mut line = Vec::new();
+    let mut at_line_start = true;
+
+    loop {
+        line.clear();
+
+        let read = (&mut *reader)
+            .take(MAX_LINE_CHUNK)
+            .read_until(b'\n', &mut line)
+            .await
+            .map_err(ReadError::Io)?;
+
+        if read == 0 {
+            return Ok(StreamEnd::Eof);
+        }
+
+        let chunk_at_line_start = at_line_start;
+        at_line_start = line.last() == Some(&b'\n');
+
+        if let (true, Some(rest)) = (chunk_at_line_start, line.strip_prefix(marker.as_bytes())) {
+            if buffer.last() == Some(&b'\n') {
+                buffer.pop();
+            }
+
+            return Ok(StreamEnd::Marker(
+                String::from_utf8_lossy(rest).into_owned(),
+            ));
+        }
+
+        let captured_before = captured_bytes.fetch_add(read, Ordering::SeqCst);
+
+        match max_output_bytes {
+            Some(max_output_bytes) if captured_before + read > max_output_bytes => {
+                let allowed = max_output_bytes.saturating_sub(captured_before).min(read);
+                buffer.extend_from_slice(&line[..allowed]);
+
+                return Err(ReadError::OutputExceeded);
+            }
+            _ => buffer.extend_from_slice(&line),
+        }
+    }
+}
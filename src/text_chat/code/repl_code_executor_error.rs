@@ -0,0 +1,11 @@
+#[derive(Debug)]
+pub enum ReplCodeExecutorError {
+    /// Preparing the working directory, writing the source file or communicating with the session
+    /// failed.
+    Io(std::io::Error),
+    /// The interpreter could not be started, most likely because it is not installed.
+    Spawn {
+        program: String,
+        source: std::io::Error,
+    },
+}
@@ -0,0 +1,219 @@
+//! Static analysis of code blocks, looking for what the code could do to the machine. The report
+//! is shown to the user agent when it is asked for the feedback and passed to the
+//! [crate::text_chat::approval_policy::ApprovalPolicy].
+//!
+//! Python and shell are analyzed with lightweight parsers. They do not follow values through
+//! variables, so the report is a hint rather than a guarantee.
+
+mod python;
+mod shell;
+
+use super::{CodeBlock, Language};
+
+use std::path::{Component, Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RiskKind {
+    /// Writing, changing or removing files outside of the working directory.
+    FileWriteOutsideWorkdir,
+    NetworkAccess,
+    /// Starting other programs, e.g. `subprocess` in python or background jobs in shell.
+    ProcessSpawn,
+    PackageInstall,
+    /// Loop without a way out, e.g. `while True:` without a `break`.
+    InfiniteLoop,
+}
+
+impl std::fmt::Display for RiskKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RiskKind::FileWriteOutsideWorkdir => {
+                write!(f, "file write outside the working directory")
+            }
+            RiskKind::NetworkAccess => write!(f, "network access"),
+            RiskKind::ProcessSpawn => write!(f, "process spawning"),
+            RiskKind::PackageInstall => write!(f, "package installation"),
+            RiskKind::InfiniteLoop => write!(f, "possible infinite loop"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Risk {
+    pub kind: RiskKind,
+    /// Starting at 1.
+    pub line: usize,
+    /// The line of code, trimmed.
+    pub snippet: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RiskReport {
+    /// False if the language is not analyzed, nothing is known about the code then.
+    pub analyzed: bool,
+    /// Ordered by line.
+    pub risks: Vec<Risk>,
+}
+
+impl RiskReport {
+    pub fn is_risky(&self) -> bool {
+        !self.risks.is_empty()
+    }
+
+    pub fn contains(&self, kind: RiskKind) -> bool {
+        self.risks.iter().any(|risk| risk.kind == kind)
+    }
+
+    /// Every kind once, in the order of [RiskKind].
+    pub fn kinds(&self) -> Vec<RiskKind> {
+        let mut kinds: Vec<RiskKind> = self.risks.iter().map(|risk| risk.kind).collect();
+        kinds.sort();
+        kinds.dedup();
+
+        kinds
+    }
+}
+
+impl std::fmt::Display for RiskReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.analyzed, self.risks.is_empty()) {
+            (false, _) => write!(f, "Risks: unknown, the language is not analyzed."),
+            (true, true) => write!(f, "Risks: none found."),
+            (true, false) => {
+                write!(f, "Risks:")?;
+                for risk in &self.risks {
+                    write!(
+                        f,
+                        "\n- line {}, {}: `{}`",
+                        risk.line, risk.kind, risk.snippet
+                    )?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Analyzes python (`python`, `python3`, `py`) and shell (`bash`, `sh`, `shell`, `console`,
+/// `zsh`) code.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RiskAnalyzer {
+    workdir: Option<PathBuf>,
+}
+
+impl RiskAnalyzer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Absolute paths inside of it are not reported. Without it, every absolute path is outside of
+    /// the working directory.
+    pub fn with_workdir(mut self, workdir: impl Into<PathBuf>) -> Self {
+        self.workdir = Some(workdir.into());
+        self
+    }
+
+    pub fn analyze(&self, code_block: &CodeBlock) -> RiskReport {
+        let risks = match code_block.known_language() {
+            Some(Language::Python) => python::analyze(&code_block.code, self),
+            Some(Language::Bash | Language::Sh) => shell::analyze(&code_block.code, self),
+            Some(Language::Rust) | None => return RiskReport::default(),
+        };
+
+        let lines: Vec<&str> = code_block.code.lines().collect();
+
+        let mut found: Vec<(usize, RiskKind)> = risks;
+        found.sort();
+        found.dedup();
+
+        RiskReport {
+            analyzed: true,
+            risks: found
+                .into_iter()
+                .map(|(line, kind)| Risk {
+                    kind,
+                    line,
+                    snippet: lines
+                        .get(line.saturating_sub(1))
+                        .map(|line| line.trim().to_string())
+                        .unwrap_or_default(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Home directory, absolute paths not in the working directory and relative ones leaving it
+    /// with `..`. Of a path with variables, only the directory in front of the first one is known.
+    /// Paths starting with a variable could be anywhere, so they are outside as well.
+    fn is_outside_workdir(&self, path: &str) -> bool {
+        let path = path.trim();
+
+        if path.starts_with('~') || path.starts_with("$HOME") || path.starts_with("${HOME}") {
+            return true;
+        }
+
+        let path = match path.split_once('$') {
+            Some(("", _)) => return true,
+            Some((prefix, _)) => prefix.rfind('/').map_or("", |slash| &prefix[..=slash]),
+            None => path,
+        };
+
+        if path.is_empty() || is_harmless_device(path) {
+            return false;
+        }
+
+        let path = Path::new(path);
+
+        match (path.is_absolute(), &self.workdir) {
+            (true, Some(workdir)) => !normalize(path).starts_with(normalize(workdir)),
+            (true, None) => true,
+            (false, Some(workdir)) if workdir.is_absolute() => {
+                !normalize(&workdir.join(path)).starts_with(normalize(workdir))
+            }
+            (false, _) => leaves(path),
+        }
+    }
+}
+
+fn is_harmless_device(path: &str) -> bool {
+    matches!(
+        path,
+        "/dev/null" | "/dev/stdout" | "/dev/stderr" | "/dev/stdin" | "/dev/tty"
+    ) || path.starts_with("/dev/fd/")
+}
+
+/// Resolves `.` and `..` without touching the file system.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+
+    normalized
+}
+
+/// Whether the relative path gets above the directory it is relative to.
+fn leaves(path: &Path) -> bool {
+    let mut depth: isize = 0;
+
+    for component in path.components() {
+        match component {
+            Component::ParentDir => depth -= 1,
+            Component::Normal(_) => depth += 1,
+            _ => {}
+        }
+
+        if depth < 0 {
+            return true;
+        }
+    }
+
+    false
+}
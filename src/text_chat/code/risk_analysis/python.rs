@@ -0,0 +1,767 @@
+//! Python code is tokenized into logical lines. Calls are resolved through the imports and judged
+//! by the function they call, loops by their condition and body.
+
+use super::shell;
+use super::{RiskAnalyzer, RiskKind};
+
+use std::collections::HashMap;
+
+/// Calls starting other programs, modules end with a dot.
+const PROCESS_CALLS: &[&str] = &[
+    "subprocess.",
+    "os.system",
+    "os.popen",
+    "os.fork",
+    "os.forkpty",
+    "os.exec",
+    "os.spawn",
+    "os.posix_spawn",
+    "pty.spawn",
+    "multiprocessing.Process",
+    "asyncio.create_subprocess_exec",
+    "asyncio.create_subprocess_shell",
+];
+
+const NETWORK_MODULES: &[&str] = &[
+    "socket.",
+    "requests.",
+    "urllib.request.",
+    "urllib3.",
+    "http.client.",
+    "httpx.",
+    "aiohttp.",
+    "ftplib.",
+    "smtplib.",
+    "poplib.",
+    "imaplib.",
+    "telnetlib.",
+    "paramiko.",
+    "websocket.",
+    "websockets.",
+    "xmlrpc.client.",
+];
+
+/// Calls changing all the paths they are given.
+const FILE_WRITING_CALLS: &[&str] = &[
+    "os.remove",
+    "os.unlink",
+    "os.rmdir",
+    "os.removedirs",
+    "os.mkdir",
+    "os.makedirs",
+    "os.rename",
+    "os.renames",
+    "os.replace",
+    "os.chmod",
+    "os.chown",
+    "os.truncate",
+    "shutil.move",
+    "shutil.rmtree",
+    "shutil.chown",
+];
+
+/// Calls changing only the destination, their second argument.
+const FILE_COPYING_CALLS: &[&str] = &[
+    "shutil.copy",
+    "shutil.copy2",
+    "shutil.copyfile",
+    "shutil.copytree",
+    "os.symlink",
+    "os.link",
+];
+
+const PATH_CLASSES: &[&str] = &["pathlib.Path", "pathlib.PosixPath", "pathlib.PurePath"];
+
+/// Methods of the path classes changing the file.
+const PATH_WRITING_METHODS: &[&str] = &[
+    "write_text",
+    "write_bytes",
+    "mkdir",
+    "touch",
+    "unlink",
+    "rmdir",
+    "rename",
+    "replace",
+    "chmod",
+    "symlink_to",
+];
+
+/// Iterators which never end.
+const ENDLESS_ITERATORS: &[&str] = &["itertools.count", "itertools.cycle"];
+
+pub(super) fn analyze(code: &str, analyzer: &RiskAnalyzer) -> Vec<(usize, RiskKind)> {
+    // Whole cell run by the shell in jupyter.
+    let first = code.lines().position(|line| !line.trim().is_empty());
+    if let Some(first) = first {
+        let magic = code.lines().nth(first).unwrap_or_default().trim();
+
+        if matches!(magic, "%%bash" | "%%sh" | "%%script bash" | "%%script sh") {
+            let rest: Vec<&str> = code.lines().skip(first + 1).collect();
+
+            return shell::analyze(&rest.join("\n"), analyzer)
+                .into_iter()
+                .map(|(line, kind)| (line + first + 1, kind))
+                .collect();
+        }
+    }
+
+    let lines = logical_lines(code);
+    let imports = Imports::new(&lines);
+
+    let mut risks = Vec::new();
+
+    for (index, line) in lines.iter().enumerate() {
+        if let Some(command) = &line.shell {
+            if !line.magic {
+                risks.push((line.line, RiskKind::ProcessSpawn));
+            }
+            risks.extend(
+                shell::analyze_command(command, analyzer)
+                    .into_iter()
+                    .map(|kind| (line.line, kind)),
+            );
+            continue;
+        }
+
+        risks.extend(calls(&line.tokens, &imports, analyzer));
+
+        if let Some(loop_line) = infinite_loop(&lines[index..], &imports) {
+            risks.push((loop_line, RiskKind::InfiniteLoop));
+        }
+    }
+
+    risks
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    /// Identifiers, keywords and numbers.
+    Name(String),
+    /// Contents of a string literal.
+    Str(String),
+    Op(String),
+}
+
+#[derive(Debug, Default)]
+struct LogicalLine {
+    /// Line the logical line starts on.
+    line: usize,
+    indent: usize,
+    /// With the lines they are on.
+    tokens: Vec<(Token, usize)>,
+    /// Shell command of an IPython `!` line or a `%pip` / `%conda` magic.
+    shell: Option<String>,
+    /// The shell command is a magic, not run by the shell.
+    magic: bool,
+}
+
+impl LogicalLine {
+    fn starts_with(&self, keyword: &str) -> bool {
+        matches!(self.tokens.first(), Some((Token::Name(name), _)) if name == keyword)
+    }
+}
+
+/// Names bound by the imports to the full dotted names, e.g. `sp` to `subprocess` after
+/// `import subprocess as sp`.
+struct Imports {
+    names: HashMap<String, String>,
+    /// Modules imported with `from module import *`.
+    everything: Vec<String>,
+}
+
+impl Imports {
+    fn new(lines: &[LogicalLine]) -> Self {
+        let mut imports = Self {
+            names: HashMap::new(),
+            everything: Vec::new(),
+        };
+
+        for line in lines {
+            let tokens: Vec<&Token> = line.tokens.iter().map(|(token, _)| token).collect();
+
+            match tokens.first() {
+                Some(Token::Name(name)) if name == "import" => imports.import(&tokens[1..]),
+                Some(Token::Name(name)) if name == "from" => imports.import_from(&tokens[1..]),
+                _ => {}
+            }
+        }
+
+        imports
+    }
+
+    /// `import a.b, c as d`
+    fn import(&mut self, tokens: &[&Token]) {
+        for item in tokens.split(|token| is_op(token, ",")) {
+            let (module, rest) = dotted(item);
+
+            match rest {
+                [Token::Name(keyword), Token::Name(alias), ..] if keyword == "as" => {
+                    self.names.insert(alias.clone(), module);
+                }
+                _ => {
+                    if let Some(first) = module.split('.').next() {
+                        self.names.insert(first.to_string(), first.to_string());
+                    }
+                }
+            }
+        }
+    }
+
+    /// `from a.b import c, d as e`
+    fn import_from(&mut self, tokens: &[&Token]) {
+        let (module, rest) = dotted(tokens);
+
+        let Some((Token::Name(keyword), names)) = rest.split_first() else {
+            return;
+        };
+        if keyword != "import" {
+            return;
+        }
+
+        for item in names.split(|token| is_op(token, ",")) {
+            let item: Vec<&Token> = item
+                .iter()
+                .copied()
+                .filter(|token| !is_op(token, "(") && !is_op(token, ")"))
+                .collect();
+
+            match &item[..] {
+                [Token::Op(star)] if star == "*" => self.everything.push(module.clone()),
+                [Token::Name(name), Token::Name(keyword), Token::Name(alias)]
+                    if keyword == "as" =>
+                {
+                    self.names
+                        .insert(alias.clone(), format!("{}.{}", module, name));
+                }
+                [Token::Name(name)] => {
+                    self.names
+                        .insert(name.clone(), format!("{}.{}", module, name));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Full dotted name of the called function. Names imported with `*` are resolved if they are
+    /// known to be risky.
+    fn resolve(&self, name: &str) -> String {
+        let (first, rest) = match name.split_once('.') {
+            Some((first, rest)) => (first, Some(rest)),
+            None => (name, None),
+        };
+
+        let first = match self.names.get(first) {
+            Some(full) => full.clone(),
+            None => self
+                .everything
+                .iter()
+                .map(|module| format!("{}.{}", module, first))
+                .find(|full| is_known(full))
+                .unwrap_or_else(|| first.to_string()),
+        };
+
+        match rest {
+            Some(rest) => format!("{}.{}", first, rest),
+            None => first,
+        }
+    }
+}
+
+fn is_known(name: &str) -> bool {
+    matches(PROCESS_CALLS, name)
+        || matches(NETWORK_MODULES, name)
+        || FILE_WRITING_CALLS.contains(&name)
+        || FILE_COPYING_CALLS.contains(&name)
+        || PATH_CLASSES.contains(&name)
+        || ENDLESS_ITERATORS.contains(&name)
+}
+
+/// Entries ending with a dot match the whole module, the others the name and its prefixes, e.g.
+/// `os.exec` matches `os.execvp`.
+fn matches(entries: &[&str], name: &str) -> bool {
+    entries.iter().any(|entry| name.starts_with(entry))
+}
+
+fn is_op(token: &Token, op: &str) -> bool {
+    matches!(token, Token::Op(token) if token == op)
+}
+
+/// Dotted name at the start of the tokens and the tokens after it.
+fn dotted<'a, 'b>(tokens: &'a [&'b Token]) -> (String, &'a [&'b Token]) {
+    let mut name = String::new();
+    let mut position = 0;
+
+    while let Some(token) = tokens.get(position) {
+        match token {
+            Token::Name(part) if name.is_empty() || name.ends_with('.') => name.push_str(part),
+            Token::Op(dot) if dot == "." => name.push('.'),
+            _ => break,
+        }
+        position += 1;
+    }
+
+    (name, &tokens[position..])
+}
+
+/// Risks of the calls on the logical line.
+fn calls(
+    tokens: &[(Token, usize)],
+    imports: &Imports,
+    analyzer: &RiskAnalyzer,
+) -> Vec<(usize, RiskKind)> {
+    let mut risks = Vec::new();
+    let mut position = 0;
+
+    while position < tokens.len() {
+        let after_dot = position > 0 && is_op(&tokens[position - 1].0, ".");
+
+        let Token::Name(first) = &tokens[position].0 else {
+            position += 1;
+            continue;
+        };
+        if after_dot {
+            position += 1;
+            continue;
+        }
+
+        let line = tokens[position].1;
+
+        // The dotted name of the called function.
+        let mut name = first.clone();
+        let mut end = position + 1;
+        while let (Some((Token::Op(dot), _)), Some((Token::Name(part), _))) =
+            (tokens.get(end), tokens.get(end + 1))
+        {
+            if dot != "." {
+                break;
+            }
+            name.push('.');
+            name.push_str(part);
+            end += 2;
+        }
+
+        if !tokens.get(end).is_some_and(|(token, _)| is_op(token, "(")) {
+            position = end;
+            continue;
+        }
+
+        let close = closing(tokens, end);
+        let arguments = arguments(&tokens[end + 1..close]);
+        let name = imports.resolve(&name);
+
+        risks.extend(
+            call_kinds(&name, &arguments, &tokens[close..], analyzer)
+                .into_iter()
+                .map(|kind| (line, kind)),
+        );
+
+        // Calls in the arguments are checked as well.
+        position = end + 1;
+    }
+
+    risks
+}
+
+fn call_kinds(
+    name: &str,
+    arguments: &[Vec<&Token>],
+    after: &[(Token, usize)],
+    analyzer: &RiskAnalyzer,
+) -> Vec<RiskKind> {
+    let mut kinds = Vec::new();
+
+    let strings = |argument: &Vec<&Token>| -> Vec<String> {
+        argument
+            .iter()
+            .filter_map(|token| match token {
+                Token::Str(string) => Some(string.clone()),
+                _ => None,
+            })
+            .collect()
+    };
+    let outside = |argument: Option<&Vec<&Token>>| {
+        argument.is_some_and(|argument| {
+            strings(argument)
+                .iter()
+                .any(|path| analyzer.is_outside_workdir(path))
+        })
+    };
+    let positional: Vec<&Vec<&Token>> = arguments
+        .iter()
+        .filter(|argument| !matches!(argument.get(1), Some(token) if is_op(token, "=")))
+        .collect();
+
+    if matches(PROCESS_CALLS, name) {
+        kinds.push(RiskKind::ProcessSpawn);
+
+        // The command, either a string or a list of arguments.
+        let command: Vec<String> = positional.iter().flat_map(|a| strings(a)).collect();
+        kinds.extend(shell::analyze_command(&command.join(" "), analyzer));
+    }
+
+    if matches(NETWORK_MODULES, name) {
+        kinds.push(RiskKind::NetworkAccess);
+    }
+
+    // E.g. `pandas.read_csv("https://..")`.
+    let last = name.rsplit('.').next().unwrap_or(name);
+    let url = arguments.iter().flat_map(strings).any(|string| {
+        ["http://", "https://", "ftp://"]
+            .iter()
+            .any(|scheme| string.starts_with(scheme))
+    });
+    if url && (last.starts_with("read_") || last.contains("download") || last == "urlretrieve") {
+        kinds.push(RiskKind::NetworkAccess);
+    }
+
+    if name == "pip.main" || name.starts_with("pip._internal") {
+        kinds.push(RiskKind::PackageInstall);
+    }
+
+    let writes = match name {
+        "open" | "io.open" | "codecs.open" | "builtins.open" => {
+            let mode = arguments
+                .iter()
+                .find(|argument| matches!(argument.first(), Some(Token::Name(name)) if name == "mode"))
+                .or(positional.get(1).copied());
+
+            let writing = mode.is_some_and(|mode| {
+                strings(mode)
+                    .iter()
+                    .any(|mode| mode.contains(['w', 'a', 'x', '+']))
+            });
+
+            writing && outside(positional.first().copied())
+        }
+        name if FILE_WRITING_CALLS.contains(&name) => positional.iter().any(|a| outside(Some(a))),
+        name if FILE_COPYING_CALLS.contains(&name) => outside(positional.get(1).copied()),
+        name if PATH_CLASSES.contains(&name) => {
+            // `Path("/etc/hosts").write_text(..)`
+            let method = match after {
+                [_, (dot, _), (Token::Name(method), _), ..] if is_op(dot, ".") => Some(method),
+                _ => None,
+            };
+
+            method.is_some_and(|method| PATH_WRITING_METHODS.contains(&method.as_str()))
+                && outside(positional.first().copied())
+        }
+        _ => false,
+    };
+
+    if writes {
+        kinds.push(RiskKind::FileWriteOutsideWorkdir);
+    }
+
+    kinds
+}
+
+/// Position of the bracket closing the one at the given position, the end if there is none.
+fn closing(tokens: &[(Token, usize)], open: usize) -> usize {
+    let mut depth = 0;
+
+    for (position, (token, _)) in tokens.iter().enumerate().skip(open) {
+        match token {
+            Token::Op(op) if matches!(op.as_str(), "(" | "[" | "{") => depth += 1,
+            Token::Op(op) if matches!(op.as_str(), ")" | "]" | "}") => {
+                depth -= 1;
+                if depth == 0 {
+                    return position;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    tokens.len()
+}
+
+/// Arguments split by the top level commas.
+fn arguments(tokens: &[(Token, usize)]) -> Vec<Vec<&Token>> {
+    let mut arguments = vec![Vec::new()];
+    let mut depth = 0;
+
+    for (token, _) in tokens {
+        match token {
+            Token::Op(op) if matches!(op.as_str(), "(" | "[" | "{") => depth += 1,
+            Token::Op(op) if matches!(op.as_str(), ")" | "]" | "}") => depth -= 1,
+            Token::Op(op) if op == "," && depth == 0 => {
+                arguments.push(Vec::new());
+                continue;
+            }
+            _ => {}
+        }
+
+        if let Some(argument) = arguments.last_mut() {
+            argument.push(token);
+        }
+    }
+
+    arguments.retain(|argument| !argument.is_empty());
+    arguments
+}
+
+/// Line of the loop starting the lines if it never ends - `while True:`, `while 1:` or a loop
+/// over an endless iterator, without a `break` of its own, `return`, `raise` or exit in it.
+fn infinite_loop(lines: &[LogicalLine], imports: &Imports) -> Option<usize> {
+    let header = lines.first()?;
+    let tokens: Vec<&Token> = header.tokens.iter().map(|(token, _)| token).collect();
+
+    let colon = tokens.iter().rposition(|token| is_op(token, ":"))?;
+    let endless = match tokens.first() {
+        Some(Token::Name(keyword)) if keyword == "while" => {
+            let condition: Vec<&Token> = tokens[1..colon]
+                .iter()
+                .copied()
+                .filter(|token| !is_op(token, "(") && !is_op(token, ")"))
+                .collect();
+
+            matches!(&condition[..], [Token::Name(condition)] if condition == "True" || condition == "1")
+        }
+        Some(Token::Name(keyword)) if keyword == "for" => {
+            let iterator = tokens
+                .iter()
+                .position(|token| matches!(token, Token::Name(name) if name == "in"))
+                .map(|position| dotted(&tokens[position + 1..colon]).0);
+
+            iterator.is_some_and(|iterator| {
+                ENDLESS_ITERATORS.contains(&imports.resolve(&iterator).as_str())
+            })
+        }
+        _ => false,
+    };
+
+    if !endless {
+        return None;
+    }
+
+    // Body on the same line, e.g. `while True: pass`.
+    if colon + 1 < tokens.len() {
+        return match exits(&tokens[colon + 1..], true) {
+            true => None,
+            false => Some(header.line),
+        };
+    }
+
+    // Indentation of the loops nested in the body.
+    let mut nested: Vec<usize> = Vec::new();
+
+    for line in lines[1..]
+        .iter()
+        .take_while(|line| line.indent > header.indent)
+    {
+        nested.retain(|indent| *indent < line.indent);
+
+        let tokens: Vec<&Token> = line.tokens.iter().map(|(token, _)| token).collect();
+        if exits(&tokens, nested.is_empty()) {
+            return None;
+        }
+
+        if line.starts_with("while") || line.starts_with("for") || line.starts_with("async") {
+            nested.push(line.indent);
+        }
+    }
+
+    Some(header.line)
+}
+
+/// Whether the tokens leave the loop, `break` counts only if it is not in a nested loop.
+fn exits(tokens: &[&Token], breaks: bool) -> bool {
+    tokens.iter().any(|token| match token {
+        Token::Name(name) => match name.as_str() {
+            "break" => breaks,
+            "return" | "raise" | "exit" | "quit" | "_exit" => true,
+            _ => false,
+        },
+        _ => false,
+    })
+}
+
+/// Tokens of the code grouped into logical lines, without the comments and blank lines.
+fn logical_lines(code: &str) -> Vec<LogicalLine> {
+    let chars: Vec<char> = code.chars().collect();
+    let mut lines: Vec<LogicalLine> = Vec::new();
+
+    let mut position = 0;
+    let mut line_number = 1;
+    let mut current = LogicalLine::default();
+    let mut depth: usize = 0;
+    let mut line_start = true;
+
+    while position < chars.len() {
+        let c = chars[position];
+
+        if line_start {
+            line_start = false;
+
+            let indent = chars[position..]
+                .iter()
+                .take_while(|c| **c == ' ' || **c == '\t')
+                .count();
+            position += indent;
+
+            let rest: String = chars[position..]
+                .iter()
+                .take_while(|c| **c != '\n')
+                .collect();
+
+            // IPython shell escapes and package magics.
+            let escape = rest
+                .strip_prefix('!')
+                .or_else(|| rest.strip_prefix("%system "));
+            let (shell, magic) = match escape {
+                Some(command) => (Some(command.to_string()), false),
+                None => match ["%pip ", "%conda "]
+                    .iter()
+                    .any(|magic| rest.starts_with(magic))
+                {
+                    true => (Some(rest[1..].to_string()), true),
+                    false => (None, false),
+                },
+            };
+
+            if let Some(shell) = shell {
+                lines.push(LogicalLine {
+                    line: line_number,
+                    indent,
+                    tokens: Vec::new(),
+                    shell: Some(shell),
+                    magic,
+                });
+                position += rest.chars().count();
+                continue;
+            }
+
+            current.line = line_number;
+            current.indent = indent;
+            continue;
+        }
+
+        match c {
+            '\n' => {
+                position += 1;
+                line_number += 1;
+
+                if depth == 0 {
+                    if !current.tokens.is_empty() {
+                        lines.push(std::mem::take(&mut current));
+                    }
+                    line_start = true;
+                }
+            }
+            '\\' if chars.get(position + 1) == Some(&'\n') => {
+                position += 2;
+                line_number += 1;
+            }
+            '#' => {
+                while position < chars.len() && chars[position] != '\n' {
+                    position += 1;
+                }
+            }
+            ' ' | '\t' | '\r' => position += 1,
+            '"' | '\'' => {
+                let (string, end, newlines) = read_string(&chars, position, false);
+                current.tokens.push((Token::Str(string), line_number));
+                position = end;
+                line_number += newlines;
+            }
+            c if c.is_alphanumeric() || c == '_' => {
+                let end = chars[position..]
+                    .iter()
+                    .position(|c| !(c.is_alphanumeric() || *c == '_'))
+                    .map_or(chars.len(), |end| position + end);
+                let name: String = chars[position..end].iter().collect();
+
+                let prefix = name.len() <= 3
+                    && name.chars().all(|c| "rRbBuUfF".contains(c))
+                    && matches!(chars.get(end), Some('"' | '\''));
+
+                match prefix {
+                    true => {
+                        let raw = name.contains(['r', 'R']);
+                        let (string, string_end, newlines) = read_string(&chars, end, raw);
+                        current.tokens.push((Token::Str(string), line_number));
+                        position = string_end;
+                        line_number += newlines;
+                    }
+                    false => {
+                        current.tokens.push((Token::Name(name), line_number));
+                        position = end;
+                    }
+                }
+            }
+            c => {
+                match c {
+                    '(' | '[' | '{' => depth += 1,
+                    ')' | ']' | '}' => depth = depth.saturating_sub(1),
+                    _ => {}
+                }
+
+                let two: String = chars[position..(position + 2).min(chars.len())]
+                    .iter()
+                    .collect();
+                let op = match two.as_str() {
+                    "==" | "!=" | "<=" | ">=" | ":=" | "->" | "**" | "//" => two,
+                    _ => c.to_string(),
+                };
+
+                position += op.chars().count();
+                current.tokens.push((Token::Op(op), line_number));
+            }
+        }
+    }
+
+    if !current.tokens.is_empty() {
+        lines.push(current);
+    }
+
+    lines
+}
+
+/// Contents of the string literal starting with the quote at the position, the position after
+/// it and the number of new lines in it.
+fn read_string(chars: &[char], start: usize, raw: bool) -> (String, usize, usize) {
+    let quote = chars[start];
+    let triple = chars.get(start + 1) == Some(&quote) && chars.get(start + 2) == Some(&quote);
+    let delimiter = match triple {
+        true => 3,
+        false => 1,
+    };
+
+    let mut string = String::new();
+    let mut newlines = 0;
+    let mut position = start + delimiter;
+
+    while position < chars.len() {
+        let c = chars[position];
+
+        let closed = match triple {
+            true => chars[position..].starts_with(&[quote, quote, quote]),
+            false => c == quote,
+        };
+        if closed {
+            return (string, position + delimiter, newlines);
+        }
+
+        match c {
+            '\\' if !raw => {
+                if let Some(escaped) = chars.get(position + 1) {
+                    if *escaped == '\n' {
+                        newlines += 1;
+                    }
+                    string.push(*escaped);
+                }
+                position += 2;
+                continue;
+            }
+            // Unterminated string.
+            '\n' if !triple => return (string, position, newlines),
+            '\n' => newlines += 1,
+            _ => {}
+        }
+
+        string.push(c);
+        position += 1;
+    }
+
+    (string, position, newlines)
+}
@@ -0,0 +1,807 @@
+//! Shell code is split into simple commands, which are judged by the program they run, their
+//! arguments and redirections. Relative paths are resolved against the directory the commands
+//! change to with `cd`, `pushd` and `popd`.
+
+use super::{python, RiskAnalyzer, RiskKind};
+
+use std::path::PathBuf;
+
+const NETWORK_COMMANDS: &[&str] = &[
+    "curl", "wget", "nc", "ncat", "netcat", "ssh", "scp", "sftp", "rsync", "ftp", "telnet", "ping",
+    "dig", "nslookup", "host", "socat",
+];
+
+/// Commands which change all the paths they are given.
+const FILE_WRITING_COMMANDS: &[&str] = &[
+    "tee", "touch", "mkdir", "rm", "rmdir", "chmod", "chown", "chgrp", "truncate", "shred",
+    "unlink",
+];
+
+/// Commands which change only the last path they are given.
+const FILE_COPYING_COMMANDS: &[&str] = &["cp", "mv", "ln", "install"];
+
+/// Commands running the rest of the command line as another command.
+const WRAPPERS: &[&str] = &[
+    "sudo", "env", "command", "time", "nice", "timeout", "stdbuf", "nohup", "setsid", "exec",
+    "xargs",
+];
+
+/// Options of the wrappers which take the next word as their value.
+const WRAPPER_OPTIONS_WITH_VALUE: &[(&str, &[&str])] = &[
+    (
+        "sudo",
+        &[
+            "-u",
+            "-g",
+            "-C",
+            "-D",
+            "-p",
+            "-r",
+            "-t",
+            "-T",
+            "-U",
+            "--user",
+            "--group",
+            "--close-from",
+            "--chdir",
+            "--prompt",
+            "--role",
+            "--type",
+            "--command-timeout",
+            "--other-user",
+        ],
+    ),
+    ("env", &["-u", "-C", "--unset", "--chdir"]),
+    (
+        "xargs",
+        &[
+            "-I",
+            "-n",
+            "-P",
+            "-L",
+            "-d",
+            "-E",
+            "-s",
+            "-a",
+            "--max-args",
+            "--max-procs",
+            "--max-lines",
+            "--delimiter",
+            "--arg-file",
+        ],
+    ),
+    ("nice", &["-n", "--adjustment"]),
+    ("timeout", &["-s", "-k", "--signal", "--kill-after"]),
+    ("stdbuf", &["-i", "-o", "-e"]),
+    ("exec", &["-a"]),
+];
+
+/// Package manager and the subcommands installing packages.
+const PACKAGE_MANAGERS: &[(&str, &[&str])] = &[
+    ("pip", &["install"]),
+    ("pip3", &["install"]),
+    ("apt", &["install"]),
+    ("apt-get", &["install"]),
+    ("yum", &["install"]),
+    ("dnf", &["install"]),
+    ("zypper", &["install", "in"]),
+    ("apk", &["add"]),
+    ("brew", &["install"]),
+    ("npm", &["install", "i", "add"]),
+    ("pnpm", &["install", "i", "add"]),
+    ("yarn", &["install", "add"]),
+    ("cargo", &["install"]),
+    ("gem", &["install"]),
+    ("conda", &["install"]),
+    ("mamba", &["install"]),
+    ("go", &["install", "get"]),
+    ("poetry", &["add", "install"]),
+];
+
+pub(super) fn analyze(code: &str, analyzer: &RiskAnalyzer) -> Vec<(usize, RiskKind)> {
+    analyze_in(code, &mut Directories::default(), analyzer)
+}
+
+fn analyze_in(
+    code: &str,
+    directories: &mut Directories,
+    analyzer: &RiskAnalyzer,
+) -> Vec<(usize, RiskKind)> {
+    let mut risks = Vec::new();
+    let mut loops: Vec<Loop> = Vec::new();
+
+    for command in commands(&lex(code)) {
+        let mut words = &command.words[..];
+
+        // Reserved words in front of the command.
+        while let Some(word) = words.first() {
+            match word.as_str() {
+                "while" | "until" => {
+                    loops.push(Loop {
+                        line: command.line,
+                        infinite: is_always(word, &words[1..]),
+                        exits: false,
+                    });
+                    words = &words[1..];
+                }
+                "for" | "select" => {
+                    loops.push(Loop {
+                        line: command.line,
+                        infinite: words
+                            .get(1)
+                            .is_some_and(|word| word.replace(' ', "") == "((;;))"),
+                        exits: false,
+                    });
+                    // The rest is the list to loop over.
+                    words = &[];
+                }
+                "done" => {
+                    if let Some(done) = loops.pop() {
+                        if done.infinite && !done.exits {
+                            risks.push((done.line, RiskKind::InfiniteLoop));
+                        }
+                    }
+                    words = &words[1..];
+                }
+                "case" => words = &[],
+                "if" | "then" | "else" | "elif" | "fi" | "do" | "esac" | "!" | "{" | "}" => {
+                    words = &words[1..]
+                }
+                _ => break,
+            }
+        }
+
+        match words.first().map(String::as_str) {
+            Some("break") => {
+                if let Some(innermost) = loops.last_mut() {
+                    innermost.exits = true;
+                }
+            }
+            Some("exit" | "return") => loops.iter_mut().for_each(|open| open.exits = true),
+            _ => {}
+        }
+
+        let kinds = command_kinds(
+            words,
+            &command.redirects,
+            command.background,
+            directories,
+            analyzer,
+        );
+        risks.extend(kinds.into_iter().map(|kind| (command.line, kind)));
+
+        // Background jobs run in a subshell, their directory does not matter.
+        if !command.background {
+            directories.change(words);
+        }
+    }
+
+    risks
+}
+
+/// Risks of a command line run by other code, e.g. by `subprocess` in python.
+pub(super) fn analyze_command(command: &str, analyzer: &RiskAnalyzer) -> Vec<RiskKind> {
+    analyze(command, analyzer)
+        .into_iter()
+        .map(|(_, kind)| kind)
+        .collect()
+}
+
+/// Directory the commands run in, as far as it is known.
+#[derive(Debug, Clone, Default)]
+struct Directories {
+    /// Relative to the working directory unless absolute or starting with `~`, empty for the working
+    /// directory itself.
+    current: PathBuf,
+    /// For `cd -`.
+    previous: PathBuf,
+    /// Of `pushd`.
+    stack: Vec<PathBuf>,
+}
+
+impl Directories {
+    /// Follows `cd`, `pushd` and `popd`. Directories made of variables other than the home one are
+    /// unknown, so the current one is kept.
+    fn change(&mut self, words: &[String]) {
+        let Some(program) = words.first() else {
+            return;
+        };
+        let target = words[1..]
+            .iter()
+            .map(String::as_str)
+            .find(|word| !word.starts_with('-') || *word == "-");
+
+        let next = match (program.as_str(), target) {
+            ("cd", None) => PathBuf::from("~"),
+            ("cd" | "pushd", Some("-")) => self.previous.clone(),
+            ("cd" | "pushd", Some(target)) if target.contains('$') && !is_home(target) => return,
+            ("cd" | "pushd", Some(target)) => self.resolve(target),
+            // Swaps the two top directories.
+            ("pushd", None) => match self.stack.pop() {
+                Some(top) => top,
+                None => return,
+            },
+            ("popd", _) => match self.stack.pop() {
+                Some(top) => top,
+                None => return,
+            },
+            _ => return,
+        };
+
+        if program == "pushd" {
+            self.stack.push(self.current.clone());
+        }
+
+        self.previous = std::mem::replace(&mut self.current, next);
+    }
+
+    /// The path as seen from the working directory. `$PWD` is the current directory.
+    fn resolve(&self, path: &str) -> PathBuf {
+        let path = path.trim();
+
+        if let Some(rest) = ["$PWD", "${PWD}"]
+            .iter()
+            .find_map(|pwd| path.strip_prefix(pwd))
+            .filter(|rest| rest.is_empty() || rest.starts_with('/'))
+        {
+            return self.current.join(rest.trim_start_matches('/'));
+        }
+
+        match path.is_empty() || path.starts_with(['/', '~', '$']) {
+            true => PathBuf::from(path),
+            false => self.current.join(path),
+        }
+    }
+
+    fn is_outside(&self, path: &str, analyzer: &RiskAnalyzer) -> bool {
+        analyzer.is_outside_workdir(&self.resolve(path).to_string_lossy())
+    }
+}
+
+fn is_home(path: &str) -> bool {
+    path.starts_with("$HOME") || path.starts_with("${HOME}")
+}
+
+struct Loop {
+    line: usize,
+    infinite: bool,
+    /// There is a `break`, `exit` or `return` in it.
+    exits: bool,
+}
+
+/// `while true`, `while :` and `until false`.
+fn is_always(keyword: &str, condition: &[String]) -> bool {
+    let condition: Vec<&str> = condition.iter().map(String::as_str).collect();
+
+    match keyword {
+        "while" => matches!(condition[..], ["true"] | [":"] | ["((1))"]),
+        _ => matches!(condition[..], ["false"]),
+    }
+}
+
+fn command_kinds(
+    words: &[String],
+    redirects: &[String],
+    background: bool,
+    directories: &Directories,
+    analyzer: &RiskAnalyzer,
+) -> Vec<RiskKind> {
+    let mut kinds = Vec::new();
+
+    if background {
+        kinds.push(RiskKind::ProcessSpawn);
+    }
+
+    if redirects
+        .iter()
+        .any(|target| target.starts_with("/dev/tcp/") || target.starts_with("/dev/udp/"))
+    {
+        kinds.push(RiskKind::NetworkAccess);
+    }
+
+    if redirects
+        .iter()
+        .any(|target| directories.is_outside(target, analyzer))
+    {
+        kinds.push(RiskKind::FileWriteOutsideWorkdir);
+    }
+
+    let words = skip_assignments(words);
+    let words = unwrap(words, &mut kinds);
+
+    let Some(program) = words.first() else {
+        return kinds;
+    };
+    let program = basename(program);
+    let arguments = &words[1..];
+
+    if installs_packages(words) {
+        kinds.push(RiskKind::PackageInstall);
+    }
+
+    match program {
+        "eval" | "disown" => kinds.push(RiskKind::ProcessSpawn),
+        "sh" | "bash" | "zsh" | "dash" => {
+            if let Some(position) = arguments.iter().position(|argument| argument == "-c") {
+                kinds.push(RiskKind::ProcessSpawn);
+
+                if let Some(command) = arguments.get(position + 1) {
+                    let risks = analyze_in(command, &mut directories.clone(), analyzer);
+                    kinds.extend(risks.into_iter().map(|(_, kind)| kind));
+                }
+            }
+        }
+        program if is_python(program) => {
+            if let Some(position) = arguments.iter().position(|argument| argument == "-c") {
+                kinds.push(RiskKind::ProcessSpawn);
+
+                if let Some(code) = arguments.get(position + 1) {
+                    let risks = python::analyze(code, analyzer);
+                    kinds.extend(risks.into_iter().map(|(_, kind)| kind));
+                }
+            }
+        }
+        "git" => {
+            let subcommand = arguments.iter().find(|argument| !argument.starts_with('-'));
+
+            if subcommand.is_some_and(|subcommand| {
+                matches!(
+                    subcommand.as_str(),
+                    "clone" | "pull" | "push" | "fetch" | "ls-remote" | "submodule"
+                )
+            }) {
+                kinds.push(RiskKind::NetworkAccess);
+            }
+        }
+        "dd" => {
+            let writes = arguments.iter().any(|argument| {
+                argument
+                    .strip_prefix("of=")
+                    .is_some_and(|path| directories.is_outside(path, analyzer))
+            });
+
+            if writes {
+                kinds.push(RiskKind::FileWriteOutsideWorkdir);
+            }
+        }
+        program if NETWORK_COMMANDS.contains(&program) => {
+            kinds.push(RiskKind::NetworkAccess);
+
+            // Downloads, e.g. `curl -o` or `wget -O`.
+            let writes = arguments.windows(2).any(|pair| {
+                matches!(
+                    pair[0].as_str(),
+                    "-o" | "-O" | "--output" | "--output-document"
+                ) && directories.is_outside(&pair[1], analyzer)
+            });
+
+            if writes {
+                kinds.push(RiskKind::FileWriteOutsideWorkdir);
+            }
+        }
+        program
+            if FILE_WRITING_COMMANDS.contains(&program)
+                && paths(arguments).any(|path| directories.is_outside(path, analyzer)) =>
+        {
+            kinds.push(RiskKind::FileWriteOutsideWorkdir);
+        }
+        program if FILE_COPYING_COMMANDS.contains(&program) => {
+            // `mv` removes the source as well.
+            let outside = match program {
+                "mv" => paths(arguments).any(|path| directories.is_outside(path, analyzer)),
+                _ => paths(arguments)
+                    .last()
+                    .is_some_and(|path| directories.is_outside(path, analyzer)),
+            };
+
+            if outside {
+                kinds.push(RiskKind::FileWriteOutsideWorkdir);
+            }
+        }
+        _ => {}
+    }
+
+    kinds
+}
+
+/// Skips `VAR=value` in front of the command.
+fn skip_assignments(words: &[String]) -> &[String] {
+    let assignments = words
+        .iter()
+        .take_while(|word| {
+            word.split_once('=').is_some_and(|(name, _)| {
+                !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+            })
+        })
+        .count();
+
+    &words[assignments..]
+}
+
+/// Skips wrappers like `sudo` or `nohup` with their options, the ones detaching the command count
+/// as spawning a process.
+fn unwrap<'a>(mut words: &'a [String], kinds: &mut Vec<RiskKind>) -> &'a [String] {
+    while let Some(wrapper) = words
+        .first()
+        .map(|word| basename(word))
+        .filter(|word| WRAPPERS.contains(word))
+    {
+        if matches!(wrapper, "nohup" | "setsid" | "exec" | "xargs") {
+            kinds.push(RiskKind::ProcessSpawn);
+        }
+
+        let with_value = WRAPPER_OPTIONS_WITH_VALUE
+            .iter()
+            .find(|(name, _)| *name == wrapper)
+            .map_or(&[][..], |(_, options)| *options);
+
+        let mut skipped = 1;
+        while let Some(word) = words.get(skipped) {
+            if !(word.starts_with('-')
+                || word.contains('=')
+                || word.starts_with(|c: char| c.is_ascii_digit()))
+            {
+                break;
+            }
+
+            skipped += match with_value.contains(&word.as_str()) {
+                true => 2,
+                false => 1,
+            };
+        }
+
+        words = &words[skipped.min(words.len())..];
+    }
+
+    words
+}
+
+/// E.g. `pip install`, `python -m pip install` or `uv pip install`.
+fn installs_packages(words: &[String]) -> bool {
+    words.iter().enumerate().any(|(index, word)| {
+        let Some((_, subcommands)) = PACKAGE_MANAGERS
+            .iter()
+            .find(|(manager, _)| *manager == basename(word))
+        else {
+            return false;
+        };
+
+        words[index + 1..]
+            .iter()
+            .find(|word| !word.starts_with('-'))
+            .is_some_and(|subcommand| subcommands.contains(&subcommand.as_str()))
+    })
+}
+
+fn paths(arguments: &[String]) -> impl Iterator<Item = &str> {
+    arguments
+        .iter()
+        .map(String::as_str)
+        .filter(|argument| !argument.starts_with('-'))
+}
+
+/// `python`, `python3`, `python3.12` etc.
+fn is_python(program: &str) -> bool {
+    program
+        .strip_prefix("python")
+        .is_some_and(|version| version.chars().all(|c| c.is_ascii_digit() || c == '.'))
+}
+
+fn basename(word: &str) -> &str {
+    word.rsplit('/').next().unwrap_or(word)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    /// Redirection of the output, with its target.
+    Redirect(String),
+    /// `;`, `&&`, `|`, a new line, parentheses etc.
+    Separator,
+    /// `&` at the end of a command.
+    Background,
+}
+
+#[derive(Debug, Default)]
+struct Command {
+    words: Vec<String>,
+    redirects: Vec<String>,
+    background: bool,
+    line: usize,
+}
+
+fn commands(tokens: &[(Token, usize)]) -> Vec<Command> {
+    let mut commands = Vec::new();
+    let mut command = Command::default();
+
+    for (token, line) in tokens {
+        if command.words.is_empty() && command.redirects.is_empty() {
+            command.line = *line;
+        }
+
+        match token {
+            Token::Word(word) => command.words.push(word.clone()),
+            Token::Redirect(target) => command.redirects.push(target.clone()),
+            Token::Separator | Token::Background => {
+                if !command.words.is_empty() || !command.redirects.is_empty() {
+                    command.background = *token == Token::Background;
+                    commands.push(std::mem::take(&mut command));
+                }
+            }
+        }
+    }
+
+    if !command.words.is_empty() || !command.redirects.is_empty() {
+        commands.push(command);
+    }
+
+    commands
+}
+
+/// Tokens with the lines they start on. Command substitutions are split off as separate commands,
+/// heredoc bodies are skipped.
+fn lex(code: &str) -> Vec<(Token, usize)> {
+    let chars: Vec<char> = code.chars().collect();
+    let mut lexer = Lexer {
+        chars: &chars,
+        position: 0,
+        line: 1,
+        tokens: Vec::new(),
+        heredocs: Vec::new(),
+    };
+
+    lexer.run();
+    lexer.tokens
+}
+
+struct Lexer<'a> {
+    chars: &'a [char],
+    position: usize,
+    line: usize,
+    tokens: Vec<(Token, usize)>,
+    /// Delimiters of the heredocs starting on the next line.
+    heredocs: Vec<String>,
+}
+
+impl Lexer<'_> {
+    fn peek(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.position + offset).copied()
+    }
+
+    fn push(&mut self, token: Token, line: usize) {
+        self.tokens.push((token, line));
+    }
+
+    fn run(&mut self) {
+        while let Some(c) = self.peek(0) {
+            match c {
+                '\n' => {
+                    self.position += 1;
+                    self.push(Token::Separator, self.line);
+                    self.line += 1;
+                    self.skip_heredocs();
+                }
+                ' ' | '\t' | '\r' => self.position += 1,
+                '\\' if self.peek(1) == Some('\n') => {
+                    self.position += 2;
+                    self.line += 1;
+                }
+                '#' => {
+                    while self.peek(0).is_some_and(|c| c != '\n') {
+                        self.position += 1;
+                    }
+                }
+                '(' if self.peek(1) == Some('(') => self.word(),
+                '|' if self.peek(1) == Some('&') => {
+                    self.position += 2;
+                    self.push(Token::Separator, self.line);
+                }
+                ';' | '|' | '(' | ')' | '`' => {
+                    self.position += 1;
+                    self.push(Token::Separator, self.line);
+                }
+                '&' if self.peek(1) == Some('&') => {
+                    self.position += 2;
+                    self.push(Token::Separator, self.line);
+                }
+                '&' if self.peek(1) == Some('>') => {
+                    self.position += 1;
+                    self.redirect();
+                }
+                '&' => {
+                    self.position += 1;
+                    self.push(Token::Background, self.line);
+                }
+                '>' => self.redirect(),
+                '<' => self.input(),
+                _ => self.word(),
+            }
+        }
+    }
+
+    /// `>`, `>>`, `>|`, `>&2` etc.
+    fn redirect(&mut self) {
+        let line = self.line;
+        self.position += 1;
+
+        if matches!(self.peek(0), Some('>' | '|')) {
+            self.position += 1;
+        }
+
+        // Duplicating a file descriptor.
+        if self.peek(0) == Some('&') {
+            self.position += 1;
+            self.read_word();
+            return;
+        }
+
+        let target = self.read_word();
+        self.push(Token::Redirect(target), line);
+    }
+
+    /// `<`, heredocs `<<` and here-strings `<<<`.
+    fn input(&mut self) {
+        let arrows = self.chars[self.position..]
+            .iter()
+            .take_while(|c| **c == '<')
+            .count();
+        self.position += arrows;
+
+        if arrows == 2 {
+            if self.peek(0) == Some('-') {
+                self.position += 1;
+            }
+
+            let delimiter = self.read_word();
+            self.heredocs.push(delimiter);
+        } else {
+            self.read_word();
+        }
+    }
+
+    fn skip_heredocs(&mut self) {
+        for delimiter in std::mem::take(&mut self.heredocs) {
+            while self.position < self.chars.len() {
+                let end = self.chars[self.position..]
+                    .iter()
+                    .position(|c| *c == '\n')
+                    .map_or(self.chars.len(), |end| self.position + end);
+
+                let line: String = self.chars[self.position..end].iter().collect();
+                self.position = (end + 1).min(self.chars.len());
+                self.line += 1;
+
+                if line.trim() == delimiter {
+                    break;
+                }
+            }
+        }
+    }
+
+    fn word(&mut self) {
+        let line = self.line;
+        let start = self.position;
+        let word = self.read_word();
+
+        match word.is_empty() {
+            true if self.position == start => self.position += 1,
+            true => {}
+            false => {
+                // File descriptor of a redirection, e.g. `2>`.
+                let descriptor =
+                    self.peek(0) == Some('>') && word.chars().all(|c| c.is_ascii_digit());
+
+                if !descriptor {
+                    self.push(Token::Word(word), line);
+                }
+            }
+        }
+    }
+
+    /// Word with the quotes removed. Stops at a command substitution, which is lexed on its own.
+    fn read_word(&mut self) -> String {
+        while matches!(self.peek(0), Some(' ' | '\t')) {
+            self.position += 1;
+        }
+
+        // Arithmetic, e.g. `((;;))`.
+        if self.peek(0) == Some('(') && self.peek(1) == Some('(') {
+            return self.read_until("))");
+        }
+
+        let mut word = String::new();
+
+        while let Some(c) = self.peek(0) {
+            match c {
+                '\'' => {
+                    self.position += 1;
+                    while let Some(c) = self.peek(0) {
+                        self.position += 1;
+                        match c {
+                            '\'' => break,
+                            '\n' => {
+                                self.line += 1;
+                                word.push(c);
+                            }
+                            c => word.push(c),
+                        }
+                    }
+                }
+                '"' => {
+                    self.position += 1;
+                    while let Some(c) = self.peek(0) {
+                        self.position += 1;
+                        match c {
+                            '"' => break,
+                            '\\' => {
+                                if let Some(escaped) = self.peek(0) {
+                                    self.position += 1;
+                                    word.push(escaped);
+                                }
+                            }
+                            '\n' => {
+                                self.line += 1;
+                                word.push(c);
+                            }
+                            c => word.push(c),
+                        }
+                    }
+                }
+                '\\' => {
+                    self.position += 1;
+                    match self.peek(0) {
+                        Some('\n') => {
+                            self.position += 1;
+                            self.line += 1;
+                        }
+                        Some(escaped) => {
+                            self.position += 1;
+                            word.push(escaped);
+                        }
+                        None => {}
+                    }
+                }
+                '$' if self.peek(1) == Some('(') && self.peek(2) == Some('(') => {
+                    self.position += 1;
+                    word.push('$');
+                    word.push_str(&self.read_until("))"));
+                }
+                // Command substitution, the separator is pushed by the caller.
+                '$' if self.peek(1) == Some('(') => {
+                    self.position += 1;
+                    break;
+                }
+                ' ' | '\t' | '\r' | '\n' | ';' | '&' | '|' | '<' | '>' | '(' | ')' | '`' => break,
+                c => {
+                    self.position += 1;
+                    word.push(c);
+                }
+            }
+        }
+
+        word
+    }
+
+    fn read_until(&mut self, end: &str) -> String {
+        let end: Vec<char> = end.chars().collect();
+        let mut text = String::new();
+
+        while self.position < self.chars.len() {
+            if self.chars[self.position..].starts_with(&end) {
+                self.position += end.len();
+                text.extend(&end);
+                break;
+            }
+
+            let c = self.chars[self.position];
+            if c == '\n' {
+                self.line += 1;
+            }
+
+            text.push(c);
+            self.position += 1;
+        }
+
+        text
+    }
+}
@@ -0,0 +1,350 @@
+//! Linux namespace and seccomp isolation applied to the commands spawned by the
+//! [super::sandboxed_code_executor::SandboxedCodeExecutor].
+//!
+//! Everything that requires allocation is prepared in the parent. The closure passed to
+//! `pre_exec` runs between fork and exec, so it only makes raw syscalls.
+
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+use tokio::process::Command;
+
+const MOUNT_ATTR_RDONLY: u64 = 0x00000001;
+const AT_RECURSIVE: libc::c_uint = 0x8000;
+
+/// struct mount_attr from linux/mount.h.
+#[repr(C)]
+struct MountAttr {
+    attr_set: u64,
+    attr_clr: u64,
+    propagation: u64,
+    userns_fd: u64,
+}
+
+const SECCOMP_DATA_NR_OFFSET: u32 = 0;
+const SECCOMP_DATA_ARCH_OFFSET: u32 = 4;
+/// Lower half of the first argument. All the clone flags fit into it.
+const SECCOMP_DATA_ARG0_OFFSET: u32 = 16;
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000_003e;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+/// Syscalls with the x32 ABI bit set are rejected as a whole, so they cannot be used to bypass the
+/// filter.
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+/// Syscalls which are not needed by ordinary programs and are commonly used to escape or to attack
+/// the kernel. They fail with EPERM.
+const DENIED_SYSCALLS: &[libc::c_long] = &[
+    libc::SYS_ptrace,
+    libc::SYS_process_vm_readv,
+    libc::SYS_process_vm_writev,
+    libc::SYS_mount,
+    libc::SYS_umount2,
+    libc::SYS_mount_setattr,
+    libc::SYS_open_tree,
+    libc::SYS_move_mount,
+    libc::SYS_fsopen,
+    libc::SYS_fsconfig,
+    libc::SYS_fsmount,
+    libc::SYS_fspick,
+    libc::SYS_pivot_root,
+    libc::SYS_chroot,
+    libc::SYS_unshare,
+    libc::SYS_setns,
+    libc::SYS_bpf,
+    libc::SYS_perf_event_open,
+    libc::SYS_userfaultfd,
+    libc::SYS_keyctl,
+    libc::SYS_add_key,
+    libc::SYS_request_key,
+    libc::SYS_kexec_load,
+    libc::SYS_kexec_file_load,
+    libc::SYS_init_module,
+    libc::SYS_finit_module,
+    libc::SYS_delete_module,
+    libc::SYS_reboot,
+    libc::SYS_swapon,
+    libc::SYS_swapoff,
+    libc::SYS_acct,
+    libc::SYS_settimeofday,
+    libc::SYS_clock_settime,
+    libc::SYS_clock_adjtime,
+    libc::SYS_adjtimex,
+    libc::SYS_sethostname,
+    libc::SYS_setdomainname,
+    libc::SYS_open_by_handle_at,
+    libc::SYS_name_to_handle_at,
+    // Operations submitted through io_uring are not seen by the filter.
+    libc::SYS_io_uring_setup,
+    libc::SYS_io_uring_enter,
+    libc::SYS_io_uring_register,
+];
+
+/// Flags of clone that create new namespaces. Clone is denied when any of them is set.
+const CLONE_NAMESPACE_FLAGS: libc::c_int = libc::CLONE_NEWUSER
+    | libc::CLONE_NEWNS
+    | libc::CLONE_NEWNET
+    | libc::CLONE_NEWPID
+    | libc::CLONE_NEWIPC
+    | libc::CLONE_NEWUTS
+    | libc::CLONE_NEWCGROUP
+    | libc::CLONE_NEWTIME;
+
+/// Makes the command run in fresh user, mount, network, pid, ipc and uts namespaces with a
+/// read-only view of the file system where only `work_dir` is writable, and with a seccomp filter
+/// denying [DENIED_SYSCALLS], clone3 and clone with [CLONE_NAMESPACE_FLAGS].
+pub(crate) fn apply(command: &mut Command, work_dir: &Path) -> io::Result<()> {
+    let work_dir = work_dir.canonicalize()?;
+    let work_dir_c = CString::new(work_dir.as_os_str().as_bytes())?;
+
+    // SAFETY: getuid and getgid cannot fail.
+    let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+
+    // The ids are kept the same inside of the namespace. Since the code does not run as root
+    // there, it loses all the capabilities on exec.
+    let uid_map = format!("{} {} 1", uid, uid).into_bytes();
+    let gid_map = format!("{} {} 1", gid, gid).into_bytes();
+
+    let filter = seccomp_filter();
+
+    command.env("HOME", &work_dir).env("TMPDIR", &work_dir);
+
+    // SAFETY: enter_sandbox only makes raw syscalls on data prepared above.
+    unsafe {
+        command.pre_exec(move || enter_sandbox(&work_dir_c, &uid_map, &gid_map, &filter));
+    }
+
+    Ok(())
+}
+
+unsafe fn enter_sandbox(
+    work_dir: &CString,
+    uid_map: &[u8],
+    gid_map: &[u8],
+    filter: &[libc::sock_filter],
+) -> io::Result<()> {
+    cvt(libc::unshare(
+        libc::CLONE_NEWUSER
+            | libc::CLONE_NEWNS
+            | libc::CLONE_NEWNET
+            | libc::CLONE_NEWPID
+            | libc::CLONE_NEWIPC
+            | libc::CLONE_NEWUTS,
+    ))?;
+
+    write_file(c"/proc/self/setgroups", b"deny")?;
+    write_file(c"/proc/self/uid_map", uid_map)?;
+    write_file(c"/proc/self/gid_map", gid_map)?;
+
+    // Nothing done here may propagate back to the host.
+    cvt(libc::mount(
+        std::ptr::null(),
+        c"/".as_ptr(),
+        std::ptr::null(),
+        libc::MS_REC | libc::MS_PRIVATE,
+        std::ptr::null(),
+    ))?;
+
+    // Work dir becomes a mount of its own, so it can be made writable again after the whole tree
+    // is turned read-only.
+    cvt(libc::mount(
+        work_dir.as_ptr(),
+        work_dir.as_ptr(),
+        std::ptr::null(),
+        libc::MS_BIND | libc::MS_REC,
+        std::ptr::null(),
+    ))?;
+
+    set_mount_attr(c"/", AT_RECURSIVE, MOUNT_ATTR_RDONLY, 0)?;
+    set_mount_attr(work_dir, 0, 0, MOUNT_ATTR_RDONLY)?;
+
+    // Current dir was set before this runs, so it still points to the directory below the bind
+    // mount.
+    cvt(libc::chdir(work_dir.as_ptr()))?;
+
+    // The new pid namespace only applies to the children, so the code is run in a child which
+    // becomes its init. This process stays outside and passes the exit status through.
+    match libc::fork() {
+        -1 => Err(io::Error::last_os_error()),
+        0 => {
+            cvt(libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL))?;
+
+            cvt(libc::mount(
+                c"proc".as_ptr(),
+                c"/proc".as_ptr(),
+                c"proc".as_ptr(),
+                libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+                std::ptr::null(),
+            ))?;
+
+            cvt(libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0))?;
+
+            let program = libc::sock_fprog {
+                len: filter.len() as libc::c_ushort,
+                filter: filter.as_ptr() as *mut libc::sock_filter,
+            };
+
+            cvt(libc::prctl(
+                libc::PR_SET_SECCOMP,
+                libc::SECCOMP_MODE_FILTER,
+                &program as *const libc::sock_fprog,
+            ))?;
+
+            Ok(())
+        }
+        child => wait_and_exit(child),
+    }
+}
+
+/// Waits for the code running in the pid namespace and exits the same way it did.
+unsafe fn wait_and_exit(child: libc::pid_t) -> ! {
+    // Closes the pipe used by the parent to detect failed exec - otherwise spawn would wait for
+    // this process to exit.
+    libc::syscall(libc::SYS_close_range, 3, libc::c_uint::MAX, 0);
+
+    let mut status = 0;
+    while libc::waitpid(child, &mut status, 0) == -1 {
+        if io::Error::last_os_error().raw_os_error() != Some(libc::EINTR) {
+            libc::_exit(127);
+        }
+    }
+
+    if libc::WIFSIGNALED(status) {
+        let signal = libc::WTERMSIG(status);
+
+        let mut mask: libc::sigset_t = std::mem::zeroed();
+        libc::sigemptyset(&mut mask);
+        libc::sigprocmask(libc::SIG_SETMASK, &mask, std::ptr::null_mut());
+
+        libc::signal(signal, libc::SIG_DFL);
+        libc::kill(libc::getpid(), signal);
+
+        libc::_exit(128 + signal);
+    }
+
+    libc::_exit(libc::WEXITSTATUS(status))
+}
+
+unsafe fn set_mount_attr(
+    path: &std::ffi::CStr,
+    flags: libc::c_uint,
+    attr_set: u64,
+    attr_clr: u64,
+) -> io::Result<()> {
+    let attr = MountAttr {
+        attr_set,
+        attr_clr,
+        propagation: 0,
+        userns_fd: 0,
+    };
+
+    let result = libc::syscall(
+        libc::SYS_mount_setattr,
+        libc::AT_FDCWD,
+        path.as_ptr(),
+        flags,
+        &attr as *const MountAttr,
+        std::mem::size_of::<MountAttr>(),
+    );
+
+    match result {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+unsafe fn write_file(path: &std::ffi::CStr, content: &[u8]) -> io::Result<()> {
+    let fd = libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+    if fd == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let written = libc::write(fd, content.as_ptr() as *const libc::c_void, content.len());
+    libc::close(fd);
+
+    match written {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+fn cvt(result: libc::c_int) -> io::Result<()> {
+    match result {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+fn statement(code: u32, k: u32) -> libc::sock_filter {
+    libc::sock_filter {
+        code: code as u16,
+        jt: 0,
+        jf: 0,
+        k,
+    }
+}
+
+fn jump(code: u32, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+    libc::sock_filter {
+        code: code as u16,
+        jt,
+        jf,
+        k,
+    }
+}
+
+fn seccomp_filter() -> Vec<libc::sock_filter> {
+    let load = libc::BPF_LD | libc::BPF_W | libc::BPF_ABS;
+    let jump_equal = libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K;
+    let ret = libc::BPF_RET | libc::BPF_K;
+
+    let jump_set = libc::BPF_JMP | libc::BPF_JSET | libc::BPF_K;
+
+    let deny = libc::SECCOMP_RET_ERRNO | libc::EPERM as u32;
+    let allow = libc::SECCOMP_RET_ALLOW;
+
+    let mut filter = vec![
+        statement(load, SECCOMP_DATA_ARCH_OFFSET),
+        jump(jump_equal, AUDIT_ARCH, 1, 0),
+        statement(ret, libc::SECCOMP_RET_KILL_PROCESS),
+        statement(load, SECCOMP_DATA_NR_OFFSET),
+    ];
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        let jump_greater_equal = libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K;
+
+        filter.push(jump(jump_greater_equal, X32_SYSCALL_BIT, 0, 1));
+        filter.push(statement(ret, deny));
+    }
+
+    for syscall in DENIED_SYSCALLS {
+        filter.push(jump(jump_equal, *syscall as u32, 0, 1));
+        filter.push(statement(ret, deny));
+    }
+
+    // Its flags are behind a pointer which the filter cannot follow. ENOSYS makes libc fall back to
+    // clone.
+    filter.push(jump(jump_equal, libc::SYS_clone3 as u32, 0, 1));
+    filter.push(statement(
+        ret,
+        libc::SECCOMP_RET_ERRNO | libc::ENOSYS as u32,
+    ));
+
+    // Loading the flags replaces the syscall number, so clone is decided on right away.
+    filter.push(jump(jump_equal, libc::SYS_clone as u32, 0, 4));
+    filter.push(statement(load, SECCOMP_DATA_ARG0_OFFSET));
+    filter.push(jump(jump_set, CLONE_NAMESPACE_FLAGS as u32, 0, 1));
+    filter.push(statement(ret, deny));
+    filter.push(statement(ret, allow));
+
+    filter.push(statement(ret, allow));
+
+    filter
+}
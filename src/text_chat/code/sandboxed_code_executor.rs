@@ -0,0 +1,60 @@
+use super::{CodeBlock, CodeBlockExecutionResult, CodeExecutor};
+
+use super::execution_limits::ExecutionLimits;
+use super::local_code_executor::LocalCodeExecutor;
+use super::local_code_executor_error::LocalCodeExecutorError;
+
+use std::path::{Path, PathBuf};
+
+/// Runs code blocks the same way as the [LocalCodeExecutor], but every process is isolated from
+/// the host:
+///
+/// - fresh user, mount, network, pid, ipc and uts namespaces,
+/// - the whole file system is mounted read-only, only `work_dir` stays writable (it is also used as
+///   `HOME` and `TMPDIR`),
+/// - no network - the network namespace has no interfaces apart from the loopback, which is down,
+/// - a seccomp filter denying syscalls that are only useful to escape the sandbox or to attack
+///   the kernel (mounting, new namespaces, ptrace, module loading, bpf, io_uring, etc.).
+///
+/// With this, allowing execution via
+/// [super::super::chat_user_agent::CodeBlockFeedback::AllowExecution] still gives the code access
+/// to the work dir and cpu time, but not to the rest of the machine. [ExecutionLimits] should be
+/// used for the latter.
+///
+/// Requires Linux 5.12 or newer with unprivileged user namespaces enabled. If the sandbox cannot
+/// be set up, execution fails with [LocalCodeExecutorError::Spawn] - code is never run without it.
+pub struct SandboxedCodeExecutor {
+    executor: LocalCodeExecutor,
+}
+
+impl SandboxedCodeExecutor {
+    pub fn new(work_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            executor: LocalCodeExecutor::new(work_dir).with_command_hook(super::sandbox::apply),
+        }
+    }
+
+    pub fn with_limits(mut self, limits: ExecutionLimits) -> Self {
+        self.executor = self.executor.with_limits(limits);
+        self
+    }
+
+    pub fn work_dir(&self) -> &Path {
+        self.executor.work_dir()
+    }
+
+    pub fn limits(&self) -> &ExecutionLimits {
+        self.executor.limits()
+    }
+}
+
+impl CodeExecutor for SandboxedCodeExecutor {
+    type Error = LocalCodeExecutorError;
+
+    async fn execute_code_block(
+        &self,
+        code_block: &CodeBlock,
+    ) -> Result<CodeBlockExecutionResult, Self::Error> {
+        self.executor.execute_code_block(code_block).await
+    }
+}
@@ -0,0 +1,442 @@
+use super::{CodeBlock, CodeBlockExecutionOutput, CodeBlockExecutionResult, CodeExecutor};
+
+use super::execution_limits::{ExecutionLimit, ExecutionLimits};
+use super::local_code_executor::{source_file_stem, FilesSnapshot};
+use super::wasm_code_executor_error::WasmCodeExecutorError;
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use wasmtime::{Config, Engine, Linker, Module, Store, StoreLimits, StoreLimitsBuilder, Trap};
+use wasmtime_wasi::preview1::WasiP1Ctx;
+use wasmtime_wasi::{
+    DirPerms, FilePerms, I32Exit, OutputStream, Pollable, StdoutStream, StreamError, StreamResult,
+    WasiCtxBuilder,
+};
+
+use bytes::Bytes;
+
+use tracing::debug;
+
+/// Interval in which the epoch of the engine is incremented. Timeouts are rounded up to it.
+const EPOCH_TICK: Duration = Duration::from_millis(10);
+
+/// Placeholder in [WasmInterpreter::args] replaced with the guest path of the source file.
+pub const SOURCE_PLACEHOLDER: &str = "{source}";
+
+/// An interpreter compiled to a WASI command module, e.g. CPython or QuickJS built for
+/// `wasm32-wasi`.
+#[derive(Debug, Clone, Default)]
+pub struct WasmInterpreter {
+    /// Arguments passed to the module, including the program name. [SOURCE_PLACEHOLDER] is
+    /// replaced with the path to the code block.
+    ///
+    /// Example: `["python", "{source}"]`.
+    pub args: Vec<String>,
+    /// Host directories mounted read-only into the guest, e.g. the standard library of the
+    /// interpreter. Pairs of the host path and the guest path.
+    pub dirs: Vec<(PathBuf, String)>,
+}
+
+struct RegisteredInterpreter {
+    module: Module,
+    interpreter: WasmInterpreter,
+}
+
+/// Bytes left for stdout and stderr of a single execution together.
+#[derive(Clone)]
+struct OutputBudget {
+    remaining: Arc<AtomicUsize>,
+    exceeded: Arc<AtomicBool>,
+}
+
+impl OutputBudget {
+    fn new(max_output_bytes: usize) -> Self {
+        Self {
+            remaining: Arc::new(AtomicUsize::new(max_output_bytes)),
+            exceeded: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// How many of the bytes fit. Whatever does not marks the budget as exceeded.
+    fn take(&self, bytes: usize) -> usize {
+        let remaining = self
+            .remaining
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |remaining| {
+                Some(remaining.saturating_sub(bytes))
+            })
+            .unwrap_or_default();
+
+        if bytes > remaining {
+            self.exceeded.store(true, Ordering::SeqCst);
+        }
+
+        bytes.min(remaining)
+    }
+
+    fn is_exceeded(&self) -> bool {
+        self.exceeded.load(Ordering::SeqCst)
+    }
+}
+
+/// Captures stdout or stderr of the guest. Once the [OutputBudget] is used up, the write traps,
+/// which stops the execution.
+#[derive(Clone)]
+struct CapturedOutput {
+    buffer: Arc<Mutex<Vec<u8>>>,
+    budget: OutputBudget,
+}
+
+impl CapturedOutput {
+    fn new(budget: OutputBudget) -> Self {
+        Self {
+            buffer: Arc::new(Mutex::new(Vec::new())),
+            budget,
+        }
+    }
+
+    fn contents(&self) -> Vec<u8> {
+        self.buffer.lock().unwrap().clone()
+    }
+}
+
+impl StdoutStream for CapturedOutput {
+    fn stream(&self) -> Box<dyn OutputStream> {
+        Box::new(self.clone())
+    }
+
+    fn isatty(&self) -> bool {
+        false
+    }
+}
+
+#[wasmtime_wasi::async_trait]
+impl OutputStream for CapturedOutput {
+    fn write(&mut self, bytes: Bytes) -> StreamResult<()> {
+        let allowed = self.budget.take(bytes.len());
+        self.buffer
+            .lock()
+            .unwrap()
+            .extend_from_slice(&bytes[..allowed]);
+
+        match allowed < bytes.len() {
+            true => Err(StreamError::trap("output limit exceeded")),
+            false => Ok(()),
+        }
+    }
+
+    fn flush(&mut self) -> StreamResult<()> {
+        Ok(())
+    }
+
+    fn check_write(&mut self) -> StreamResult<usize> {
+        // Writes are never held back, going over the budget is detected in write.
+        Ok(64 * 1024)
+    }
+}
+
+#[wasmtime_wasi::async_trait]
+impl Pollable for CapturedOutput {
+    async fn ready(&mut self) {}
+}
+
+struct WasmState {
+    wasi: WasiP1Ctx,
+    limits: StoreLimits,
+}
+
+/// Runs code blocks inside of an embedded WASI runtime.
+///
+/// `wat` and `wasm` code blocks (WebAssembly text format) are compiled and run directly as WASI
+/// commands. Other languages need an interpreter compiled to wasm registered with
+/// [WasmCodeExecutor::with_interpreter].
+///
+/// The guest has no access to the host apart from:
+/// - `work_dir`, mounted as `/` in the guest. Source files are written there.
+/// - directories listed in [WasmInterpreter::dirs], mounted read-only.
+///
+/// Cpu time is limited with fuel - every executed instruction consumes some - which makes the
+/// limit deterministic, unlike wall-clock timeouts. From [ExecutionLimits] the timeout, output
+/// limit and memory limit are supported. The remaining ones have no meaning inside of wasm.
+pub struct WasmCodeExecutor {
+    engine: Engine,
+    work_dir: PathBuf,
+    interpreters: HashMap<String, RegisteredInterpreter>,
+    fuel: Option<u64>,
+    limits: ExecutionLimits,
+    epoch_ticker_stop: Arc<AtomicBool>,
+}
+
+impl WasmCodeExecutor {
+    pub fn new(work_dir: impl Into<PathBuf>) -> Result<Self, WasmCodeExecutorError> {
+        let mut config = Config::new();
+        config.consume_fuel(true).epoch_interruption(true);
+
+        let engine = Engine::new(&config).map_err(WasmCodeExecutorError::Wasmtime)?;
+
+        let epoch_ticker_stop = Arc::new(AtomicBool::new(false));
+
+        {
+            let engine = engine.clone();
+            let stop = epoch_ticker_stop.clone();
+
+            std::thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    std::thread::sleep(EPOCH_TICK);
+                    engine.increment_epoch();
+                }
+            });
+        }
+
+        Ok(Self {
+            engine,
+            work_dir: work_dir.into(),
+            interpreters: HashMap::new(),
+            fuel: None,
+            limits: ExecutionLimits::default(),
+            epoch_ticker_stop,
+        })
+    }
+
+    /// Compiles the interpreter module and uses it for code blocks in the given language, compared
+    /// with [CodeBlock::is_in_language].
+    pub fn with_interpreter(
+        mut self,
+        language: impl Into<String>,
+        wasm: impl AsRef<[u8]>,
+        interpreter: WasmInterpreter,
+    ) -> Result<Self, WasmCodeExecutorError> {
+        let module = Module::new(&self.engine, wasm).map_err(WasmCodeExecutorError::Wasmtime)?;
+
+        self.interpreters.insert(
+            language.into().to_lowercase(),
+            RegisteredInterpreter {
+                module,
+                interpreter,
+            },
+        );
+
+        Ok(self)
+    }
+
+    /// Amount of fuel given to every execution.
+    pub fn with_fuel(mut self, fuel: u64) -> Self {
+        self.fuel = Some(fuel);
+        self
+    }
+
+    pub fn with_limits(mut self, limits: ExecutionLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn work_dir(&self) -> &Path {
+        &self.work_dir
+    }
+
+    fn store(
+        &self,
+        args: &[String],
+        dirs: &[(PathBuf, String)],
+        stdout: &CapturedOutput,
+        stderr: &CapturedOutput,
+    ) -> Result<Store<WasmState>, WasmCodeExecutorError> {
+        let mut wasi = WasiCtxBuilder::new();
+        wasi.args(args)
+            .stdout(stdout.clone())
+            .stderr(stderr.clone())
+            .preopened_dir(&self.work_dir, "/", DirPerms::all(), FilePerms::all())
+            .map_err(WasmCodeExecutorError::Wasmtime)?;
+
+        for (host_path, guest_path) in dirs {
+            wasi.preopened_dir(host_path, guest_path, DirPerms::READ, FilePerms::READ)
+                .map_err(WasmCodeExecutorError::Wasmtime)?;
+        }
+
+        let mut limits = StoreLimitsBuilder::new();
+        if let Some(max_memory_bytes) = self.limits.max_memory_bytes {
+            limits = limits.memory_size(max_memory_bytes as usize);
+        }
+
+        let mut store = Store::new(
+            &self.engine,
+            WasmState {
+                wasi: wasi.build_p1(),
+                limits: limits.build(),
+            },
+        );
+        store.limiter(|state| &mut state.limits);
+
+        store
+            .set_fuel(self.fuel.unwrap_or(u64::MAX))
+            .map_err(WasmCodeExecutorError::Wasmtime)?;
+
+        let deadline = match self.limits.timeout {
+            Some(timeout) => timeout.as_millis().div_ceil(EPOCH_TICK.as_millis()) as u64,
+            None => u64::MAX / 2,
+        };
+        store.set_epoch_deadline(deadline);
+
+        Ok(store)
+    }
+}
+
+impl Drop for WasmCodeExecutor {
+    fn drop(&mut self) {
+        self.epoch_ticker_stop.store(true, Ordering::Relaxed);
+    }
+}
+
+impl CodeExecutor for WasmCodeExecutor {
+    type Error = WasmCodeExecutorError;
+
+    async fn execute_code_block(
+        &self,
+        code_block: &CodeBlock,
+    ) -> Result<CodeBlockExecutionResult, Self::Error> {
+        let language = code_block.language.trim().to_lowercase();
+
+        tokio::fs::create_dir_all(&self.work_dir)
+            .await
+            .map_err(WasmCodeExecutorError::Io)?;
+
+        let stem = source_file_stem();
+
+        let registered = self
+            .interpreters
+            .iter()
+            .find(|(name, _)| code_block.is_in_language(name))
+            .map(|(_, registered)| registered);
+
+        let (module, args, dirs, source_path) = match (language.as_str(), registered) {
+            (_, Some(registered)) => {
+                let source_name = format!("{}.{}", stem, language);
+                let source_path = self.work_dir.join(&source_name);
+
+                tokio::fs::write(&source_path, &code_block.code)
+                    .await
+                    .map_err(WasmCodeExecutorError::Io)?;
+
+                let guest_source = format!("/{}", source_name);
+                let args = registered
+                    .interpreter
+                    .args
+                    .iter()
+                    .map(|arg| arg.replace(SOURCE_PLACEHOLDER, &guest_source))
+                    .collect();
+
+                (
+                    registered.module.clone(),
+                    args,
+                    registered.interpreter.dirs.clone(),
+                    Some(source_path),
+                )
+            }
+            ("wat" | "wasm", None) => match Module::new(&self.engine, &code_block.code) {
+                Ok(module) => (module, vec![stem], Vec::new(), None),
+                Err(e) => {
+                    debug!("compilation failed");
+
+                    return Ok(CodeBlockExecutionResult::Failure(
+                        CodeBlockExecutionOutput {
+                            stderr: format!("{:#}", e),
+                            ..Default::default()
+                        },
+                    ));
+                }
+            },
+            (_, None) => {
+                debug!("unsupported language: {}", code_block.language);
+
+                return Ok(CodeBlockExecutionResult::Failure(
+                    CodeBlockExecutionOutput {
+                        stderr: format!("unsupported language: {}", code_block.language),
+                        ..Default::default()
+                    },
+                ));
+            }
+        };
+
+        let max_output_bytes = self.limits.max_output_bytes.unwrap_or(usize::MAX);
+        let budget = OutputBudget::new(max_output_bytes);
+        let stdout = CapturedOutput::new(budget.clone());
+        let stderr = CapturedOutput::new(budget.clone());
+
+        let mut store = self.store(&args, &dirs, &stdout, &stderr)?;
+
+        let mut linker: Linker<WasmState> = Linker::new(&self.engine);
+        wasmtime_wasi::preview1::add_to_linker_sync(&mut linker, |state: &mut WasmState| {
+            &mut state.wasi
+        })
+        .map_err(WasmCodeExecutorError::Wasmtime)?;
+
+        let files_before = FilesSnapshot::take(self.work_dir.clone())
+            .await
+            .map_err(WasmCodeExecutorError::Io)?;
+        let start = Instant::now();
+
+        let outcome = tokio::task::spawn_blocking(move || {
+            let instance = linker.instantiate(&mut store, &module)?;
+            let entry_point = instance.get_typed_func::<(), ()>(&mut store, "_start")?;
+
+            entry_point.call(&mut store, ())
+        })
+        .await
+        .map_err(|e| WasmCodeExecutorError::Io(e.into()))?;
+
+        let duration = start.elapsed();
+
+        let excluded: Vec<&Path> = source_path.iter().map(|path| path.as_path()).collect();
+        let produced_files = FilesSnapshot::take(self.work_dir.clone())
+            .await
+            .map_err(WasmCodeExecutorError::Io)?
+            .changed_since(&files_before, &excluded);
+
+        let stdout = String::from_utf8_lossy(&stdout.contents()).into_owned();
+        let mut stderr = String::from_utf8_lossy(&stderr.contents()).into_owned();
+
+        let output_exceeded = budget.is_exceeded();
+
+        let (exit_code, limit_exceeded) = match outcome {
+            Ok(()) => (Some(0), None),
+            Err(_) if output_exceeded => (None, Some(ExecutionLimit::OutputSize(max_output_bytes))),
+            Err(e) => match (e.downcast_ref::<I32Exit>(), e.downcast_ref::<Trap>()) {
+                (Some(I32Exit(code)), _) => (Some(*code), None),
+                (None, Some(Trap::OutOfFuel)) => (
+                    None,
+                    Some(ExecutionLimit::Fuel(self.fuel.unwrap_or_default())),
+                ),
+                (None, Some(Trap::Interrupt)) => (
+                    None,
+                    Some(ExecutionLimit::Timeout(
+                        self.limits.timeout.unwrap_or_default(),
+                    )),
+                ),
+                (None, _) => {
+                    debug!("execution trapped: {:?}", e);
+                    stderr.push_str(&format!("{:#}", e));
+
+                    (None, None)
+                }
+            },
+        };
+
+        let output = CodeBlockExecutionOutput {
+            exit_code,
+            stdout,
+            stderr,
+            duration,
+            truncated: output_exceeded,
+            produced_files,
+        };
+
+        match (limit_exceeded, exit_code) {
+            (Some(limit), _) => Ok(CodeBlockExecutionResult::LimitExceeded { limit, output }),
+            (None, Some(0)) => Ok(CodeBlockExecutionResult::Success(output)),
+            (None, _) => Ok(CodeBlockExecutionResult::Failure(output)),
+        }
+    }
+}
@@ -0,0 +1,7 @@
+#[derive(Debug)]
+pub enum WasmCodeExecutorError {
+    /// Creating the engine, compiling an interpreter or instantiating a module failed.
+    Wasmtime(wasmtime::Error),
+    /// Preparing the working directory or writing the source file failed.
+    Io(std::io::Error),
+}
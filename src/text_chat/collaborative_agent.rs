@@ -1,9 +1,16 @@
 use super::code::{CodeBlock, CodeBlockExecutionResult};
 
-use crate::agent_traits::{ConsumerAgent, ProducerAgent};
+use crate::agent_traits::{ConsumerAgent, ProducerAgent, TokenUsage};
 
 use super::collaborative_agent_error::CollaborativeAgentError;
 
+use super::conversation_history::{describe_reports, describe_tool_call_reports};
+use super::markdown::MarkdownResponseParser;
+
+use super::tool::{ToolCall, ToolCallReport};
+
+use serde::Serialize;
+
 /// This should correspond to the response from the LLM.
 /// User: Please write Hello World in Python and then execute it.
 ///
@@ -12,7 +19,7 @@ use super::collaborative_agent_error::CollaborativeAgentError;
 /// print("Hello World")
 /// ```
 ///
-/// Which will translate to:
+/// Which will translate to (see [super::markdown::MarkdownResponseParser]):
 ///
 /// CommentedCodeBlock {
 ///    comment: "Certainly I can write Hello World in Python. Here it is:".to_string(),
@@ -22,25 +29,128 @@ use super::collaborative_agent_error::CollaborativeAgentError;
 ///    },
 ///    request_execution: true,
 /// }
-
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct CommentedCodeBlock {
     pub comment: String,
     pub code_block: CodeBlock,
     pub request_execution: bool,
 }
 
-/// Agent may simply respond with a text message or with a code blocks.
+/// Part of a response consisting of several code blocks interleaved with text.
+#[derive(Debug, Clone, Serialize)]
+pub enum ResponseSegment {
+    Text(String),
+    CodeBlock {
+        code_block: CodeBlock,
+        request_execution: bool,
+    },
+}
 
-#[derive(Debug, Clone)]
+/// Agent may simply respond with a text message or with a code blocks.
+#[derive(Debug, Clone, Serialize)]
 pub enum CollaborativeAgentResponse {
     Text(String),
     CommentedCodeBlock(CommentedCodeBlock),
+    /// Ordered text and code, e.g. a `requirements.txt` followed by a `main.py`.
+    Segments(Vec<ResponseSegment>),
+    /// Calls of the tools registered in [super::tool::ToolRegistry].
+    ToolCalls {
+        comment: String,
+        tool_calls: Vec<ToolCall>,
+    },
 }
 
+impl CollaborativeAgentResponse {
+    /// Renders the response back as markdown, with code blocks fenced and labeled with their
+    /// language.
+    pub fn to_markdown(&self) -> String {
+        match self {
+            CollaborativeAgentResponse::Text(text) => text.clone(),
+            CollaborativeAgentResponse::CommentedCodeBlock(commented_code_block) => format!(
+                "{}\n\n{}",
+                commented_code_block.comment,
+                fenced(&commented_code_block.code_block)
+            ),
+            CollaborativeAgentResponse::Segments(segments) => segments
+                .iter()
+                .map(|segment| match segment {
+                    ResponseSegment::Text(text) => text.trim().to_string(),
+                    ResponseSegment::CodeBlock { code_block, .. } => fenced(code_block),
+                })
+                .collect::<Vec<_>>()
+                .join("\n\n"),
+            CollaborativeAgentResponse::ToolCalls {
+                comment,
+                tool_calls,
+            } => {
+                let calls = tool_calls
+                    .iter()
+                    .map(|tool_call| {
+                        format!(
+                            "Calling `{}` with `{}`",
+                            tool_call.name, tool_call.arguments
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n");
+
+                match comment.trim().is_empty() {
+                    true => calls,
+                    false => format!("{}\n\n{}", comment.trim(), calls),
+                }
+            }
+        }
+    }
+}
+
+/// Uses a fence longer than any backtick run in the code, so code containing fences stays intact.
+pub(crate) fn fenced(code_block: &CodeBlock) -> String {
+    let longest_run = code_block
+        .code
+        .split(|c| c != '`')
+        .map(str::len)
+        .max()
+        .unwrap_or_default();
+
+    let fence = "`".repeat(longest_run.max(2) + 1);
+
+    format!(
+        "{}{}\n{}\n{}",
+        fence, code_block.language, code_block.code, fence
+    )
+}
+
+/// What happened to a code block of a [CollaborativeAgentResponse::Segments] response which
+/// requested execution.
+#[derive(Debug, Clone, Serialize)]
+pub enum CodeBlockOutcome {
+    Executed(CodeBlockExecutionResult),
+    Denied {
+        reason: String,
+    },
+    /// Not executed, since an earlier code block failed or was denied.
+    Skipped,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CodeBlockReport {
+    pub code_block: CodeBlock,
+    pub outcome: CodeBlockOutcome,
+}
+
+/// Parses the markdown reply with the default [MarkdownResponseParser], so agents replying with
+/// plain strings can be used in the collaborative chat directly.
+impl From<String> for CollaborativeAgentResponse {
+    fn from(text: String) -> Self {
+        MarkdownResponseParser::default().parse(&text)
+    }
+}
+
+/// Sender of the results passed as text by the default implementations of [CollaborativeAgent].
+pub const RESULTS_SENDER: &str = "executor";
+
 /// Similarly to the [super::chat_user_agent::ChatUserAgent], I believe its a better choice to have a separate trait for
 /// this purpose and then implement it for the [ConsumerAgent] and [ProducerAgent].
-
 pub trait CollaborativeAgent {
     // Shared error should be the sufficient for both functions.
     type Error;
@@ -80,8 +190,6 @@ pub trait CollaborativeAgent {
     ///
     /// we may repeat the same process until agent decides that it no longer has an interest in
     /// destroying the world.
-    ///
-
     async fn deny_code_block_execution(
         &mut self,
         code_block: CodeBlock,
@@ -92,8 +200,79 @@ pub trait CollaborativeAgent {
         &mut self,
         code_execution_result: CodeBlockExecutionResult,
     ) -> Result<CollaborativeAgentResponse, Self::Error>;
+
+    /// Results of all the code blocks of a [CollaborativeAgentResponse::Segments] response which
+    /// requested execution, in order, sent at once. By default they are described in a text
+    /// message from [RESULTS_SENDER].
+    async fn receive_code_and_reply_to_execution_results(
+        &mut self,
+        reports: Vec<CodeBlockReport>,
+    ) -> Result<CollaborativeAgentResponse, Self::Error> {
+        self.receive_and_reply(RESULTS_SENDER.to_string(), describe_reports(&reports))
+            .await
+    }
+
+    /// Outcomes of all the calls of a [CollaborativeAgentResponse::ToolCalls] response, in order.
+    /// By default they are described in a text message from [RESULTS_SENDER].
+    async fn receive_and_reply_to_tool_call_results(
+        &mut self,
+        reports: Vec<ToolCallReport>,
+    ) -> Result<CollaborativeAgentResponse, Self::Error> {
+        self.receive_and_reply(
+            RESULTS_SENDER.to_string(),
+            describe_tool_call_reports(&reports),
+        )
+        .await
+    }
+
+    /// Passes the message without asking for a reply, e.g. what the other agents of a
+    /// [super::group_chat::GroupChat] said. Ignored by default.
+    async fn receive(&mut self, _message: Message) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Same as [ProducerAgent::usage].
+    fn token_usage(&self) -> Option<TokenUsage> {
+        None
+    }
+}
+
+/// Calls the method of the agent corresponding to the message.
+pub(crate) async fn reply_to_message<CA: CollaborativeAgent>(
+    collaborative_agent: &mut CA,
+    message: Message,
+) -> Result<CollaborativeAgentResponse, CA::Error> {
+    match message {
+        Message::Text { sender, message } => {
+            collaborative_agent.receive_and_reply(sender, message).await
+        }
+        Message::CodeExecutionDenied {
+            comment,
+            code_block,
+        } => {
+            collaborative_agent
+                .deny_code_block_execution(code_block, comment)
+                .await
+        }
+        Message::CodeExecutionResult(result) => {
+            collaborative_agent
+                .receive_code_and_reply_to_execution_result(result)
+                .await
+        }
+        Message::CodeExecutionResults(reports) => {
+            collaborative_agent
+                .receive_code_and_reply_to_execution_results(reports)
+                .await
+        }
+        Message::ToolCallResults(reports) => {
+            collaborative_agent
+                .receive_and_reply_to_tool_call_results(reports)
+                .await
+        }
+    }
 }
 
+#[derive(Debug, Clone, Serialize)]
 pub enum Message {
     Text {
         sender: String,
@@ -104,6 +283,8 @@ pub enum Message {
         code_block: CodeBlock,
     },
     CodeExecutionResult(CodeBlockExecutionResult),
+    CodeExecutionResults(Vec<CodeBlockReport>),
+    ToolCallResults(Vec<ToolCallReport>),
 }
 
 /// This may be used when the agent returns output as a string or any other type.
@@ -111,7 +292,6 @@ pub enum Message {
 /// In that case we may simply implement the communication via ConsumerAgent and ProducerAgent and
 /// then use after implementing TryFrom<Message> and TryInto<CollaborativeAgentResponse>, we may
 /// use this trait implementation in collaborative chat.
-
 impl<CA, Mrx, Mtx> CollaborativeAgent for CA
 where
     CA: ConsumerAgent<Mrx = Mrx> + ProducerAgent<Mtx = Mtx>,
@@ -157,6 +337,37 @@ where
 
         send_and_get_reply(message, self).await
     }
+
+    async fn receive_code_and_reply_to_execution_results(
+        &mut self,
+        reports: Vec<CodeBlockReport>,
+    ) -> Result<CollaborativeAgentResponse, Self::Error> {
+        let message = Message::CodeExecutionResults(reports);
+
+        send_and_get_reply(message, self).await
+    }
+
+    async fn receive_and_reply_to_tool_call_results(
+        &mut self,
+        reports: Vec<ToolCallReport>,
+    ) -> Result<CollaborativeAgentResponse, Self::Error> {
+        let message = Message::ToolCallResults(reports);
+
+        send_and_get_reply(message, self).await
+    }
+
+    async fn receive(&mut self, message: Message) -> Result<(), Self::Error> {
+        let message =
+            Mrx::try_from(message).map_err(|_| CollaborativeAgentError::TryFromMessage)?;
+
+        self.receive_message(message)
+            .await
+            .map_err(CollaborativeAgentError::Receiving)
+    }
+
+    fn token_usage(&self) -> Option<TokenUsage> {
+        self.usage()
+    }
 }
 
 /// Helper function.
@@ -1,17 +1,42 @@
 use super::chat_user_agent::CodeBlockFeedback;
-use super::collaborative_agent::{CollaborativeAgent, CollaborativeAgentResponse};
-use crate::agent_traits::NamedAgent;
+use super::collaborative_agent::{
+    reply_to_message, CodeBlockOutcome, CodeBlockReport, CollaborativeAgent,
+    CollaborativeAgentResponse, Message, ResponseSegment,
+};
+use super::collaborative_agent_error::CollaborativeAgentError;
+use crate::agent_traits::{ConsumerAgent, NamedAgent, StreamingProducerAgent, TokenUsage};
+
+use super::approval_policy::{ApprovalDecision, ApprovalPolicy};
+
+use super::budget::Budget;
+
+use super::chat_result::{ChatResult, Transcript, TranscriptEvent};
+
+use super::human_input::{AutoReply, FixedReply, HumanInputMode};
+
+use super::termination::{ChatEvent, ChatState, MetCondition, TerminationCondition};
 
 use super::chat_user_agent::ChatUserAgent;
 
-use super::code::CodeExecutor;
+use super::code::risk_analysis::{RiskAnalyzer, RiskReport};
+use super::code::{CodeBlock, CodeExecutor};
+
+use super::tool::{ToolCall, ToolCallOutcome, ToolCallReport, ToolRegistry};
 
 use tracing::debug;
 
-use super::collaborative_chat_error::CollaborativeChatError;
+use super::collaborative_chat_error::{ChatFailure, CollaborativeChatError};
 
 use tokio_util::sync::CancellationToken;
 
+use futures::StreamExt;
+
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Instant;
+
+use serde::Serialize;
+
 pub trait SystemAgent {
     fn initial_message(&self) -> String;
 }
@@ -22,17 +47,205 @@ impl<SA: SystemAgent> NamedAgent for SA {
     }
 }
 
+/// How code blocks of a [CollaborativeAgentResponse::Segments] response are approved.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExecutionMode {
+    /// Feedback is requested for every code block right before its execution.
+    #[default]
+    Sequential,
+    /// Feedback is requested once for all the code blocks, which are then executed.
+    Batch,
+}
+
+/// Whether the user agent is asked before tool calls.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ToolCallApproval {
+    /// Every call goes through [ChatUserAgent::request_tool_call_feedback].
+    #[default]
+    Required,
+    /// Tools are called right away.
+    Automatic,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CollaborativeChatOptions {
+    /// In both modes code blocks are executed in order and the execution stops at the first failed
+    /// or denied one. The remaining ones are reported as [CodeBlockOutcome::Skipped].
+    pub execution_mode: ExecutionMode,
+    /// Tools for [CollaborativeAgentResponse::ToolCalls]. Calls of tools missing here are reported
+    /// to the agent as failed.
+    pub tools: ToolRegistry,
+    pub tool_call_approval: ToolCallApproval,
+    pub budget: Budget,
+    /// The chat goes on until cancelled if there is none.
+    pub termination: Option<Arc<dyn TerminationCondition>>,
+    pub human_input_mode: HumanInputMode,
+    /// Used unless the mode is [HumanInputMode::Always], [FixedReply] if there is none.
+    pub auto_reply: Option<Arc<dyn AutoReply>>,
+    /// Decides about the execution of code blocks before the user agent is asked. Without it, the
+    /// user agent is asked about every code block.
+    pub approval_policy: Option<ApprovalPolicy>,
+    /// Its reports are passed to the approval policy and shown to the user agent along with the
+    /// code.
+    pub risk_analyzer: RiskAnalyzer,
+}
+
+/// Why the chat ended.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum TerminationReason {
+    /// The cancellation token was cancelled.
+    Cancelled,
+    /// Usage of the collaborative agent went over the [CollaborativeChatOptions::budget]. Its last
+    /// reply is passed to the user agent, but not acted upon.
+    BudgetExceeded { usage: TokenUsage },
+    /// The [CollaborativeChatOptions::termination] condition was met.
+    ConditionMet(MetCondition),
+    /// An error ended the chat, see [ChatFailure].
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ChatOutcome {
+    pub reason: TerminationReason,
+    /// Replies of the collaborative agent.
+    pub turns: usize,
+}
+
 /// Regarding Assignment requirement to provide grouping chat for collaboration.
 /// Even though this accepts a single collaborative agent, it is not a problem to create a specific implementation of an agent that would accumulate multiple collaborative agents.
-
-/// This function is the main entry point for the collaborative chat.
+/// [super::group_chat::GroupChat] is such an agent.
+///
+/// This function is the main entry point for the collaborative chat. Once it ends, the returned
+/// [ChatResult] holds its transcript. So does the [ChatFailure] if an error ends it.
 pub async fn collaborative_chat<UA, CA, SA, E>(
+    user_agent: UA,
+    collaborative_agent: CA,
+    system_agent: SA,
+    executor: E,
+    cancellation_token: CancellationToken,
+) -> Result<ChatResult, ChatFailure<UA, CA, E>>
+where
+    UA: ChatUserAgent,
+    UA: NamedAgent,
+
+    CA: CollaborativeAgent,
+    CA: NamedAgent,
+
+    SA: SystemAgent,
+
+    E: CodeExecutor,
+{
+    collaborative_chat_with_options(
+        user_agent,
+        collaborative_agent,
+        system_agent,
+        executor,
+        CollaborativeChatOptions::default(),
+        cancellation_token,
+    )
+    .await
+}
+
+/// Same as [collaborative_chat], with options.
+pub async fn collaborative_chat_with_options<UA, CA, SA, E>(
+    user_agent: UA,
+    collaborative_agent: CA,
+    system_agent: SA,
+    executor: E,
+    options: CollaborativeChatOptions,
+    cancellation_token: CancellationToken,
+) -> Result<ChatResult, ChatFailure<UA, CA, E>>
+where
+    UA: ChatUserAgent,
+    UA: NamedAgent,
+
+    CA: CollaborativeAgent,
+    CA: NamedAgent,
+
+    SA: SystemAgent,
+
+    E: CodeExecutor,
+{
+    let mut transcript = Transcript::new();
+
+    let outcome = run_chat::<UA, CA, SA, E, Complete>(
+        user_agent,
+        collaborative_agent,
+        system_agent,
+        executor,
+        options,
+        cancellation_token,
+        &mut transcript,
+    )
+    .await;
+
+    match outcome {
+        Ok(outcome) => Ok(transcript.finish(outcome)),
+        Err(error) => Err(ChatFailure {
+            error,
+            result: transcript.fail(),
+        }),
+    }
+}
+
+/// Same as [collaborative_chat_with_options], but replies of the collaborative agent are streamed -
+/// every delta is passed to [ChatUserAgent::receive_partial_text] as soon as it arrives. Once the
+/// stream ends, the reply is assembled and the chat goes on as usual.
+pub async fn streaming_collaborative_chat<UA, CA, SA, E, Mrx, Mtx>(
+    user_agent: UA,
+    collaborative_agent: CA,
+    system_agent: SA,
+    executor: E,
+    options: CollaborativeChatOptions,
+    cancellation_token: CancellationToken,
+) -> Result<ChatResult, ChatFailure<UA, CA, E>>
+where
+    UA: ChatUserAgent,
+    UA: NamedAgent,
+
+    CA: ConsumerAgent<Mrx = Mrx> + StreamingProducerAgent<Mtx = Mtx>,
+    CA: NamedAgent,
+    CA: Send,
+    CA::Delta: AsRef<str>,
+
+    Mrx: TryFrom<Message> + Send,
+    Mtx: TryInto<CollaborativeAgentResponse>,
+
+    SA: SystemAgent,
+
+    E: CodeExecutor,
+{
+    let mut transcript = Transcript::new();
+
+    let outcome = run_chat::<UA, CA, SA, E, Streamed>(
+        user_agent,
+        collaborative_agent,
+        system_agent,
+        executor,
+        options,
+        cancellation_token,
+        &mut transcript,
+    )
+    .await;
+
+    match outcome {
+        Ok(outcome) => Ok(transcript.finish(outcome)),
+        Err(error) => Err(ChatFailure {
+            error,
+            result: transcript.fail(),
+        }),
+    }
+}
+
+async fn run_chat<UA, CA, SA, E, R>(
     mut user_agent: UA,
     mut collaborative_agent: CA,
     system_agent: SA,
     executor: E,
+    options: CollaborativeChatOptions,
     cancellation_token: CancellationToken,
-) -> Result<(), CollaborativeChatError<UA, CA, E>>
+    transcript: &mut Transcript,
+) -> Result<ChatOutcome, CollaborativeChatError<UA, CA, E>>
 where
     UA: ChatUserAgent,
     UA: NamedAgent,
@@ -43,104 +256,877 @@ where
     SA: SystemAgent,
 
     E: CodeExecutor,
+
+    R: Replies<UA, CA, E>,
 {
     debug!("starting chat..");
+    check_options(&options).map_err(CollaborativeChatError::InvalidOptions)?;
+
+    let mut state = ChatState::new();
 
     debug!("sending welcome message..");
-    let ua_response = user_agent
-        .receive_and_reply(
-            system_agent.name().to_string(),
-            system_agent.initial_message(),
-        )
-        .await
-        .map_err(CollaborativeChatError::ChatUserAgent)?;
+    let initial_message = system_agent.initial_message();
+    transcript.record(TranscriptEvent::Welcome {
+        sender: system_agent.name().to_string(),
+        message: initial_message.clone(),
+    });
 
-    debug!("sending user message to collaborative_agent..");
-    let mut ca_response = collaborative_agent
-        .receive_and_reply(user_agent.name().to_string(), ua_response.clone())
-        .await
-        .map_err(CollaborativeChatError::CollaborativeAgent)?;
+    let welcome = user_agent.receive_and_reply(system_agent.name().to_string(), initial_message);
+    let ua_response = match before_deadline(&options, &state, welcome).await {
+        Ok(ua_response) => ua_response.map_err(CollaborativeChatError::ChatUserAgent)?,
+        Err(met) => return Ok(condition_met(met, &state)),
+    };
+
+    let mut message = Message::Text {
+        sender: user_agent.name().to_string(),
+        message: ua_response,
+    };
+    // Messages of the user deciding to go on are not checked.
+    let mut going_on = false;
+
+    loop {
+        transcript.record(TranscriptEvent::Message(message.clone()));
 
-    while !cancellation_token.is_cancelled() {
-        match ca_response {
-            CollaborativeAgentResponse::CommentedCodeBlock(ref commented_code_block) => {
+        let met = match going_on {
+            true => None,
+            false => check(&options, ChatEvent::Message(&message), &state),
+        };
+        going_on = false;
+
+        if let Some(met) = met {
+            match ask_to_go_on(&mut user_agent, system_agent.name(), &options, &met).await? {
+                Some(ua_response) => {
+                    message = Message::Text {
+                        sender: user_agent.name().to_string(),
+                        message: ua_response,
+                    };
+                    going_on = true;
+                    continue;
+                }
+                None => return Ok(condition_met(met, &state)),
+            }
+        }
+        state.last_activity = Instant::now();
+
+        debug!("sending message to collaborative_agent..");
+        let reply = R::reply(&mut user_agent, &mut collaborative_agent, message);
+        let ca_response = match before_deadline(&options, &state, reply).await {
+            Ok(ca_response) => ca_response?,
+            Err(met) => return Ok(condition_met(met, &state)),
+        };
+        state.turns += 1;
+
+        transcript.record(TranscriptEvent::Reply {
+            sender: collaborative_agent.name().to_string(),
+            response: ca_response.clone(),
+        });
+
+        if cancellation_token.is_cancelled() {
+            return Ok(ChatOutcome {
+                reason: TerminationReason::Cancelled,
+                turns: state.turns,
+            });
+        }
+
+        let reason = match collaborative_agent
+            .token_usage()
+            .filter(|usage| options.budget.is_exceeded(usage))
+        {
+            Some(usage) => {
+                debug!("budget exceeded. Ending chat..");
+                Some(TerminationReason::BudgetExceeded { usage })
+            }
+            None => check(&options, ChatEvent::Reply(&ca_response), &state)
+                .map(TerminationReason::ConditionMet),
+        };
+
+        if let Some(reason) = reason {
+            user_agent
+                .silent_receive_collaborative_agent_response(
+                    collaborative_agent.name().to_string(),
+                    ca_response,
+                )
+                .await
+                .map_err(CollaborativeChatError::ChatUserAgent)?;
+
+            if let TerminationReason::ConditionMet(met) = &reason {
+                if let Some(ua_response) =
+                    ask_to_go_on(&mut user_agent, system_agent.name(), &options, met).await?
+                {
+                    message = Message::Text {
+                        sender: user_agent.name().to_string(),
+                        message: ua_response,
+                    };
+                    going_on = true;
+                    continue;
+                }
+            }
+
+            return Ok(ChatOutcome {
+                reason,
+                turns: state.turns,
+            });
+        }
+        state.last_activity = Instant::now();
+
+        let next = next_message(
+            &mut user_agent,
+            collaborative_agent.name(),
+            &executor,
+            &options,
+            ca_response,
+            transcript,
+        );
+        message = match before_deadline(&options, &state, next).await {
+            Ok(message) => message?,
+            Err(met) => return Ok(condition_met(met, &state)),
+        };
+    }
+}
+
+/// Acts upon the reply of the collaborative agent, which gives the next message for it.
+async fn next_message<UA, CA, E>(
+    user_agent: &mut UA,
+    sender: &str,
+    executor: &E,
+    options: &CollaborativeChatOptions,
+    ca_response: CollaborativeAgentResponse,
+    transcript: &mut Transcript,
+) -> Result<Message, CollaborativeChatError<UA, CA, E>>
+where
+    UA: ChatUserAgent,
+    UA: NamedAgent,
+
+    CA: CollaborativeAgent,
+
+    E: CodeExecutor,
+{
+    let message = match ca_response {
+        CollaborativeAgentResponse::CommentedCodeBlock(ref commented_code_block) => {
+            // Code which is only shown reaches the user_agent in the reply phase, so it must not be
+            // shown twice.
+            if commented_code_block.request_execution
+                || options.human_input_mode != HumanInputMode::Always
+            {
                 user_agent
                     .silent_receive_collaborative_agent_response(
-                        collaborative_agent.name().to_string(),
+                        sender.to_string(),
                         ca_response.clone(),
                     )
                     .await
                     .map_err(CollaborativeChatError::ChatUserAgent)?;
+            }
 
-                match commented_code_block.request_execution {
-                    true => {
-                        debug!("code execution requested. Sending code block to user_agent..");
+            match commented_code_block.request_execution {
+                true => {
+                    debug!("code execution requested. Sending code block to user_agent..");
 
-                        let ua_feedback = user_agent
-                            .request_code_block_feedback(
-                                collaborative_agent.name().to_string(),
-                                commented_code_block.comment.clone(),
-                                commented_code_block.code_block.clone(),
-                            )
-                            .await
-                            .map_err(CollaborativeChatError::ChatUserAgent)?;
-
-                        match ua_feedback {
-                            CodeBlockFeedback::AllowExecution => {
-                                debug!("code execution allowed. Executing code..");
-
-                                let execution_result = executor
-                                    .execute_code_block(&commented_code_block.code_block)
-                                    .await
-                                    .map_err(CollaborativeChatError::CodeExecutor)?;
-
-                                debug!("sending execution result to user_agent..");
-                                user_agent
-                                    .receive_code_execution_result(execution_result.clone())
-                                    .await
-                                    .map_err(CollaborativeChatError::ChatUserAgent)?;
-
-                                debug!("sending execution result to collaborative_agent..");
-
-                                ca_response = collaborative_agent
-                                    .receive_code_and_reply_to_execution_result(execution_result)
-                                    .await
-                                    .map_err(CollaborativeChatError::CollaborativeAgent)?;
-                            }
-                            CodeBlockFeedback::DenyExecution { reason } => {
-                                debug!("code execution denied. Sending reason to collaborative_agent..");
-
-                                ca_response = collaborative_agent
-                                    .deny_code_block_execution(
-                                        commented_code_block.code_block.clone(),
-                                        reason,
-                                    )
-                                    .await
-                                    .map_err(CollaborativeChatError::CollaborativeAgent)?;
+                    let ua_feedback = code_block_feedback(
+                        user_agent,
+                        sender,
+                        options,
+                        commented_code_block.comment.clone(),
+                        &commented_code_block.code_block,
+                        transcript,
+                    )
+                    .await?;
+
+                    match ua_feedback {
+                        CodeBlockFeedback::AllowExecution => {
+                            debug!("code execution allowed. Executing code..");
+
+                            let execution_result = executor
+                                .execute_code_block(&commented_code_block.code_block)
+                                .await
+                                .map_err(CollaborativeChatError::CodeExecutor)?;
+
+                            transcript.record(TranscriptEvent::Execution {
+                                code_block: commented_code_block.code_block.clone(),
+                                result: execution_result.clone(),
+                            });
+
+                            debug!("sending execution result to user_agent..");
+                            user_agent
+                                .receive_code_execution_result(execution_result.clone())
+                                .await
+                                .map_err(CollaborativeChatError::ChatUserAgent)?;
+
+                            debug!("sending execution result to collaborative_agent..");
+                            Message::CodeExecutionResult(execution_result)
+                        }
+                        CodeBlockFeedback::DenyExecution { reason } => {
+                            debug!(
+                                "code execution denied. Sending reason to collaborative_agent.."
+                            );
+
+                            Message::CodeExecutionDenied {
+                                comment: reason,
+                                code_block: commented_code_block.code_block.clone(),
                             }
                         }
                     }
-                    false => {
-                        debug!("code execution not requested. Skipping feedback phase..");
-                        todo!()
+                }
+                false => {
+                    debug!("code execution not requested. Skipping feedback phase..");
+
+                    // The code is only shown, so it is passed to the user_agent like any other
+                    // message and the conversation goes on with the user's reply.
+                    let ua_response =
+                        reply_to_text(user_agent, sender, options, ca_response.to_markdown())
+                            .await?;
+
+                    debug!("sending user_agent response to collaborative_agent..");
+                    Message::Text {
+                        sender: user_agent.name().to_string(),
+                        message: ua_response,
                     }
                 }
             }
-            CollaborativeAgentResponse::Text(text) => {
-                debug!("sending text to user_agent..");
-                let ua_response = user_agent
-                    .receive_and_reply(collaborative_agent.name().to_string(), text)
+        }
+        CollaborativeAgentResponse::Segments(ref segments) => {
+            let requested = requested_code_blocks(segments);
+
+            if !requested.is_empty() || options.human_input_mode != HumanInputMode::Always {
+                user_agent
+                    .silent_receive_collaborative_agent_response(
+                        sender.to_string(),
+                        ca_response.clone(),
+                    )
                     .await
                     .map_err(CollaborativeChatError::ChatUserAgent)?;
+            }
+
+            match requested.is_empty() {
+                false => {
+                    let reports = match options.execution_mode {
+                        ExecutionMode::Sequential => {
+                            debug!("code execution requested. Asking for feedback on each code block..");
+                            execute_sequentially(
+                                user_agent, sender, executor, options, requested, transcript,
+                            )
+                            .await?
+                        }
+                        ExecutionMode::Batch => {
+                            debug!("code execution requested. Asking for feedback on all code blocks..");
+                            execute_batch(
+                                user_agent,
+                                sender,
+                                executor,
+                                options,
+                                segments.clone(),
+                                requested,
+                                transcript,
+                            )
+                            .await?
+                        }
+                    };
+
+                    debug!("sending execution results to collaborative_agent..");
+                    Message::CodeExecutionResults(reports)
+                }
+                true => {
+                    debug!(
+                        "code execution not requested. Sending segments as text to user_agent.."
+                    );
+                    let ua_response =
+                        reply_to_text(user_agent, sender, options, ca_response.to_markdown())
+                            .await?;
 
-                debug!("sending user_agent response to collaborative_agent..");
-                ca_response = collaborative_agent
-                    .receive_and_reply(user_agent.name().to_string(), ua_response)
+                    debug!("sending user_agent response to collaborative_agent..");
+                    Message::Text {
+                        sender: user_agent.name().to_string(),
+                        message: ua_response,
+                    }
+                }
+            }
+        }
+        CollaborativeAgentResponse::ToolCalls { ref tool_calls, .. } => {
+            user_agent
+                .silent_receive_collaborative_agent_response(
+                    sender.to_string(),
+                    ca_response.clone(),
+                )
+                .await
+                .map_err(CollaborativeChatError::ChatUserAgent)?;
+
+            debug!("tool calls requested..");
+            let reports =
+                call_tools(user_agent, sender, options, tool_calls.clone(), transcript).await?;
+
+            debug!("sending tool call results to collaborative_agent..");
+            Message::ToolCallResults(reports)
+        }
+        CollaborativeAgentResponse::Text(ref text) => {
+            debug!("sending text to user_agent..");
+            if options.human_input_mode != HumanInputMode::Always {
+                user_agent
+                    .silent_receive_collaborative_agent_response(
+                        sender.to_string(),
+                        ca_response.clone(),
+                    )
                     .await
-                    .map_err(CollaborativeChatError::CollaborativeAgent)?;
+                    .map_err(CollaborativeChatError::ChatUserAgent)?;
+            }
+
+            let ua_response = reply_to_text(user_agent, sender, options, text.clone()).await?;
+
+            debug!("sending user_agent response to collaborative_agent..");
+            Message::Text {
+                sender: user_agent.name().to_string(),
+                message: ua_response,
             }
         }
+    };
+
+    Ok(message)
+}
+
+/// Reply of the user agent, or the auto reply in the modes other than [HumanInputMode::Always].
+async fn reply_to_text<UA, CA, E>(
+    user_agent: &mut UA,
+    sender: &str,
+    options: &CollaborativeChatOptions,
+    text: String,
+) -> Result<String, CollaborativeChatError<UA, CA, E>>
+where
+    UA: ChatUserAgent,
+    CA: CollaborativeAgent,
+    E: CodeExecutor,
+{
+    match options.human_input_mode {
+        HumanInputMode::Always => user_agent
+            .receive_and_reply(sender.to_string(), text)
+            .await
+            .map_err(CollaborativeChatError::ChatUserAgent),
+        HumanInputMode::Terminate | HumanInputMode::Never => {
+            debug!("replying automatically..");
+
+            let reply = match &options.auto_reply {
+                Some(auto_reply) => auto_reply.reply(sender, &text),
+                None => FixedReply::default().reply(sender, &text),
+            };
+            Ok(reply)
+        }
+    }
+}
+
+/// In [HumanInputMode::Terminate] the user agent decides whether the chat ends once the condition
+/// is met. Gives its reply if the chat goes on.
+async fn ask_to_go_on<UA, CA, E>(
+    user_agent: &mut UA,
+    sender: &str,
+    options: &CollaborativeChatOptions,
+    met: &MetCondition,
+) -> Result<Option<String>, CollaborativeChatError<UA, CA, E>>
+where
+    UA: ChatUserAgent,
+    CA: CollaborativeAgent,
+    E: CodeExecutor,
+{
+    if options.human_input_mode != HumanInputMode::Terminate {
+        return Ok(None);
+    }
+
+    debug!("termination condition met. Asking user_agent whether to go on..");
+    let ua_response = user_agent
+        .receive_and_reply(
+            sender.to_string(),
+            format!(
+                "The chat is about to end: {}. Reply to go on, or reply with nothing or `exit` to end it.",
+                met
+            ),
+        )
+        .await
+        .map_err(CollaborativeChatError::ChatUserAgent)?;
+
+    let answer = ua_response.trim();
+    match answer.is_empty() || answer.eq_ignore_ascii_case("exit") {
+        true => Ok(None),
+        false => Ok(Some(ua_response)),
+    }
+}
+
+fn check(
+    options: &CollaborativeChatOptions,
+    event: ChatEvent<'_>,
+    state: &ChatState,
+) -> Option<MetCondition> {
+    options
+        .termination
+        .as_ref()
+        .and_then(|termination| termination.check(event, state))
+}
+
+/// Waits for the future, unless the deadline of the termination condition passes first.
+async fn before_deadline<F: Future>(
+    options: &CollaborativeChatOptions,
+    state: &ChatState,
+    future: F,
+) -> Result<F::Output, MetCondition> {
+    let deadline = options
+        .termination
+        .as_ref()
+        .and_then(|termination| termination.deadline(state));
+
+    match deadline {
+        Some((deadline, met)) => {
+            tokio::time::timeout_at(tokio::time::Instant::from_std(deadline), future)
+                .await
+                .map_err(|_| met)
+        }
+        None => Ok(future.await),
+    }
+}
+
+fn condition_met(met: MetCondition, state: &ChatState) -> ChatOutcome {
+    debug!("termination condition met. Ending chat..");
+
+    ChatOutcome {
+        reason: TerminationReason::ConditionMet(met),
+        turns: state.turns,
+    }
+}
+
+/// How the chat obtains replies of the collaborative agent.
+trait Replies<UA, CA, E>
+where
+    UA: ChatUserAgent,
+    CA: CollaborativeAgent,
+    E: CodeExecutor,
+{
+    async fn reply(
+        user_agent: &mut UA,
+        collaborative_agent: &mut CA,
+        message: Message,
+    ) -> Result<CollaborativeAgentResponse, CollaborativeChatError<UA, CA, E>>;
+}
+
+/// Replies come whole, through the [CollaborativeAgent] methods.
+struct Complete;
+
+/// Replies are streamed through [StreamingProducerAgent] and shown to the user agent piece by
+/// piece.
+struct Streamed;
+
+impl<UA, CA, E> Replies<UA, CA, E> for Complete
+where
+    UA: ChatUserAgent,
+    CA: CollaborativeAgent,
+    E: CodeExecutor,
+{
+    async fn reply(
+        _user_agent: &mut UA,
+        collaborative_agent: &mut CA,
+        message: Message,
+    ) -> Result<CollaborativeAgentResponse, CollaborativeChatError<UA, CA, E>> {
+        reply_to_message(collaborative_agent, message)
+            .await
+            .map_err(CollaborativeChatError::CollaborativeAgent)
+    }
+}
+
+impl<UA, CA, E, Mrx, Mtx> Replies<UA, CA, E> for Streamed
+where
+    UA: ChatUserAgent,
+
+    CA: ConsumerAgent<Mrx = Mrx> + StreamingProducerAgent<Mtx = Mtx>,
+    CA: NamedAgent,
+    CA: Send,
+    CA::Delta: AsRef<str>,
+
+    Mrx: TryFrom<Message> + Send,
+    Mtx: TryInto<CollaborativeAgentResponse>,
+
+    E: CodeExecutor,
+{
+    async fn reply(
+        user_agent: &mut UA,
+        collaborative_agent: &mut CA,
+        message: Message,
+    ) -> Result<CollaborativeAgentResponse, CollaborativeChatError<UA, CA, E>> {
+        let message = Mrx::try_from(message).map_err(|_| {
+            CollaborativeChatError::CollaborativeAgent(CollaborativeAgentError::TryFromMessage)
+        })?;
+
+        collaborative_agent
+            .receive_message(message)
+            .await
+            .map_err(|e| {
+                CollaborativeChatError::CollaborativeAgent(CollaborativeAgentError::Receiving(e))
+            })?;
+
+        let sender = collaborative_agent.name().to_string();
+
+        debug!("streaming reply of collaborative_agent to user_agent..");
+        let mut deltas = Vec::new();
+        {
+            let mut stream = std::pin::pin!(collaborative_agent.send_message_stream());
+
+            while let Some(delta) = stream.next().await {
+                let delta = delta.map_err(|e| {
+                    CollaborativeChatError::CollaborativeAgent(CollaborativeAgentError::Sending(e))
+                })?;
+
+                user_agent
+                    .receive_partial_text(sender.clone(), delta.as_ref().to_string())
+                    .await
+                    .map_err(CollaborativeChatError::ChatUserAgent)?;
+
+                deltas.push(delta);
+            }
+        }
+
+        let reply = collaborative_agent.assemble_message(deltas).map_err(|e| {
+            CollaborativeChatError::CollaborativeAgent(CollaborativeAgentError::Sending(e))
+        })?;
+
+        reply.try_into().map_err(|_| {
+            CollaborativeChatError::CollaborativeAgent(CollaborativeAgentError::TryIntoString)
+        })
+    }
+}
+
+/// Code blocks requesting execution along with the text preceding them, which serves as their
+/// comment.
+fn requested_code_blocks(segments: &[ResponseSegment]) -> Vec<(String, CodeBlock)> {
+    let mut comment = Vec::new();
+    let mut requested = Vec::new();
+
+    for segment in segments {
+        match segment {
+            ResponseSegment::Text(text) => comment.push(text.trim()),
+            ResponseSegment::CodeBlock {
+                code_block,
+                request_execution,
+            } => {
+                if *request_execution {
+                    requested.push((comment.join("\n\n"), code_block.clone()));
+                }
+                comment.clear();
+            }
+        }
+    }
+
+    requested
+}
+
+async fn execute_sequentially<UA, CA, E>(
+    user_agent: &mut UA,
+    sender: &str,
+    executor: &E,
+    options: &CollaborativeChatOptions,
+    requested: Vec<(String, CodeBlock)>,
+    transcript: &mut Transcript,
+) -> Result<Vec<CodeBlockReport>, CollaborativeChatError<UA, CA, E>>
+where
+    UA: ChatUserAgent,
+    CA: CollaborativeAgent,
+    E: CodeExecutor,
+{
+    let mut reports = Vec::new();
+    let mut stopped = false;
+
+    for (comment, code_block) in requested {
+        let outcome = match stopped {
+            true => CodeBlockOutcome::Skipped,
+            false => {
+                let ua_feedback = code_block_feedback(
+                    user_agent,
+                    sender,
+                    options,
+                    comment,
+                    &code_block,
+                    transcript,
+                )
+                .await?;
+
+                match ua_feedback {
+                    CodeBlockFeedback::AllowExecution => {
+                        execute(user_agent, executor, &code_block, transcript).await?
+                    }
+                    CodeBlockFeedback::DenyExecution { reason } => {
+                        debug!("code execution denied.");
+                        CodeBlockOutcome::Denied { reason }
+                    }
+                }
+            }
+        };
+
+        stopped = stopped || !executed_successfully(&outcome);
+        reports.push(CodeBlockReport {
+            code_block,
+            outcome,
+        });
+    }
+
+    Ok(reports)
+}
+
+async fn execute_batch<UA, CA, E>(
+    user_agent: &mut UA,
+    sender: &str,
+    executor: &E,
+    options: &CollaborativeChatOptions,
+    segments: Vec<ResponseSegment>,
+    requested: Vec<(String, CodeBlock)>,
+    transcript: &mut Transcript,
+) -> Result<Vec<CodeBlockReport>, CollaborativeChatError<UA, CA, E>>
+where
+    UA: ChatUserAgent,
+    CA: CollaborativeAgent,
+    E: CodeExecutor,
+{
+    let code_blocks: Vec<CodeBlock> = requested
+        .iter()
+        .map(|(_, code_block)| code_block.clone())
+        .collect();
+
+    let risk_reports: Vec<RiskReport> = code_blocks
+        .iter()
+        .map(|code_block| options.risk_analyzer.analyze(code_block))
+        .collect();
+
+    let decision = match &options.approval_policy {
+        Some(approval_policy) => {
+            let decision = approval_policy.decide_all(code_blocks.iter().zip(&risk_reports));
+
+            transcript.record(TranscriptEvent::ApprovalDecision {
+                code_blocks: code_blocks.clone(),
+                decision: decision.clone(),
+            });
+            decision
+        }
+        None => ApprovalDecision::Escalate,
+    };
+
+    let ua_feedback = match decision {
+        ApprovalDecision::Allow => CodeBlockFeedback::AllowExecution,
+        ApprovalDecision::Deny { reason } => CodeBlockFeedback::DenyExecution { reason },
+        ApprovalDecision::Escalate if options.human_input_mode == HumanInputMode::Never => {
+            CodeBlockFeedback::DenyExecution {
+                reason: UNATTENDED_DENIAL.to_string(),
+            }
+        }
+        ApprovalDecision::Escalate => {
+            let ua_feedback = user_agent
+                .request_code_blocks_feedback(sender.to_string(), segments, risk_reports)
+                .await
+                .map_err(CollaborativeChatError::ChatUserAgent)?;
+
+            transcript.record(TranscriptEvent::CodeBlocksFeedback {
+                code_blocks,
+                feedback: ua_feedback.clone(),
+            });
+            ua_feedback
+        }
+    };
+
+    let reason = match ua_feedback {
+        CodeBlockFeedback::AllowExecution => None,
+        CodeBlockFeedback::DenyExecution { reason } => {
+            debug!("code execution denied.");
+            Some(reason)
+        }
+    };
+
+    let mut reports = Vec::new();
+    let mut stopped = false;
+
+    for (_, code_block) in requested {
+        let outcome = match (&reason, stopped) {
+            (Some(reason), _) => CodeBlockOutcome::Denied {
+                reason: reason.clone(),
+            },
+            (None, true) => CodeBlockOutcome::Skipped,
+            (None, false) => execute(user_agent, executor, &code_block, transcript).await?,
+        };
+
+        stopped = stopped || !executed_successfully(&outcome);
+        reports.push(CodeBlockReport {
+            code_block,
+            outcome,
+        });
+    }
+
+    Ok(reports)
+}
+
+/// Reason given to the collaborative agent for code escalated in [HumanInputMode::Never].
+const UNATTENDED_DENIAL: &str = "Nobody is available to approve this code.";
+
+/// [HumanInputMode::Never] needs something deciding about the code and tool calls in place of the
+/// user agent.
+fn check_options(options: &CollaborativeChatOptions) -> Result<(), String> {
+    if options.human_input_mode != HumanInputMode::Never {
+        return Ok(());
+    }
+
+    if options.approval_policy.is_none() {
+        return Err("HumanInputMode::Never requires an approval policy".to_string());
+    }
+
+    if !options.tools.is_empty() && options.tool_call_approval == ToolCallApproval::Required {
+        return Err("HumanInputMode::Never requires ToolCallApproval::Automatic".to_string());
     }
 
     Ok(())
 }
+
+/// Feedback decided by the approval policy, the user agent is asked only if the policy escalates it
+/// or there is no policy.
+async fn code_block_feedback<UA, CA, E>(
+    user_agent: &mut UA,
+    sender: &str,
+    options: &CollaborativeChatOptions,
+    comment: String,
+    code_block: &CodeBlock,
+    transcript: &mut Transcript,
+) -> Result<CodeBlockFeedback, CollaborativeChatError<UA, CA, E>>
+where
+    UA: ChatUserAgent,
+    CA: CollaborativeAgent,
+    E: CodeExecutor,
+{
+    let risk_report = options.risk_analyzer.analyze(code_block);
+
+    let decision = match &options.approval_policy {
+        Some(approval_policy) => {
+            let decision = approval_policy.decide(code_block, &risk_report);
+
+            transcript.record(TranscriptEvent::ApprovalDecision {
+                code_blocks: vec![code_block.clone()],
+                decision: decision.clone(),
+            });
+            decision
+        }
+        None => ApprovalDecision::Escalate,
+    };
+
+    match decision {
+        ApprovalDecision::Allow => {
+            debug!("code execution allowed by the policy.");
+            Ok(CodeBlockFeedback::AllowExecution)
+        }
+        ApprovalDecision::Deny { reason } => {
+            debug!("code execution denied by the policy.");
+            Ok(CodeBlockFeedback::DenyExecution { reason })
+        }
+        ApprovalDecision::Escalate if options.human_input_mode == HumanInputMode::Never => {
+            debug!("code execution escalated, but there is nobody to ask.");
+            Ok(CodeBlockFeedback::DenyExecution {
+                reason: UNATTENDED_DENIAL.to_string(),
+            })
+        }
+        ApprovalDecision::Escalate => {
+            let ua_feedback = user_agent
+                .request_code_block_feedback_with_risks(
+                    sender.to_string(),
+                    comment,
+                    code_block.clone(),
+                    risk_report,
+                )
+                .await
+                .map_err(CollaborativeChatError::ChatUserAgent)?;
+
+            transcript.record(TranscriptEvent::CodeBlockFeedback {
+                code_block: code_block.clone(),
+                feedback: ua_feedback.clone(),
+            });
+            Ok(ua_feedback)
+        }
+    }
+}
+
+/// Executes the code block and passes the result to the user agent.
+async fn execute<UA, CA, E>(
+    user_agent: &mut UA,
+    executor: &E,
+    code_block: &CodeBlock,
+    transcript: &mut Transcript,
+) -> Result<CodeBlockOutcome, CollaborativeChatError<UA, CA, E>>
+where
+    UA: ChatUserAgent,
+    CA: CollaborativeAgent,
+    E: CodeExecutor,
+{
+    debug!("code execution allowed. Executing code..");
+
+    let execution_result = executor
+        .execute_code_block(code_block)
+        .await
+        .map_err(CollaborativeChatError::CodeExecutor)?;
+
+    transcript.record(TranscriptEvent::Execution {
+        code_block: code_block.clone(),
+        result: execution_result.clone(),
+    });
+
+    debug!("sending execution result to user_agent..");
+    user_agent
+        .receive_code_execution_result(execution_result.clone())
+        .await
+        .map_err(CollaborativeChatError::ChatUserAgent)?;
+
+    Ok(CodeBlockOutcome::Executed(execution_result))
+}
+
+/// Calls the tools one by one. Errors of the tools are reported to the agent rather than ending the
+/// chat.
+async fn call_tools<UA, CA, E>(
+    user_agent: &mut UA,
+    sender: &str,
+    options: &CollaborativeChatOptions,
+    tool_calls: Vec<ToolCall>,
+    transcript: &mut Transcript,
+) -> Result<Vec<ToolCallReport>, CollaborativeChatError<UA, CA, E>>
+where
+    UA: ChatUserAgent,
+    CA: CollaborativeAgent,
+    E: CodeExecutor,
+{
+    let mut reports = Vec::new();
+
+    for tool_call in tool_calls {
+        let ua_feedback = match options.tool_call_approval {
+            ToolCallApproval::Required => {
+                let ua_feedback = user_agent
+                    .request_tool_call_feedback(sender.to_string(), tool_call.clone())
+                    .await
+                    .map_err(CollaborativeChatError::ChatUserAgent)?;
+
+                transcript.record(TranscriptEvent::ToolCallFeedback {
+                    tool_call: tool_call.clone(),
+                    feedback: ua_feedback.clone(),
+                });
+
+                ua_feedback
+            }
+            ToolCallApproval::Automatic => CodeBlockFeedback::AllowExecution,
+        };
+
+        let outcome = match ua_feedback {
+            CodeBlockFeedback::AllowExecution => match options.tools.call(&tool_call).await {
+                Ok(value) => ToolCallOutcome::Returned(value),
+                Err(e) => {
+                    debug!("tool call failed: {}", e);
+                    ToolCallOutcome::Failed(e.to_string())
+                }
+            },
+            CodeBlockFeedback::DenyExecution { reason } => {
+                debug!("tool call denied.");
+                ToolCallOutcome::Denied { reason }
+            }
+        };
+
+        reports.push(ToolCallReport { tool_call, outcome });
+    }
+
+    Ok(reports)
+}
+
+fn executed_successfully(outcome: &CodeBlockOutcome) -> bool {
+    match outcome {
+        CodeBlockOutcome::Executed(result) => result.is_success(),
+        CodeBlockOutcome::Denied { .. } | CodeBlockOutcome::Skipped => false,
+    }
+}
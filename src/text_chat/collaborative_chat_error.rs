@@ -1,3 +1,4 @@
+use super::chat_result::ChatResult;
 use super::chat_user_agent::ChatUserAgent;
 use super::collaborative_agent::CollaborativeAgent;
 
@@ -14,6 +15,9 @@ where
     ChatUserAgent(UA::Error),
     CollaborativeAgent(CA::Error),
     CodeExecutor(E::Error),
+    /// The options do not work together, e.g. [super::human_input::HumanInputMode::Never] without
+    /// an approval policy. Reported before the chat starts.
+    InvalidOptions(String),
 }
 
 impl<UA, CA, E> std::fmt::Debug for CollaborativeChatError<UA, CA, E>
@@ -38,6 +42,43 @@ where
             CollaborativeChatError::CodeExecutor(e) => {
                 write!(f, "CodeExecutor({:?})", e)
             }
+            CollaborativeChatError::InvalidOptions(reason) => {
+                write!(f, "InvalidOptions({:?})", reason)
+            }
         }
     }
 }
+
+/// Error which ended the chat, along with what happened until then.
+pub struct ChatFailure<UA, CA, E>
+where
+    UA: ChatUserAgent,
+
+    CA: CollaborativeAgent,
+
+    E: CodeExecutor,
+{
+    pub error: CollaborativeChatError<UA, CA, E>,
+    /// Transcript up to the error, its reason is
+    /// [super::collaborative_chat::TerminationReason::Failed].
+    pub result: ChatResult,
+}
+
+impl<UA, CA, E> std::fmt::Debug for ChatFailure<UA, CA, E>
+where
+    UA: ChatUserAgent,
+    <UA as ChatUserAgent>::Error: std::fmt::Debug,
+
+    CA: CollaborativeAgent,
+    <CA as CollaborativeAgent>::Error: std::fmt::Debug,
+
+    E: CodeExecutor,
+    <E as CodeExecutor>::Error: std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChatFailure")
+            .field("error", &self.error)
+            .field("result", &self.result)
+            .finish()
+    }
+}
@@ -0,0 +1,621 @@
+//! History of the conversation kept by the LLM backends, along with strategies keeping what is sent
+//! to the model within its context window.
+
+use super::code::{CodeBlock, CodeBlockExecutionResult};
+use super::collaborative_agent::{
+    fenced, CodeBlockOutcome, CodeBlockReport, CollaborativeAgentResponse, Message, ResponseSegment,
+};
+use super::tokenizer::{EstimatingTokenizer, Tokenizer};
+use super::tool::{ToolCall, ToolCallOutcome, ToolCallReport};
+
+use std::borrow::Cow;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+
+use serde::{Deserialize, Serialize};
+
+use tracing::debug;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+}
+
+/// A single turn of the conversation.
+#[derive(Debug, Clone)]
+pub struct HistoryEntry {
+    pub role: Role,
+    /// Name of the participant, for chats with more than one user.
+    pub sender: Option<String>,
+    /// What the model sees. Code related messages are described in plain text.
+    pub content: String,
+    /// Code written by the assistant, or the code whose execution was denied.
+    pub code_blocks: Vec<CodeBlock>,
+    pub execution_results: Vec<CodeBlockExecutionResult>,
+    /// Tools the assistant asked to call. Backends with native tool calling send them as such.
+    pub tool_calls: Vec<ToolCall>,
+    /// Outcomes of the tool calls, described in the content as well.
+    pub tool_call_reports: Vec<ToolCallReport>,
+    pub timestamp: SystemTime,
+    /// Tokens of the content, if known - reported by the model for its replies, or counted by the
+    /// tokenizer of the history.
+    pub tokens: Option<usize>,
+}
+
+impl HistoryEntry {
+    pub fn new(role: Role, content: impl Into<String>) -> Self {
+        Self {
+            role,
+            sender: None,
+            content: content.into(),
+            code_blocks: Vec::new(),
+            execution_results: Vec::new(),
+            tool_calls: Vec::new(),
+            tool_call_reports: Vec::new(),
+            timestamp: SystemTime::now(),
+            tokens: None,
+        }
+    }
+
+    pub fn with_sender(mut self, sender: impl Into<String>) -> Self {
+        self.sender = Some(sender.into());
+        self
+    }
+
+    pub fn with_tokens(mut self, tokens: usize) -> Self {
+        self.tokens = Some(tokens);
+        self
+    }
+
+    /// Turns the message of the chat into a user turn, so the model knows what happened to its
+    /// code.
+    pub fn from_message(message: Message) -> Self {
+        match message {
+            Message::Text { sender, message } => Self::new(Role::User, message).with_sender(sender),
+            Message::CodeExecutionDenied {
+                comment,
+                code_block,
+            } => Self {
+                code_blocks: vec![code_block.clone()],
+                ..Self::new(
+                    Role::User,
+                    format!(
+                        "Execution of the code was denied:\n{}\n\nReason: {}",
+                        fenced(&code_block),
+                        comment
+                    ),
+                )
+            },
+            Message::CodeExecutionResult(result) => Self {
+                execution_results: vec![result.clone()],
+                ..Self::new(Role::User, describe_execution_result(&result))
+            },
+            Message::CodeExecutionResults(reports) => Self {
+                execution_results: reports
+                    .iter()
+                    .filter_map(|report| match &report.outcome {
+                        CodeBlockOutcome::Executed(result) => Some(result.clone()),
+                        CodeBlockOutcome::Denied { .. } | CodeBlockOutcome::Skipped => None,
+                    })
+                    .collect(),
+                ..Self::new(Role::User, describe_reports(&reports))
+            },
+            Message::ToolCallResults(reports) => Self {
+                tool_call_reports: reports.clone(),
+                ..Self::new(Role::User, describe_tool_call_reports(&reports))
+            },
+        }
+    }
+
+    /// Assistant turn with the raw `content` of the reply and the code blocks of its parsed form.
+    pub fn from_reply(content: impl Into<String>, reply: &CollaborativeAgentResponse) -> Self {
+        let code_blocks = match reply {
+            CollaborativeAgentResponse::CommentedCodeBlock(commented_code_block) => {
+                vec![commented_code_block.code_block.clone()]
+            }
+            CollaborativeAgentResponse::Segments(segments) => segments
+                .iter()
+                .filter_map(|segment| match segment {
+                    ResponseSegment::CodeBlock { code_block, .. } => Some(code_block.clone()),
+                    ResponseSegment::Text(_) => None,
+                })
+                .collect(),
+            CollaborativeAgentResponse::Text(_) | CollaborativeAgentResponse::ToolCalls { .. } => {
+                Vec::new()
+            }
+        };
+
+        let tool_calls = match reply {
+            CollaborativeAgentResponse::ToolCalls { tool_calls, .. } => tool_calls.clone(),
+            _ => Vec::new(),
+        };
+
+        Self {
+            code_blocks,
+            tool_calls,
+            ..Self::new(Role::Assistant, content)
+        }
+    }
+}
+
+/// Decides which part of the history is sent to the model.
+///
+/// Implemented for closures as well, so custom strategies do not need a type of their own. The
+/// strategies of this module never start the kept entries with an assistant turn or with results of
+/// tool calls made before them, since the APIs reject such conversations.
+pub trait TruncationStrategy: Send + Sync {
+    /// `entries` is the whole history, oldest first. Returned entries are sent in order.
+    fn truncate(&self, entries: &[HistoryEntry]) -> Vec<HistoryEntry>;
+}
+
+impl<F> TruncationStrategy for F
+where
+    F: Fn(&[HistoryEntry]) -> Vec<HistoryEntry> + Send + Sync,
+{
+    fn truncate(&self, entries: &[HistoryEntry]) -> Vec<HistoryEntry> {
+        self(entries)
+    }
+}
+
+/// Last `max_entries` entries, along with the system entries before them. The window is moved to
+/// the start of a user turn, so it may hold fewer entries.
+#[derive(Debug, Clone, Copy)]
+pub struct SlidingWindow {
+    pub max_entries: usize,
+}
+
+impl TruncationStrategy for SlidingWindow {
+    fn truncate(&self, entries: &[HistoryEntry]) -> Vec<HistoryEntry> {
+        let first_kept = match self.max_entries {
+            0 => entries.len(),
+            max_entries => turn_start(entries, entries.len().saturating_sub(max_entries)),
+        };
+
+        keep_system_and_from(entries, first_kept)
+    }
+}
+
+/// System entries and the last `n` of the others, moved to the start of a user turn.
+#[derive(Debug, Clone, Copy)]
+pub struct KeepSystemAndLastN {
+    pub n: usize,
+}
+
+impl TruncationStrategy for KeepSystemAndLastN {
+    fn truncate(&self, entries: &[HistoryEntry]) -> Vec<HistoryEntry> {
+        keep_system_and_from(entries, first_kept(entries, self.n))
+    }
+}
+
+type TokenCounter = Arc<dyn Fn(&str) -> usize + Send + Sync>;
+
+/// System entries and as many of the latest other entries as fit in `max_tokens`. The latest turn
+/// is always kept, even if it does not fit on its own.
+///
+/// Entries with known [HistoryEntry::tokens] are taken as they are, the others are counted.
+#[derive(Clone)]
+pub struct TokenBudget {
+    max_tokens: usize,
+    counter: TokenCounter,
+}
+
+impl TokenBudget {
+    /// Tokens are estimated with the [EstimatingTokenizer] unless another tokenizer or a counter is
+    /// set.
+    pub fn new(max_tokens: usize) -> Self {
+        Self {
+            max_tokens,
+            counter: Arc::new(|content| EstimatingTokenizer.count_tokens(content)),
+        }
+    }
+
+    /// Counts the tokens of the content of a single entry.
+    pub fn with_counter(mut self, counter: impl Fn(&str) -> usize + Send + Sync + 'static) -> Self {
+        self.counter = Arc::new(counter);
+        self
+    }
+
+    /// Counts the tokens with the tokenizer of the model.
+    pub fn with_tokenizer(self, tokenizer: impl Tokenizer + 'static) -> Self {
+        self.with_counter(move |content| tokenizer.count_tokens(content))
+    }
+}
+
+impl TruncationStrategy for TokenBudget {
+    fn truncate(&self, entries: &[HistoryEntry]) -> Vec<HistoryEntry> {
+        let count = |entry: &HistoryEntry| {
+            entry
+                .tokens
+                .unwrap_or_else(|| (self.counter)(&entry.content))
+        };
+
+        let mut used: usize = entries
+            .iter()
+            .filter(|entry| entry.role == Role::System)
+            .map(count)
+            .sum();
+
+        let mut kept = 0;
+        for entry in entries
+            .iter()
+            .rev()
+            .filter(|entry| entry.role != Role::System)
+        {
+            used += count(entry);
+
+            if used > self.max_tokens && kept > 0 {
+                break;
+            }
+
+            kept += 1;
+        }
+
+        KeepSystemAndLastN { n: kept }.truncate(entries)
+    }
+}
+
+impl std::fmt::Debug for TokenBudget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TokenBudget")
+            .field("max_tokens", &self.max_tokens)
+            .finish_non_exhaustive()
+    }
+}
+
+type Summarizer = Arc<dyn Fn(Vec<HistoryEntry>) -> BoxFuture<'static, String> + Send + Sync>;
+
+/// Replaces the older entries with a system entry summarizing them, see
+/// [ConversationHistory::with_summarize].
+///
+/// Once more than `max_entries` entries (other than system ones) are not summarized yet, all but
+/// the last `keep_last` of them are passed to the summarizer, preceded by the previous summary if
+/// there is one. So an expensive summarizer (e.g. asking a model) runs once in a while, not on
+/// every request.
+#[derive(Clone)]
+pub struct Summarize {
+    keep_last: usize,
+    max_entries: usize,
+    summarizer: Summarizer,
+}
+
+impl Summarize {
+    /// Summarizes once more than twice `keep_last` entries are not summarized, unless set
+    /// otherwise with [Summarize::with_max_entries].
+    pub fn new<F, Fut>(keep_last: usize, summarizer: F) -> Self
+    where
+        F: Fn(Vec<HistoryEntry>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = String> + Send + 'static,
+    {
+        Self {
+            keep_last,
+            max_entries: keep_last * 2,
+            summarizer: Arc::new(move |entries| summarizer(entries).boxed()),
+        }
+    }
+
+    /// Not less than `keep_last`.
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries.max(self.keep_last);
+        self
+    }
+}
+
+impl std::fmt::Debug for Summarize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Summarize")
+            .field("keep_last", &self.keep_last)
+            .field("max_entries", &self.max_entries)
+            .finish_non_exhaustive()
+    }
+}
+
+fn count_tokens(entry: &mut HistoryEntry, tokenizer: &dyn Tokenizer) {
+    if entry.tokens.is_none() {
+        entry.tokens = Some(tokenizer.count_tokens(&entry.content));
+    }
+}
+
+/// Index of the first of the last `n` entries which are not system ones, moved to the start of a
+/// turn.
+fn first_kept(entries: &[HistoryEntry], n: usize) -> usize {
+    match n {
+        0 => entries.len(),
+        n => turn_start(
+            entries,
+            entries
+                .iter()
+                .enumerate()
+                .rev()
+                .filter(|(_, entry)| entry.role != Role::System)
+                .nth(n - 1)
+                .map(|(index, _)| index)
+                .unwrap_or_default(),
+        ),
+    }
+}
+
+/// Whether the conversation may start with the entry. Results of tool calls may not, since their
+/// calls would be missing.
+fn starts_turn(entry: &HistoryEntry) -> bool {
+    entry.role == Role::User && entry.tool_call_reports.is_empty()
+}
+
+/// Moves the cut at `index` forward to the closest entry starting a turn, or back if there is none
+/// after it.
+fn turn_start(entries: &[HistoryEntry], index: usize) -> usize {
+    entries[index..]
+        .iter()
+        .position(starts_turn)
+        .map(|position| index + position)
+        .or_else(|| entries[..index].iter().rposition(starts_turn))
+        .unwrap_or(index)
+}
+
+fn keep_system_and_from(entries: &[HistoryEntry], first_kept: usize) -> Vec<HistoryEntry> {
+    entries
+        .iter()
+        .enumerate()
+        .filter(|(index, entry)| entry.role == Role::System || *index >= first_kept)
+        .map(|(_, entry)| entry.clone())
+        .collect()
+}
+
+/// Every entry of the conversation, oldest first, and the [TruncationStrategy] applied before
+/// sending it to the model. Nothing is truncated by default.
+///
+/// With a [Tokenizer], tokens of the entries are counted as they are pushed.
+///
+/// With [Summarize], older entries are sent as a summary once [ConversationHistory::summarize] gets
+/// to them. The backends call it whenever they receive a message.
+///
+/// ```ignore
+/// let history = ConversationHistory::new().with_truncation(KeepSystemAndLastN { n: 20 });
+///
+/// let agent = OpenAiAgent::new(OPENAI_BASE_URL, "gpt-4o").with_history(history);
+/// ```
+#[derive(Clone, Default)]
+pub struct ConversationHistory {
+    entries: Vec<HistoryEntry>,
+    truncation: Option<Arc<dyn TruncationStrategy>>,
+    tokenizer: Option<Arc<dyn Tokenizer>>,
+    summarize: Option<Summarize>,
+    summary: Option<HistoryEntry>,
+    /// Entries before this one, other than system ones, are covered by the summary.
+    summarized: usize,
+}
+
+impl ConversationHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_truncation(mut self, truncation: impl TruncationStrategy + 'static) -> Self {
+        self.truncation = Some(Arc::new(truncation));
+        self
+    }
+
+    pub fn with_summarize(mut self, summarize: Summarize) -> Self {
+        self.summarize = Some(summarize);
+        self
+    }
+
+    /// Counts the tokens of the entries which are already there as well.
+    pub fn with_tokenizer(mut self, tokenizer: impl Tokenizer + 'static) -> Self {
+        let tokenizer: Arc<dyn Tokenizer> = Arc::new(tokenizer);
+
+        for entry in &mut self.entries {
+            count_tokens(entry, tokenizer.as_ref());
+        }
+
+        self.tokenizer = Some(tokenizer);
+        self
+    }
+
+    pub fn push(&mut self, mut entry: HistoryEntry) {
+        if let Some(tokenizer) = &self.tokenizer {
+            count_tokens(&mut entry, tokenizer.as_ref());
+        }
+
+        self.entries.push(entry);
+    }
+
+    /// The whole history, regardless of the truncation.
+    pub fn entries(&self) -> &[HistoryEntry] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.summary = None;
+        self.summarized = 0;
+    }
+
+    /// Summary of the older entries, sent instead of them.
+    pub fn summary(&self) -> Option<&HistoryEntry> {
+        self.summary.as_ref()
+    }
+
+    /// Runs the summarizer of [Summarize] if there are enough entries which are not summarized,
+    /// otherwise does nothing.
+    pub async fn summarize(&mut self) {
+        let Some(summarize) = &self.summarize else {
+            return;
+        };
+
+        let pending: Vec<usize> = (self.summarized..self.entries.len())
+            .filter(|index| self.entries[*index].role != Role::System)
+            .collect();
+
+        if pending.len() <= summarize.max_entries {
+            return;
+        }
+
+        let cut = match summarize.keep_last {
+            0 => self.entries.len(),
+            keep_last => turn_start(&self.entries, pending[pending.len() - keep_last]),
+        };
+
+        if cut <= self.summarized {
+            return;
+        }
+
+        let left_out: Vec<HistoryEntry> = self
+            .summary
+            .iter()
+            .chain(
+                self.entries[self.summarized..cut]
+                    .iter()
+                    .filter(|entry| entry.role != Role::System),
+            )
+            .cloned()
+            .collect();
+
+        debug!("summarizing {} entries..", left_out.len());
+
+        let summary = (summarize.summarizer)(left_out).await;
+
+        let mut summary = HistoryEntry::new(
+            Role::System,
+            format!("Summary of the earlier conversation:\n{}", summary),
+        );
+        if let Some(tokenizer) = &self.tokenizer {
+            count_tokens(&mut summary, tokenizer.as_ref());
+        }
+
+        self.summary = Some(summary);
+        self.summarized = cut;
+    }
+
+    /// Sum of the known [HistoryEntry::tokens].
+    pub fn total_tokens(&self) -> usize {
+        self.entries.iter().filter_map(|entry| entry.tokens).sum()
+    }
+
+    /// Entries to be sent to the model. The summary takes the place of the entries it covers.
+    pub fn context(&self) -> Vec<HistoryEntry> {
+        let entries: Cow<[HistoryEntry]> = match &self.summary {
+            Some(summary) => self.entries[..self.summarized]
+                .iter()
+                .filter(|entry| entry.role == Role::System)
+                .chain(std::iter::once(summary))
+                .chain(&self.entries[self.summarized..])
+                .cloned()
+                .collect(),
+            None => Cow::Borrowed(&self.entries),
+        };
+
+        match &self.truncation {
+            Some(truncation) => truncation.truncate(&entries),
+            None => entries.into_owned(),
+        }
+    }
+}
+
+impl std::fmt::Debug for ConversationHistory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ConversationHistory")
+            .field("entries", &self.entries)
+            .field("summary", &self.summary)
+            .finish_non_exhaustive()
+    }
+}
+
+fn describe_execution_result(result: &CodeBlockExecutionResult) -> String {
+    let output = result.output();
+
+    let mut description = match result {
+        CodeBlockExecutionResult::Success(_) => "The code was executed successfully.".to_string(),
+        CodeBlockExecutionResult::Failure(_) => "The code failed.".to_string(),
+        CodeBlockExecutionResult::LimitExceeded { limit, .. } => {
+            format!("The code was stopped: {}.", limit)
+        }
+    };
+
+    if let Some(exit_code) = output.exit_code {
+        description.push_str(&format!("\nExit code: {}", exit_code));
+    }
+
+    for (name, stream) in [("stdout", &output.stdout), ("stderr", &output.stderr)] {
+        if !stream.is_empty() {
+            description.push_str(&format!("\n{}:\n```\n{}\n```", name, stream.trim_end()));
+        }
+    }
+
+    if output.truncated {
+        description.push_str("\nThe output was truncated.");
+    }
+
+    if !output.produced_files.is_empty() {
+        let files: Vec<String> = output
+            .produced_files
+            .iter()
+            .map(|file| file.display().to_string())
+            .collect();
+
+        description.push_str(&format!("\nProduced files: {}", files.join(", ")));
+    }
+
+    description
+}
+
+pub(crate) fn describe_reports(reports: &[CodeBlockReport]) -> String {
+    reports
+        .iter()
+        .enumerate()
+        .map(|(index, report)| {
+            let outcome = match &report.outcome {
+                CodeBlockOutcome::Executed(result) => describe_execution_result(result),
+                CodeBlockOutcome::Denied { reason } => {
+                    format!("Execution was denied. Reason: {}", reason)
+                }
+                CodeBlockOutcome::Skipped => {
+                    "Not executed, since an earlier code block did not succeed.".to_string()
+                }
+            };
+
+            format!(
+                "Code block {} ({}):\n{}",
+                index + 1,
+                report.code_block.language,
+                outcome
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+pub(crate) fn describe_tool_call_reports(reports: &[ToolCallReport]) -> String {
+    reports
+        .iter()
+        .map(|report| {
+            let outcome = match &report.outcome {
+                ToolCallOutcome::Returned(value) => format!("returned:\n```json\n{}\n```", value),
+                ToolCallOutcome::Failed(error) => format!("failed: {}", error),
+                ToolCallOutcome::Denied { reason } => {
+                    format!("was denied. Reason: {}", reason)
+                }
+            };
+
+            format!(
+                "Tool call `{}` ({}) {}",
+                report.tool_call.name, report.tool_call.id, outcome
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
@@ -0,0 +1,275 @@
+//! Several collaborative agents taking turns, as a single [CollaborativeAgent] for the
+//! [super::collaborative_chat].
+//!
+//! Every message is passed to all the agents - the next speaker replies to it, the others only
+//! receive it (see [CollaborativeAgent::receive]), and so they do with the reply.
+
+pub mod speaker_selection;
+pub mod speaker_transitions;
+pub mod speaker_transitions_error;
+
+use super::collaborative_agent::{
+    reply_to_message, CodeBlockReport, CollaborativeAgent, CollaborativeAgentResponse, Message,
+};
+use super::group_chat_error::GroupChatError;
+
+use super::code::{CodeBlock, CodeBlockExecutionResult};
+
+use super::tool::ToolCallReport;
+
+use crate::agent_traits::{NamedAgent, TokenUsage};
+
+use speaker_selection::SpeakerSelection;
+use speaker_transitions::SpeakerTransitions;
+
+use tracing::debug;
+
+/// What the [SpeakerSelection] chooses from.
+#[derive(Debug, Clone, Copy)]
+pub struct SpeakerContext<'a> {
+    /// Names of all the agents. The selection returns an index into it.
+    pub names: &'a [String],
+    /// Indices of the agents which may speak next, in order. All of them, unless limited by the
+    /// [SpeakerTransitions].
+    pub candidates: &'a [usize],
+    pub last_speaker: Option<usize>,
+    /// What the next speaker replies to.
+    pub message: &'a Message,
+}
+
+/// Agents are of a single type, use an enum to mix different ones.
+///
+/// The name of the group chat is the name of the last speaker, so the user agent knows who it
+/// talks to.
+pub struct GroupChat<CA, S> {
+    agents: Vec<CA>,
+    names: Vec<String>,
+    selection: S,
+    transitions: Option<SpeakerTransitions>,
+    last_speaker: Option<usize>,
+}
+
+impl<CA, S> GroupChat<CA, S>
+where
+    CA: CollaborativeAgent,
+    CA: NamedAgent,
+
+    S: SpeakerSelection,
+{
+    pub fn new(agents: Vec<CA>, selection: S) -> Result<Self, GroupChatError<CA, S>> {
+        if agents.is_empty() {
+            return Err(GroupChatError::NoAgents);
+        }
+
+        let mut names: Vec<String> = Vec::new();
+        for agent in &agents {
+            let name = agent.name().to_string();
+
+            if names.contains(&name) {
+                return Err(GroupChatError::DuplicateName(name));
+            }
+            names.push(name);
+        }
+
+        Ok(Self {
+            agents,
+            names,
+            selection,
+            transitions: None,
+            last_speaker: None,
+        })
+    }
+
+    /// Only the speakers allowed by the graph are selected. The graph is validated against the
+    /// names of the agents.
+    pub fn with_transitions(
+        mut self,
+        transitions: SpeakerTransitions,
+    ) -> Result<Self, GroupChatError<CA, S>> {
+        transitions
+            .validate(&self.names)
+            .map_err(GroupChatError::Transitions)?;
+
+        self.transitions = Some(transitions);
+        Ok(self)
+    }
+
+    pub fn agents(&self) -> &[CA] {
+        &self.agents
+    }
+
+    pub fn last_speaker(&self) -> Option<&CA> {
+        self.last_speaker.map(|speaker| &self.agents[speaker])
+    }
+
+    /// Results of tool calls always go back to the agent which called the tools, regardless of the
+    /// transitions. The next speaker is selected otherwise.
+    async fn reply(
+        &mut self,
+        message: Message,
+    ) -> Result<CollaborativeAgentResponse, GroupChatError<CA, S>> {
+        let speaker = match (&message, self.last_speaker) {
+            (Message::ToolCallResults(_), Some(last_speaker)) => last_speaker,
+            _ => self.select_speaker(&message).await?,
+        };
+        debug!("{} speaks next..", self.names[speaker]);
+
+        // Nobody else knows of the tool calls.
+        if !matches!(message, Message::ToolCallResults(_)) {
+            self.broadcast(speaker, &message).await?;
+        }
+
+        let reply = reply_to_message(&mut self.agents[speaker], message)
+            .await
+            .map_err(GroupChatError::Agent)?;
+
+        let message = Message::Text {
+            sender: self.names[speaker].clone(),
+            message: reply.to_markdown(),
+        };
+        self.broadcast(speaker, &message).await?;
+
+        self.last_speaker = Some(speaker);
+
+        Ok(reply)
+    }
+
+    async fn select_speaker(&mut self, message: &Message) -> Result<usize, GroupChatError<CA, S>> {
+        let last_speaker = self
+            .last_speaker
+            .map(|speaker| self.names[speaker].as_str());
+
+        let candidates: Vec<usize> = match &self.transitions {
+            Some(transitions) => {
+                let next_speakers = transitions.next_speakers(last_speaker);
+
+                (0..self.agents.len())
+                    .filter(|index| next_speakers.contains(&self.names[*index]))
+                    .collect()
+            }
+            None => (0..self.agents.len()).collect(),
+        };
+
+        let context = SpeakerContext {
+            names: &self.names,
+            candidates: &candidates,
+            last_speaker: self.last_speaker,
+            message,
+        };
+
+        let speaker = self
+            .selection
+            .select(context)
+            .await
+            .map_err(GroupChatError::SpeakerSelection)?;
+
+        match (candidates.contains(&speaker), self.names.get(speaker)) {
+            (true, _) => Ok(speaker),
+            (false, Some(name)) => Err(GroupChatError::TransitionNotAllowed {
+                from: last_speaker.map(str::to_string),
+                to: name.clone(),
+            }),
+            (false, None) => Err(GroupChatError::InvalidSpeaker(speaker)),
+        }
+    }
+
+    /// Passes the message to everyone but the speaker.
+    async fn broadcast(
+        &mut self,
+        speaker: usize,
+        message: &Message,
+    ) -> Result<(), GroupChatError<CA, S>> {
+        for (index, agent) in self.agents.iter_mut().enumerate() {
+            if index != speaker {
+                agent
+                    .receive(message.clone())
+                    .await
+                    .map_err(GroupChatError::Agent)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<CA, S> NamedAgent for GroupChat<CA, S> {
+    fn name(&self) -> &str {
+        match self.last_speaker {
+            Some(speaker) => &self.names[speaker],
+            None => "group_chat",
+        }
+    }
+}
+
+impl<CA, S> CollaborativeAgent for GroupChat<CA, S>
+where
+    CA: CollaborativeAgent,
+    CA: NamedAgent,
+
+    S: SpeakerSelection,
+{
+    type Error = GroupChatError<CA, S>;
+
+    async fn receive_and_reply(
+        &mut self,
+        sender: String,
+        message: String,
+    ) -> Result<CollaborativeAgentResponse, Self::Error> {
+        self.reply(Message::Text { sender, message }).await
+    }
+
+    async fn deny_code_block_execution(
+        &mut self,
+        code_block: CodeBlock,
+        feedback: String,
+    ) -> Result<CollaborativeAgentResponse, Self::Error> {
+        self.reply(Message::CodeExecutionDenied {
+            comment: feedback,
+            code_block,
+        })
+        .await
+    }
+
+    async fn receive_code_and_reply_to_execution_result(
+        &mut self,
+        code_execution_result: CodeBlockExecutionResult,
+    ) -> Result<CollaborativeAgentResponse, Self::Error> {
+        self.reply(Message::CodeExecutionResult(code_execution_result))
+            .await
+    }
+
+    async fn receive_code_and_reply_to_execution_results(
+        &mut self,
+        reports: Vec<CodeBlockReport>,
+    ) -> Result<CollaborativeAgentResponse, Self::Error> {
+        self.reply(Message::CodeExecutionResults(reports)).await
+    }
+
+    async fn receive_and_reply_to_tool_call_results(
+        &mut self,
+        reports: Vec<ToolCallReport>,
+    ) -> Result<CollaborativeAgentResponse, Self::Error> {
+        self.reply(Message::ToolCallResults(reports)).await
+    }
+
+    async fn receive(&mut self, message: Message) -> Result<(), Self::Error> {
+        for agent in &mut self.agents {
+            agent
+                .receive(message.clone())
+                .await
+                .map_err(GroupChatError::Agent)?;
+        }
+
+        Ok(())
+    }
+
+    /// Usage of all the agents, including the one selecting the speakers.
+    fn token_usage(&self) -> Option<TokenUsage> {
+        self.agents
+            .iter()
+            .map(CollaborativeAgent::token_usage)
+            .chain([self.selection.token_usage()])
+            .flatten()
+            .reduce(|total, usage| total + usage)
+    }
+}
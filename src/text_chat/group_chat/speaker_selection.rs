@@ -0,0 +1,218 @@
+//! Strategies choosing the next speaker of the [super::GroupChat].
+
+use super::SpeakerContext;
+
+use crate::agent_traits::TokenUsage;
+use crate::text_chat::chat_user_agent::ChatUserAgent;
+use crate::text_chat::collaborative_agent::CollaborativeAgent;
+use crate::text_chat::conversation_history::HistoryEntry;
+
+use std::convert::Infallible;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tracing::debug;
+
+pub trait SpeakerSelection {
+    type Error;
+
+    /// Index of the next speaker, one of the [SpeakerContext::candidates].
+    async fn select(&mut self, context: SpeakerContext<'_>) -> Result<usize, Self::Error>;
+
+    /// Usage of the model selecting the speakers, if there is one.
+    fn token_usage(&self) -> Option<TokenUsage> {
+        None
+    }
+}
+
+/// Candidates take turns in order, starting with the first one.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RoundRobin;
+
+impl SpeakerSelection for RoundRobin {
+    type Error = Infallible;
+
+    async fn select(&mut self, context: SpeakerContext<'_>) -> Result<usize, Self::Error> {
+        Ok(next_in_turn(&context))
+    }
+}
+
+/// The first candidate after the last speaker.
+fn next_in_turn(context: &SpeakerContext<'_>) -> usize {
+    let after_last = |candidate: &&usize| {
+        context
+            .last_speaker
+            .is_none_or(|last_speaker| **candidate > last_speaker)
+    };
+
+    context
+        .candidates
+        .iter()
+        .find(after_last)
+        .or(context.candidates.first())
+        .copied()
+        .unwrap_or_default()
+}
+
+/// Any of the candidates, the last speaker included. Not suitable for cryptography.
+#[derive(Debug, Clone)]
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    /// Seeded with the current time.
+    pub fn new() -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_nanos() as u64)
+            .unwrap_or_default();
+
+        Self::with_seed(seed)
+    }
+
+    /// Same seed, same speakers.
+    pub fn with_seed(seed: u64) -> Self {
+        // Xorshift never leaves the zero state.
+        Self { state: seed | 1 }
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+}
+
+impl Default for Random {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SpeakerSelection for Random {
+    type Error = Infallible;
+
+    async fn select(&mut self, context: SpeakerContext<'_>) -> Result<usize, Self::Error> {
+        let index = self.next() % context.candidates.len() as u64;
+
+        Ok(context.candidates[index as usize])
+    }
+}
+
+/// The user is asked through a [ChatUserAgent] and answers with the name or the number of the
+/// speaker. The question is repeated until the answer is valid.
+pub struct Manual<UA> {
+    user_agent: UA,
+}
+
+impl<UA: ChatUserAgent> Manual<UA> {
+    pub fn new(user_agent: UA) -> Self {
+        Self { user_agent }
+    }
+}
+
+impl<UA: ChatUserAgent> SpeakerSelection for Manual<UA> {
+    type Error = UA::Error;
+
+    async fn select(&mut self, context: SpeakerContext<'_>) -> Result<usize, Self::Error> {
+        let choices: Vec<String> = context
+            .candidates
+            .iter()
+            .enumerate()
+            .map(|(number, candidate)| format!("{}. {}", number + 1, context.names[*candidate]))
+            .collect();
+
+        let question = format!("Who speaks next?\n{}", choices.join("\n"));
+
+        loop {
+            let answer = self
+                .user_agent
+                .receive_and_reply("group_chat".to_string(), question.clone())
+                .await?;
+            let answer = answer.trim();
+
+            let speaker = match answer.parse::<usize>() {
+                Ok(number) => number
+                    .checked_sub(1)
+                    .and_then(|index| context.candidates.get(index)),
+                Err(_) => context
+                    .candidates
+                    .iter()
+                    .find(|candidate| context.names[**candidate].eq_ignore_ascii_case(answer)),
+            };
+
+            match speaker {
+                Some(speaker) => return Ok(*speaker),
+                None => debug!("unknown speaker: {}", answer),
+            }
+        }
+    }
+}
+
+/// Another agent, usually backed by a model, picks the speaker based on the last message. If its
+/// answer names none of the candidates, they take turns like in [RoundRobin].
+pub struct Manager<CA> {
+    agent: CA,
+}
+
+impl<CA: CollaborativeAgent> Manager<CA> {
+    pub fn new(agent: CA) -> Self {
+        Self { agent }
+    }
+}
+
+impl<CA: CollaborativeAgent> SpeakerSelection for Manager<CA> {
+    type Error = CA::Error;
+
+    async fn select(&mut self, context: SpeakerContext<'_>) -> Result<usize, Self::Error> {
+        let candidates: Vec<&str> = context
+            .candidates
+            .iter()
+            .map(|candidate| context.names[*candidate].as_str())
+            .collect();
+
+        let message = HistoryEntry::from_message(context.message.clone());
+        let sender = message.sender.unwrap_or_else(|| "the user".to_string());
+
+        let prompt = format!(
+            "You are managing a group chat of: {}.\n\nThe last message, from {}, was:\n\n{}\n\nWho should speak next? Reply with the name only.",
+            candidates.join(", "),
+            sender,
+            message.content
+        );
+
+        let answer = self
+            .agent
+            .receive_and_reply("group_chat".to_string(), prompt)
+            .await?
+            .to_markdown()
+            .to_lowercase();
+
+        // The name mentioned first, the longest one if some names contain the others.
+        let speaker = context
+            .candidates
+            .iter()
+            .filter_map(|candidate| {
+                let name = context.names[*candidate].to_lowercase();
+
+                answer
+                    .find(&name)
+                    .map(|position| (position, usize::MAX - name.len(), *candidate))
+            })
+            .min()
+            .map(|(_, _, candidate)| candidate);
+
+        match speaker {
+            Some(speaker) => Ok(speaker),
+            None => {
+                debug!("manager did not name any speaker: {}", answer);
+                Ok(next_in_turn(&context))
+            }
+        }
+    }
+
+    fn token_usage(&self) -> Option<TokenUsage> {
+        self.agent.token_usage()
+    }
+}
@@ -0,0 +1,85 @@
+//! Which agents may speak after which, e.g. planner -> coder -> reviewer -> planner.
+
+use super::speaker_transitions_error::SpeakerTransitionsError;
+
+use std::collections::{BTreeMap, HashSet, VecDeque};
+
+/// Graph of the allowed transitions over the names of the agents, see
+/// [super::GroupChat::with_transitions].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SpeakerTransitions {
+    first: Vec<String>,
+    allowed: BTreeMap<String, Vec<String>>,
+}
+
+impl SpeakerTransitions {
+    /// Agents which may start the chat.
+    pub fn new<N: Into<String>>(first: impl IntoIterator<Item = N>) -> Self {
+        Self {
+            first: first.into_iter().map(Into::into).collect(),
+            allowed: BTreeMap::new(),
+        }
+    }
+
+    /// Adds to the transitions allowed so far. An agent may be allowed to follow itself.
+    pub fn allow<N: Into<String>>(
+        mut self,
+        from: impl Into<String>,
+        to: impl IntoIterator<Item = N>,
+    ) -> Self {
+        self.allowed
+            .entry(from.into())
+            .or_default()
+            .extend(to.into_iter().map(Into::into));
+        self
+    }
+
+    /// Who may speak after the last speaker, or first if nobody spoke yet.
+    pub fn next_speakers(&self, last_speaker: Option<&str>) -> &[String] {
+        match last_speaker {
+            Some(last_speaker) => self
+                .allowed
+                .get(last_speaker)
+                .map(Vec::as_slice)
+                .unwrap_or_default(),
+            None => &self.first,
+        }
+    }
+
+    /// Every agent has to be reachable from the first speakers and has to have someone to pass the
+    /// turn to.
+    pub fn validate(&self, names: &[String]) -> Result<(), SpeakerTransitionsError> {
+        let mentioned = self
+            .first
+            .iter()
+            .chain(self.allowed.keys())
+            .chain(self.allowed.values().flatten());
+
+        if let Some(unknown) = mentioned.into_iter().find(|name| !names.contains(name)) {
+            return Err(SpeakerTransitionsError::UnknownSpeaker(unknown.clone()));
+        }
+
+        let mut reached: HashSet<&str> = self.first.iter().map(String::as_str).collect();
+        let mut queue: VecDeque<&str> = reached.iter().copied().collect();
+
+        while let Some(speaker) = queue.pop_front() {
+            for next in self.next_speakers(Some(speaker)) {
+                if reached.insert(next) {
+                    queue.push_back(next);
+                }
+            }
+        }
+
+        for name in names {
+            if !reached.contains(name.as_str()) {
+                return Err(SpeakerTransitionsError::Unreachable(name.clone()));
+            }
+
+            if self.next_speakers(Some(name)).is_empty() {
+                return Err(SpeakerTransitionsError::DeadEnd(name.clone()));
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,9 @@
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SpeakerTransitionsError {
+    /// Name in the graph is not a name of any agent.
+    UnknownSpeaker(String),
+    /// Agent can never speak - there is no path to it from the first speakers.
+    Unreachable(String),
+    /// Agent has no transitions to anyone, so the chat could not go on after it speaks.
+    DeadEnd(String),
+}
@@ -0,0 +1,52 @@
+use super::collaborative_agent::CollaborativeAgent;
+use super::group_chat::speaker_selection::SpeakerSelection;
+use super::group_chat::speaker_transitions_error::SpeakerTransitionsError;
+
+pub enum GroupChatError<CA, S>
+where
+    CA: CollaborativeAgent,
+
+    S: SpeakerSelection,
+{
+    NoAgents,
+    /// Agents are told apart by their names.
+    DuplicateName(String),
+    /// Invalid graph given to [super::group_chat::GroupChat::with_transitions].
+    Transitions(SpeakerTransitionsError),
+    /// The selection returned an index out of the agents.
+    InvalidSpeaker(usize),
+    /// The selection returned an agent which is not one of the candidates.
+    TransitionNotAllowed {
+        from: Option<String>,
+        to: String,
+    },
+    Agent(CA::Error),
+    SpeakerSelection(S::Error),
+}
+
+impl<CA, S> std::fmt::Debug for GroupChatError<CA, S>
+where
+    CA: CollaborativeAgent,
+    <CA as CollaborativeAgent>::Error: std::fmt::Debug,
+
+    S: SpeakerSelection,
+    <S as SpeakerSelection>::Error: std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GroupChatError::NoAgents => write!(f, "NoAgents"),
+            GroupChatError::DuplicateName(name) => write!(f, "DuplicateName({:?})", name),
+            GroupChatError::Transitions(e) => write!(f, "Transitions({:?})", e),
+            GroupChatError::InvalidSpeaker(speaker) => write!(f, "InvalidSpeaker({})", speaker),
+            GroupChatError::TransitionNotAllowed { from, to } => {
+                write!(
+                    f,
+                    "TransitionNotAllowed {{ from: {:?}, to: {:?} }}",
+                    from, to
+                )
+            }
+            GroupChatError::Agent(e) => write!(f, "Agent({:?})", e),
+            GroupChatError::SpeakerSelection(e) => write!(f, "SpeakerSelection({:?})", e),
+        }
+    }
+}
@@ -0,0 +1,46 @@
+//! When the user is asked to reply, so the same chat can run interactively or unattended.
+
+use std::fmt::Debug;
+
+/// Which replies to the texts of the collaborative agent come from the user agent, see
+/// [super::collaborative_chat::CollaborativeChatOptions::human_input_mode].
+///
+/// The user agent always replies to the welcome message, as it gives the task. It is asked for
+/// the feedback on code and tool calls in every mode but [HumanInputMode::Never].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HumanInputMode {
+    /// The user agent replies to every text.
+    #[default]
+    Always,
+    /// Texts get the [AutoReply]. Once the termination condition is met, the user agent decides
+    /// whether the chat ends - its reply is passed to the collaborative agent instead of the
+    /// message that met the condition, an empty reply or `exit` ends the chat.
+    Terminate,
+    /// Texts get the [AutoReply] and the chat ends as soon as the termination condition is met.
+    ///
+    /// Nobody is asked about code and tool calls either. The chat requires an
+    /// [super::approval_policy::ApprovalPolicy], whose escalations count as denials, and
+    /// [super::collaborative_chat::ToolCallApproval::Automatic] if there are tools.
+    Never,
+}
+
+/// Replies on behalf of the user agent when it is not asked. The texts are still shown to it.
+pub trait AutoReply: Debug + Send + Sync {
+    fn reply(&self, sender: &str, text: &str) -> String;
+}
+
+/// Same reply to everything, `Continue.` by default.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FixedReply(pub String);
+
+impl Default for FixedReply {
+    fn default() -> Self {
+        Self("Continue.".to_string())
+    }
+}
+
+impl AutoReply for FixedReply {
+    fn reply(&self, _sender: &str, _text: &str) -> String {
+        self.0.clone()
+    }
+}
@@ -0,0 +1,21 @@
+//! Agents backed by LLM APIs. They implement [crate::agent_traits::ConsumerAgent] and
+//! [crate::agent_traits::ProducerAgent] with [super::collaborative_agent::Message] and
+//! [super::collaborative_agent::CollaborativeAgentResponse], so they are
+//! [super::collaborative_agent::CollaborativeAgent]s through the blanket implementation.
+//!
+//! All of them keep the conversation in a [super::conversation_history::ConversationHistory].
+
+#[cfg(feature = "anthropic")]
+pub mod anthropic_agent;
+#[cfg(feature = "anthropic")]
+pub mod anthropic_agent_error;
+
+#[cfg(feature = "openai")]
+pub mod openai_agent;
+#[cfg(feature = "openai")]
+pub mod openai_agent_error;
+
+#[cfg(feature = "ollama")]
+pub mod ollama_agent;
+#[cfg(feature = "ollama")]
+pub mod ollama_agent_error;
@@ -0,0 +1,321 @@
+use super::anthropic_agent_error::AnthropicAgentError;
+
+use crate::agent_traits::{ConsumerAgent, NamedAgent, ProducerAgent, TokenUsage};
+use crate::text_chat::collaborative_agent::{CollaborativeAgentResponse, Message};
+use crate::text_chat::conversation_history::{ConversationHistory, HistoryEntry, Role};
+use crate::text_chat::markdown::MarkdownResponseParser;
+
+use serde::{Deserialize, Serialize};
+
+use tracing::debug;
+
+pub const ANTHROPIC_BASE_URL: &str = "https://api.anthropic.com";
+
+/// Value of the `anthropic-version` header.
+pub const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// The API requires a limit, this one is used unless set with [AnthropicAgent::with_max_tokens].
+pub const DEFAULT_MAX_TOKENS: u32 = 4096;
+
+/// Why the model stopped generating.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StopReason {
+    /// The model finished its turn.
+    EndTurn,
+    /// The reply hit the token limit and is cut short.
+    MaxTokens,
+    /// One of the stop sequences was generated.
+    StopSequence,
+    /// The model wants to use a tool. Tools are not sent by this agent, so this is not expected.
+    ToolUse,
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Serialize)]
+struct MessagesRequest<'a> {
+    model: &'a str,
+    max_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<&'a str>,
+    messages: Vec<AnthropicMessage<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    stop_sequences: &'a [String],
+}
+
+#[derive(Serialize)]
+struct AnthropicMessage<'a> {
+    role: Role,
+    content: Vec<RequestContentBlock<'a>>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum RequestContentBlock<'a> {
+    Text { text: &'a str },
+}
+
+#[derive(Deserialize)]
+struct MessagesResponse {
+    content: Vec<ResponseContentBlock>,
+    stop_reason: Option<StopReason>,
+    usage: Option<Usage>,
+}
+
+#[derive(Deserialize)]
+struct Usage {
+    input_tokens: u64,
+    output_tokens: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ResponseContentBlock {
+    Text {
+        text: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
+/// Agent talking to the Anthropic Messages API (`/v1/messages`).
+///
+/// Works the same way as [super::openai_agent::OpenAiAgent] - the history is sent on every request
+/// and replies are parsed with the [MarkdownResponseParser]. The API requires turns to
+/// alternate between the user and the assistant, so consecutive messages of the same role are sent
+/// as separate content blocks of a single turn.
+pub struct AnthropicAgent {
+    name: String,
+    client: reqwest::Client,
+    base_url: String,
+    api_key: Option<String>,
+    model: String,
+    system_prompt: Option<String>,
+    max_tokens: u32,
+    temperature: Option<f32>,
+    stop_sequences: Vec<String>,
+    parser: MarkdownResponseParser,
+    history: ConversationHistory,
+    stop_reason: Option<StopReason>,
+    usage: TokenUsage,
+}
+
+impl AnthropicAgent {
+    /// `base_url` is the part before `/v1/messages`, e.g. [ANTHROPIC_BASE_URL].
+    pub fn new(base_url: impl Into<String>, model: impl Into<String>) -> Self {
+        Self {
+            name: "assistant".to_string(),
+            client: reqwest::Client::new(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            api_key: None,
+            model: model.into(),
+            system_prompt: None,
+            max_tokens: DEFAULT_MAX_TOKENS,
+            temperature: None,
+            stop_sequences: Vec::new(),
+            parser: MarkdownResponseParser::default(),
+            history: ConversationHistory::new(),
+            stop_reason: None,
+            usage: TokenUsage::default(),
+        }
+    }
+
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Sent in the `x-api-key` header.
+    pub fn with_api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+
+    pub fn with_system_prompt(mut self, system_prompt: impl Into<String>) -> Self {
+        self.system_prompt = Some(system_prompt.into());
+        self
+    }
+
+    pub fn with_max_tokens(mut self, max_tokens: u32) -> Self {
+        self.max_tokens = max_tokens;
+        self
+    }
+
+    pub fn with_temperature(mut self, temperature: f32) -> Self {
+        self.temperature = Some(temperature);
+        self
+    }
+
+    pub fn with_stop_sequences(mut self, stop_sequences: Vec<String>) -> Self {
+        self.stop_sequences = stop_sequences;
+        self
+    }
+
+    pub fn with_parser(mut self, parser: MarkdownResponseParser) -> Self {
+        self.parser = parser;
+        self
+    }
+
+    pub fn with_client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Starts from the given history, e.g. one with a
+    /// [crate::text_chat::conversation_history::TruncationStrategy] or taken over from another
+    /// agent.
+    pub fn with_history(mut self, history: ConversationHistory) -> Self {
+        self.history = history;
+        self
+    }
+
+    /// Conversation so far, without the system prompt.
+    pub fn history(&self) -> &ConversationHistory {
+        &self.history
+    }
+
+    /// Stop reason of the last reply, if any was received.
+    pub fn stop_reason(&self) -> Option<StopReason> {
+        self.stop_reason
+    }
+
+    /// Groups the history into alternating turns. System messages have no place among the turns,
+    /// they are sent as user ones. The API requires the first turn to be a user one, so assistant
+    /// entries left at the start by a truncation are skipped.
+    fn request_messages(context: &[HistoryEntry]) -> Vec<AnthropicMessage<'_>> {
+        let mut messages: Vec<AnthropicMessage> = Vec::new();
+
+        for entry in context
+            .iter()
+            .skip_while(|entry| entry.role == Role::Assistant)
+        {
+            let role = match entry.role {
+                Role::Assistant => Role::Assistant,
+                Role::User | Role::System => Role::User,
+            };
+
+            let block = RequestContentBlock::Text {
+                text: &entry.content,
+            };
+
+            match messages.last_mut() {
+                Some(last) if last.role == role => last.content.push(block),
+                _ => messages.push(AnthropicMessage {
+                    role,
+                    content: vec![block],
+                }),
+            }
+        }
+
+        messages
+    }
+}
+
+impl NamedAgent for AnthropicAgent {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl ConsumerAgent for AnthropicAgent {
+    type Mrx = Message;
+    type Error = AnthropicAgentError;
+
+    async fn receive_message(&mut self, mrx: Self::Mrx) -> Result<(), Self::Error> {
+        self.history.push(HistoryEntry::from_message(mrx));
+        self.history.summarize().await;
+
+        Ok(())
+    }
+}
+
+impl ProducerAgent for AnthropicAgent {
+    type Mtx = CollaborativeAgentResponse;
+    type Error = AnthropicAgentError;
+
+    async fn send_message(&mut self) -> Result<Self::Mtx, Self::Error> {
+        let context = self.history.context();
+
+        let request = MessagesRequest {
+            model: &self.model,
+            max_tokens: self.max_tokens,
+            system: self.system_prompt.as_deref(),
+            messages: Self::request_messages(&context),
+            temperature: self.temperature,
+            stop_sequences: &self.stop_sequences,
+        };
+
+        debug!("requesting message from {}..", self.base_url);
+
+        let mut builder = self
+            .client
+            .post(format!("{}/v1/messages", self.base_url))
+            .header("anthropic-version", ANTHROPIC_VERSION)
+            .json(&request);
+
+        if let Some(api_key) = &self.api_key {
+            builder = builder.header("x-api-key", api_key);
+        }
+
+        let response = builder.send().await.map_err(AnthropicAgentError::Http)?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+
+            return Err(AnthropicAgentError::Status {
+                status: status.as_u16(),
+                body,
+            });
+        }
+
+        let message: MessagesResponse = response.json().await.map_err(AnthropicAgentError::Http)?;
+
+        let texts: Vec<String> = message
+            .content
+            .into_iter()
+            .filter_map(|block| match block {
+                ResponseContentBlock::Text { text } => Some(text),
+                ResponseContentBlock::Other => None,
+            })
+            .collect();
+
+        if texts.is_empty() {
+            return Err(AnthropicAgentError::InvalidResponse(format!(
+                "no text in the reply, stop reason: {:?}",
+                message.stop_reason
+            )));
+        }
+
+        let content = texts.concat();
+
+        if message.stop_reason == Some(StopReason::MaxTokens) {
+            debug!("reply was cut short by the token limit..");
+        }
+
+        self.stop_reason = message.stop_reason;
+
+        let reply = self.parser.parse(&content);
+        let mut entry = HistoryEntry::from_reply(content, &reply);
+
+        if let Some(usage) = message.usage {
+            self.usage += TokenUsage {
+                prompt_tokens: usage.input_tokens,
+                completion_tokens: usage.output_tokens,
+            };
+
+            entry = entry.with_tokens(usage.output_tokens as usize);
+        }
+
+        self.history.push(entry);
+
+        Ok(reply)
+    }
+
+    fn usage(&self) -> Option<TokenUsage> {
+        Some(self.usage)
+    }
+}
@@ -0,0 +1,9 @@
+#[derive(Debug)]
+pub enum AnthropicAgentError {
+    /// The request could not be sent or the reply could not be read.
+    Http(reqwest::Error),
+    /// The server replied with an error status. The body describes the error.
+    Status { status: u16, body: String },
+    /// The reply does not contain any text.
+    InvalidResponse(String),
+}
@@ -0,0 +1,263 @@
+use super::ollama_agent_error::OllamaAgentError;
+
+use crate::agent_traits::{ConsumerAgent, NamedAgent, ProducerAgent, TokenUsage};
+use crate::text_chat::collaborative_agent::{CollaborativeAgentResponse, Message};
+use crate::text_chat::conversation_history::{ConversationHistory, HistoryEntry, Role};
+use crate::text_chat::markdown::MarkdownResponseParser;
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use tracing::debug;
+
+pub const OLLAMA_BASE_URL: &str = "http://localhost:11434";
+
+/// Model options sent with every request. Unset options are left to the Modelfile of the model.
+///
+/// See <https://github.com/ollama/ollama/blob/main/docs/modelfile.md#valid-parameters-and-values>.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct OllamaOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    /// Size of the context window. Ollama defaults to a rather small one, so longer conversations
+    /// usually need this raised.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_ctx: Option<u32>,
+    /// Maximum number of tokens to generate.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_predict: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub stop: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct ChatRequest<'a> {
+    model: &'a str,
+    messages: Vec<OllamaMessage<'a>>,
+    stream: bool,
+    options: &'a OllamaOptions,
+    /// Seconds, negative keeps the model loaded indefinitely.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keep_alive: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct OllamaMessage<'a> {
+    role: Role,
+    content: &'a str,
+}
+
+#[derive(Deserialize)]
+struct ChatResponse {
+    message: Option<ResponseMessage>,
+    /// Left out when the prompt was cached.
+    prompt_eval_count: Option<u64>,
+    eval_count: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct ResponseMessage {
+    content: String,
+}
+
+/// Agent talking to the native `/api/chat` endpoint of Ollama.
+///
+/// Works the same way as [super::openai_agent::OpenAiAgent] - the history is sent on every request
+/// and replies are parsed with the [MarkdownResponseParser]. Ollama has no notion of
+/// participant names, so senders of the messages are not passed to the model.
+pub struct OllamaAgent {
+    name: String,
+    client: reqwest::Client,
+    base_url: String,
+    model: String,
+    system_prompt: Option<String>,
+    options: OllamaOptions,
+    keep_alive: Option<i64>,
+    parser: MarkdownResponseParser,
+    history: ConversationHistory,
+    usage: TokenUsage,
+}
+
+impl OllamaAgent {
+    /// `base_url` is the address of the Ollama server, e.g. [OLLAMA_BASE_URL].
+    pub fn new(base_url: impl Into<String>, model: impl Into<String>) -> Self {
+        Self {
+            name: "assistant".to_string(),
+            client: reqwest::Client::new(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            model: model.into(),
+            system_prompt: None,
+            options: OllamaOptions::default(),
+            keep_alive: None,
+            parser: MarkdownResponseParser::default(),
+            history: ConversationHistory::new(),
+            usage: TokenUsage::default(),
+        }
+    }
+
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    pub fn with_system_prompt(mut self, system_prompt: impl Into<String>) -> Self {
+        self.system_prompt = Some(system_prompt.into());
+        self
+    }
+
+    pub fn with_options(mut self, options: OllamaOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn with_temperature(mut self, temperature: f32) -> Self {
+        self.options.temperature = Some(temperature);
+        self
+    }
+
+    pub fn with_num_ctx(mut self, num_ctx: u32) -> Self {
+        self.options.num_ctx = Some(num_ctx);
+        self
+    }
+
+    /// How long the model stays loaded after the request. Ollama unloads it after 5 minutes by
+    /// default.
+    pub fn with_keep_alive(mut self, keep_alive: Duration) -> Self {
+        self.keep_alive = Some(keep_alive.as_secs().try_into().unwrap_or(i64::MAX));
+        self
+    }
+
+    /// Keeps the model loaded until the server is stopped.
+    pub fn with_keep_alive_forever(mut self) -> Self {
+        self.keep_alive = Some(-1);
+        self
+    }
+
+    pub fn with_parser(mut self, parser: MarkdownResponseParser) -> Self {
+        self.parser = parser;
+        self
+    }
+
+    pub fn with_client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Starts from the given history, e.g. one with a
+    /// [crate::text_chat::conversation_history::TruncationStrategy] or taken over from another
+    /// agent.
+    pub fn with_history(mut self, history: ConversationHistory) -> Self {
+        self.history = history;
+        self
+    }
+
+    /// Conversation so far, without the system prompt.
+    pub fn history(&self) -> &ConversationHistory {
+        &self.history
+    }
+
+    fn request_messages<'a>(&'a self, context: &'a [HistoryEntry]) -> Vec<OllamaMessage<'a>> {
+        let system_prompt = self
+            .system_prompt
+            .as_deref()
+            .map(|system_prompt| OllamaMessage {
+                role: Role::System,
+                content: system_prompt,
+            });
+
+        let history = context.iter().map(|entry| OllamaMessage {
+            role: entry.role,
+            content: &entry.content,
+        });
+
+        system_prompt.into_iter().chain(history).collect()
+    }
+}
+
+impl NamedAgent for OllamaAgent {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl ConsumerAgent for OllamaAgent {
+    type Mrx = Message;
+    type Error = OllamaAgentError;
+
+    async fn receive_message(&mut self, mrx: Self::Mrx) -> Result<(), Self::Error> {
+        self.history.push(HistoryEntry::from_message(mrx));
+        self.history.summarize().await;
+
+        Ok(())
+    }
+}
+
+impl ProducerAgent for OllamaAgent {
+    type Mtx = CollaborativeAgentResponse;
+    type Error = OllamaAgentError;
+
+    async fn send_message(&mut self) -> Result<Self::Mtx, Self::Error> {
+        let context = self.history.context();
+
+        let request = ChatRequest {
+            model: &self.model,
+            messages: self.request_messages(&context),
+            stream: false,
+            options: &self.options,
+            keep_alive: self.keep_alive,
+        };
+
+        debug!("requesting chat from ollama at {}..", self.base_url);
+
+        let response = self
+            .client
+            .post(format!("{}/api/chat", self.base_url))
+            .json(&request)
+            .send()
+            .await
+            .map_err(OllamaAgentError::Http)?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+
+            return Err(OllamaAgentError::Status {
+                status: status.as_u16(),
+                body,
+            });
+        }
+
+        let chat: ChatResponse = response.json().await.map_err(OllamaAgentError::Http)?;
+
+        let content = chat.message.map(|message| message.content).ok_or_else(|| {
+            OllamaAgentError::InvalidResponse("no message in the reply".to_string())
+        })?;
+
+        let reply = self.parser.parse(&content);
+        let mut entry = HistoryEntry::from_reply(content, &reply);
+
+        self.usage += TokenUsage {
+            prompt_tokens: chat.prompt_eval_count.unwrap_or_default(),
+            completion_tokens: chat.eval_count.unwrap_or_default(),
+        };
+
+        if let Some(eval_count) = chat.eval_count {
+            entry = entry.with_tokens(eval_count as usize);
+        }
+
+        self.history.push(entry);
+
+        Ok(reply)
+    }
+
+    fn usage(&self) -> Option<TokenUsage> {
+        Some(self.usage)
+    }
+}
@@ -0,0 +1,9 @@
+#[derive(Debug)]
+pub enum OllamaAgentError {
+    /// The request could not be sent or the reply could not be read.
+    Http(reqwest::Error),
+    /// The server replied with an error status, e.g. when the model is not pulled.
+    Status { status: u16, body: String },
+    /// The reply does not contain a message.
+    InvalidResponse(String),
+}
@@ -0,0 +1,649 @@
+use super::openai_agent_error::OpenAiAgentError;
+
+use crate::agent_traits::{
+    ConsumerAgent, NamedAgent, ProducerAgent, StreamingProducerAgent, TokenUsage,
+};
+use crate::text_chat::collaborative_agent::{CollaborativeAgentResponse, Message};
+use crate::text_chat::conversation_history::{ConversationHistory, HistoryEntry, Role};
+use crate::text_chat::markdown::MarkdownResponseParser;
+use crate::text_chat::tool::{ToolCall, ToolCallOutcome, ToolDefinition};
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+
+use futures::{Stream, StreamExt, TryStreamExt};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use tracing::debug;
+
+pub const OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+
+#[derive(Serialize)]
+struct ChatCompletionRequest<'a> {
+    model: &'a str,
+    messages: Vec<OpenAiMessage<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<StreamOptions>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<OpenAiTool<'a>>,
+}
+
+#[derive(Serialize)]
+struct OpenAiTool<'a> {
+    r#type: &'static str,
+    function: OpenAiFunction<'a>,
+}
+
+#[derive(Serialize)]
+struct OpenAiFunction<'a> {
+    name: &'a str,
+    description: &'a str,
+    parameters: &'a Value,
+}
+
+/// Asks for the usage in the last event of the stream.
+#[derive(Serialize)]
+struct StreamOptions {
+    include_usage: bool,
+}
+
+#[derive(Serialize)]
+struct OpenAiMessage<'a> {
+    role: OpenAiRole,
+    /// None only for tool calls without any text.
+    content: Option<Cow<'a, str>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<OpenAiToolCall>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_call_id: Option<&'a str>,
+}
+
+impl<'a> OpenAiMessage<'a> {
+    fn new(role: OpenAiRole, content: impl Into<Cow<'a, str>>) -> Self {
+        Self {
+            role,
+            content: Some(content.into()),
+            name: None,
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+        }
+    }
+}
+
+/// [Role] along with the role of tool results, which only OpenAI knows about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum OpenAiRole {
+    System,
+    User,
+    Assistant,
+    Tool,
+}
+
+impl From<Role> for OpenAiRole {
+    fn from(role: Role) -> Self {
+        match role {
+            Role::System => OpenAiRole::System,
+            Role::User => OpenAiRole::User,
+            Role::Assistant => OpenAiRole::Assistant,
+        }
+    }
+}
+
+/// Arguments are a JSON document in a string, both in requests and replies.
+#[derive(Serialize, Deserialize)]
+struct OpenAiToolCall {
+    id: String,
+    r#type: String,
+    function: OpenAiFunctionCall,
+}
+
+#[derive(Serialize, Deserialize)]
+struct OpenAiFunctionCall {
+    name: String,
+    arguments: String,
+}
+
+impl From<&ToolCall> for OpenAiToolCall {
+    fn from(tool_call: &ToolCall) -> Self {
+        Self {
+            id: tool_call.id.clone(),
+            r#type: "function".to_string(),
+            function: OpenAiFunctionCall {
+                name: tool_call.name.clone(),
+                arguments: tool_call.arguments.to_string(),
+            },
+        }
+    }
+}
+
+impl From<OpenAiToolCall> for ToolCall {
+    fn from(tool_call: OpenAiToolCall) -> Self {
+        ToolCall {
+            id: tool_call.id,
+            name: tool_call.function.name,
+            arguments: parse_arguments(tool_call.function.arguments),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ChatCompletionResponse {
+    choices: Vec<Choice>,
+    usage: Option<Usage>,
+}
+
+#[derive(Deserialize)]
+struct Usage {
+    prompt_tokens: u64,
+    completion_tokens: u64,
+}
+
+impl From<Usage> for TokenUsage {
+    fn from(usage: Usage) -> Self {
+        Self {
+            prompt_tokens: usage.prompt_tokens,
+            completion_tokens: usage.completion_tokens,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Choice {
+    message: ResponseMessage,
+}
+
+#[derive(Deserialize)]
+struct ResponseMessage {
+    content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<OpenAiToolCall>,
+}
+
+/// Event of a streamed completion.
+#[derive(Deserialize)]
+struct ChatCompletionChunk {
+    choices: Vec<ChunkChoice>,
+    /// Only in the last event, with no choices.
+    usage: Option<Usage>,
+}
+
+#[derive(Deserialize)]
+struct ChunkChoice {
+    delta: ChunkDelta,
+}
+
+#[derive(Deserialize)]
+struct ChunkDelta {
+    content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<ToolCallChunk>,
+}
+
+/// Piece of a streamed tool call. The id and name come in the first piece of the call, the
+/// arguments are spread over all of them.
+#[derive(Deserialize)]
+struct ToolCallChunk {
+    index: usize,
+    id: Option<String>,
+    function: Option<FunctionCallChunk>,
+}
+
+#[derive(Deserialize)]
+struct FunctionCallChunk {
+    name: Option<String>,
+    arguments: Option<String>,
+}
+
+/// Agent talking to an OpenAI compatible `/chat/completions` endpoint - OpenAI itself, vLLM,
+/// llama.cpp server, LM Studio and others.
+///
+/// Every received message is appended to the history, which is sent on every request - whole,
+/// unless truncated, see [ConversationHistory::with_truncation]. Token usage reported by the
+/// server is summed up in [ProducerAgent::usage].
+/// Replies are parsed with the [MarkdownResponseParser], so the system prompt should explain the
+/// convention for requesting execution, see [MarkdownResponseParser::instructions].
+///
+/// Tools given with [OpenAiAgent::with_tools] are passed to the model natively, and the calls it
+/// makes become [CollaborativeAgentResponse::ToolCalls].
+pub struct OpenAiAgent {
+    name: String,
+    client: reqwest::Client,
+    base_url: String,
+    api_key: Option<String>,
+    model: String,
+    system_prompt: Option<String>,
+    temperature: Option<f32>,
+    max_tokens: Option<u32>,
+    parser: MarkdownResponseParser,
+    tools: Vec<ToolDefinition>,
+    history: ConversationHistory,
+    usage: Option<TokenUsage>,
+    /// Usage of the reply being produced, set once the server reports it.
+    reply_usage: Option<TokenUsage>,
+    /// Tool calls of the reply being streamed, put together from the pieces.
+    reply_tool_calls: Vec<OpenAiToolCall>,
+}
+
+impl OpenAiAgent {
+    /// `base_url` is the part before `/chat/completions`, e.g. [OPENAI_BASE_URL] or
+    /// `http://localhost:8000/v1`.
+    pub fn new(base_url: impl Into<String>, model: impl Into<String>) -> Self {
+        Self {
+            name: "assistant".to_string(),
+            client: reqwest::Client::new(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            api_key: None,
+            model: model.into(),
+            system_prompt: None,
+            temperature: None,
+            max_tokens: None,
+            parser: MarkdownResponseParser::default(),
+            tools: Vec::new(),
+            history: ConversationHistory::new(),
+            usage: None,
+            reply_usage: None,
+            reply_tool_calls: Vec::new(),
+        }
+    }
+
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Sent as a bearer token. Local servers usually do not need it.
+    pub fn with_api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+
+    pub fn with_system_prompt(mut self, system_prompt: impl Into<String>) -> Self {
+        self.system_prompt = Some(system_prompt.into());
+        self
+    }
+
+    pub fn with_temperature(mut self, temperature: f32) -> Self {
+        self.temperature = Some(temperature);
+        self
+    }
+
+    pub fn with_max_tokens(mut self, max_tokens: u32) -> Self {
+        self.max_tokens = Some(max_tokens);
+        self
+    }
+
+    pub fn with_parser(mut self, parser: MarkdownResponseParser) -> Self {
+        self.parser = parser;
+        self
+    }
+
+    /// Usually [crate::text_chat::tool::ToolRegistry::definitions] of the registry passed to the
+    /// chat.
+    pub fn with_tools(mut self, tools: Vec<ToolDefinition>) -> Self {
+        self.tools = tools;
+        self
+    }
+
+    pub fn with_client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Starts from the given history, e.g. one with a
+    /// [crate::text_chat::conversation_history::TruncationStrategy] or taken over from another
+    /// agent.
+    pub fn with_history(mut self, history: ConversationHistory) -> Self {
+        self.history = history;
+        self
+    }
+
+    /// Conversation so far, without the system prompt.
+    pub fn history(&self) -> &ConversationHistory {
+        &self.history
+    }
+
+    fn request(&self, stream: bool) -> reqwest::RequestBuilder {
+        let context = self.history.context();
+
+        let request = ChatCompletionRequest {
+            model: &self.model,
+            messages: self.request_messages(&context),
+            temperature: self.temperature,
+            max_tokens: self.max_tokens,
+            stream,
+            stream_options: stream.then_some(StreamOptions {
+                include_usage: true,
+            }),
+            tools: self
+                .tools
+                .iter()
+                .map(|tool| OpenAiTool {
+                    r#type: "function",
+                    function: OpenAiFunction {
+                        name: &tool.name,
+                        description: &tool.description,
+                        parameters: &tool.parameters,
+                    },
+                })
+                .collect(),
+        };
+
+        let builder = self
+            .client
+            .post(format!("{}/chat/completions", self.base_url))
+            .json(&request);
+
+        match &self.api_key {
+            Some(api_key) => builder.bearer_auth(api_key),
+            None => builder,
+        }
+    }
+
+    /// Parses the reply and adds it to the history, along with its usage. Text of a reply with tool
+    /// calls is their comment.
+    fn finish_reply(
+        &mut self,
+        content: String,
+        tool_calls: Vec<OpenAiToolCall>,
+    ) -> CollaborativeAgentResponse {
+        let reply = match tool_calls.is_empty() {
+            true => self.parser.parse(&content),
+            false => CollaborativeAgentResponse::ToolCalls {
+                comment: content.trim().to_string(),
+                tool_calls: tool_calls.into_iter().map(ToolCall::from).collect(),
+            },
+        };
+        let mut entry = HistoryEntry::from_reply(content, &reply);
+
+        if let Some(reply_usage) = self.reply_usage.take() {
+            *self.usage.get_or_insert_with(TokenUsage::default) += reply_usage;
+            entry = entry.with_tokens(reply_usage.completion_tokens as usize);
+        }
+
+        self.history.push(entry);
+
+        reply
+    }
+
+    /// Tool calls and their results are sent natively. Results of calls that are not in the
+    /// context (e.g. cut off by the truncation) are sent as the plain text of the entry, since
+    /// OpenAI rejects results without their call.
+    fn request_messages<'a>(&'a self, context: &'a [HistoryEntry]) -> Vec<OpenAiMessage<'a>> {
+        let mut messages: Vec<OpenAiMessage> = self
+            .system_prompt
+            .as_deref()
+            .map(|system_prompt| OpenAiMessage::new(OpenAiRole::System, system_prompt))
+            .into_iter()
+            .collect();
+
+        let mut pending_calls: HashSet<&str> = HashSet::new();
+
+        for entry in context {
+            let answered = !entry.tool_call_reports.is_empty()
+                && entry
+                    .tool_call_reports
+                    .iter()
+                    .all(|report| pending_calls.contains(report.tool_call.id.as_str()));
+
+            match (entry.tool_calls.is_empty(), answered) {
+                (_, true) => {
+                    messages.extend(entry.tool_call_reports.iter().map(|report| OpenAiMessage {
+                        tool_call_id: Some(&report.tool_call.id),
+                        ..OpenAiMessage::new(OpenAiRole::Tool, tool_result_content(&report.outcome))
+                    }));
+                    pending_calls.clear();
+                }
+                (false, false) => {
+                    pending_calls = entry
+                        .tool_calls
+                        .iter()
+                        .map(|tool_call| tool_call.id.as_str())
+                        .collect();
+
+                    messages.push(OpenAiMessage {
+                        content: (!entry.content.is_empty())
+                            .then_some(Cow::Borrowed(entry.content.as_str())),
+                        tool_calls: entry.tool_calls.iter().map(OpenAiToolCall::from).collect(),
+                        ..OpenAiMessage::new(OpenAiRole::Assistant, "")
+                    });
+                }
+                (true, false) => {
+                    pending_calls.clear();
+
+                    messages.push(OpenAiMessage {
+                        name: entry.sender.as_deref().and_then(participant_name),
+                        ..OpenAiMessage::new(entry.role.into(), entry.content.as_str())
+                    });
+                }
+            }
+        }
+
+        messages
+    }
+}
+
+impl NamedAgent for OpenAiAgent {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl ConsumerAgent for OpenAiAgent {
+    type Mrx = Message;
+    type Error = OpenAiAgentError;
+
+    async fn receive_message(&mut self, mrx: Self::Mrx) -> Result<(), Self::Error> {
+        self.history.push(HistoryEntry::from_message(mrx));
+        self.history.summarize().await;
+
+        Ok(())
+    }
+}
+
+impl ProducerAgent for OpenAiAgent {
+    type Mtx = CollaborativeAgentResponse;
+    type Error = OpenAiAgentError;
+
+    async fn send_message(&mut self) -> Result<Self::Mtx, Self::Error> {
+        debug!("requesting chat completion from {}..", self.base_url);
+
+        let response = send(self.request(false)).await?;
+
+        let completion: ChatCompletionResponse =
+            response.json().await.map_err(OpenAiAgentError::Http)?;
+
+        self.reply_usage = completion.usage.map(TokenUsage::from);
+
+        let message = completion
+            .choices
+            .into_iter()
+            .next()
+            .map(|choice| choice.message)
+            .filter(|message| message.content.is_some() || !message.tool_calls.is_empty())
+            .ok_or_else(|| {
+                OpenAiAgentError::InvalidResponse("no message content in the reply".to_string())
+            })?;
+
+        Ok(self.finish_reply(message.content.unwrap_or_default(), message.tool_calls))
+    }
+
+    fn usage(&self) -> Option<TokenUsage> {
+        self.usage
+    }
+}
+
+/// Streams the completion as server-sent events. Deltas are pieces of the message content.
+impl StreamingProducerAgent for OpenAiAgent {
+    type Delta = String;
+
+    fn send_message_stream(&mut self) -> impl Stream<Item = Result<Self::Delta, Self::Error>> + '_ {
+        debug!(
+            "requesting streamed chat completion from {}..",
+            self.base_url
+        );
+
+        let request = self.request(true);
+        let reply_usage = &mut self.reply_usage;
+        let reply_tool_calls = &mut self.reply_tool_calls;
+        reply_tool_calls.clear();
+
+        futures::stream::once(send(request))
+            .map_ok(|response| event_data(response.bytes_stream()))
+            .try_flatten()
+            .try_filter_map(move |data| {
+                let chunk = serde_json::from_str::<ChatCompletionChunk>(&data)
+                    .map_err(|e| OpenAiAgentError::InvalidResponse(e.to_string()))
+                    .map(|chunk| {
+                        if let Some(usage) = chunk.usage {
+                            *reply_usage = Some(usage.into());
+                        }
+
+                        let delta = chunk.choices.into_iter().next()?.delta;
+
+                        for tool_call in delta.tool_calls {
+                            add_tool_call_chunk(reply_tool_calls, tool_call);
+                        }
+
+                        delta.content.filter(|content| !content.is_empty())
+                    });
+
+                futures::future::ready(chunk)
+            })
+    }
+
+    fn assemble_message(&mut self, deltas: Vec<Self::Delta>) -> Result<Self::Mtx, Self::Error> {
+        let tool_calls = std::mem::take(&mut self.reply_tool_calls);
+
+        Ok(self.finish_reply(deltas.concat(), tool_calls))
+    }
+}
+
+/// Sends the request and turns error statuses into errors.
+async fn send(request: reqwest::RequestBuilder) -> Result<reqwest::Response, OpenAiAgentError> {
+    let response = request.send().await.map_err(OpenAiAgentError::Http)?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+
+        return Err(OpenAiAgentError::Status {
+            status: status.as_u16(),
+            body,
+        });
+    }
+
+    Ok(response)
+}
+
+/// Data of server-sent events, until the `[DONE]` one. Other fields of the events are ignored.
+fn event_data<B>(
+    bytes: impl Stream<Item = Result<B, reqwest::Error>> + Send + 'static,
+) -> impl Stream<Item = Result<String, OpenAiAgentError>>
+where
+    B: AsRef<[u8]>,
+{
+    let state = (Box::pin(bytes), Vec::new());
+
+    futures::stream::try_unfold(state, |(mut bytes, mut buffer)| async move {
+        loop {
+            // Lines are split on bytes, so multibyte characters cut between chunks stay intact.
+            if let Some(end) = buffer.iter().position(|byte| *byte == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=end).collect();
+                let line = String::from_utf8_lossy(&line);
+
+                match line.strip_prefix("data:").map(str::trim) {
+                    Some("[DONE]") => return Ok(None),
+                    Some(data) => return Ok(Some((data.to_string(), (bytes, buffer)))),
+                    None => continue,
+                }
+            }
+
+            match bytes.next().await {
+                Some(chunk) => {
+                    buffer.extend_from_slice(chunk.map_err(OpenAiAgentError::Http)?.as_ref())
+                }
+                None => return Ok(None),
+            }
+        }
+    })
+}
+
+fn add_tool_call_chunk(tool_calls: &mut Vec<OpenAiToolCall>, chunk: ToolCallChunk) {
+    while tool_calls.len() <= chunk.index {
+        tool_calls.push(OpenAiToolCall {
+            id: String::new(),
+            r#type: "function".to_string(),
+            function: OpenAiFunctionCall {
+                name: String::new(),
+                arguments: String::new(),
+            },
+        });
+    }
+
+    let tool_call = &mut tool_calls[chunk.index];
+
+    if let Some(id) = chunk.id {
+        tool_call.id = id;
+    }
+
+    if let Some(function) = chunk.function {
+        tool_call
+            .function
+            .name
+            .push_str(&function.name.unwrap_or_default());
+        tool_call
+            .function
+            .arguments
+            .push_str(&function.arguments.unwrap_or_default());
+    }
+}
+
+/// Arguments which are not valid JSON are kept as a string, so the call fails on them and the
+/// model gets to know.
+fn parse_arguments(arguments: String) -> Value {
+    match arguments.trim().is_empty() {
+        true => Value::Object(Default::default()),
+        false => serde_json::from_str(&arguments).unwrap_or(Value::String(arguments)),
+    }
+}
+
+fn tool_result_content(outcome: &ToolCallOutcome) -> String {
+    match outcome {
+        ToolCallOutcome::Returned(value) => value.to_string(),
+        ToolCallOutcome::Failed(error) => format!("The call failed: {}", error),
+        ToolCallOutcome::Denied { reason } => {
+            format!("The call was denied. Reason: {}", reason)
+        }
+    }
+}
+
+/// OpenAI only accepts names made of letters, digits, underscores and dashes, up to 64
+/// characters. Other characters are replaced.
+fn participant_name(name: &str) -> Option<String> {
+    let name: String = name
+        .chars()
+        .map(
+            |c| match c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                true => c,
+                false => '_',
+            },
+        )
+        .take(64)
+        .collect();
+
+    match name.is_empty() {
+        true => None,
+        false => Some(name),
+    }
+}
@@ -0,0 +1,381 @@
+//! Translation of free-form markdown replies of LLMs into [CollaborativeAgentResponse].
+
+use super::code::CodeBlock;
+use super::collaborative_agent::{CollaborativeAgentResponse, CommentedCodeBlock, ResponseSegment};
+
+/// Word in the info string of a fenced code block with which the agent requests execution, e.g.
+/// ` ```python execute`.
+pub const DEFAULT_EXECUTION_MARKER: &str = "execute";
+
+/// Part of a markdown document - either prose or a code block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MarkdownSegment {
+    Text(String),
+    Code(MarkdownCodeBlock),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MarkdownCodeBlock {
+    /// Whole info string of a fenced block, e.g. `python execute`. Empty for unlabeled and
+    /// indented blocks.
+    pub info: String,
+    pub code: String,
+    /// The block as written in the document, including the fences.
+    pub raw: String,
+}
+
+impl MarkdownCodeBlock {
+    /// First word of the info string. Attribute syntax like `{.python}` is accepted as well.
+    pub fn language(&self) -> &str {
+        self.info
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .trim_start_matches('{')
+            .trim_start_matches('.')
+            .trim_end_matches('}')
+    }
+
+    /// Words of the info string after the language.
+    pub fn attributes(&self) -> impl Iterator<Item = &str> {
+        self.info
+            .split_whitespace()
+            .skip(1)
+            .map(|word| word.trim_matches(|c| c == '{' || c == '}'))
+    }
+}
+
+/// Extracts code blocks from markdown: fenced ones (with backticks or tildes, labeled or not,
+/// nested in longer fences) as well as indented ones. Everything else is kept as text.
+///
+/// An unclosed fence runs until the end of the document - LLMs tend to cut their replies short.
+pub fn parse_markdown(text: &str) -> Vec<MarkdownSegment> {
+    let lines: Vec<&str> = text.split_inclusive('\n').collect();
+
+    let mut segments = Vec::new();
+    let mut prose = String::new();
+    let mut in_list = false;
+    let mut previous_blank = true;
+
+    let mut index = 0;
+    while index < lines.len() {
+        let line = lines[index];
+
+        // Fences of list items are often indented further than usual.
+        let max_fence_indent = match in_list {
+            true => usize::MAX,
+            false => 3,
+        };
+
+        if let Some(fence) = Fence::opening(line, max_fence_indent) {
+            let (code_block, next_index) = fence.read_block(&lines, index);
+
+            push_text(&mut segments, &mut prose);
+            segments.push(MarkdownSegment::Code(code_block));
+
+            index = next_index;
+            previous_blank = true;
+            in_list = false;
+            continue;
+        }
+
+        if previous_blank && !in_list && indented_code_line(line).is_some() {
+            let (code_block, next_index) = read_indented_block(&lines, index);
+
+            push_text(&mut segments, &mut prose);
+            segments.push(MarkdownSegment::Code(code_block));
+
+            index = next_index;
+            previous_blank = true;
+            continue;
+        }
+
+        let blank = line.trim().is_empty();
+        match (blank, list_item(line), line.starts_with([' ', '\t'])) {
+            (true, _, _) => {}
+            (false, true, _) => in_list = true,
+            // Continuation of a list item.
+            (false, false, true) => {}
+            (false, false, false) => in_list = false,
+        }
+
+        prose.push_str(line);
+        previous_blank = blank;
+        index += 1;
+    }
+
+    push_text(&mut segments, &mut prose);
+
+    segments
+}
+
+/// Turns markdown replies into [CollaborativeAgentResponse].
+///
+/// A single code block becomes the [CommentedCodeBlock] and the rest of the reply its comment.
+/// Replies with more code blocks become [CollaborativeAgentResponse::Segments] and replies without
+/// code are plain [CollaborativeAgentResponse::Text].
+///
+/// By default the agent requests execution by putting [DEFAULT_EXECUTION_MARKER] into the info
+/// string, so code that is only shown is not executed by accident. See
+/// [MarkdownResponseParser::instructions] for the text explaining the convention to the LLM.
+#[derive(Debug, Clone)]
+pub struct MarkdownResponseParser {
+    execution_marker: Option<String>,
+    default_language: Option<String>,
+}
+
+impl Default for MarkdownResponseParser {
+    fn default() -> Self {
+        Self {
+            execution_marker: Some(DEFAULT_EXECUTION_MARKER.to_string()),
+            default_language: None,
+        }
+    }
+}
+
+impl MarkdownResponseParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_execution_marker(mut self, marker: impl Into<String>) -> Self {
+        self.execution_marker = Some(marker.into());
+        self
+    }
+
+    /// Every code block requests execution, no marker is needed.
+    pub fn execute_all(mut self) -> Self {
+        self.execution_marker = None;
+        self
+    }
+
+    /// Language assumed for unlabeled and indented code blocks. They are left with an empty
+    /// language otherwise.
+    pub fn with_default_language(mut self, language: impl Into<String>) -> Self {
+        self.default_language = Some(language.into());
+        self
+    }
+
+    /// May be added to the system prompt, so the LLM knows how to request execution.
+    pub fn instructions(&self) -> String {
+        match &self.execution_marker {
+            Some(marker) => format!(
+                "Put code in fenced code blocks labeled with the language. If you want the code to be executed, add \"{}\" after the language, e.g. ```python {}. Such blocks are executed in order.",
+                marker, marker
+            ),
+            None => "Put code in fenced code blocks labeled with the language. Every code block will be executed in order.".to_string(),
+        }
+    }
+
+    pub fn parse(&self, text: &str) -> CollaborativeAgentResponse {
+        let segments = parse_markdown(text);
+
+        let code_blocks: Vec<&MarkdownCodeBlock> = segments
+            .iter()
+            .filter_map(|segment| match segment {
+                MarkdownSegment::Code(code_block) => Some(code_block),
+                MarkdownSegment::Text(_) => None,
+            })
+            .collect();
+
+        match code_blocks.as_slice() {
+            [] => CollaborativeAgentResponse::Text(text.trim().to_string()),
+            [code_block] => {
+                let comment: Vec<&str> = segments
+                    .iter()
+                    .filter_map(|segment| match segment {
+                        MarkdownSegment::Text(text) => Some(text.trim()),
+                        MarkdownSegment::Code(_) => None,
+                    })
+                    .collect();
+
+                CollaborativeAgentResponse::CommentedCodeBlock(CommentedCodeBlock {
+                    comment: comment.join("\n\n"),
+                    code_block: self.code_block(code_block),
+                    request_execution: self.requests_execution(code_block),
+                })
+            }
+            _ => CollaborativeAgentResponse::Segments(
+                segments
+                    .iter()
+                    .map(|segment| match segment {
+                        MarkdownSegment::Text(text) => {
+                            ResponseSegment::Text(text.trim().to_string())
+                        }
+                        MarkdownSegment::Code(code_block) => ResponseSegment::CodeBlock {
+                            code_block: self.code_block(code_block),
+                            request_execution: self.requests_execution(code_block),
+                        },
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    fn code_block(&self, code_block: &MarkdownCodeBlock) -> CodeBlock {
+        let language = match (code_block.language(), &self.default_language) {
+            ("", Some(default_language)) => default_language.clone(),
+            (language, _) => language.to_string(),
+        };
+
+        CodeBlock {
+            language,
+            code: code_block.code.clone(),
+        }
+    }
+
+    fn requests_execution(&self, code_block: &MarkdownCodeBlock) -> bool {
+        match &self.execution_marker {
+            Some(marker) => code_block
+                .attributes()
+                .any(|attribute| attribute.eq_ignore_ascii_case(marker)),
+            None => true,
+        }
+    }
+}
+
+/// Opening fence of a fenced code block.
+struct Fence {
+    character: char,
+    length: usize,
+    indent: usize,
+    info: String,
+}
+
+impl Fence {
+    /// Indentation followed by at least 3 backticks or tildes. Backtick fences cannot have
+    /// backticks in the info string - that is inline code.
+    fn opening(line: &str, max_indent: usize) -> Option<Self> {
+        let indent = line.len() - line.trim_start_matches(' ').len();
+        if indent > max_indent {
+            return None;
+        }
+
+        let rest = &line[indent..];
+        let character = rest.chars().next().filter(|c| *c == '`' || *c == '~')?;
+        let length = rest.len() - rest.trim_start_matches(character).len();
+        if length < 3 {
+            return None;
+        }
+
+        let info = rest[length..].trim();
+        if character == '`' && info.contains('`') {
+            return None;
+        }
+
+        Some(Self {
+            character,
+            length,
+            indent,
+            info: info.to_string(),
+        })
+    }
+
+    /// Same character, at least as long as the opening one and nothing but whitespace after it.
+    fn is_closed_by(&self, line: &str) -> bool {
+        let trimmed = line.trim_start_matches(' ');
+        if line.len() - trimmed.len() > self.indent.max(3) {
+            return false;
+        }
+
+        let rest = trimmed.trim_start_matches(self.character);
+        trimmed.len() - rest.len() >= self.length && rest.trim().is_empty()
+    }
+
+    /// Reads the block starting at the opening fence. Returns it along with the index of the line
+    /// after the closing fence.
+    fn read_block(&self, lines: &[&str], start: usize) -> (MarkdownCodeBlock, usize) {
+        let mut raw = lines[start].to_string();
+        let mut code = String::new();
+
+        let mut index = start + 1;
+        while index < lines.len() {
+            let line = lines[index];
+            raw.push_str(line);
+            index += 1;
+
+            if self.is_closed_by(line) {
+                break;
+            }
+
+            // Content is unindented by the indentation of the opening fence.
+            let removable = line.len() - line.trim_start_matches(' ').len();
+            code.push_str(&line[removable.min(self.indent)..]);
+        }
+
+        let code_block = MarkdownCodeBlock {
+            info: self.info.clone(),
+            code: code.trim_end_matches(['\n', '\r']).to_string(),
+            raw,
+        };
+
+        (code_block, index)
+    }
+}
+
+/// Content of a line indented by at least 4 spaces or a tab.
+fn indented_code_line(line: &str) -> Option<&str> {
+    match (line.strip_prefix("    "), line.strip_prefix('\t')) {
+        (Some(content), _) | (None, Some(content)) if !content.trim().is_empty() => Some(content),
+        _ => None,
+    }
+}
+
+/// Indented block continues through blank lines as long as indented lines follow.
+fn read_indented_block(lines: &[&str], start: usize) -> (MarkdownCodeBlock, usize) {
+    let mut raw = String::new();
+    let mut code = String::new();
+    let mut pending_blank_lines = String::new();
+
+    let mut index = start;
+    while index < lines.len() {
+        let line = lines[index];
+
+        match (indented_code_line(line), line.trim().is_empty()) {
+            (Some(content), _) => {
+                code.push_str(&pending_blank_lines);
+                code.push_str(content);
+                raw.push_str(&pending_blank_lines);
+                raw.push_str(line);
+                pending_blank_lines.clear();
+            }
+            (None, true) => pending_blank_lines.push_str(line),
+            (None, false) => break,
+        }
+
+        index += 1;
+    }
+
+    let code_block = MarkdownCodeBlock {
+        info: String::new(),
+        code: code.trim_end_matches(['\n', '\r']).to_string(),
+        raw,
+    };
+
+    // Trailing blank lines are left for the text.
+    (code_block, index - pending_blank_lines.lines().count())
+}
+
+fn list_item(line: &str) -> bool {
+    let trimmed = line.trim_start();
+
+    let bullet = ["- ", "* ", "+ "]
+        .iter()
+        .any(|bullet| trimmed.starts_with(bullet));
+
+    let digits = trimmed.len()
+        - trimmed
+            .trim_start_matches(|c: char| c.is_ascii_digit())
+            .len();
+    let numbered =
+        digits > 0 && (trimmed[digits..].starts_with(". ") || trimmed[digits..].starts_with(") "));
+
+    bullet || numbered
+}
+
+fn push_text(segments: &mut Vec<MarkdownSegment>, prose: &mut String) {
+    if !prose.trim().is_empty() {
+        segments.push(MarkdownSegment::Text(std::mem::take(prose)));
+    }
+    prose.clear();
+}
@@ -0,0 +1,306 @@
+//! Conditions ending the collaborative chat once the task is done, see
+//! [super::collaborative_chat::CollaborativeChatOptions::termination].
+//!
+//! Conditions are checked on every message for the collaborative agent and every reply of it.
+//! They can be combined with [TerminationCondition::and] and [TerminationCondition::or].
+
+use super::collaborative_agent::{CodeBlockOutcome, CollaborativeAgentResponse, Message};
+
+use std::fmt::Debug;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+/// What the conditions are checked on.
+#[derive(Debug, Clone, Copy)]
+pub enum ChatEvent<'a> {
+    /// Message about to be passed to the collaborative agent - a reply of the user agent, results
+    /// of the code execution etc.
+    Message(&'a Message),
+    /// Reply of the collaborative agent, before it is acted upon.
+    Reply(&'a CollaborativeAgentResponse),
+}
+
+/// Kept by the chat, so the conditions themselves can be shared between chats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChatState {
+    /// Replies of the collaborative agent so far.
+    pub turns: usize,
+    pub started_at: Instant,
+    /// Time of the last message or reply.
+    pub last_activity: Instant,
+}
+
+impl ChatState {
+    pub fn new() -> Self {
+        let now = Instant::now();
+
+        Self {
+            turns: 0,
+            started_at: now,
+            last_activity: now,
+        }
+    }
+}
+
+impl Default for ChatState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Which condition ended the chat.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum MetCondition {
+    MaxTurns(usize),
+    TextMention(String),
+    UserExit,
+    ExecutionSucceeded,
+    IdleTimeout(Duration),
+    /// Description of the [Predicate].
+    Predicate(String),
+    /// Conditions combined with [TerminationCondition::and].
+    All(Vec<MetCondition>),
+}
+
+impl std::fmt::Display for MetCondition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MetCondition::MaxTurns(turns) => write!(f, "{} turns reached", turns),
+            MetCondition::TextMention(keyword) => write!(f, "`{}` mentioned", keyword),
+            MetCondition::UserExit => write!(f, "user exited"),
+            MetCondition::ExecutionSucceeded => write!(f, "code executed successfully"),
+            MetCondition::IdleTimeout(timeout) => write!(f, "idle for {:?}", timeout),
+            MetCondition::Predicate(description) => write!(f, "{}", description),
+            MetCondition::All(conditions) => {
+                let conditions: Vec<String> = conditions.iter().map(ToString::to_string).collect();
+                write!(f, "{}", conditions.join(" and "))
+            }
+        }
+    }
+}
+
+pub trait TerminationCondition: Debug + Send + Sync {
+    fn check(&self, event: ChatEvent<'_>, state: &ChatState) -> Option<MetCondition>;
+
+    /// Time at which the condition is met if nothing happens in the meantime. The chat stops
+    /// waiting for the agents then.
+    fn deadline(&self, _state: &ChatState) -> Option<(Instant, MetCondition)> {
+        None
+    }
+
+    /// Both conditions have to be met on the same event. Conditions like [MaxTurns] stay met once
+    /// they are.
+    fn and<C>(self, other: C) -> And<Self, C>
+    where
+        Self: Sized,
+        C: TerminationCondition,
+    {
+        And(self, other)
+    }
+
+    fn or<C>(self, other: C) -> Or<Self, C>
+    where
+        Self: Sized,
+        C: TerminationCondition,
+    {
+        Or(self, other)
+    }
+}
+
+/// Met once the collaborative agent replied the given number of times. Its last reply is passed to
+/// the user agent, but not acted upon.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaxTurns(pub usize);
+
+impl TerminationCondition for MaxTurns {
+    fn check(&self, _event: ChatEvent<'_>, state: &ChatState) -> Option<MetCondition> {
+        match state.turns >= self.0 {
+            true => Some(MetCondition::MaxTurns(self.0)),
+            false => None,
+        }
+    }
+}
+
+/// Met when a reply of the collaborative agent contains the keyword, e.g. `TERMINATE`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextMention(pub String);
+
+impl TextMention {
+    pub fn new(keyword: impl Into<String>) -> Self {
+        Self(keyword.into())
+    }
+}
+
+impl TerminationCondition for TextMention {
+    fn check(&self, event: ChatEvent<'_>, _state: &ChatState) -> Option<MetCondition> {
+        match event {
+            ChatEvent::Reply(reply) if reply.to_markdown().contains(&self.0) => {
+                Some(MetCondition::TextMention(self.0.clone()))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Met when the user agent replies with `exit`, ignoring case and surrounding whitespace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UserExit;
+
+impl TerminationCondition for UserExit {
+    fn check(&self, event: ChatEvent<'_>, _state: &ChatState) -> Option<MetCondition> {
+        match event {
+            ChatEvent::Message(Message::Text { message, .. })
+                if message.trim().eq_ignore_ascii_case("exit") =>
+            {
+                Some(MetCondition::UserExit)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Met when the executed code succeeded - all of the code blocks, if there were several of them.
+/// The results are passed to the user agent, but not to the collaborative agent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExecutionSucceeded;
+
+impl TerminationCondition for ExecutionSucceeded {
+    fn check(&self, event: ChatEvent<'_>, _state: &ChatState) -> Option<MetCondition> {
+        let succeeded = match event {
+            ChatEvent::Message(Message::CodeExecutionResult(result)) => result.is_success(),
+            ChatEvent::Message(Message::CodeExecutionResults(reports)) => {
+                !reports.is_empty()
+                    && reports.iter().all(|report| match &report.outcome {
+                        CodeBlockOutcome::Executed(result) => result.is_success(),
+                        CodeBlockOutcome::Denied { .. } | CodeBlockOutcome::Skipped => false,
+                    })
+            }
+            _ => false,
+        };
+
+        match succeeded {
+            true => Some(MetCondition::ExecutionSucceeded),
+            false => None,
+        }
+    }
+}
+
+/// Met when nothing happens for the given time, e.g. the user does not reply. Waiting for the
+/// agents is abandoned then.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IdleTimeout(pub Duration);
+
+impl TerminationCondition for IdleTimeout {
+    fn check(&self, _event: ChatEvent<'_>, state: &ChatState) -> Option<MetCondition> {
+        match state.last_activity.elapsed() >= self.0 {
+            true => Some(MetCondition::IdleTimeout(self.0)),
+            false => None,
+        }
+    }
+
+    fn deadline(&self, state: &ChatState) -> Option<(Instant, MetCondition)> {
+        Some((
+            state.last_activity + self.0,
+            MetCondition::IdleTimeout(self.0),
+        ))
+    }
+}
+
+type PredicateFn = dyn Fn(ChatEvent<'_>, &ChatState) -> bool + Send + Sync;
+
+/// Custom condition, described for the [MetCondition::Predicate].
+pub struct Predicate {
+    description: String,
+    predicate: Box<PredicateFn>,
+}
+
+impl Predicate {
+    pub fn new<F>(description: impl Into<String>, predicate: F) -> Self
+    where
+        F: Fn(ChatEvent<'_>, &ChatState) -> bool + Send + Sync + 'static,
+    {
+        Self {
+            description: description.into(),
+            predicate: Box::new(predicate),
+        }
+    }
+}
+
+impl Debug for Predicate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Predicate")
+            .field("description", &self.description)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TerminationCondition for Predicate {
+    fn check(&self, event: ChatEvent<'_>, state: &ChatState) -> Option<MetCondition> {
+        match (self.predicate)(event, state) {
+            true => Some(MetCondition::Predicate(self.description.clone())),
+            false => None,
+        }
+    }
+}
+
+/// See [TerminationCondition::and].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct And<A, B>(pub A, pub B);
+
+impl<A, B> TerminationCondition for And<A, B>
+where
+    A: TerminationCondition,
+    B: TerminationCondition,
+{
+    fn check(&self, event: ChatEvent<'_>, state: &ChatState) -> Option<MetCondition> {
+        let a = self.0.check(event, state)?;
+        let b = self.1.check(event, state)?;
+
+        Some(all(a, b))
+    }
+
+    /// Only when both conditions have a deadline - the later one.
+    fn deadline(&self, state: &ChatState) -> Option<(Instant, MetCondition)> {
+        let (a_deadline, a) = self.0.deadline(state)?;
+        let (b_deadline, b) = self.1.deadline(state)?;
+
+        Some((a_deadline.max(b_deadline), all(a, b)))
+    }
+}
+
+fn all(a: MetCondition, b: MetCondition) -> MetCondition {
+    let flatten = |condition| match condition {
+        MetCondition::All(conditions) => conditions,
+        condition => vec![condition],
+    };
+
+    MetCondition::All(flatten(a).into_iter().chain(flatten(b)).collect())
+}
+
+/// See [TerminationCondition::or]. The first condition wins if both are met.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Or<A, B>(pub A, pub B);
+
+impl<A, B> TerminationCondition for Or<A, B>
+where
+    A: TerminationCondition,
+    B: TerminationCondition,
+{
+    fn check(&self, event: ChatEvent<'_>, state: &ChatState) -> Option<MetCondition> {
+        self.0
+            .check(event, state)
+            .or_else(|| self.1.check(event, state))
+    }
+
+    /// The earlier of the deadlines.
+    fn deadline(&self, state: &ChatState) -> Option<(Instant, MetCondition)> {
+        match (self.0.deadline(state), self.1.deadline(state)) {
+            (Some(a), Some(b)) => match a.0 <= b.0 {
+                true => Some(a),
+                false => Some(b),
+            },
+            (a, b) => a.or(b),
+        }
+    }
+}
@@ -0,0 +1,21 @@
+//! Counting tokens, so the conversation can be kept within the context window and the budget.
+
+#[cfg(feature = "tokenizer")]
+pub mod bpe_tokenizer;
+#[cfg(feature = "tokenizer")]
+pub mod bpe_tokenizer_error;
+
+pub trait Tokenizer: Send + Sync {
+    fn count_tokens(&self, text: &str) -> usize;
+}
+
+/// About 4 characters per token, which is close enough for English text and most models. Use a
+/// real tokenizer (see the `tokenizer` feature) where precision matters.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EstimatingTokenizer;
+
+impl Tokenizer for EstimatingTokenizer {
+    fn count_tokens(&self, text: &str) -> usize {
+        text.chars().count().div_ceil(4)
+    }
+}
@@ -0,0 +1,180 @@
+use super::bpe_tokenizer_error::BpeTokenizerError;
+use super::Tokenizer;
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use base64::Engine;
+
+use fancy_regex::Regex;
+
+/// Encodings used by the OpenAI models, which other vendors' token counts are usually close to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// GPT-3.
+    R50kBase,
+    /// Codex models.
+    P50kBase,
+    /// GPT-3.5 and GPT-4.
+    Cl100kBase,
+    /// GPT-4o and newer.
+    O200kBase,
+}
+
+impl Encoding {
+    /// Regex splitting the text into pieces before the merges.
+    pub fn pattern(&self) -> &'static str {
+        match self {
+            Encoding::R50kBase | Encoding::P50kBase => {
+                r"'s|'t|'re|'ve|'m|'ll|'d| ?\p{L}+| ?\p{N}+| ?[^\s\p{L}\p{N}]+|\s+(?!\S)|\s+"
+            }
+            Encoding::Cl100kBase => {
+                r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+"
+            }
+            Encoding::O200kBase => concat!(
+                r"[^\r\n\p{L}\p{N}]?[\p{Lu}\p{Lt}\p{Lm}\p{Lo}\p{M}]*[\p{Ll}\p{Lm}\p{Lo}\p{M}]+(?i:'s|'t|'re|'ve|'m|'ll|'d)?",
+                r"|[^\r\n\p{L}\p{N}]?[\p{Lu}\p{Lt}\p{Lm}\p{Lo}\p{M}]+[\p{Ll}\p{Lm}\p{Lo}\p{M}]*(?i:'s|'t|'re|'ve|'m|'ll|'d)?",
+                r"|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n/]*|\s*[\r\n]+|\s+(?!\S)|\s+",
+            ),
+        }
+    }
+
+    /// Name of the ranks file published by OpenAI, e.g. `cl100k_base.tiktoken`.
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            Encoding::R50kBase => "r50k_base.tiktoken",
+            Encoding::P50kBase => "p50k_base.tiktoken",
+            Encoding::Cl100kBase => "cl100k_base.tiktoken",
+            Encoding::O200kBase => "o200k_base.tiktoken",
+        }
+    }
+}
+
+/// Byte pair encoding tokenizer working the same way as tiktoken.
+///
+/// The vocabularies are too big to be bundled, so they are loaded from the ranks files, see
+/// [Encoding::file_name]. Special tokens like `<|endoftext|>` are encoded as ordinary text.
+#[derive(Debug, Clone)]
+pub struct BpeTokenizer {
+    ranks: HashMap<Vec<u8>, u32>,
+    decoder: HashMap<u32, Vec<u8>>,
+    pattern: Regex,
+}
+
+impl BpeTokenizer {
+    /// Lower rank means the pair is merged earlier.
+    pub fn new(ranks: HashMap<Vec<u8>, u32>, pattern: &str) -> Result<Self, BpeTokenizerError> {
+        if let Some(byte) = (0..=u8::MAX).find(|byte| !ranks.contains_key(&vec![*byte])) {
+            return Err(BpeTokenizerError::MissingByte(byte));
+        }
+
+        let pattern = Regex::new(pattern).map_err(|e| BpeTokenizerError::Pattern(Box::new(e)))?;
+
+        let decoder = ranks
+            .iter()
+            .map(|(token, rank)| (*rank, token.clone()))
+            .collect();
+
+        Ok(Self {
+            ranks,
+            decoder,
+            pattern,
+        })
+    }
+
+    /// Parses the contents of a `.tiktoken` file - a base64 encoded token and its rank per line.
+    pub fn from_tiktoken(ranks: &str, encoding: Encoding) -> Result<Self, BpeTokenizerError> {
+        let ranks = ranks
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(index, line)| {
+                let invalid = || BpeTokenizerError::InvalidLine(index + 1);
+
+                let (token, rank) = line.split_once(' ').ok_or_else(invalid)?;
+
+                let token = base64::engine::general_purpose::STANDARD
+                    .decode(token)
+                    .map_err(|_| invalid())?;
+                let rank = rank.trim().parse().map_err(|_| invalid())?;
+
+                Ok((token, rank))
+            })
+            .collect::<Result<_, BpeTokenizerError>>()?;
+
+        Self::new(ranks, encoding.pattern())
+    }
+
+    pub fn from_tiktoken_file(
+        path: impl AsRef<Path>,
+        encoding: Encoding,
+    ) -> Result<Self, BpeTokenizerError> {
+        let ranks = std::fs::read_to_string(path).map_err(BpeTokenizerError::Io)?;
+
+        Self::from_tiktoken(&ranks, encoding)
+    }
+
+    pub fn encode(&self, text: &str) -> Vec<u32> {
+        let mut tokens = Vec::new();
+
+        // Matching can only fail on backtracking limits, such pieces are simply not matched.
+        for piece in self.pattern.find_iter(text).filter_map(Result::ok) {
+            let piece = piece.as_str().as_bytes();
+
+            match self.ranks.get(piece) {
+                Some(rank) => tokens.push(*rank),
+                None => tokens.extend(self.merge(piece)),
+            }
+        }
+
+        tokens
+    }
+
+    /// Tokens which are not a part of the vocabulary are skipped, invalid UTF-8 is replaced.
+    pub fn decode(&self, tokens: &[u32]) -> String {
+        let bytes: Vec<u8> = tokens
+            .iter()
+            .filter_map(|token| self.decoder.get(token))
+            .flatten()
+            .copied()
+            .collect();
+
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+
+    /// Merges the bytes of the piece pair by pair, always the pair of the lowest rank first.
+    fn merge(&self, piece: &[u8]) -> Vec<u32> {
+        let rank = |range: std::ops::Range<usize>| {
+            self.ranks.get(&piece[range]).copied().unwrap_or(u32::MAX)
+        };
+
+        // Starts of the parts, with the end of the piece as the last one.
+        let mut boundaries: Vec<usize> = (0..=piece.len()).collect();
+
+        loop {
+            let lowest = boundaries
+                .windows(3)
+                .enumerate()
+                .map(|(index, window)| (rank(window[0]..window[2]), index))
+                .min();
+
+            match lowest {
+                Some((rank, index)) if rank != u32::MAX => {
+                    boundaries.remove(index + 1);
+                }
+                _ => break,
+            }
+        }
+
+        boundaries
+            .windows(2)
+            .map(|window| rank(window[0]..window[1]))
+            .collect()
+    }
+}
+
+impl Tokenizer for BpeTokenizer {
+    fn count_tokens(&self, text: &str) -> usize {
+        self.encode(text).len()
+    }
+}
@@ -0,0 +1,9 @@
+#[derive(Debug)]
+pub enum BpeTokenizerError {
+    Io(std::io::Error),
+    /// Line of the ranks file (counted from 1) is not a base64 token followed by its rank.
+    InvalidLine(usize),
+    /// Every single byte has to be a token, otherwise some text could not be encoded.
+    MissingByte(u8),
+    Pattern(Box<fancy_regex::Error>),
+}
@@ -0,0 +1,184 @@
+//! Typed tools the collaborative agent may call instead of writing code.
+
+use super::tool_error::ToolError;
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::future::Future;
+use std::sync::Arc;
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+
+use tracing::debug;
+
+/// What the agent is told about a tool.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ToolDefinition {
+    pub name: String,
+    pub description: String,
+    /// JSON schema of the arguments object.
+    pub parameters: Value,
+}
+
+impl ToolDefinition {
+    pub fn new(name: impl Into<String>, description: impl Into<String>, parameters: Value) -> Self {
+        Self {
+            name: name.into(),
+            description: description.into(),
+            parameters,
+        }
+    }
+}
+
+/// Call of a tool requested by the agent.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ToolCall {
+    /// Identifies the call, so the agent can match results with its calls.
+    pub id: String,
+    pub name: String,
+    pub arguments: Value,
+}
+
+/// What happened to a [ToolCall].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum ToolCallOutcome {
+    /// Serialized value returned by the tool.
+    Returned(Value),
+    /// Unknown tool, invalid arguments or an error returned by the tool. Passed to the agent, so it
+    /// may correct the call.
+    Failed(String),
+    Denied {
+        reason: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ToolCallReport {
+    pub tool_call: ToolCall,
+    pub outcome: ToolCallOutcome,
+}
+
+type ToolFunction =
+    Arc<dyn Fn(Value) -> BoxFuture<'static, Result<Value, ToolError>> + Send + Sync>;
+
+/// Async function along with its definition, ready for the [ToolRegistry]. Usually generated with
+/// the [crate::tool] macro.
+#[derive(Clone)]
+pub struct Tool {
+    definition: ToolDefinition,
+    function: ToolFunction,
+}
+
+impl Tool {
+    /// Arguments of the calls are deserialized into `A` and the returned value is serialized back
+    /// to JSON.
+    pub fn new<F, Fut, A, R, E>(definition: ToolDefinition, function: F) -> Self
+    where
+        F: Fn(A) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<R, E>> + Send + 'static,
+        A: DeserializeOwned,
+        R: Serialize,
+        E: Display,
+    {
+        let function: ToolFunction = Arc::new(
+            move |arguments: Value| match serde_json::from_value::<A>(arguments) {
+                Ok(arguments) => function(arguments)
+                    .map(|result| {
+                        let value = result.map_err(|e| ToolError::Failed(e.to_string()))?;
+
+                        serde_json::to_value(value).map_err(ToolError::Serialization)
+                    })
+                    .boxed(),
+                Err(e) => futures::future::ready(Err(ToolError::InvalidArguments(e))).boxed(),
+            },
+        );
+
+        Self {
+            definition,
+            function,
+        }
+    }
+
+    pub fn definition(&self) -> &ToolDefinition {
+        &self.definition
+    }
+
+    pub async fn call(&self, arguments: Value) -> Result<Value, ToolError> {
+        (self.function)(arguments).await
+    }
+}
+
+impl std::fmt::Debug for Tool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Tool")
+            .field("definition", &self.definition)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Tools available in the chat, see [super::collaborative_chat::CollaborativeChatOptions::tools].
+///
+/// ```ignore
+/// /// Current weather in the city.
+/// #[autogen::tool]
+/// async fn weather(
+///     /// Name of the city in English.
+///     city: String,
+/// ) -> Result<String, Error> {
+///     Ok(format!("Sunny in {}", city))
+/// }
+///
+/// let tools = ToolRegistry::new().with_tool(weather_tool());
+/// ```
+///
+/// Tools may be defined by hand as well, with [Tool::new].
+#[derive(Clone, Default)]
+pub struct ToolRegistry {
+    tools: BTreeMap<String, Tool>,
+}
+
+impl ToolRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces any tool of the same name.
+    pub fn with_tool(mut self, tool: Tool) -> Self {
+        self.tools.insert(tool.definition.name.clone(), tool);
+        self
+    }
+
+    /// Definitions of all the tools, ordered by name. Meant to be passed to the agent.
+    pub fn definitions(&self) -> Vec<ToolDefinition> {
+        self.tools
+            .values()
+            .map(|tool| tool.definition.clone())
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tools.is_empty()
+    }
+
+    pub async fn call(&self, tool_call: &ToolCall) -> Result<Value, ToolError> {
+        let tool = self
+            .tools
+            .get(&tool_call.name)
+            .ok_or_else(|| ToolError::UnknownTool(tool_call.name.clone()))?;
+
+        debug!("calling tool {}..", tool_call.name);
+
+        tool.call(tool_call.arguments.clone()).await
+    }
+}
+
+impl std::fmt::Debug for ToolRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.tools.keys()).finish()
+    }
+}
@@ -0,0 +1,22 @@
+/// Errors of tool calls are not fatal for the chat - they are reported back to the agent, thus the
+/// [std::fmt::Display] implementation.
+#[derive(Debug)]
+pub enum ToolError {
+    UnknownTool(String),
+    InvalidArguments(serde_json::Error),
+    /// The value returned by the tool could not be serialized.
+    Serialization(serde_json::Error),
+    /// Error returned by the tool itself.
+    Failed(String),
+}
+
+impl std::fmt::Display for ToolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ToolError::UnknownTool(name) => write!(f, "unknown tool: {}", name),
+            ToolError::InvalidArguments(e) => write!(f, "invalid arguments: {}", e),
+            ToolError::Serialization(e) => write!(f, "could not serialize the result: {}", e),
+            ToolError::Failed(e) => write!(f, "{}", e),
+        }
+    }
+}
@@ -0,0 +1,120 @@
+//! JSON schemas of tool arguments derived from Rust types, see [crate::tool] and
+//! [crate::ToolSchema].
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use serde_json::{json, Map, Value};
+
+/// Type which can be described to the agent with a JSON schema.
+///
+/// Implemented for the primitives, strings, collections and [Option], and derived with
+/// [crate::ToolSchema] for structs with named fields and enums with unit variants.
+pub trait ToolSchema {
+    fn schema() -> Value;
+
+    /// Whether the property of this type may be left out of the arguments. Only [Option] may be.
+    fn optional() -> bool {
+        false
+    }
+}
+
+macro_rules! primitive_schema {
+    ($schema:tt => $($ty:ty),+) => {
+        $(
+            impl ToolSchema for $ty {
+                fn schema() -> Value {
+                    json!($schema)
+                }
+            }
+        )+
+    };
+}
+
+primitive_schema!({ "type": "boolean" } => bool);
+primitive_schema!({ "type": "integer" } => i8, i16, i32, i64, i128, isize);
+primitive_schema!({ "type": "integer", "minimum": 0 } => u8, u16, u32, u64, u128, usize);
+primitive_schema!({ "type": "number" } => f32, f64);
+primitive_schema!({ "type": "string" } => String);
+primitive_schema!({ "type": "string", "minLength": 1, "maxLength": 1 } => char);
+primitive_schema!({} => Value);
+
+impl<T: ToolSchema> ToolSchema for Option<T> {
+    fn schema() -> Value {
+        T::schema()
+    }
+
+    fn optional() -> bool {
+        true
+    }
+}
+
+impl<T: ToolSchema> ToolSchema for Box<T> {
+    fn schema() -> Value {
+        T::schema()
+    }
+
+    fn optional() -> bool {
+        T::optional()
+    }
+}
+
+macro_rules! array_schema {
+    ($($ty:ident),+) => {
+        $(
+            impl<T: ToolSchema> ToolSchema for $ty<T> {
+                fn schema() -> Value {
+                    json!({ "type": "array", "items": T::schema() })
+                }
+            }
+        )+
+    };
+}
+
+array_schema!(Vec, HashSet, BTreeSet);
+
+macro_rules! map_schema {
+    ($($ty:ident),+) => {
+        $(
+            impl<T: ToolSchema> ToolSchema for $ty<String, T> {
+                fn schema() -> Value {
+                    json!({ "type": "object", "additionalProperties": T::schema() })
+                }
+            }
+        )+
+    };
+}
+
+map_schema!(HashMap, BTreeMap);
+
+/// Adds the description to the schema. Used by the macros.
+#[doc(hidden)]
+pub fn describe(mut schema: Value, description: &str) -> Value {
+    if let (Value::Object(object), false) = (&mut schema, description.is_empty()) {
+        object.insert("description".to_string(), json!(description));
+    }
+
+    schema
+}
+
+/// Schema of an object with the given properties - name, schema and whether it is optional. Used by
+/// the macros.
+#[doc(hidden)]
+pub fn object(properties: Vec<(&str, Value, bool)>) -> Value {
+    let required: Vec<&str> = properties
+        .iter()
+        .filter(|(_, _, optional)| !optional)
+        .map(|(name, _, _)| *name)
+        .collect();
+
+    let properties: Map<String, Value> = properties
+        .into_iter()
+        .map(|(name, schema, _)| (name.to_string(), schema))
+        .collect();
+
+    json!({
+        "type": "object",
+        "properties": properties,
+        "required": required,
+        "additionalProperties": false,
+    })
+}
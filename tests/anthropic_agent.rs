@@ -0,0 +1,284 @@
+//! Runs the Anthropic agent against a local stand-in of the Messages API.
+
+use autogen::agent_traits::{ConsumerAgent, ProducerAgent, TokenUsage};
+use autogen::text_chat::code::{CodeBlockExecutionOutput, CodeBlockExecutionResult};
+use autogen::text_chat::collaborative_agent::{
+    CollaborativeAgent, CollaborativeAgentResponse, Message,
+};
+use autogen::text_chat::collaborative_agent_error::CollaborativeAgentError;
+use autogen::text_chat::conversation_history::{ConversationHistory, HistoryEntry, SlidingWindow};
+use autogen::text_chat::llm::anthropic_agent::{AnthropicAgent, StopReason, ANTHROPIC_VERSION};
+use autogen::text_chat::llm::anthropic_agent_error::AnthropicAgentError;
+
+use serde_json::{json, Value};
+
+use wiremock::matchers::{header, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn message_response(content: Value, stop_reason: &str) -> ResponseTemplate {
+    ResponseTemplate::new(200).set_body_json(json!({
+        "id": "msg_1",
+        "type": "message",
+        "role": "assistant",
+        "model": "claude-test",
+        "content": content,
+        "stop_reason": stop_reason,
+        "stop_sequence": null,
+        "usage": { "input_tokens": 10, "output_tokens": 10 },
+    }))
+}
+
+async fn mock_server(response: ResponseTemplate) -> MockServer {
+    let server = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .and(path("/v1/messages"))
+        .respond_with(response)
+        .mount(&server)
+        .await;
+
+    server
+}
+
+async fn request_bodies(server: &MockServer) -> Vec<Value> {
+    server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|request| request.body_json().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn sends_system_prompt_separately_and_joins_text_blocks() {
+    let server = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .and(path("/v1/messages"))
+        .and(header("x-api-key", "secret"))
+        .and(header("anthropic-version", ANTHROPIC_VERSION))
+        .respond_with(message_response(
+            json!([
+                { "type": "text", "text": "Sure:\n```python execute\n" },
+                { "type": "text", "text": "print(\"Hello World\")\n```" },
+            ]),
+            "end_turn",
+        ))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let mut agent = AnthropicAgent::new(server.uri(), "claude-test")
+        .with_api_key("secret")
+        .with_system_prompt("You write code.")
+        .with_max_tokens(1024);
+
+    let reply = agent
+        .receive_and_reply(
+            "user".to_string(),
+            "Write Hello World in Python".to_string(),
+        )
+        .await
+        .unwrap();
+
+    match reply {
+        CollaborativeAgentResponse::CommentedCodeBlock(commented_code_block) => {
+            assert_eq!(commented_code_block.comment, "Sure:");
+            assert_eq!(commented_code_block.code_block.language, "python");
+            assert!(commented_code_block.request_execution);
+        }
+        reply => panic!("unexpected reply: {:?}", reply),
+    }
+
+    assert_eq!(agent.stop_reason(), Some(StopReason::EndTurn));
+
+    assert_eq!(
+        request_bodies(&server).await,
+        vec![json!({
+            "model": "claude-test",
+            "max_tokens": 1024,
+            "system": "You write code.",
+            "messages": [
+                {
+                    "role": "user",
+                    "content": [{ "type": "text", "text": "Write Hello World in Python" }],
+                },
+            ],
+        })]
+    );
+}
+
+#[tokio::test]
+async fn consecutive_user_messages_share_a_turn() {
+    let server = mock_server(message_response(
+        json!([{ "type": "text", "text": "Looks good." }]),
+        "end_turn",
+    ))
+    .await;
+
+    let mut agent = AnthropicAgent::new(server.uri(), "claude-test");
+
+    agent
+        .receive_and_reply("user".to_string(), "Run it".to_string())
+        .await
+        .unwrap();
+
+    let result = CodeBlockExecutionResult::Success(CodeBlockExecutionOutput {
+        exit_code: Some(0),
+        ..Default::default()
+    });
+
+    agent
+        .receive_message(Message::CodeExecutionResult(result))
+        .await
+        .unwrap();
+    agent
+        .receive_message(Message::Text {
+            sender: "user".to_string(),
+            message: "Anything else?".to_string(),
+        })
+        .await
+        .unwrap();
+
+    agent.send_message().await.unwrap();
+
+    assert_eq!(
+        agent.usage(),
+        Some(TokenUsage {
+            prompt_tokens: 20,
+            completion_tokens: 20,
+        })
+    );
+
+    let bodies = request_bodies(&server).await;
+    assert_eq!(bodies.len(), 2);
+    assert_eq!(bodies[1]["max_tokens"], json!(4096));
+    assert_eq!(
+        bodies[1]["messages"],
+        json!([
+            { "role": "user", "content": [{ "type": "text", "text": "Run it" }] },
+            { "role": "assistant", "content": [{ "type": "text", "text": "Looks good." }] },
+            {
+                "role": "user",
+                "content": [
+                    { "type": "text", "text": "The code was executed successfully.\nExit code: 0" },
+                    { "type": "text", "text": "Anything else?" },
+                ],
+            },
+        ])
+    );
+}
+
+#[tokio::test]
+async fn truncated_history_starts_with_a_user_turn() {
+    let server = mock_server(message_response(
+        json!([{ "type": "text", "text": "Done." }]),
+        "end_turn",
+    ))
+    .await;
+
+    // The window of the second request starts with the first reply.
+    let mut agent = AnthropicAgent::new(server.uri(), "claude-test")
+        .with_history(ConversationHistory::new().with_truncation(SlidingWindow { max_entries: 2 }));
+
+    for message in ["first", "second"] {
+        agent
+            .receive_and_reply("user".to_string(), message.to_string())
+            .await
+            .unwrap();
+    }
+
+    // Custom strategies may cut anywhere.
+    let mut agent = AnthropicAgent::new(server.uri(), "claude-test").with_history(
+        ConversationHistory::new().with_truncation(|entries: &[HistoryEntry]| {
+            entries[entries.len().saturating_sub(2)..].to_vec()
+        }),
+    );
+
+    for message in ["first", "second"] {
+        agent
+            .receive_and_reply("user".to_string(), message.to_string())
+            .await
+            .unwrap();
+    }
+
+    let bodies = request_bodies(&server).await;
+    assert_eq!(bodies.len(), 4);
+    for body in [&bodies[1], &bodies[3]] {
+        assert_eq!(
+            body["messages"],
+            json!([{ "role": "user", "content": [{ "type": "text", "text": "second" }] }])
+        );
+    }
+}
+
+#[tokio::test]
+async fn reply_cut_by_token_limit_keeps_its_code() {
+    let server = mock_server(message_response(
+        json!([{ "type": "text", "text": "```bash execute\necho one\necho tw" }]),
+        "max_tokens",
+    ))
+    .await;
+
+    let mut agent = AnthropicAgent::new(server.uri(), "claude-test");
+
+    let reply = agent
+        .receive_and_reply("user".to_string(), "Count".to_string())
+        .await
+        .unwrap();
+
+    match reply {
+        CollaborativeAgentResponse::CommentedCodeBlock(commented_code_block) => {
+            assert_eq!(commented_code_block.code_block.code, "echo one\necho tw");
+        }
+        reply => panic!("unexpected reply: {:?}", reply),
+    }
+
+    assert_eq!(agent.stop_reason(), Some(StopReason::MaxTokens));
+}
+
+#[tokio::test]
+async fn reply_without_text_is_invalid() {
+    let server = mock_server(message_response(
+        json!([{ "type": "tool_use", "id": "toolu_1", "name": "search", "input": {} }]),
+        "tool_use",
+    ))
+    .await;
+
+    let mut agent = AnthropicAgent::new(server.uri(), "claude-test");
+
+    let error = agent
+        .receive_and_reply("user".to_string(), "hi".to_string())
+        .await
+        .unwrap_err();
+
+    assert!(matches!(
+        error,
+        CollaborativeAgentError::Sending(AnthropicAgentError::InvalidResponse(_))
+    ));
+}
+
+#[tokio::test]
+async fn error_status_is_reported() {
+    let server = mock_server(ResponseTemplate::new(529).set_body_json(json!({
+        "type": "error",
+        "error": { "type": "overloaded_error", "message": "Overloaded" },
+    })))
+    .await;
+
+    let mut agent = AnthropicAgent::new(server.uri(), "claude-test");
+
+    let error = agent
+        .receive_and_reply("user".to_string(), "hi".to_string())
+        .await
+        .unwrap_err();
+
+    match error {
+        CollaborativeAgentError::Sending(AnthropicAgentError::Status { status, body }) => {
+            assert_eq!(status, 529);
+            assert!(body.contains("overloaded_error"));
+        }
+        error => panic!("unexpected error: {:?}", error),
+    }
+}
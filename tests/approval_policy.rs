@@ -0,0 +1,194 @@
+use autogen::text_chat::approval_policy::{
+    AllowedLanguages, ApprovalDecision, ApprovalPolicy, MaxCodeSize, Pattern, RiskyCode,
+};
+use autogen::text_chat::code::risk_analysis::{RiskAnalyzer, RiskKind, RiskReport};
+use autogen::text_chat::code::CodeBlock;
+
+use regex::Regex;
+
+fn code_block(language: &str, code: &str) -> CodeBlock {
+    CodeBlock {
+        language: language.to_string(),
+        code: code.to_string(),
+    }
+}
+
+fn decide(policy: &ApprovalPolicy, code_block: &CodeBlock) -> ApprovalDecision {
+    policy.decide(code_block, &RiskAnalyzer::new().analyze(code_block))
+}
+
+fn deny(reason: &str) -> ApprovalDecision {
+    ApprovalDecision::Deny {
+        reason: reason.to_string(),
+    }
+}
+
+#[test]
+fn empty_policy_escalates() {
+    let policy = ApprovalPolicy::new();
+
+    assert_eq!(
+        decide(&policy, &code_block("sh", "echo hi")),
+        ApprovalDecision::Escalate
+    );
+}
+
+#[test]
+fn first_deciding_rule_wins() {
+    let policy = ApprovalPolicy::new()
+        .with_rule(Pattern::allow(Regex::new("echo").unwrap()))
+        .with_rule(Pattern::deny(Regex::new("hi").unwrap(), "no hi"))
+        .with_default(ApprovalDecision::Allow);
+
+    assert_eq!(
+        decide(&policy, &code_block("sh", "echo hi")),
+        ApprovalDecision::Allow
+    );
+    assert_eq!(decide(&policy, &code_block("sh", "ls hi")), deny("no hi"));
+    assert_eq!(
+        decide(&policy, &code_block("sh", "ls")),
+        ApprovalDecision::Allow
+    );
+}
+
+#[test]
+fn other_languages_are_denied() {
+    let policy = ApprovalPolicy::new().with_rule(AllowedLanguages::new(["python", "sh"]));
+
+    assert_eq!(
+        decide(&policy, &code_block("Python", "print(1)")),
+        ApprovalDecision::Escalate
+    );
+    assert_eq!(
+        decide(&policy, &code_block("rust", "fn main() {}")),
+        deny("rust code is not allowed")
+    );
+}
+
+#[test]
+fn language_aliases_are_allowed() {
+    let policy = ApprovalPolicy::new().with_rule(AllowedLanguages::new(["python3", "bash"]));
+
+    for language in ["python", "py", "Python3", "shell", "console"] {
+        assert_eq!(
+            decide(&policy, &code_block(language, "x")),
+            ApprovalDecision::Escalate,
+            "{} is not allowed",
+            language
+        );
+    }
+
+    // Executors run sh code with sh, not with bash.
+    for language in ["pyth", "sh", "zsh"] {
+        assert_eq!(
+            decide(&policy, &code_block(language, "x")),
+            deny(&format!("{} code is not allowed", language))
+        );
+    }
+
+    let policy = ApprovalPolicy::new()
+        .with_rule(Pattern::deny(Regex::new("rm").unwrap(), "no rm").with_languages(["bash"]))
+        .with_default(ApprovalDecision::Allow);
+
+    assert_eq!(decide(&policy, &code_block("shell", "rm x")), deny("no rm"));
+    assert_eq!(
+        decide(&policy, &code_block("sh", "rm x")),
+        ApprovalDecision::Allow
+    );
+}
+
+#[test]
+fn long_code_is_denied() {
+    let policy = ApprovalPolicy::new().with_rule(MaxCodeSize(5));
+
+    assert_eq!(
+        decide(&policy, &code_block("sh", "ls")),
+        ApprovalDecision::Escalate
+    );
+    assert_eq!(
+        decide(&policy, &code_block("sh", "echo hi")),
+        deny("code is longer than 5 bytes")
+    );
+}
+
+#[test]
+fn pattern_is_checked_only_for_its_languages() {
+    let policy = ApprovalPolicy::new()
+        .with_rule(
+            Pattern::deny(Regex::new("subprocess").unwrap(), "no subprocess")
+                .with_languages(["python"]),
+        )
+        .with_default(ApprovalDecision::Allow);
+
+    assert_eq!(
+        decide(&policy, &code_block("python", "import subprocess")),
+        deny("no subprocess")
+    );
+    assert_eq!(
+        decide(&policy, &code_block("sh", "echo subprocess")),
+        ApprovalDecision::Allow
+    );
+}
+
+#[test]
+fn all_code_blocks_are_decided_at_once() {
+    let policy = ApprovalPolicy::new()
+        .with_rule(Pattern::deny(Regex::new("rm").unwrap(), "no rm"))
+        .with_rule(Pattern::escalate(Regex::new("curl").unwrap()))
+        .with_default(ApprovalDecision::Allow);
+
+    let echo = code_block("sh", "echo hi");
+    let curl = code_block("sh", "curl example.com");
+    let rm = code_block("sh", "rm file");
+
+    let report = RiskReport::default();
+
+    assert_eq!(
+        policy.decide_all([(&echo, &report)]),
+        ApprovalDecision::Allow
+    );
+    assert_eq!(
+        policy.decide_all([(&echo, &report), (&curl, &report)]),
+        ApprovalDecision::Escalate
+    );
+    assert_eq!(
+        policy.decide_all([(&curl, &report), (&rm, &report), (&echo, &report)]),
+        deny("no rm")
+    );
+}
+
+#[test]
+fn risky_code_is_escalated() {
+    let policy = ApprovalPolicy::new()
+        .with_rule(RiskyCode::escalate())
+        .with_default(ApprovalDecision::Allow);
+
+    assert_eq!(
+        decide(&policy, &code_block("sh", "ls")),
+        ApprovalDecision::Allow
+    );
+    assert_eq!(
+        decide(&policy, &code_block("sh", "curl example.com")),
+        ApprovalDecision::Escalate
+    );
+    assert_eq!(
+        decide(&policy, &code_block("rust", "fn main() {}")),
+        ApprovalDecision::Escalate
+    );
+}
+
+#[test]
+fn risky_code_is_denied_with_the_risks() {
+    let policy = ApprovalPolicy::new()
+        .with_rule(RiskyCode::deny().with_kinds([RiskKind::PackageInstall]))
+        .with_default(ApprovalDecision::Allow);
+
+    assert_eq!(
+        decide(&policy, &code_block("sh", "curl example.com")),
+        ApprovalDecision::Allow
+    );
+    assert_eq!(
+        decide(&policy, &code_block("sh", "pip install requests")),
+        deny("the code is risky. Risks:\n- line 1, package installation: `pip install requests`")
+    );
+}
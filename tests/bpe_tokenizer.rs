@@ -0,0 +1,86 @@
+//! Byte pair encoding with a tiny vocabulary in the `.tiktoken` format.
+
+use autogen::text_chat::tokenizer::bpe_tokenizer::{BpeTokenizer, Encoding};
+use autogen::text_chat::tokenizer::bpe_tokenizer_error::BpeTokenizerError;
+use autogen::text_chat::tokenizer::Tokenizer;
+
+use base64::Engine;
+
+/// Every single byte followed by the merges, ranked in that order.
+fn ranks_file(merges: &[&str]) -> String {
+    let tokens: Vec<Vec<u8>> = (0..=u8::MAX)
+        .map(|byte| vec![byte])
+        .chain(merges.iter().map(|merge| merge.as_bytes().to_vec()))
+        .collect();
+
+    tokens
+        .iter()
+        .enumerate()
+        .map(|(rank, token)| {
+            format!(
+                "{} {}\n",
+                base64::engine::general_purpose::STANDARD.encode(token),
+                rank
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn merges_pairs_of_the_lowest_rank_first() {
+    let tokenizer =
+        BpeTokenizer::from_tiktoken(&ranks_file(&["he", "ll", "hell"]), Encoding::R50kBase)
+            .unwrap();
+
+    let tokens = tokenizer.encode("hello hello");
+    assert_eq!(
+        tokens,
+        vec![258, b'o' as u32, b' ' as u32, 258, b'o' as u32]
+    );
+    assert_eq!(tokenizer.decode(&tokens), "hello hello");
+
+    assert_eq!(tokenizer.count_tokens("hello hello"), 5);
+    assert_eq!(tokenizer.count_tokens(""), 0);
+}
+
+#[test]
+fn text_outside_of_the_vocabulary_falls_back_to_bytes() {
+    let tokenizer = BpeTokenizer::from_tiktoken(&ranks_file(&[]), Encoding::Cl100kBase).unwrap();
+
+    let text = "zażółć gęślą jaźń\n\n  print(1)";
+    let tokens = tokenizer.encode(text);
+
+    assert_eq!(tokens.len(), text.len());
+    assert_eq!(tokenizer.decode(&tokens), text);
+}
+
+#[test]
+fn patterns_of_all_encodings_compile() {
+    let ranks = ranks_file(&[]);
+
+    for encoding in [
+        Encoding::R50kBase,
+        Encoding::P50kBase,
+        Encoding::Cl100kBase,
+        Encoding::O200kBase,
+    ] {
+        let tokenizer = BpeTokenizer::from_tiktoken(&ranks, encoding).unwrap();
+
+        assert_eq!(
+            tokenizer.decode(&tokenizer.encode("It's 2024!")),
+            "It's 2024!"
+        );
+    }
+}
+
+#[test]
+fn invalid_ranks_are_rejected() {
+    let error = BpeTokenizer::from_tiktoken("aGU= 1\nnot base64!\n", Encoding::Cl100kBase);
+    assert!(matches!(error, Err(BpeTokenizerError::InvalidLine(2))));
+
+    let error = BpeTokenizer::from_tiktoken("aGU= 1\n", Encoding::Cl100kBase);
+    assert!(matches!(error, Err(BpeTokenizerError::MissingByte(0))));
+
+    let error = BpeTokenizer::from_tiktoken_file("/nonexistent.tiktoken", Encoding::Cl100kBase);
+    assert!(matches!(error, Err(BpeTokenizerError::Io(_))));
+}
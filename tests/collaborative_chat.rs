@@ -0,0 +1,1872 @@
+//! Drives `collaborative_chat` with scripted agents and a fake executor, covering every branch of
+//! the chat loop.
+
+use autogen::agent_traits::{
+    ConsumerAgent, NamedAgent, ProducerAgent, StreamingProducerAgent, TokenUsage,
+};
+use autogen::text_chat::approval_policy::{ApprovalDecision, ApprovalPolicy, Pattern, RiskyCode};
+use autogen::text_chat::budget::{Budget, Pricing};
+use autogen::text_chat::chat_result::{TranscriptEntry, TranscriptEvent};
+use autogen::text_chat::chat_user_agent::{ChatUserAgent, CodeBlockFeedback};
+use autogen::text_chat::code::risk_analysis::{RiskKind, RiskReport};
+use autogen::text_chat::code::{
+    CodeBlock, CodeBlockExecutionOutput, CodeBlockExecutionResult, CodeExecutor,
+};
+use autogen::text_chat::collaborative_agent::{
+    CodeBlockOutcome, CodeBlockReport, CollaborativeAgent, CollaborativeAgentResponse,
+    CommentedCodeBlock, Message, ResponseSegment, RESULTS_SENDER,
+};
+use autogen::text_chat::collaborative_chat::{
+    collaborative_chat, collaborative_chat_with_options, streaming_collaborative_chat, ChatOutcome,
+    CollaborativeChatOptions, ExecutionMode, SystemAgent, TerminationReason, ToolCallApproval,
+};
+use autogen::text_chat::collaborative_chat_error::CollaborativeChatError;
+use autogen::text_chat::human_input::{AutoReply, FixedReply, HumanInputMode};
+use autogen::text_chat::termination::{
+    ChatEvent, ExecutionSucceeded, IdleTimeout, MaxTurns, MetCondition, Predicate,
+    TerminationCondition, TextMention, UserExit,
+};
+use autogen::text_chat::tool::{
+    Tool, ToolCall, ToolCallOutcome, ToolCallReport, ToolDefinition, ToolRegistry,
+};
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::Stream;
+
+use regex::Regex;
+
+use serde::Deserialize;
+use serde_json::json;
+
+use tokio_util::sync::CancellationToken;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Event {
+    UserReceived {
+        sender: String,
+        message: String,
+    },
+    UserReceivedPartial {
+        sender: String,
+        delta: String,
+    },
+    UserShown(String),
+    FeedbackRequested {
+        comment: String,
+        code: String,
+    },
+    BatchFeedbackRequested {
+        code_blocks: usize,
+    },
+    /// Risks the user agent was shown along with the code.
+    RisksShown(Vec<RiskKind>),
+    UserReceivedResult(String),
+    Executed(String),
+    AgentReceived {
+        sender: String,
+        message: String,
+    },
+    AgentDenied {
+        code: String,
+        reason: String,
+    },
+    AgentReceivedResult(String),
+    AgentReceivedReports(Vec<String>),
+    ToolCallFeedbackRequested(String),
+    AgentReceivedToolCallReports(Vec<ToolCallReport>),
+}
+
+type Log = Arc<Mutex<Vec<Event>>>;
+
+fn record(log: &Log, event: Event) {
+    log.lock().unwrap().push(event);
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct ScriptError;
+
+struct ScriptedUserAgent {
+    replies: VecDeque<String>,
+    feedback: VecDeque<CodeBlockFeedback>,
+    log: Log,
+    fail_on_reply: bool,
+    /// Never replies once out of the scripted replies.
+    stalls: bool,
+}
+
+impl ScriptedUserAgent {
+    fn new(log: &Log) -> Self {
+        Self {
+            replies: VecDeque::new(),
+            feedback: VecDeque::new(),
+            log: log.clone(),
+            fail_on_reply: false,
+            stalls: false,
+        }
+    }
+
+    fn replies(mut self, replies: &[&str]) -> Self {
+        self.replies = replies.iter().map(|reply| reply.to_string()).collect();
+        self
+    }
+
+    fn feedback(mut self, feedback: Vec<CodeBlockFeedback>) -> Self {
+        self.feedback = feedback.into();
+        self
+    }
+
+    fn stalls(mut self) -> Self {
+        self.stalls = true;
+        self
+    }
+}
+
+impl NamedAgent for ScriptedUserAgent {
+    fn name(&self) -> &str {
+        "user"
+    }
+}
+
+impl ChatUserAgent for ScriptedUserAgent {
+    type Error = ScriptError;
+
+    async fn receive_and_reply(
+        &mut self,
+        sender: String,
+        message: String,
+    ) -> Result<String, Self::Error> {
+        record(&self.log, Event::UserReceived { sender, message });
+
+        if self.stalls && self.replies.is_empty() {
+            futures::future::pending::<()>().await;
+        }
+
+        match self.fail_on_reply {
+            true => Err(ScriptError),
+            false => Ok(self.replies.pop_front().unwrap_or_else(|| "ok".to_string())),
+        }
+    }
+
+    async fn silent_receive_collaborative_agent_response(
+        &mut self,
+        _sender: String,
+        response: CollaborativeAgentResponse,
+    ) -> Result<(), Self::Error> {
+        record(&self.log, Event::UserShown(response.to_markdown()));
+        Ok(())
+    }
+
+    async fn request_code_block_feedback(
+        &mut self,
+        sender: String,
+        comment: String,
+        code_block: CodeBlock,
+    ) -> Result<CodeBlockFeedback, Self::Error> {
+        self.request_code_block_feedback_with_risks(
+            sender,
+            comment,
+            code_block,
+            RiskReport::default(),
+        )
+        .await
+    }
+
+    async fn request_code_block_feedback_with_risks(
+        &mut self,
+        _sender: String,
+        comment: String,
+        code_block: CodeBlock,
+        risk_report: RiskReport,
+    ) -> Result<CodeBlockFeedback, Self::Error> {
+        record(
+            &self.log,
+            Event::FeedbackRequested {
+                comment,
+                code: code_block.code,
+            },
+        );
+
+        if risk_report.is_risky() {
+            record(&self.log, Event::RisksShown(risk_report.kinds()));
+        }
+
+        Ok(self
+            .feedback
+            .pop_front()
+            .expect("unexpected feedback request"))
+    }
+
+    async fn request_code_blocks_feedback(
+        &mut self,
+        _sender: String,
+        segments: Vec<ResponseSegment>,
+        risk_reports: Vec<RiskReport>,
+    ) -> Result<CodeBlockFeedback, Self::Error> {
+        let code_blocks = segments
+            .iter()
+            .filter(|segment| matches!(segment, ResponseSegment::CodeBlock { .. }))
+            .count();
+
+        record(&self.log, Event::BatchFeedbackRequested { code_blocks });
+
+        let mut kinds: Vec<RiskKind> = risk_reports.iter().flat_map(RiskReport::kinds).collect();
+        kinds.dedup();
+        if !kinds.is_empty() {
+            record(&self.log, Event::RisksShown(kinds));
+        }
+
+        Ok(self
+            .feedback
+            .pop_front()
+            .expect("unexpected feedback request"))
+    }
+
+    async fn receive_code_execution_result(
+        &mut self,
+        result: CodeBlockExecutionResult,
+    ) -> Result<(), Self::Error> {
+        record(
+            &self.log,
+            Event::UserReceivedResult(result.output().stdout.clone()),
+        );
+        Ok(())
+    }
+
+    async fn request_tool_call_feedback(
+        &mut self,
+        _sender: String,
+        tool_call: ToolCall,
+    ) -> Result<CodeBlockFeedback, Self::Error> {
+        record(&self.log, Event::ToolCallFeedbackRequested(tool_call.name));
+
+        Ok(self
+            .feedback
+            .pop_front()
+            .expect("unexpected feedback request"))
+    }
+
+    async fn receive_partial_text(
+        &mut self,
+        sender: String,
+        delta: String,
+    ) -> Result<(), Self::Error> {
+        record(&self.log, Event::UserReceivedPartial { sender, delta });
+        Ok(())
+    }
+}
+
+/// Replies with the scripted responses and cancels the chat once it runs out of them.
+struct ScriptedCollaborativeAgent {
+    responses: VecDeque<CollaborativeAgentResponse>,
+    cancellation_token: CancellationToken,
+    log: Log,
+    /// Grows by [REPLY_USAGE] with every reply, if set.
+    usage: Option<TokenUsage>,
+}
+
+const REPLY_USAGE: TokenUsage = TokenUsage {
+    prompt_tokens: 100,
+    completion_tokens: 10,
+};
+
+impl ScriptedCollaborativeAgent {
+    fn new(
+        log: &Log,
+        cancellation_token: &CancellationToken,
+        responses: Vec<CollaborativeAgentResponse>,
+    ) -> Self {
+        Self {
+            responses: responses.into(),
+            cancellation_token: cancellation_token.clone(),
+            log: log.clone(),
+            usage: None,
+        }
+    }
+
+    fn reply(&mut self) -> CollaborativeAgentResponse {
+        if let Some(usage) = &mut self.usage {
+            *usage += REPLY_USAGE;
+        }
+
+        match self.responses.pop_front() {
+            Some(response) => response,
+            None => {
+                self.cancellation_token.cancel();
+                CollaborativeAgentResponse::Text("out of script".to_string())
+            }
+        }
+    }
+}
+
+impl NamedAgent for ScriptedCollaborativeAgent {
+    fn name(&self) -> &str {
+        "assistant"
+    }
+}
+
+impl CollaborativeAgent for ScriptedCollaborativeAgent {
+    type Error = ScriptError;
+
+    async fn receive_and_reply(
+        &mut self,
+        sender: String,
+        message: String,
+    ) -> Result<CollaborativeAgentResponse, Self::Error> {
+        record(&self.log, Event::AgentReceived { sender, message });
+        Ok(self.reply())
+    }
+
+    async fn deny_code_block_execution(
+        &mut self,
+        code_block: CodeBlock,
+        feedback: String,
+    ) -> Result<CollaborativeAgentResponse, Self::Error> {
+        record(
+            &self.log,
+            Event::AgentDenied {
+                code: code_block.code,
+                reason: feedback,
+            },
+        );
+        Ok(self.reply())
+    }
+
+    async fn receive_code_and_reply_to_execution_result(
+        &mut self,
+        code_execution_result: CodeBlockExecutionResult,
+    ) -> Result<CollaborativeAgentResponse, Self::Error> {
+        record(
+            &self.log,
+            Event::AgentReceivedResult(code_execution_result.output().stdout.clone()),
+        );
+        Ok(self.reply())
+    }
+
+    async fn receive_code_and_reply_to_execution_results(
+        &mut self,
+        reports: Vec<CodeBlockReport>,
+    ) -> Result<CollaborativeAgentResponse, Self::Error> {
+        let reports = reports
+            .into_iter()
+            .map(|report| match report.outcome {
+                CodeBlockOutcome::Executed(result) => {
+                    format!(
+                        "{}: executed {}",
+                        report.code_block.code,
+                        result.is_success()
+                    )
+                }
+                CodeBlockOutcome::Denied { reason } => {
+                    format!("{}: denied {}", report.code_block.code, reason)
+                }
+                CodeBlockOutcome::Skipped => format!("{}: skipped", report.code_block.code),
+            })
+            .collect();
+
+        record(&self.log, Event::AgentReceivedReports(reports));
+        Ok(self.reply())
+    }
+
+    async fn receive_and_reply_to_tool_call_results(
+        &mut self,
+        reports: Vec<ToolCallReport>,
+    ) -> Result<CollaborativeAgentResponse, Self::Error> {
+        record(&self.log, Event::AgentReceivedToolCallReports(reports));
+        Ok(self.reply())
+    }
+
+    fn token_usage(&self) -> Option<TokenUsage> {
+        self.usage
+    }
+}
+
+/// Streams the scripted replies in the given pieces and cancels the chat once it runs out of them.
+struct StreamingAgent {
+    replies: VecDeque<Vec<String>>,
+    cancellation_token: CancellationToken,
+    log: Log,
+}
+
+impl NamedAgent for StreamingAgent {
+    fn name(&self) -> &str {
+        "assistant"
+    }
+}
+
+impl ConsumerAgent for StreamingAgent {
+    type Mrx = Message;
+    type Error = ScriptError;
+
+    async fn receive_message(&mut self, mrx: Self::Mrx) -> Result<(), Self::Error> {
+        match mrx {
+            Message::Text { sender, message } => {
+                record(&self.log, Event::AgentReceived { sender, message })
+            }
+            Message::CodeExecutionResult(result) => record(
+                &self.log,
+                Event::AgentReceivedResult(result.output().stdout.clone()),
+            ),
+            _ => panic!("unexpected message"),
+        }
+
+        Ok(())
+    }
+}
+
+impl ProducerAgent for StreamingAgent {
+    type Mtx = CollaborativeAgentResponse;
+    type Error = ScriptError;
+
+    async fn send_message(&mut self) -> Result<Self::Mtx, Self::Error> {
+        panic!("the chat should stream replies")
+    }
+}
+
+impl StreamingProducerAgent for StreamingAgent {
+    type Delta = String;
+
+    fn send_message_stream(&mut self) -> impl Stream<Item = Result<Self::Delta, Self::Error>> + '_ {
+        let deltas = match self.replies.pop_front() {
+            Some(deltas) => deltas,
+            None => {
+                self.cancellation_token.cancel();
+                vec!["out of script".to_string()]
+            }
+        };
+
+        futures::stream::iter(deltas.into_iter().map(Ok))
+    }
+
+    fn assemble_message(&mut self, deltas: Vec<Self::Delta>) -> Result<Self::Mtx, Self::Error> {
+        Ok(deltas.concat().into())
+    }
+}
+
+/// Echoes the code as stdout. Code `fail` fails and code `error` makes the executor itself fail.
+struct FakeExecutor {
+    log: Log,
+}
+
+impl CodeExecutor for FakeExecutor {
+    type Error = ScriptError;
+
+    async fn execute_code_block(
+        &self,
+        code_block: &CodeBlock,
+    ) -> Result<CodeBlockExecutionResult, Self::Error> {
+        record(&self.log, Event::Executed(code_block.code.clone()));
+
+        let output = CodeBlockExecutionOutput::from(code_block.code.as_str());
+
+        match code_block.code.as_str() {
+            "error" => Err(ScriptError),
+            "fail" => Ok(CodeBlockExecutionResult::Failure(output)),
+            _ => Ok(CodeBlockExecutionResult::Success(output)),
+        }
+    }
+}
+
+struct Greeting;
+
+impl SystemAgent for Greeting {
+    fn initial_message(&self) -> String {
+        "hello".to_string()
+    }
+}
+
+fn text(text: &str) -> CollaborativeAgentResponse {
+    CollaborativeAgentResponse::Text(text.to_string())
+}
+
+fn code_block(code: &str) -> CodeBlock {
+    CodeBlock {
+        language: "sh".to_string(),
+        code: code.to_string(),
+    }
+}
+
+fn commented_code_block(code: &str, request_execution: bool) -> CollaborativeAgentResponse {
+    CollaborativeAgentResponse::CommentedCodeBlock(CommentedCodeBlock {
+        comment: "here".to_string(),
+        code_block: code_block(code),
+        request_execution,
+    })
+}
+
+fn segments(codes: &[&str], request_execution: bool) -> CollaborativeAgentResponse {
+    CollaborativeAgentResponse::Segments(
+        codes
+            .iter()
+            .enumerate()
+            .flat_map(|(index, code)| {
+                [
+                    ResponseSegment::Text(format!("step {}", index)),
+                    ResponseSegment::CodeBlock {
+                        code_block: code_block(code),
+                        request_execution,
+                    },
+                ]
+            })
+            .collect(),
+    )
+}
+
+fn user_received(sender: &str, message: &str) -> Event {
+    Event::UserReceived {
+        sender: sender.to_string(),
+        message: message.to_string(),
+    }
+}
+
+fn agent_received(message: &str) -> Event {
+    Event::AgentReceived {
+        sender: "user".to_string(),
+        message: message.to_string(),
+    }
+}
+
+fn feedback_requested(comment: &str, code: &str) -> Event {
+    Event::FeedbackRequested {
+        comment: comment.to_string(),
+        code: code.to_string(),
+    }
+}
+
+fn deny(reason: &str) -> CodeBlockFeedback {
+    CodeBlockFeedback::DenyExecution {
+        reason: reason.to_string(),
+    }
+}
+
+fn reports(reports: &[&str]) -> Event {
+    Event::AgentReceivedReports(reports.iter().map(|report| report.to_string()).collect())
+}
+
+async fn run(
+    user_agent: impl FnOnce(&Log) -> ScriptedUserAgent,
+    responses: Vec<CollaborativeAgentResponse>,
+    options: CollaborativeChatOptions,
+) -> Vec<Event> {
+    let log = Log::default();
+    let cancellation_token = CancellationToken::new();
+
+    let collaborative_agent = ScriptedCollaborativeAgent::new(&log, &cancellation_token, responses);
+    let executor = FakeExecutor { log: log.clone() };
+
+    collaborative_chat_with_options(
+        user_agent(&log),
+        collaborative_agent,
+        Greeting,
+        executor,
+        options,
+        cancellation_token,
+    )
+    .await
+    .expect("chat failed");
+
+    let events = log.lock().unwrap().clone();
+    events
+}
+
+#[tokio::test]
+async fn text_is_passed_between_agents() {
+    let events = run(
+        |log| ScriptedUserAgent::new(log).replies(&["write hello world", "thanks"]),
+        vec![text("what language?")],
+        CollaborativeChatOptions::default(),
+    )
+    .await;
+
+    assert_eq!(
+        events,
+        vec![
+            user_received("system", "hello"),
+            agent_received("write hello world"),
+            user_received("assistant", "what language?"),
+            agent_received("thanks"),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn allowed_code_block_is_executed() {
+    let events = run(
+        |log| ScriptedUserAgent::new(log).feedback(vec![CodeBlockFeedback::AllowExecution]),
+        vec![commented_code_block("echo hi", true)],
+        CollaborativeChatOptions::default(),
+    )
+    .await;
+
+    assert_eq!(
+        events[2..],
+        [
+            Event::UserShown("here\n\n```sh\necho hi\n```".to_string()),
+            feedback_requested("here", "echo hi"),
+            Event::Executed("echo hi".to_string()),
+            Event::UserReceivedResult("echo hi".to_string()),
+            Event::AgentReceivedResult("echo hi".to_string()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn denied_code_block_is_not_executed() {
+    let events = run(
+        |log| ScriptedUserAgent::new(log).feedback(vec![deny("too dangerous")]),
+        vec![commented_code_block("rm -rf /", true)],
+        CollaborativeChatOptions::default(),
+    )
+    .await;
+
+    assert_eq!(
+        events[3..],
+        [
+            feedback_requested("here", "rm -rf /"),
+            Event::RisksShown(vec![RiskKind::FileWriteOutsideWorkdir]),
+            Event::AgentDenied {
+                code: "rm -rf /".to_string(),
+                reason: "too dangerous".to_string(),
+            },
+        ]
+    );
+}
+
+#[tokio::test]
+async fn code_block_without_execution_request_is_passed_as_message() {
+    let events = run(
+        |log| ScriptedUserAgent::new(log).replies(&["start", "looks good"]),
+        vec![commented_code_block("echo hi", false)],
+        CollaborativeChatOptions::default(),
+    )
+    .await;
+
+    assert_eq!(
+        events[2..],
+        [
+            user_received("assistant", "here\n\n```sh\necho hi\n```"),
+            agent_received("looks good"),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn segments_are_executed_in_order_until_failure() {
+    let events = run(
+        |log| {
+            ScriptedUserAgent::new(log).feedback(vec![
+                CodeBlockFeedback::AllowExecution,
+                CodeBlockFeedback::AllowExecution,
+            ])
+        },
+        vec![segments(&["echo one", "fail", "echo three"], true)],
+        CollaborativeChatOptions::default(),
+    )
+    .await;
+
+    assert_eq!(
+        events[3..],
+        [
+            feedback_requested("step 0", "echo one"),
+            Event::Executed("echo one".to_string()),
+            Event::UserReceivedResult("echo one".to_string()),
+            feedback_requested("step 1", "fail"),
+            Event::Executed("fail".to_string()),
+            Event::UserReceivedResult("fail".to_string()),
+            reports(&[
+                "echo one: executed true",
+                "fail: executed false",
+                "echo three: skipped",
+            ]),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn denied_segment_skips_the_rest() {
+    let events = run(
+        |log| ScriptedUserAgent::new(log).feedback(vec![deny("no")]),
+        vec![segments(&["echo one", "echo two"], true)],
+        CollaborativeChatOptions::default(),
+    )
+    .await;
+
+    assert_eq!(
+        events[3..],
+        [
+            feedback_requested("step 0", "echo one"),
+            reports(&["echo one: denied no", "echo two: skipped"]),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn batch_is_approved_at_once() {
+    let events = run(
+        |log| ScriptedUserAgent::new(log).feedback(vec![CodeBlockFeedback::AllowExecution]),
+        vec![segments(&["echo one", "echo two"], true)],
+        CollaborativeChatOptions {
+            execution_mode: ExecutionMode::Batch,
+            ..Default::default()
+        },
+    )
+    .await;
+
+    assert_eq!(
+        events[3..],
+        [
+            Event::BatchFeedbackRequested { code_blocks: 2 },
+            Event::Executed("echo one".to_string()),
+            Event::UserReceivedResult("echo one".to_string()),
+            Event::Executed("echo two".to_string()),
+            Event::UserReceivedResult("echo two".to_string()),
+            reports(&["echo one: executed true", "echo two: executed true"]),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn batch_is_denied_at_once() {
+    let events = run(
+        |log| ScriptedUserAgent::new(log).feedback(vec![deny("no")]),
+        vec![segments(&["echo one", "echo two"], true)],
+        CollaborativeChatOptions {
+            execution_mode: ExecutionMode::Batch,
+            ..Default::default()
+        },
+    )
+    .await;
+
+    assert_eq!(
+        events[3..],
+        [
+            Event::BatchFeedbackRequested { code_blocks: 2 },
+            reports(&["echo one: denied no", "echo two: denied no"]),
+        ]
+    );
+}
+
+fn policy() -> ApprovalPolicy {
+    ApprovalPolicy::new()
+        .with_rule(Pattern::deny(Regex::new(r"rm\s+-rf").unwrap(), "no rm"))
+        .with_rule(Pattern::escalate(Regex::new(r"curl").unwrap()))
+        .with_default(ApprovalDecision::Allow)
+}
+
+#[tokio::test]
+async fn code_block_allowed_by_the_policy_is_executed_without_asking() {
+    let events = run(
+        ScriptedUserAgent::new,
+        vec![commented_code_block("echo hi", true)],
+        CollaborativeChatOptions {
+            approval_policy: Some(policy()),
+            ..Default::default()
+        },
+    )
+    .await;
+
+    assert_eq!(
+        events[3..],
+        [
+            Event::Executed("echo hi".to_string()),
+            Event::UserReceivedResult("echo hi".to_string()),
+            Event::AgentReceivedResult("echo hi".to_string()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn code_block_denied_by_the_policy_gets_the_reason() {
+    let events = run(
+        ScriptedUserAgent::new,
+        vec![commented_code_block("rm -rf /", true)],
+        CollaborativeChatOptions {
+            approval_policy: Some(policy()),
+            ..Default::default()
+        },
+    )
+    .await;
+
+    assert_eq!(
+        events[3..],
+        [Event::AgentDenied {
+            code: "rm -rf /".to_string(),
+            reason: "no rm".to_string(),
+        }]
+    );
+}
+
+#[tokio::test]
+async fn code_block_escalated_by_the_policy_is_asked_about() {
+    let events = run(
+        |log| ScriptedUserAgent::new(log).feedback(vec![deny("not now")]),
+        vec![segments(&["echo one", "curl example.com"], true)],
+        CollaborativeChatOptions {
+            approval_policy: Some(policy()),
+            ..Default::default()
+        },
+    )
+    .await;
+
+    assert_eq!(
+        events[3..],
+        [
+            Event::Executed("echo one".to_string()),
+            Event::UserReceivedResult("echo one".to_string()),
+            feedback_requested("step 1", "curl example.com"),
+            Event::RisksShown(vec![RiskKind::NetworkAccess]),
+            reports(&[
+                "echo one: executed true",
+                "curl example.com: denied not now"
+            ]),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn batch_is_denied_by_the_policy_if_any_code_block_is() {
+    let events = run(
+        ScriptedUserAgent::new,
+        vec![segments(&["curl example.com", "rm -rf /"], true)],
+        CollaborativeChatOptions {
+            execution_mode: ExecutionMode::Batch,
+            approval_policy: Some(policy()),
+            ..Default::default()
+        },
+    )
+    .await;
+
+    assert_eq!(
+        events[3..],
+        [reports(&[
+            "curl example.com: denied no rm",
+            "rm -rf /: denied no rm",
+        ])]
+    );
+}
+
+#[tokio::test]
+async fn risks_of_the_batch_are_shown() {
+    let events = run(
+        |log| ScriptedUserAgent::new(log).feedback(vec![deny("no")]),
+        vec![segments(&["echo one", "wget example.com &"], true)],
+        CollaborativeChatOptions {
+            execution_mode: ExecutionMode::Batch,
+            ..Default::default()
+        },
+    )
+    .await;
+
+    assert_eq!(
+        events[3..5],
+        [
+            Event::BatchFeedbackRequested { code_blocks: 2 },
+            Event::RisksShown(vec![RiskKind::NetworkAccess, RiskKind::ProcessSpawn]),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn only_risky_code_is_asked_about() {
+    let events = run(
+        |log| ScriptedUserAgent::new(log).feedback(vec![CodeBlockFeedback::AllowExecution]),
+        vec![segments(&["echo one", "pip install requests"], true)],
+        CollaborativeChatOptions {
+            approval_policy: Some(
+                ApprovalPolicy::new()
+                    .with_rule(RiskyCode::escalate())
+                    .with_default(ApprovalDecision::Allow),
+            ),
+            ..Default::default()
+        },
+    )
+    .await;
+
+    assert_eq!(
+        events[3..],
+        [
+            Event::Executed("echo one".to_string()),
+            Event::UserReceivedResult("echo one".to_string()),
+            feedback_requested("step 1", "pip install requests"),
+            Event::RisksShown(vec![RiskKind::PackageInstall]),
+            Event::Executed("pip install requests".to_string()),
+            Event::UserReceivedResult("pip install requests".to_string()),
+            reports(&[
+                "echo one: executed true",
+                "pip install requests: executed true",
+            ]),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn segments_without_execution_request_are_passed_as_message() {
+    let events = run(
+        |log| ScriptedUserAgent::new(log).replies(&["start", "nice"]),
+        vec![segments(&["echo one"], false)],
+        CollaborativeChatOptions::default(),
+    )
+    .await;
+
+    assert_eq!(
+        events[2..],
+        [
+            user_received("assistant", "step 0\n\n```sh\necho one\n```"),
+            agent_received("nice"),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn cancelled_chat_stops_after_the_first_reply() {
+    let log = Log::default();
+    let cancellation_token = CancellationToken::new();
+    cancellation_token.cancel();
+
+    let collaborative_agent =
+        ScriptedCollaborativeAgent::new(&log, &cancellation_token, vec![text("never handled")]);
+
+    let result = collaborative_chat(
+        ScriptedUserAgent::new(&log),
+        collaborative_agent,
+        Greeting,
+        FakeExecutor { log: log.clone() },
+        cancellation_token,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        result.outcome,
+        ChatOutcome {
+            reason: TerminationReason::Cancelled,
+            turns: 1
+        }
+    );
+    assert_eq!(
+        *log.lock().unwrap(),
+        vec![user_received("system", "hello"), agent_received("ok")]
+    );
+}
+
+async fn run_with_budget(budget: Budget) -> (TerminationReason, Vec<Event>) {
+    let log = Log::default();
+    let cancellation_token = CancellationToken::new();
+
+    let mut collaborative_agent = ScriptedCollaborativeAgent::new(
+        &log,
+        &cancellation_token,
+        vec![text("first"), text("second"), text("third")],
+    );
+    collaborative_agent.usage = Some(TokenUsage::default());
+
+    let result = collaborative_chat_with_options(
+        ScriptedUserAgent::new(&log),
+        collaborative_agent,
+        Greeting,
+        FakeExecutor { log: log.clone() },
+        CollaborativeChatOptions {
+            budget,
+            ..Default::default()
+        },
+        cancellation_token,
+    )
+    .await
+    .unwrap();
+
+    let events = log.lock().unwrap().clone();
+    (result.outcome.reason, events)
+}
+
+#[tokio::test]
+async fn chat_ends_once_the_token_budget_is_exceeded() {
+    let (reason, events) = run_with_budget(Budget {
+        max_tokens: Some(200),
+        ..Default::default()
+    })
+    .await;
+
+    assert_eq!(
+        reason,
+        TerminationReason::BudgetExceeded {
+            usage: REPLY_USAGE + REPLY_USAGE
+        }
+    );
+
+    // The reply going over the budget is only shown.
+    assert_eq!(
+        events,
+        vec![
+            user_received("system", "hello"),
+            agent_received("ok"),
+            user_received("assistant", "first"),
+            agent_received("ok"),
+            Event::UserShown("second".to_string()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn chat_ends_once_the_cost_budget_is_exceeded() {
+    // Every reply costs 100 * 20 + 10 * 100 = 3000 per million tokens.
+    let (reason, _) = run_with_budget(Budget {
+        max_cost: Some(0.008),
+        pricing: Pricing {
+            prompt: 20.0,
+            completion: 100.0,
+        },
+        ..Default::default()
+    })
+    .await;
+
+    assert_eq!(
+        reason,
+        TerminationReason::BudgetExceeded {
+            usage: REPLY_USAGE + REPLY_USAGE + REPLY_USAGE
+        }
+    );
+
+    let (reason, _) = run_with_budget(Budget::default()).await;
+    assert_eq!(reason, TerminationReason::Cancelled);
+}
+
+async fn run_until(
+    user_agent: impl FnOnce(&Log) -> ScriptedUserAgent,
+    responses: Vec<CollaborativeAgentResponse>,
+    termination: impl TerminationCondition + 'static,
+) -> (ChatOutcome, Vec<Event>) {
+    run_with_outcome(
+        user_agent,
+        responses,
+        CollaborativeChatOptions {
+            termination: Some(Arc::new(termination)),
+            ..Default::default()
+        },
+    )
+    .await
+}
+
+async fn run_with_outcome(
+    user_agent: impl FnOnce(&Log) -> ScriptedUserAgent,
+    responses: Vec<CollaborativeAgentResponse>,
+    options: CollaborativeChatOptions,
+) -> (ChatOutcome, Vec<Event>) {
+    let log = Log::default();
+    let cancellation_token = CancellationToken::new();
+
+    let result = collaborative_chat_with_options(
+        user_agent(&log),
+        ScriptedCollaborativeAgent::new(&log, &cancellation_token, responses),
+        Greeting,
+        FakeExecutor { log: log.clone() },
+        options,
+        cancellation_token,
+    )
+    .await
+    .unwrap();
+
+    let events = log.lock().unwrap().clone();
+    (result.outcome, events)
+}
+
+fn condition_met(met: MetCondition, turns: usize) -> ChatOutcome {
+    ChatOutcome {
+        reason: TerminationReason::ConditionMet(met),
+        turns,
+    }
+}
+
+#[tokio::test]
+async fn chat_ends_after_max_turns() {
+    let (outcome, events) = run_until(
+        ScriptedUserAgent::new,
+        vec![text("first"), text("second"), text("third")],
+        MaxTurns(2),
+    )
+    .await;
+
+    assert_eq!(outcome, condition_met(MetCondition::MaxTurns(2), 2));
+
+    // The last reply is only shown.
+    assert_eq!(
+        events[2..],
+        [
+            user_received("assistant", "first"),
+            agent_received("ok"),
+            Event::UserShown("second".to_string()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn chat_ends_on_keyword_or_user_exit() {
+    let termination = || TextMention::new("TERMINATE").or(UserExit);
+
+    let (outcome, events) = run_until(
+        ScriptedUserAgent::new,
+        vec![text("working"), text("done. TERMINATE"), text("never")],
+        termination(),
+    )
+    .await;
+
+    assert_eq!(
+        outcome,
+        condition_met(MetCondition::TextMention("TERMINATE".to_string()), 2)
+    );
+    assert_eq!(
+        events.last(),
+        Some(&Event::UserShown("done. TERMINATE".to_string()))
+    );
+
+    let (outcome, events) = run_until(
+        |log| ScriptedUserAgent::new(log).replies(&["go", " Exit\n"]),
+        vec![text("working"), text("never")],
+        termination(),
+    )
+    .await;
+
+    // The exit is not passed to the collaborative agent.
+    assert_eq!(outcome, condition_met(MetCondition::UserExit, 1));
+    assert_eq!(
+        events,
+        vec![
+            user_received("system", "hello"),
+            agent_received("go"),
+            user_received("assistant", "working"),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn chat_ends_once_the_code_succeeds() {
+    let (outcome, events) = run_until(
+        |log| {
+            ScriptedUserAgent::new(log).feedback(vec![
+                CodeBlockFeedback::AllowExecution,
+                CodeBlockFeedback::AllowExecution,
+            ])
+        },
+        vec![
+            segments(&["fail"], true),
+            commented_code_block("echo", true),
+            text("never"),
+        ],
+        ExecutionSucceeded,
+    )
+    .await;
+
+    assert_eq!(outcome, condition_met(MetCondition::ExecutionSucceeded, 2));
+    assert_eq!(
+        events[events.len() - 3..],
+        [
+            feedback_requested("here", "echo"),
+            Event::Executed("echo".to_string()),
+            Event::UserReceivedResult("echo".to_string()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn chat_ends_when_the_user_is_idle() {
+    let timeout = Duration::from_millis(50);
+
+    let (outcome, events) = run_until(
+        |log| ScriptedUserAgent::new(log).replies(&["go"]).stalls(),
+        vec![text("anyone there?")],
+        IdleTimeout(timeout).or(MaxTurns(10)),
+    )
+    .await;
+
+    assert_eq!(
+        outcome,
+        condition_met(MetCondition::IdleTimeout(timeout), 1)
+    );
+    assert_eq!(
+        events.last(),
+        Some(&user_received("assistant", "anyone there?"))
+    );
+}
+
+#[tokio::test]
+async fn combined_conditions_have_to_be_met_together() {
+    let short_reply = Predicate::new(
+        "short reply",
+        |event, _| matches!(event, ChatEvent::Reply(CollaborativeAgentResponse::Text(text)) if text.len() < 5),
+    );
+
+    let (outcome, events) = run_until(
+        ScriptedUserAgent::new,
+        vec![text("a"), text("longer"), text("b"), text("never")],
+        MaxTurns(2).and(short_reply),
+    )
+    .await;
+
+    assert_eq!(
+        outcome,
+        condition_met(
+            MetCondition::All(vec![
+                MetCondition::MaxTurns(2),
+                MetCondition::Predicate("short reply".to_string()),
+            ]),
+            3
+        )
+    );
+    assert_eq!(events.last(), Some(&Event::UserShown("b".to_string())));
+}
+
+#[tokio::test]
+async fn transcript_records_the_whole_chat() {
+    let log = Log::default();
+    let cancellation_token = CancellationToken::new();
+
+    let result = collaborative_chat_with_options(
+        ScriptedUserAgent::new(&log)
+            .replies(&["run it"])
+            .feedback(vec![CodeBlockFeedback::AllowExecution]),
+        ScriptedCollaborativeAgent::new(
+            &log,
+            &cancellation_token,
+            vec![commented_code_block("echo", true), text("done. TERMINATE")],
+        ),
+        Greeting,
+        FakeExecutor { log: log.clone() },
+        CollaborativeChatOptions {
+            termination: Some(Arc::new(TextMention::new("TERMINATE"))),
+            ..Default::default()
+        },
+        cancellation_token,
+    )
+    .await
+    .unwrap();
+
+    let transcript: Vec<String> = result
+        .transcript
+        .iter()
+        .map(|entry| match &entry.event {
+            TranscriptEvent::Welcome { sender, message } => format!("welcome {sender}: {message}"),
+            TranscriptEvent::Message(Message::Text { sender, message }) => {
+                format!("message {sender}: {message}")
+            }
+            TranscriptEvent::Message(Message::CodeExecutionResult(result)) => {
+                format!("message result: {}", result.output().stdout)
+            }
+            TranscriptEvent::Reply { sender, response } => {
+                format!("reply {sender}: {}", response.to_markdown())
+            }
+            TranscriptEvent::CodeBlockFeedback {
+                code_block,
+                feedback,
+            } => format!("feedback {}: {:?}", code_block.code, feedback),
+            TranscriptEvent::Execution { code_block, result } => {
+                format!("execution {}: {}", code_block.code, result.is_success())
+            }
+            event => panic!("unexpected event {:?}", event),
+        })
+        .collect();
+
+    assert_eq!(
+        transcript,
+        vec![
+            "welcome system: hello",
+            "message user: run it",
+            "reply assistant: here\n\n```sh\necho\n```",
+            "feedback echo: AllowExecution",
+            "execution echo: true",
+            "message result: echo",
+            "reply assistant: done. TERMINATE",
+        ]
+    );
+
+    assert_eq!(result.outcome.turns, 2);
+    assert!(result
+        .transcript
+        .windows(2)
+        .all(|entries| entries[0].elapsed <= entries[1].elapsed));
+    assert!(result.transcript.last().unwrap().elapsed <= result.duration);
+}
+
+/// Replies with the length of the text.
+#[derive(Debug)]
+struct Length;
+
+impl AutoReply for Length {
+    fn reply(&self, sender: &str, text: &str) -> String {
+        format!("{} said {} characters", sender, text.len())
+    }
+}
+
+#[tokio::test]
+async fn user_is_never_asked_to_reply() {
+    let (outcome, events) = run_with_outcome(
+        |log| ScriptedUserAgent::new(log).replies(&["task"]),
+        vec![text("first"), segments(&["echo"], false)],
+        CollaborativeChatOptions {
+            human_input_mode: HumanInputMode::Never,
+            auto_reply: Some(Arc::new(Length)),
+            approval_policy: Some(ApprovalPolicy::new()),
+            ..Default::default()
+        },
+    )
+    .await;
+
+    assert_eq!(outcome.reason, TerminationReason::Cancelled);
+
+    // Only the welcome message gets the reply of the user, the texts are shown.
+    assert_eq!(
+        events,
+        vec![
+            user_received("system", "hello"),
+            agent_received("task"),
+            Event::UserShown("first".to_string()),
+            agent_received("assistant said 5 characters"),
+            Event::UserShown("step 0\n\n```sh\necho\n```".to_string()),
+            agent_received("assistant said 22 characters"),
+        ]
+    );
+
+    let (_, events) = run_with_outcome(
+        ScriptedUserAgent::new,
+        vec![text("first")],
+        CollaborativeChatOptions {
+            human_input_mode: HumanInputMode::Never,
+            approval_policy: Some(ApprovalPolicy::new()),
+            ..Default::default()
+        },
+    )
+    .await;
+
+    assert_eq!(events[3], agent_received(&FixedReply::default().0));
+}
+
+#[tokio::test]
+async fn unattended_chat_executes_code_without_asking() {
+    // The scripted user agent has no feedback to give, it would panic if asked.
+    let events = run(
+        |log| ScriptedUserAgent::new(log).replies(&["task"]),
+        vec![
+            commented_code_block("echo hi", true),
+            segments(&["curl example.com"], true),
+            tool_calls(vec![tool_call("call_1", "add", json!({ "a": 1, "b": 2 }))]),
+        ],
+        CollaborativeChatOptions {
+            human_input_mode: HumanInputMode::Never,
+            approval_policy: Some(policy()),
+            tools: calculator(),
+            tool_call_approval: ToolCallApproval::Automatic,
+            ..Default::default()
+        },
+    )
+    .await;
+
+    let events: Vec<Event> = events
+        .into_iter()
+        .skip(3)
+        .filter(|event| !matches!(event, Event::UserShown(_)))
+        .collect();
+    assert_eq!(
+        events,
+        [
+            Event::Executed("echo hi".to_string()),
+            Event::UserReceivedResult("echo hi".to_string()),
+            Event::AgentReceivedResult("echo hi".to_string()),
+            // Escalated by the policy, but there is nobody to ask.
+            reports(&["curl example.com: denied Nobody is available to approve this code."]),
+            Event::AgentReceivedToolCallReports(vec![ToolCallReport {
+                tool_call: tool_call("call_1", "add", json!({ "a": 1, "b": 2 })),
+                outcome: ToolCallOutcome::Returned(json!(3)),
+            }]),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn unattended_chat_requires_something_to_approve_in_place_of_the_user() {
+    let log = Log::default();
+
+    for options in [
+        CollaborativeChatOptions {
+            human_input_mode: HumanInputMode::Never,
+            ..Default::default()
+        },
+        CollaborativeChatOptions {
+            human_input_mode: HumanInputMode::Never,
+            approval_policy: Some(policy()),
+            tools: calculator(),
+            ..Default::default()
+        },
+    ] {
+        let cancellation_token = CancellationToken::new();
+
+        let failure = collaborative_chat_with_options(
+            ScriptedUserAgent::new(&log),
+            ScriptedCollaborativeAgent::new(&log, &cancellation_token, vec![]),
+            Greeting,
+            FakeExecutor { log: log.clone() },
+            options,
+            cancellation_token,
+        )
+        .await
+        .unwrap_err();
+
+        assert!(matches!(
+            failure.error,
+            CollaborativeChatError::InvalidOptions(_)
+        ));
+        assert!(failure.result.transcript.is_empty());
+    }
+
+    assert!(log.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn user_decides_once_the_condition_is_met() {
+    let (outcome, events) = run_with_outcome(
+        |log| ScriptedUserAgent::new(log).replies(&["task", "one more thing", " "]),
+        vec![
+            text("working"),
+            text("done. TERMINATE"),
+            text("really done. TERMINATE"),
+            text("never"),
+        ],
+        CollaborativeChatOptions {
+            termination: Some(Arc::new(TextMention::new("TERMINATE"))),
+            human_input_mode: HumanInputMode::Terminate,
+            auto_reply: Some(Arc::new(FixedReply("go on".to_string()))),
+            ..Default::default()
+        },
+    )
+    .await;
+
+    assert_eq!(
+        outcome,
+        condition_met(MetCondition::TextMention("TERMINATE".to_string()), 3)
+    );
+
+    let question = "The chat is about to end: `TERMINATE` mentioned. Reply to go on, or reply with nothing or `exit` to end it.";
+    assert_eq!(
+        events,
+        vec![
+            user_received("system", "hello"),
+            agent_received("task"),
+            Event::UserShown("working".to_string()),
+            agent_received("go on"),
+            Event::UserShown("done. TERMINATE".to_string()),
+            user_received("system", question),
+            agent_received("one more thing"),
+            Event::UserShown("really done. TERMINATE".to_string()),
+            user_received("system", question),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn user_agent_errors_end_the_chat() {
+    let log = Log::default();
+    let cancellation_token = CancellationToken::new();
+
+    let mut user_agent = ScriptedUserAgent::new(&log);
+    user_agent.fail_on_reply = true;
+
+    let result = collaborative_chat(
+        user_agent,
+        ScriptedCollaborativeAgent::new(&log, &cancellation_token, vec![]),
+        Greeting,
+        FakeExecutor { log: log.clone() },
+        cancellation_token,
+    )
+    .await;
+
+    let failure = result.unwrap_err();
+    assert!(matches!(
+        failure.error,
+        CollaborativeChatError::ChatUserAgent(ScriptError)
+    ));
+    assert_eq!(failure.result.outcome.reason, TerminationReason::Failed);
+    assert!(matches!(
+        failure.result.transcript[..],
+        [TranscriptEntry {
+            event: TranscriptEvent::Welcome { .. },
+            ..
+        }]
+    ));
+}
+
+#[tokio::test]
+async fn executor_errors_end_the_chat() {
+    let log = Log::default();
+    let cancellation_token = CancellationToken::new();
+
+    let result = collaborative_chat(
+        ScriptedUserAgent::new(&log).feedback(vec![CodeBlockFeedback::AllowExecution]),
+        ScriptedCollaborativeAgent::new(
+            &log,
+            &cancellation_token,
+            vec![commented_code_block("error", true)],
+        ),
+        Greeting,
+        FakeExecutor { log: log.clone() },
+        cancellation_token,
+    )
+    .await;
+
+    let failure = result.unwrap_err();
+    assert!(matches!(
+        failure.error,
+        CollaborativeChatError::CodeExecutor(ScriptError)
+    ));
+
+    // The transcript up to the error is kept, and may be saved.
+    assert_eq!(
+        failure.result.outcome,
+        ChatOutcome {
+            reason: TerminationReason::Failed,
+            turns: 1,
+        }
+    );
+    let transcript = serde_json::to_value(&failure.result).unwrap()["transcript"].clone();
+    assert_eq!(
+        transcript[2]["event"]["Reply"]["response"]["CommentedCodeBlock"]["code_block"]["code"],
+        "error"
+    );
+}
+
+#[tokio::test]
+async fn streamed_replies_are_shown_piece_by_piece() {
+    let log = Log::default();
+    let cancellation_token = CancellationToken::new();
+
+    let collaborative_agent = StreamingAgent {
+        replies: [vec!["Run", " this:\n```sh exe", "cute\nhi\n```"]]
+            .into_iter()
+            .map(|deltas| deltas.into_iter().map(str::to_string).collect())
+            .collect(),
+        cancellation_token: cancellation_token.clone(),
+        log: log.clone(),
+    };
+
+    streaming_collaborative_chat(
+        ScriptedUserAgent::new(&log)
+            .replies(&["say hi"])
+            .feedback(vec![CodeBlockFeedback::AllowExecution]),
+        collaborative_agent,
+        Greeting,
+        FakeExecutor { log: log.clone() },
+        CollaborativeChatOptions::default(),
+        cancellation_token,
+    )
+    .await
+    .expect("chat failed");
+
+    let partial = |delta: &str| Event::UserReceivedPartial {
+        sender: "assistant".to_string(),
+        delta: delta.to_string(),
+    };
+
+    let events = log.lock().unwrap().clone();
+    assert_eq!(
+        events,
+        vec![
+            user_received("system", "hello"),
+            agent_received("say hi"),
+            partial("Run"),
+            partial(" this:\n```sh exe"),
+            partial("cute\nhi\n```"),
+            Event::UserShown("Run this:\n\n```sh\nhi\n```".to_string()),
+            feedback_requested("Run this:", "hi"),
+            Event::Executed("hi".to_string()),
+            Event::UserReceivedResult("hi".to_string()),
+            Event::AgentReceivedResult("hi".to_string()),
+            partial("out of script"),
+        ]
+    );
+}
+
+#[derive(Deserialize)]
+struct AddArguments {
+    a: i64,
+    b: i64,
+}
+
+fn calculator() -> ToolRegistry {
+    ToolRegistry::new().with_tool(Tool::new(
+        ToolDefinition::new(
+            "add",
+            "Adds two integers.",
+            json!({
+                "type": "object",
+                "properties": { "a": { "type": "integer" }, "b": { "type": "integer" } },
+                "required": ["a", "b"],
+            }),
+        ),
+        |arguments: AddArguments| async move {
+            arguments
+                .a
+                .checked_add(arguments.b)
+                .ok_or("overflow".to_string())
+        },
+    ))
+}
+
+fn tool_call(id: &str, name: &str, arguments: serde_json::Value) -> ToolCall {
+    ToolCall {
+        id: id.to_string(),
+        name: name.to_string(),
+        arguments,
+    }
+}
+
+fn tool_calls(tool_calls: Vec<ToolCall>) -> CollaborativeAgentResponse {
+    CollaborativeAgentResponse::ToolCalls {
+        comment: "let me calculate".to_string(),
+        tool_calls,
+    }
+}
+
+#[tokio::test]
+async fn approved_tool_calls_are_dispatched() {
+    let add = tool_call("1", "add", json!({ "a": 2, "b": 3 }));
+    let denied = tool_call("2", "add", json!({ "a": 1, "b": 1 }));
+
+    let events = run(
+        |log| {
+            ScriptedUserAgent::new(log)
+                .feedback(vec![CodeBlockFeedback::AllowExecution, deny("enough")])
+        },
+        vec![tool_calls(vec![add.clone(), denied.clone()])],
+        CollaborativeChatOptions {
+            tools: calculator(),
+            ..Default::default()
+        },
+    )
+    .await;
+
+    assert_eq!(
+        events[2..],
+        [
+            Event::UserShown(
+                "let me calculate\n\nCalling `add` with `{\"a\":2,\"b\":3}`\nCalling `add` with `{\"a\":1,\"b\":1}`"
+                    .to_string()
+            ),
+            Event::ToolCallFeedbackRequested("add".to_string()),
+            Event::ToolCallFeedbackRequested("add".to_string()),
+            Event::AgentReceivedToolCallReports(vec![
+                ToolCallReport {
+                    tool_call: add,
+                    outcome: ToolCallOutcome::Returned(json!(5)),
+                },
+                ToolCallReport {
+                    tool_call: denied,
+                    outcome: ToolCallOutcome::Denied {
+                        reason: "enough".to_string(),
+                    },
+                },
+            ]),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn tool_call_errors_are_reported_to_the_agent() {
+    let events = run(
+        ScriptedUserAgent::new,
+        vec![tool_calls(vec![
+            tool_call("1", "multiply", json!({ "a": 2, "b": 3 })),
+            tool_call("2", "add", json!({ "a": "two" })),
+            tool_call("3", "add", json!({ "a": i64::MAX, "b": 1 })),
+        ])],
+        CollaborativeChatOptions {
+            tools: calculator(),
+            tool_call_approval: ToolCallApproval::Automatic,
+            ..Default::default()
+        },
+    )
+    .await;
+
+    let reports = match &events[3] {
+        Event::AgentReceivedToolCallReports(reports) => reports,
+        event => panic!("unexpected event: {:?}", event),
+    };
+
+    let errors: Vec<String> = reports
+        .iter()
+        .map(|report| match &report.outcome {
+            ToolCallOutcome::Failed(error) => error.clone(),
+            outcome => panic!("unexpected outcome: {:?}", outcome),
+        })
+        .collect();
+
+    assert_eq!(errors[0], "unknown tool: multiply");
+    assert!(errors[1].starts_with("invalid arguments: "));
+    assert_eq!(errors[2], "overflow");
+}
+
+/// Implements only the required methods, so the defaults of the trait are used.
+struct MinimalUserAgent(ScriptedUserAgent);
+
+impl NamedAgent for MinimalUserAgent {
+    fn name(&self) -> &str {
+        self.0.name()
+    }
+}
+
+impl ChatUserAgent for MinimalUserAgent {
+    type Error = ScriptError;
+
+    async fn receive_and_reply(
+        &mut self,
+        sender: String,
+        message: String,
+    ) -> Result<String, Self::Error> {
+        self.0.receive_and_reply(sender, message).await
+    }
+
+    async fn silent_receive_collaborative_agent_response(
+        &mut self,
+        sender: String,
+        response: CollaborativeAgentResponse,
+    ) -> Result<(), Self::Error> {
+        self.0
+            .silent_receive_collaborative_agent_response(sender, response)
+            .await
+    }
+
+    async fn request_code_block_feedback(
+        &mut self,
+        sender: String,
+        comment: String,
+        code_block: CodeBlock,
+    ) -> Result<CodeBlockFeedback, Self::Error> {
+        self.0
+            .request_code_block_feedback(sender, comment, code_block)
+            .await
+    }
+
+    async fn receive_code_execution_result(
+        &mut self,
+        result: CodeBlockExecutionResult,
+    ) -> Result<(), Self::Error> {
+        self.0.receive_code_execution_result(result).await
+    }
+}
+
+/// Implements only the required methods, so the defaults of the trait are used.
+struct MinimalCollaborativeAgent(ScriptedCollaborativeAgent);
+
+impl NamedAgent for MinimalCollaborativeAgent {
+    fn name(&self) -> &str {
+        self.0.name()
+    }
+}
+
+impl CollaborativeAgent for MinimalCollaborativeAgent {
+    type Error = ScriptError;
+
+    async fn receive_and_reply(
+        &mut self,
+        sender: String,
+        message: String,
+    ) -> Result<CollaborativeAgentResponse, Self::Error> {
+        self.0.receive_and_reply(sender, message).await
+    }
+
+    async fn deny_code_block_execution(
+        &mut self,
+        code_block: CodeBlock,
+        feedback: String,
+    ) -> Result<CollaborativeAgentResponse, Self::Error> {
+        self.0.deny_code_block_execution(code_block, feedback).await
+    }
+
+    async fn receive_code_and_reply_to_execution_result(
+        &mut self,
+        code_execution_result: CodeBlockExecutionResult,
+    ) -> Result<CollaborativeAgentResponse, Self::Error> {
+        self.0
+            .receive_code_and_reply_to_execution_result(code_execution_result)
+            .await
+    }
+}
+
+async fn run_minimal(
+    feedback: Vec<CodeBlockFeedback>,
+    responses: Vec<CollaborativeAgentResponse>,
+    options: CollaborativeChatOptions,
+) -> Vec<Event> {
+    let log = Log::default();
+    let cancellation_token = CancellationToken::new();
+
+    collaborative_chat_with_options(
+        MinimalUserAgent(ScriptedUserAgent::new(&log).feedback(feedback)),
+        MinimalCollaborativeAgent(ScriptedCollaborativeAgent::new(
+            &log,
+            &cancellation_token,
+            responses,
+        )),
+        Greeting,
+        FakeExecutor { log: log.clone() },
+        options,
+        cancellation_token,
+    )
+    .await
+    .expect("chat failed");
+
+    let events = log.lock().unwrap().clone();
+    events
+}
+
+#[tokio::test]
+async fn batch_feedback_falls_back_to_every_code_block() {
+    let events = run_minimal(
+        vec![CodeBlockFeedback::AllowExecution, deny("not this one")],
+        vec![segments(&["echo one", "echo two", "echo three"], true)],
+        CollaborativeChatOptions {
+            execution_mode: ExecutionMode::Batch,
+            ..Default::default()
+        },
+    )
+    .await;
+
+    assert_eq!(
+        events[3..],
+        [
+            feedback_requested("step 0", "echo one"),
+            feedback_requested("step 1", "echo two"),
+            agent_received_results(
+                "Code block 1 (sh):\nExecution was denied. Reason: not this one\n\nCode block 2 (sh):\nExecution was denied. Reason: not this one\n\nCode block 3 (sh):\nExecution was denied. Reason: not this one"
+            ),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn tool_calls_are_denied_by_default() {
+    let events = run_minimal(
+        Vec::new(),
+        vec![tool_calls(vec![tool_call(
+            "1",
+            "add",
+            json!({ "a": 2, "b": 3 }),
+        )])],
+        CollaborativeChatOptions {
+            tools: calculator(),
+            ..Default::default()
+        },
+    )
+    .await;
+
+    assert_eq!(
+        events[3..],
+        [agent_received_results(
+            "Tool call `add` (1) was denied. Reason: The user cannot approve tool calls, `add` was not called."
+        )]
+    );
+}
+
+fn agent_received_results(message: &str) -> Event {
+    Event::AgentReceived {
+        sender: RESULTS_SENDER.to_string(),
+        message: message.to_string(),
+    }
+}
@@ -0,0 +1,303 @@
+//! Conversation history and its truncation strategies.
+
+use autogen::text_chat::code::{CodeBlock, CodeBlockExecutionOutput, CodeBlockExecutionResult};
+use autogen::text_chat::collaborative_agent::{
+    CodeBlockOutcome, CodeBlockReport, CollaborativeAgentResponse, Message,
+};
+use autogen::text_chat::conversation_history::{
+    ConversationHistory, HistoryEntry, KeepSystemAndLastN, Role, SlidingWindow, Summarize,
+    TokenBudget,
+};
+use autogen::text_chat::tokenizer::{EstimatingTokenizer, Tokenizer};
+use autogen::text_chat::tool::{ToolCall, ToolCallOutcome, ToolCallReport};
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+fn history() -> ConversationHistory {
+    let mut history = ConversationHistory::new();
+
+    history.push(HistoryEntry::new(Role::System, "Be brief."));
+    history.push(HistoryEntry::new(Role::User, "one").with_sender("user"));
+    history.push(HistoryEntry::new(Role::Assistant, "two"));
+    history.push(HistoryEntry::new(Role::User, "three").with_sender("user"));
+    history.push(HistoryEntry::new(Role::Assistant, "four"));
+
+    history
+}
+
+fn contents(entries: &[HistoryEntry]) -> Vec<&str> {
+    entries.iter().map(|entry| entry.content.as_str()).collect()
+}
+
+#[test]
+fn nothing_is_truncated_by_default() {
+    let history = history();
+
+    assert_eq!(history.len(), 5);
+    assert_eq!(
+        contents(&history.context()),
+        vec!["Be brief.", "one", "two", "three", "four"]
+    );
+}
+
+#[test]
+fn sliding_window_keeps_the_latest_entries() {
+    let history = history().with_truncation(SlidingWindow { max_entries: 2 });
+
+    assert_eq!(
+        contents(&history.context()),
+        vec!["Be brief.", "three", "four"]
+    );
+    assert_eq!(history.entries().len(), 5);
+
+    // Moved forward, past the reply.
+    let history = history.with_truncation(SlidingWindow { max_entries: 3 });
+    assert_eq!(
+        contents(&history.context()),
+        vec!["Be brief.", "three", "four"]
+    );
+}
+
+#[test]
+fn system_entries_survive_keep_system_and_last_n() {
+    let history = history().with_truncation(KeepSystemAndLastN { n: 2 });
+
+    assert_eq!(
+        contents(&history.context()),
+        vec!["Be brief.", "three", "four"]
+    );
+}
+
+#[test]
+fn truncation_starts_at_a_user_turn() {
+    // No user turn after the cut, so it is moved back.
+    let last_one = history().with_truncation(KeepSystemAndLastN { n: 1 });
+    assert_eq!(
+        contents(&last_one.context()),
+        vec!["Be brief.", "three", "four"]
+    );
+
+    let tool_call = ToolCall {
+        id: "call_1".to_string(),
+        name: "add".to_string(),
+        arguments: serde_json::json!({ "a": 1, "b": 2 }),
+    };
+
+    let mut with_tool_call = history();
+    with_tool_call.push(HistoryEntry {
+        tool_calls: vec![tool_call.clone()],
+        ..HistoryEntry::new(Role::Assistant, "")
+    });
+    with_tool_call.push(HistoryEntry::from_message(Message::ToolCallResults(vec![
+        ToolCallReport {
+            tool_call,
+            outcome: ToolCallOutcome::Returned(serde_json::json!(3)),
+        },
+    ])));
+    with_tool_call.push(HistoryEntry::new(Role::Assistant, "It is 3."));
+    with_tool_call.push(HistoryEntry::new(Role::User, "five").with_sender("user"));
+
+    // Results of the call are not kept without the call.
+    let with_tool_call = with_tool_call.with_truncation(SlidingWindow { max_entries: 3 });
+    assert_eq!(
+        contents(&with_tool_call.context()),
+        vec!["Be brief.", "five"]
+    );
+}
+
+#[test]
+fn token_budget_keeps_what_fits() {
+    let counted = |content: &str| content.len();
+
+    // "Be brief." takes 9, "four" 4 and "three" 5 - "two" does not fit anymore.
+    let history = history().with_truncation(TokenBudget::new(20).with_counter(counted));
+    assert_eq!(
+        contents(&history.context()),
+        vec!["Be brief.", "three", "four"]
+    );
+
+    // The latest turn is kept even if over the budget.
+    let history = history.with_truncation(TokenBudget::new(1).with_counter(counted));
+    assert_eq!(
+        contents(&history.context()),
+        vec!["Be brief.", "three", "four"]
+    );
+}
+
+#[test]
+fn token_budget_estimates_the_tokens_by_default() {
+    assert_eq!(EstimatingTokenizer.count_tokens("12345678"), 2);
+    assert_eq!(EstimatingTokenizer.count_tokens("123456789"), 3);
+
+    // Estimated at 3, 1, 1, 2 and 1 tokens.
+    let history = history().with_truncation(TokenBudget::new(8));
+    assert_eq!(
+        contents(&history.context()),
+        vec!["Be brief.", "one", "two", "three", "four"]
+    );
+
+    // "two" still fits, but the history cannot start with a reply.
+    let history = history.with_truncation(TokenBudget::new(7));
+    assert_eq!(
+        contents(&history.context()),
+        vec!["Be brief.", "three", "four"]
+    );
+}
+
+#[tokio::test]
+async fn summarize_replaces_the_left_out_entries() {
+    let calls = Arc::new(AtomicUsize::new(0));
+
+    let summarize = {
+        let calls = calls.clone();
+
+        Summarize::new(2, move |entries: Vec<HistoryEntry>| {
+            calls.fetch_add(1, Ordering::SeqCst);
+
+            async move { contents(&entries).join(", ") }
+        })
+    };
+
+    let mut history = history().with_summarize(summarize.clone().with_max_entries(3));
+
+    history.summarize().await;
+    let context = history.context();
+    assert_eq!(
+        contents(&context),
+        vec![
+            "Be brief.",
+            "Summary of the earlier conversation:\none, two",
+            "three",
+            "four"
+        ]
+    );
+    assert_eq!(context[1].role, Role::System);
+    assert_eq!(history.entries().len(), 5);
+
+    // Nothing new to summarize.
+    history.summarize().await;
+    history.push(HistoryEntry::new(Role::User, "five"));
+    history.summarize().await;
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+    // The previous summary is summarized along with the newly left out entries.
+    history.push(HistoryEntry::new(Role::Assistant, "six"));
+    history.summarize().await;
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+    assert_eq!(
+        contents(&history.context()),
+        vec![
+            "Be brief.",
+            "Summary of the earlier conversation:\nSummary of the earlier conversation:\none, two, three, four",
+            "five",
+            "six"
+        ]
+    );
+
+    history.clear();
+    assert!(history.summary().is_none());
+
+    // Not enough entries, the summarizer is not called.
+    let mut history = ConversationHistory::new();
+    history.push(HistoryEntry::new(Role::User, "one"));
+    let mut history = history.with_summarize(summarize);
+    history.summarize().await;
+    assert_eq!(history.context().len(), 1);
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn custom_strategies_are_closures() {
+    let history = history().with_truncation(|entries: &[HistoryEntry]| {
+        entries
+            .iter()
+            .filter(|entry| entry.role == Role::User)
+            .cloned()
+            .collect()
+    });
+
+    assert_eq!(contents(&history.context()), vec!["one", "three"]);
+}
+
+#[test]
+fn entries_keep_code_and_execution_results() {
+    let code_block = CodeBlock {
+        language: "python".to_string(),
+        code: "print(1)".to_string(),
+    };
+
+    let reply: CollaborativeAgentResponse =
+        "Here:\n```python execute\nprint(1)\n```".to_string().into();
+    let entry = HistoryEntry::from_reply("Here:\n```python execute\nprint(1)\n```", &reply);
+    assert_eq!(entry.role, Role::Assistant);
+    assert_eq!(entry.code_blocks.len(), 1);
+    assert_eq!(entry.code_blocks[0].code, "print(1)");
+
+    let result = CodeBlockExecutionResult::Success(CodeBlockExecutionOutput {
+        exit_code: Some(0),
+        stdout: "1\n".to_string(),
+        ..Default::default()
+    });
+
+    let entry = HistoryEntry::from_message(Message::CodeExecutionResults(vec![
+        CodeBlockReport {
+            code_block: code_block.clone(),
+            outcome: CodeBlockOutcome::Executed(result.clone()),
+        },
+        CodeBlockReport {
+            code_block: code_block.clone(),
+            outcome: CodeBlockOutcome::Skipped,
+        },
+    ]));
+    assert_eq!(entry.role, Role::User);
+    assert_eq!(entry.execution_results, vec![result]);
+    assert!(entry
+        .content
+        .contains("Code block 2 (python):\nNot executed"));
+
+    let entry = HistoryEntry::from_message(Message::CodeExecutionDenied {
+        comment: "No.".to_string(),
+        code_block,
+    });
+    assert_eq!(entry.code_blocks[0].code, "print(1)");
+    assert!(entry.content.ends_with("Reason: No."));
+
+    let entry = HistoryEntry::from_message(Message::Text {
+        sender: "user".to_string(),
+        message: "hi".to_string(),
+    });
+    assert_eq!(entry.sender.as_deref(), Some("user"));
+    assert!(entry.timestamp <= std::time::SystemTime::now());
+}
+
+#[test]
+fn tokenizer_counts_the_entries() {
+    let mut history = history().with_tokenizer(EstimatingTokenizer);
+
+    history.push(HistoryEntry::new(Role::User, "twelve chars"));
+    history.push(HistoryEntry::new(Role::Assistant, "known").with_tokens(100));
+
+    let tokens: Vec<Option<usize>> = history.entries().iter().map(|entry| entry.tokens).collect();
+    assert_eq!(
+        tokens,
+        vec![
+            Some(3),
+            Some(1),
+            Some(1),
+            Some(2),
+            Some(1),
+            Some(3),
+            Some(100)
+        ]
+    );
+    assert_eq!(history.total_tokens(), 111);
+
+    // Known tokens take precedence over the counter.
+    let history =
+        history.with_truncation(TokenBudget::new(106).with_tokenizer(EstimatingTokenizer));
+    assert_eq!(
+        contents(&history.context()),
+        vec!["Be brief.", "twelve chars", "known"]
+    );
+}
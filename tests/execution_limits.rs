@@ -0,0 +1,222 @@
+use autogen::text_chat::code::execution_limits::{ExecutionLimit, ExecutionLimits};
+use autogen::text_chat::code::local_code_executor::LocalCodeExecutor;
+use autogen::text_chat::code::{CodeBlock, CodeBlockExecutionResult, CodeExecutor};
+
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+fn work_dir(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "autogen_execution_limits_{}_{}",
+        name,
+        std::process::id()
+    ))
+}
+
+/// Runs the shell code in a fresh working directory.
+async fn execute(name: &str, limits: ExecutionLimits, code: &str) -> CodeBlockExecutionResult {
+    let _ = std::fs::remove_dir_all(work_dir(name));
+
+    LocalCodeExecutor::new(work_dir(name))
+        .with_limits(limits)
+        .execute_code_block(&CodeBlock {
+            language: "sh".to_string(),
+            code: code.to_string(),
+        })
+        .await
+        .expect("execution failed")
+}
+
+/// Zombies are gone as far as the test is concerned.
+fn is_alive(pid: &str) -> bool {
+    match std::fs::read_to_string(format!("/proc/{}/stat", pid)) {
+        Ok(stat) => !stat.contains(") Z "),
+        Err(_) => false,
+    }
+}
+
+#[tokio::test]
+async fn timeout_kills_the_process() {
+    let limits = ExecutionLimits {
+        timeout: Some(Duration::from_millis(200)),
+        ..Default::default()
+    };
+
+    let start = Instant::now();
+    let result = execute("timeout", limits, "echo started\nsleep 5\necho finished").await;
+
+    assert!(start.elapsed() < Duration::from_secs(4));
+    assert!(matches!(
+        result,
+        CodeBlockExecutionResult::LimitExceeded {
+            limit: ExecutionLimit::Timeout(_),
+            ..
+        }
+    ));
+    assert_eq!(result.output().stdout, "started\n");
+    assert!(!result.output().truncated);
+}
+
+#[tokio::test]
+async fn output_size_truncates_the_output() {
+    let limits = ExecutionLimits {
+        max_output_bytes: Some(1000),
+        ..Default::default()
+    };
+
+    let result = execute("output_size", limits, "yes").await;
+
+    assert!(matches!(
+        result,
+        CodeBlockExecutionResult::LimitExceeded {
+            limit: ExecutionLimit::OutputSize(1000),
+            ..
+        }
+    ));
+    assert_eq!(result.output().stdout.len(), 1000);
+    assert!(result.output().stderr.is_empty());
+    assert!(result.output().stdout.starts_with("y\ny\n"));
+    assert!(result.output().truncated);
+}
+
+#[tokio::test]
+async fn output_size_is_shared_by_stdout_and_stderr() {
+    let limits = ExecutionLimits {
+        max_output_bytes: Some(1000),
+        ..Default::default()
+    };
+
+    let result = execute("output_size_shared", limits, "yes &\nyes >&2").await;
+
+    let output = result.output();
+    assert!(output.truncated);
+    assert_eq!(output.stdout.len() + output.stderr.len(), 1000);
+}
+
+#[tokio::test]
+async fn output_within_the_limit_is_not_truncated() {
+    let limits = ExecutionLimits {
+        max_output_bytes: Some(1000),
+        ..Default::default()
+    };
+
+    let result = execute("output_within", limits, "echo hello").await;
+
+    assert!(result.is_success());
+    assert_eq!(result.output().stdout, "hello\n");
+    assert!(!result.output().truncated);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn cpu_time_stops_busy_loops() {
+    let limits = ExecutionLimits {
+        max_cpu_time: Some(Duration::from_secs(1)),
+        timeout: Some(Duration::from_secs(30)),
+        ..Default::default()
+    };
+
+    let result = execute("cpu_time", limits, "while :; do :; done").await;
+
+    assert!(matches!(
+        result,
+        CodeBlockExecutionResult::LimitExceeded {
+            limit: ExecutionLimit::CpuTime(_),
+            ..
+        }
+    ));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn memory_limit_fails_large_allocations() {
+    let limits = ExecutionLimits {
+        max_memory_bytes: Some(512 * 1024 * 1024),
+        ..Default::default()
+    };
+
+    let result = execute(
+        "memory",
+        limits,
+        "python3 -c 'bytearray(1 << 20); print(\"small\")'\npython3 -c 'bytearray(1 << 30)'",
+    )
+    .await;
+
+    assert!(matches!(result, CodeBlockExecutionResult::Failure(_)));
+    assert_eq!(result.output().stdout, "small\n");
+    assert!(result.output().stderr.contains("MemoryError"));
+}
+
+/// Not used by anything, so the code running as this user is the only process of it.
+#[cfg(target_os = "linux")]
+const UNUSED_UID: u32 = 54321;
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn process_limit_fails_forks() {
+    let limits = ExecutionLimits {
+        max_processes: Some(1),
+        ..Default::default()
+    };
+
+    let code = "echo before\n/bin/true\necho after";
+    // The limit counts all the processes of the user and does not apply to root at all. Root thus
+    // runs the code as a user without any other process.
+    let code = match unsafe { libc::geteuid() } == 0 {
+        true => format!(
+            "exec setpriv --reuid={uid} --regid={uid} --clear-groups sh -c '{code}'",
+            uid = UNUSED_UID,
+        ),
+        false => code.to_string(),
+    };
+
+    let result = execute("processes", limits, &code).await;
+
+    assert!(matches!(result, CodeBlockExecutionResult::Failure(_)));
+    assert_eq!(result.output().stdout, "before\n");
+    assert!(result.output().stderr.contains("fork"));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn background_processes_do_not_block_the_execution() {
+    let start = Instant::now();
+    let result = tokio::time::timeout(
+        Duration::from_secs(10),
+        execute(
+            "background_pipe",
+            ExecutionLimits::default(),
+            "sleep 1000 &\necho done",
+        ),
+    )
+    .await
+    .expect("execution blocked on the background process");
+
+    assert!(start.elapsed() < Duration::from_secs(10));
+    assert!(result.is_success());
+    assert_eq!(result.output().stdout, "done\n");
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn background_processes_are_killed() {
+    let name = "background_killed";
+    let result = execute(
+        name,
+        ExecutionLimits::default(),
+        "sleep 1000 > /dev/null 2>&1 &\necho $! > sleep.pid",
+    )
+    .await;
+    assert!(result.is_success());
+
+    let pid = std::fs::read_to_string(work_dir(name).join("sleep.pid")).unwrap();
+    let pid = pid.trim();
+
+    // The orphan has to be reaped by whoever adopted it.
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while is_alive(pid) && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    assert!(!is_alive(pid));
+}
@@ -0,0 +1,502 @@
+//! Group chat of scripted agents - who speaks when and who hears what.
+
+use autogen::agent_traits::{NamedAgent, TokenUsage};
+use autogen::text_chat::chat_user_agent::{ChatUserAgent, CodeBlockFeedback};
+use autogen::text_chat::code::{CodeBlock, CodeBlockExecutionResult};
+use autogen::text_chat::collaborative_agent::{
+    CodeBlockReport, CollaborativeAgent, CollaborativeAgentResponse, Message,
+};
+use autogen::text_chat::group_chat::speaker_selection::{
+    Manager, Manual, Random, RoundRobin, SpeakerSelection,
+};
+use autogen::text_chat::group_chat::speaker_transitions::SpeakerTransitions;
+use autogen::text_chat::group_chat::speaker_transitions_error::SpeakerTransitionsError;
+use autogen::text_chat::group_chat::{GroupChat, SpeakerContext};
+use autogen::text_chat::group_chat_error::GroupChatError;
+use autogen::text_chat::tool::{ToolCall, ToolCallOutcome, ToolCallReport};
+
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+
+use serde_json::json;
+
+type Log = Arc<Mutex<Vec<String>>>;
+
+fn record(log: &Log, entry: String) {
+    log.lock().unwrap().push(entry);
+}
+
+fn describe(message: &Message) -> String {
+    match message {
+        Message::Text { sender, message } => format!("{}: {}", sender, message),
+        Message::ToolCallResults(reports) => format!("{} tool results", reports.len()),
+        _ => "other".to_string(),
+    }
+}
+
+/// Replies with `<name> #<number of the reply>`, or the scripted replies while there are some.
+struct Member {
+    name: String,
+    log: Log,
+    replies: usize,
+    script: VecDeque<CollaborativeAgentResponse>,
+}
+
+impl Member {
+    fn new(name: &str, log: &Log) -> Self {
+        Self {
+            name: name.to_string(),
+            log: log.clone(),
+            replies: 0,
+            script: VecDeque::new(),
+        }
+    }
+
+    fn script(mut self, script: Vec<CollaborativeAgentResponse>) -> Self {
+        self.script = script.into();
+        self
+    }
+
+    fn reply(&mut self, message: Message) -> CollaborativeAgentResponse {
+        record(
+            &self.log,
+            format!("{} replies to {}", self.name, describe(&message)),
+        );
+
+        self.replies += 1;
+        self.script.pop_front().unwrap_or_else(|| {
+            CollaborativeAgentResponse::Text(format!("{} #{}", self.name, self.replies))
+        })
+    }
+}
+
+impl NamedAgent for Member {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl CollaborativeAgent for Member {
+    type Error = Infallible;
+
+    async fn receive_and_reply(
+        &mut self,
+        sender: String,
+        message: String,
+    ) -> Result<CollaborativeAgentResponse, Self::Error> {
+        Ok(self.reply(Message::Text { sender, message }))
+    }
+
+    async fn deny_code_block_execution(
+        &mut self,
+        code_block: CodeBlock,
+        feedback: String,
+    ) -> Result<CollaborativeAgentResponse, Self::Error> {
+        Ok(self.reply(Message::CodeExecutionDenied {
+            comment: feedback,
+            code_block,
+        }))
+    }
+
+    async fn receive_code_and_reply_to_execution_result(
+        &mut self,
+        code_execution_result: CodeBlockExecutionResult,
+    ) -> Result<CollaborativeAgentResponse, Self::Error> {
+        Ok(self.reply(Message::CodeExecutionResult(code_execution_result)))
+    }
+
+    async fn receive_code_and_reply_to_execution_results(
+        &mut self,
+        reports: Vec<CodeBlockReport>,
+    ) -> Result<CollaborativeAgentResponse, Self::Error> {
+        Ok(self.reply(Message::CodeExecutionResults(reports)))
+    }
+
+    async fn receive_and_reply_to_tool_call_results(
+        &mut self,
+        reports: Vec<ToolCallReport>,
+    ) -> Result<CollaborativeAgentResponse, Self::Error> {
+        Ok(self.reply(Message::ToolCallResults(reports)))
+    }
+
+    async fn receive(&mut self, message: Message) -> Result<(), Self::Error> {
+        record(
+            &self.log,
+            format!("{} hears {}", self.name, describe(&message)),
+        );
+        Ok(())
+    }
+
+    fn token_usage(&self) -> Option<TokenUsage> {
+        Some(TokenUsage {
+            prompt_tokens: self.replies as u64,
+            completion_tokens: 1,
+        })
+    }
+}
+
+fn members(log: &Log, names: &[&str]) -> Vec<Member> {
+    names.iter().map(|name| Member::new(name, log)).collect()
+}
+
+async fn say<S: SpeakerSelection>(group_chat: &mut GroupChat<Member, S>, message: &str) -> String
+where
+    S::Error: std::fmt::Debug,
+{
+    group_chat
+        .receive_and_reply("user".to_string(), message.to_string())
+        .await
+        .unwrap()
+        .to_markdown()
+}
+
+#[tokio::test]
+async fn round_robin_broadcasts_to_everyone() {
+    let log = Log::default();
+    let mut group_chat = GroupChat::new(members(&log, &["coder", "reviewer"]), RoundRobin).unwrap();
+
+    assert_eq!(group_chat.name(), "group_chat");
+
+    assert_eq!(say(&mut group_chat, "hi").await, "coder #1");
+    assert_eq!(group_chat.name(), "coder");
+
+    assert_eq!(say(&mut group_chat, "review it").await, "reviewer #1");
+    assert_eq!(say(&mut group_chat, "again").await, "coder #2");
+
+    assert_eq!(
+        log.lock().unwrap()[..6],
+        [
+            "reviewer hears user: hi",
+            "coder replies to user: hi",
+            "reviewer hears coder: coder #1",
+            "coder hears user: review it",
+            "reviewer replies to user: review it",
+            "coder hears reviewer: reviewer #1",
+        ]
+    );
+
+    // Usage of every member, each reply counted as a prompt token.
+    assert_eq!(
+        group_chat.token_usage(),
+        Some(TokenUsage {
+            prompt_tokens: 3,
+            completion_tokens: 2,
+        })
+    );
+}
+
+#[tokio::test]
+async fn tool_call_results_go_back_to_the_caller() {
+    let log = Log::default();
+
+    let tool_call = ToolCall {
+        id: "call_1".to_string(),
+        name: "add".to_string(),
+        arguments: json!({"a": 1, "b": 2}),
+    };
+
+    let agents = vec![
+        Member::new("coder", &log),
+        Member::new("caller", &log).script(vec![CollaborativeAgentResponse::ToolCalls {
+            comment: String::new(),
+            tool_calls: vec![tool_call.clone()],
+        }]),
+    ];
+    let mut group_chat = GroupChat::new(agents, RoundRobin).unwrap();
+
+    say(&mut group_chat, "first").await;
+    say(&mut group_chat, "call the tool").await;
+    log.lock().unwrap().clear();
+
+    let reply = group_chat
+        .receive_and_reply_to_tool_call_results(vec![ToolCallReport {
+            tool_call,
+            outcome: ToolCallOutcome::Returned(json!(3)),
+        }])
+        .await
+        .unwrap();
+
+    assert_eq!(reply.to_markdown(), "caller #2");
+    assert_eq!(
+        *log.lock().unwrap(),
+        vec![
+            "caller replies to 1 tool results",
+            "coder hears caller: caller #2",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn random_selection_depends_on_the_seed_only() {
+    let speakers = |seed| async move {
+        let log = Log::default();
+        let mut group_chat =
+            GroupChat::new(members(&log, &["a", "b", "c"]), Random::with_seed(seed)).unwrap();
+
+        let mut speakers = Vec::new();
+        for _ in 0..20 {
+            say(&mut group_chat, "next").await;
+            speakers.push(group_chat.name().to_string());
+        }
+
+        speakers
+    };
+
+    let first = speakers(7).await;
+    assert_eq!(first, speakers(7).await);
+
+    for name in ["a", "b", "c"] {
+        assert!(first.iter().any(|speaker| speaker == name));
+    }
+}
+
+/// Answers the questions of the [Manual] selection.
+struct Chooser {
+    answers: VecDeque<String>,
+    log: Log,
+}
+
+impl ChatUserAgent for Chooser {
+    type Error = Infallible;
+
+    async fn receive_and_reply(
+        &mut self,
+        _sender: String,
+        message: String,
+    ) -> Result<String, Self::Error> {
+        record(&self.log, message);
+        Ok(self.answers.pop_front().unwrap())
+    }
+
+    async fn silent_receive_collaborative_agent_response(
+        &mut self,
+        _sender: String,
+        _response: CollaborativeAgentResponse,
+    ) -> Result<(), Self::Error> {
+        unimplemented!()
+    }
+
+    async fn request_code_block_feedback(
+        &mut self,
+        _sender: String,
+        _comment: String,
+        _code_block: CodeBlock,
+    ) -> Result<CodeBlockFeedback, Self::Error> {
+        unimplemented!()
+    }
+
+    async fn receive_code_execution_result(
+        &mut self,
+        _result: CodeBlockExecutionResult,
+    ) -> Result<(), Self::Error> {
+        unimplemented!()
+    }
+}
+
+#[tokio::test]
+async fn user_chooses_the_speaker_manually() {
+    let log = Log::default();
+    let questions = Log::default();
+
+    let chooser = Chooser {
+        answers: ["nobody", "2", " Coder "]
+            .into_iter()
+            .map(String::from)
+            .collect(),
+        log: questions.clone(),
+    };
+
+    let mut group_chat =
+        GroupChat::new(members(&log, &["coder", "reviewer"]), Manual::new(chooser)).unwrap();
+
+    // The unknown speaker is asked about again.
+    assert_eq!(say(&mut group_chat, "hi").await, "reviewer #1");
+    assert_eq!(say(&mut group_chat, "hi").await, "coder #1");
+
+    assert_eq!(questions.lock().unwrap().len(), 3);
+    assert_eq!(
+        questions.lock().unwrap()[0],
+        "Who speaks next?\n1. coder\n2. reviewer"
+    );
+}
+
+#[tokio::test]
+async fn manager_picks_the_named_speaker() {
+    let log = Log::default();
+    let manager_log = Log::default();
+
+    let manager = Member::new("manager", &manager_log).script(vec![
+        CollaborativeAgentResponse::Text("The Reviewer should go.".to_string()),
+        CollaborativeAgentResponse::Text("no idea".to_string()),
+    ]);
+
+    let mut group_chat =
+        GroupChat::new(members(&log, &["coder", "reviewer"]), Manager::new(manager)).unwrap();
+
+    assert_eq!(say(&mut group_chat, "check this").await, "reviewer #1");
+
+    let prompt = manager_log.lock().unwrap()[0].clone();
+    assert!(prompt.contains("group chat of: coder, reviewer"));
+    assert!(prompt.contains("from user, was:\n\ncheck this"));
+
+    // Without a name the next one takes the turn.
+    assert_eq!(say(&mut group_chat, "and now?").await, "coder #1");
+
+    // The manager's replies are counted too.
+    assert_eq!(
+        group_chat.token_usage(),
+        Some(TokenUsage {
+            prompt_tokens: 4,
+            completion_tokens: 3,
+        })
+    );
+}
+
+#[test]
+fn agents_need_distinct_names() {
+    let log = Log::default();
+
+    assert!(matches!(
+        GroupChat::<Member, _>::new(Vec::new(), RoundRobin),
+        Err(GroupChatError::NoAgents)
+    ));
+
+    assert!(matches!(
+        GroupChat::new(members(&log, &["coder", "coder"]), RoundRobin),
+        Err(GroupChatError::DuplicateName(name)) if name == "coder"
+    ));
+}
+
+fn pipeline() -> SpeakerTransitions {
+    SpeakerTransitions::new(["planner"])
+        .allow("planner", ["coder"])
+        .allow("coder", ["reviewer"])
+        .allow("reviewer", ["planner", "coder"])
+}
+
+#[tokio::test]
+async fn only_allowed_transitions_are_taken() {
+    let log = Log::default();
+    let names = ["reviewer", "coder", "planner"];
+
+    let mut group_chat = GroupChat::new(members(&log, &names), RoundRobin)
+        .unwrap()
+        .with_transitions(pipeline())
+        .unwrap();
+
+    let mut speakers = Vec::new();
+    for _ in 0..5 {
+        say(&mut group_chat, "next").await;
+        speakers.push(group_chat.name().to_string());
+    }
+
+    // The reviewer passes the turn to the coder, being the next one after it.
+    assert_eq!(
+        speakers,
+        ["planner", "coder", "reviewer", "coder", "reviewer"]
+    );
+
+    let mut group_chat = GroupChat::new(members(&log, &names), Random::with_seed(3))
+        .unwrap()
+        .with_transitions(pipeline())
+        .unwrap();
+
+    let mut last_speaker: Option<String> = None;
+    for _ in 0..20 {
+        say(&mut group_chat, "next").await;
+        let speaker = group_chat.name().to_string();
+
+        let allowed = pipeline()
+            .next_speakers(last_speaker.as_deref())
+            .contains(&speaker);
+        assert!(allowed, "{:?} -> {}", last_speaker, speaker);
+
+        last_speaker = Some(speaker);
+    }
+}
+
+/// Always picks the same agent, whether allowed or not.
+struct Fixed(usize);
+
+impl SpeakerSelection for Fixed {
+    type Error = Infallible;
+
+    async fn select(&mut self, _context: SpeakerContext<'_>) -> Result<usize, Self::Error> {
+        Ok(self.0)
+    }
+}
+
+#[tokio::test]
+async fn selection_breaking_the_transitions_is_an_error() {
+    let log = Log::default();
+    let names = ["planner", "coder", "reviewer"];
+
+    let mut group_chat = GroupChat::new(members(&log, &names), Fixed(0))
+        .unwrap()
+        .with_transitions(pipeline())
+        .unwrap();
+
+    say(&mut group_chat, "plan").await;
+
+    let error = group_chat
+        .receive_and_reply("user".to_string(), "again".to_string())
+        .await;
+    assert!(matches!(
+        error,
+        Err(GroupChatError::TransitionNotAllowed { from: Some(from), to })
+            if from == "planner" && to == "planner"
+    ));
+
+    let mut group_chat = GroupChat::new(members(&log, &names), Fixed(3)).unwrap();
+
+    let error = group_chat
+        .receive_and_reply("user".to_string(), "hi".to_string())
+        .await;
+    assert!(matches!(error, Err(GroupChatError::InvalidSpeaker(3))));
+}
+
+#[test]
+fn transitions_are_validated() {
+    let names: Vec<String> = ["planner", "coder", "reviewer"]
+        .into_iter()
+        .map(String::from)
+        .collect();
+
+    assert_eq!(pipeline().validate(&names), Ok(()));
+
+    assert_eq!(
+        pipeline().allow("coder", ["tester"]).validate(&names),
+        Err(SpeakerTransitionsError::UnknownSpeaker(
+            "tester".to_string()
+        ))
+    );
+
+    let unreachable = SpeakerTransitions::new(["planner"])
+        .allow("planner", ["coder"])
+        .allow("coder", ["planner"])
+        .allow("reviewer", ["planner"]);
+    assert_eq!(
+        unreachable.validate(&names),
+        Err(SpeakerTransitionsError::Unreachable("reviewer".to_string()))
+    );
+
+    let dead_end = SpeakerTransitions::new(["planner"])
+        .allow("planner", ["coder"])
+        .allow("coder", ["reviewer"]);
+    assert_eq!(
+        dead_end.validate(&names),
+        Err(SpeakerTransitionsError::DeadEnd("reviewer".to_string()))
+    );
+
+    let log = Log::default();
+    let group_chat = GroupChat::new(members(&log, &["planner", "coder"]), RoundRobin)
+        .unwrap()
+        .with_transitions(pipeline());
+    assert!(matches!(
+        group_chat,
+        Err(GroupChatError::Transitions(
+            SpeakerTransitionsError::UnknownSpeaker(name)
+        )) if name == "reviewer"
+    ));
+}
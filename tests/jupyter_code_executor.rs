@@ -0,0 +1,414 @@
+//! Runs the executor against a stub kernel speaking the messaging protocol, so the mapping of the
+//! messages does not depend on ipykernel being installed.
+//!
+//! The process launched by the executor only stores its connection file and pid, then sleeps. The
+//! test binds the channels and answers the requests in its place.
+
+use autogen::text_chat::code::execution_limits::{ExecutionLimit, ExecutionLimits};
+use autogen::text_chat::code::jupyter_code_executor::{
+    JupyterCodeExecutor, JupyterKernel, CONNECTION_FILE_PLACEHOLDER,
+};
+use autogen::text_chat::code::{CodeBlock, CodeBlockExecutionResult, CodeExecutor};
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use base64::Engine;
+use bytes::Bytes;
+use hmac::{Hmac, Mac};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use zeromq::{PubSocket, RouterSocket, Socket, SocketRecv, SocketSend, ZmqMessage};
+
+const DELIMITER: &[u8] = b"<IDS|MSG>";
+
+/// Fresh working directory for every test.
+fn work_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "autogen_jupyter_code_executor_{}_{}",
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    dir
+}
+
+/// Executor whose kernel is served by [serve] and the pid of the kernel process.
+async fn start(name: &str, limits: ExecutionLimits) -> (JupyterCodeExecutor, String) {
+    let work_dir = work_dir(name);
+
+    let kernel = JupyterKernel {
+        argv: vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            format!(
+                "echo $$ > kernel.pid\ncp {} connection.tmp\nmv connection.tmp connection.json\nexec sleep 1000",
+                CONNECTION_FILE_PLACEHOLDER
+            ),
+        ],
+        languages: vec!["python".to_string()],
+    };
+
+    let executor = JupyterCodeExecutor::new(&work_dir)
+        .with_kernel(kernel)
+        .with_limits(limits)
+        .with_startup_timeout(Duration::from_secs(10));
+
+    // The work dir is created by the executor once the first code block arrives.
+    let connection_file = work_dir.join("connection.json");
+    tokio::spawn(async move { serve(&connection_file).await });
+
+    let result = execute(&executor, "1").await;
+    assert!(result.is_success());
+
+    let pid = std::fs::read_to_string(work_dir.join("kernel.pid")).unwrap();
+
+    (executor, pid.trim().to_string())
+}
+
+async fn execute(executor: &JupyterCodeExecutor, code: &str) -> CodeBlockExecutionResult {
+    executor
+        .execute_code_block(&CodeBlock {
+            language: "python".to_string(),
+            code: code.to_string(),
+        })
+        .await
+        .expect("execution failed")
+}
+
+/// Zombies are gone as far as the test is concerned.
+fn is_alive(pid: &str) -> bool {
+    match std::fs::read_to_string(format!("/proc/{}/stat", pid)) {
+        Ok(stat) => !stat.contains(") Z "),
+        Err(_) => false,
+    }
+}
+
+struct StubKernel {
+    key: Vec<u8>,
+    iopub: PubSocket,
+    pid: i32,
+    sent: usize,
+}
+
+impl StubKernel {
+    /// Frames of a message, signed with `key`.
+    fn message(
+        &mut self,
+        key: &[u8],
+        msg_type: &str,
+        parent: &Value,
+        content: Value,
+    ) -> ZmqMessage {
+        self.sent += 1;
+
+        let parts = [
+            json!({
+                "msg_id": format!("stub_{}", self.sent),
+                "session": "stub",
+                "username": "stub",
+                "date": "2024-01-01T12:00:00.000000Z",
+                "msg_type": msg_type,
+                "version": "5.3",
+            }),
+            parent.clone(),
+            json!({}),
+            content,
+        ]
+        .map(|part| part.to_string().into_bytes());
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+        for part in &parts {
+            mac.update(part);
+        }
+        let signature: String = mac
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+
+        let frames: Vec<Bytes> = [DELIMITER.to_vec(), signature.into_bytes()]
+            .into_iter()
+            .chain(parts)
+            .map(Bytes::from)
+            .collect();
+
+        ZmqMessage::try_from(frames).unwrap()
+    }
+
+    async fn publish(&mut self, msg_type: &str, parent: &Value, content: Value) {
+        let key = self.key.clone();
+        let message = self.message(&key, msg_type, parent, content);
+        self.iopub.send(message).await.unwrap();
+    }
+
+    async fn status(&mut self, parent: &Value, execution_state: &str) {
+        self.publish(
+            "status",
+            parent,
+            json!({ "execution_state": execution_state }),
+        )
+        .await;
+    }
+
+    async fn stream(&mut self, parent: &Value, name: &str, text: &str) {
+        self.publish("stream", parent, json!({ "name": name, "text": text }))
+            .await;
+    }
+
+    /// Publishes the outputs of the code and returns the status of the reply, None if the kernel
+    /// never becomes idle.
+    async fn execute(&mut self, parent: &Value, code: &str) -> Option<&'static str> {
+        self.status(parent, "busy").await;
+
+        match code {
+            "outputs" => {
+                // Signed with another key, so it has to be ignored.
+                let forged = self.message(
+                    b"forged",
+                    "stream",
+                    parent,
+                    json!({ "name": "stdout", "text": "forged\n" }),
+                );
+                self.iopub.send(forged).await.unwrap();
+
+                self.stream(parent, "stdout", "out\n").await;
+                self.stream(parent, "stderr", "err\n").await;
+                self.publish(
+                    "execute_result",
+                    parent,
+                    json!({ "execution_count": 1, "data": { "text/plain": "42" }, "metadata": {} }),
+                )
+                .await;
+                self.publish(
+                    "display_data",
+                    parent,
+                    json!({
+                        "data": {
+                            "text/plain": "<Figure>",
+                            "image/png": base64::engine::general_purpose::STANDARD.encode("png"),
+                        },
+                        "metadata": {},
+                    }),
+                )
+                .await;
+            }
+            "error" => {
+                self.stream(parent, "stdout", "before\n").await;
+                self.publish(
+                    "error",
+                    parent,
+                    json!({
+                        "ename": "ValueError",
+                        "evalue": "boom",
+                        "traceback": [
+                            "\u{1b}[0;31m----> 1\u{1b}[0m raise ValueError('boom')",
+                            "\u{1b}[0;31mValueError\u{1b}[0m: boom",
+                        ],
+                    }),
+                )
+                .await;
+                self.status(parent, "idle").await;
+
+                return Some("error");
+            }
+            "flood" => {
+                for _ in 0..100 {
+                    self.stream(parent, "stdout", &"x".repeat(99)).await;
+                }
+            }
+            "hang" => return None,
+            "die" => {
+                // SAFETY: kill is a raw syscall.
+                unsafe { libc::kill(self.pid, libc::SIGKILL) };
+                return None;
+            }
+            _ => {}
+        }
+
+        self.status(parent, "idle").await;
+
+        Some("ok")
+    }
+}
+
+/// Serves the kernel once the connection file appears, until the test ends.
+async fn serve(connection_file: &Path) {
+    let connection = loop {
+        match std::fs::read(connection_file) {
+            Ok(connection) => break serde_json::from_slice::<Value>(&connection).unwrap(),
+            Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+        }
+    };
+
+    let pid = std::fs::read_to_string(connection_file.with_file_name("kernel.pid")).unwrap();
+
+    let endpoint = |port: &str| format!("tcp://127.0.0.1:{}", connection[port]);
+
+    let mut shell = RouterSocket::new();
+    shell.bind(&endpoint("shell_port")).await.unwrap();
+    // Shutdown requests are not answered, the kernel is killed after the grace period.
+    let mut control = RouterSocket::new();
+    control.bind(&endpoint("control_port")).await.unwrap();
+    let mut iopub = PubSocket::new();
+    iopub.bind(&endpoint("iopub_port")).await.unwrap();
+
+    let mut kernel = StubKernel {
+        key: connection["key"].as_str().unwrap().as_bytes().to_vec(),
+        iopub,
+        pid: pid.trim().parse().unwrap(),
+        sent: 0,
+    };
+
+    loop {
+        let request = shell.recv().await.unwrap().into_vec();
+
+        let delimiter = request
+            .iter()
+            .position(|frame| frame.as_ref() == DELIMITER)
+            .unwrap();
+        let identities = request[..delimiter].to_vec();
+        let header: Value = serde_json::from_slice(&request[delimiter + 2]).unwrap();
+        let content: Value = serde_json::from_slice(&request[delimiter + 5]).unwrap();
+
+        let (reply_type, status) = match header["msg_type"].as_str().unwrap() {
+            "kernel_info_request" => {
+                kernel.status(&header, "busy").await;
+                kernel.status(&header, "idle").await;
+
+                ("kernel_info_reply", Some("ok"))
+            }
+            "execute_request" => (
+                "execute_reply",
+                kernel
+                    .execute(&header, content["code"].as_str().unwrap())
+                    .await,
+            ),
+            _ => continue,
+        };
+
+        let Some(status) = status else {
+            continue;
+        };
+
+        let key = kernel.key.clone();
+        let reply = kernel.message(&key, reply_type, &header, json!({ "status": status }));
+
+        let mut frames = identities;
+        frames.extend(reply.into_vec());
+        shell
+            .send(ZmqMessage::try_from(frames).unwrap())
+            .await
+            .unwrap();
+    }
+}
+
+#[tokio::test]
+async fn outputs_are_mapped() {
+    let (executor, _) = start("outputs", ExecutionLimits::default()).await;
+
+    let result = execute(&executor, "outputs").await;
+
+    assert!(result.is_success());
+    let output = result.output();
+    assert_eq!(output.stdout, "out\n42\n<Figure>\n");
+    assert_eq!(output.stderr, "err\n");
+    assert_eq!(output.exit_code, None);
+
+    assert_eq!(output.produced_files.len(), 1);
+    let image = &output.produced_files[0];
+    assert_eq!(image.extension().unwrap(), "png");
+    assert_eq!(
+        std::fs::read(executor.work_dir().join(image)).unwrap(),
+        b"png"
+    );
+}
+
+#[tokio::test]
+async fn errors_fail_the_execution() {
+    let (executor, _) = start("errors", ExecutionLimits::default()).await;
+
+    let result = execute(&executor, "error").await;
+
+    assert!(matches!(result, CodeBlockExecutionResult::Failure(_)));
+    assert_eq!(result.output().stdout, "before\n");
+    assert_eq!(
+        result.output().stderr,
+        "----> 1 raise ValueError('boom')\nValueError: boom\n"
+    );
+
+    // The kernel keeps running.
+    assert!(execute(&executor, "1").await.is_success());
+}
+
+#[tokio::test]
+async fn timeout_kills_the_kernel() {
+    let limits = ExecutionLimits {
+        timeout: Some(Duration::from_millis(500)),
+        ..Default::default()
+    };
+    let (executor, pid) = start("timeout", limits).await;
+
+    let start = Instant::now();
+    let result = execute(&executor, "hang").await;
+
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert!(matches!(
+        result,
+        CodeBlockExecutionResult::LimitExceeded {
+            limit: ExecutionLimit::Timeout(_),
+            ..
+        }
+    ));
+    assert!(!is_alive(&pid));
+}
+
+#[tokio::test]
+async fn output_limit_kills_the_kernel() {
+    let limits = ExecutionLimits {
+        max_output_bytes: Some(1000),
+        ..Default::default()
+    };
+    let (executor, pid) = start("output_limit", limits).await;
+
+    let result = execute(&executor, "flood").await;
+
+    assert!(matches!(
+        result,
+        CodeBlockExecutionResult::LimitExceeded {
+            limit: ExecutionLimit::OutputSize(1000),
+            ..
+        }
+    ));
+    assert_eq!(result.output().stdout.len(), 1000);
+    assert!(result.output().truncated);
+    assert!(!is_alive(&pid));
+}
+
+#[tokio::test]
+async fn dead_kernel_fails_the_execution() {
+    let (executor, _) = start("dead", ExecutionLimits::default()).await;
+
+    let result = execute(&executor, "die").await;
+
+    assert!(matches!(result, CodeBlockExecutionResult::Failure(_)));
+    assert_eq!(result.output().stderr, "kernel died\n");
+}
+
+#[tokio::test]
+async fn unsupported_languages_are_not_sent_to_the_kernel() {
+    let executor = JupyterCodeExecutor::new(work_dir("unsupported"));
+
+    let result = executor
+        .execute_code_block(&CodeBlock {
+            language: "rust".to_string(),
+            code: "fn main() {}".to_string(),
+        })
+        .await
+        .unwrap();
+
+    assert!(matches!(result, CodeBlockExecutionResult::Failure(_)));
+    assert_eq!(result.output().stderr, "unsupported language: rust");
+}
@@ -0,0 +1,82 @@
+use autogen::text_chat::code::local_code_executor::LocalCodeExecutor;
+use autogen::text_chat::code::{CodeBlock, CodeBlockExecutionResult, CodeExecutor};
+
+use std::path::PathBuf;
+
+/// Fresh working directory for every test.
+fn work_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "autogen_local_code_executor_{}_{}",
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    dir
+}
+
+async fn execute(name: &str, language: &str, code: &str) -> CodeBlockExecutionResult {
+    LocalCodeExecutor::new(work_dir(name))
+        .execute_code_block(&CodeBlock {
+            language: language.to_string(),
+            code: code.to_string(),
+        })
+        .await
+        .expect("execution failed")
+}
+
+#[tokio::test]
+async fn python_is_executed() {
+    let result = execute("python", "python", "import sys\nprint(sys.version_info[0])").await;
+
+    assert!(result.is_success());
+    assert_eq!(result.output().stdout, "3\n");
+    assert_eq!(result.output().exit_code, Some(0));
+}
+
+#[tokio::test]
+async fn bash_is_executed() {
+    let result = execute("bash", "bash", "words=(a b c)\necho ${#words[@]}").await;
+
+    assert!(result.is_success());
+    assert_eq!(result.output().stdout, "3\n");
+}
+
+#[tokio::test]
+async fn sh_is_executed() {
+    let result = execute("sh", "sh", "echo out\necho err >&2\nexit 3").await;
+
+    assert!(!result.is_success());
+    assert_eq!(result.output().stdout, "out\n");
+    assert_eq!(result.output().stderr, "err\n");
+    assert_eq!(result.output().exit_code, Some(3));
+}
+
+#[tokio::test]
+async fn rust_is_compiled_and_executed() {
+    let result = execute(
+        "rust",
+        "rust",
+        "fn main() {\n    println!(\"{}\", 6 * 7);\n}",
+    )
+    .await;
+
+    assert!(result.is_success());
+    assert_eq!(result.output().stdout, "42\n");
+}
+
+#[tokio::test]
+async fn rust_compilation_errors_are_failures() {
+    let result = execute("rust_error", "rust", "fn main() { undefined() }").await;
+
+    assert!(!result.is_success());
+    assert!(result.output().stderr.contains("undefined"));
+}
+
+#[tokio::test]
+async fn unknown_language_is_a_failure() {
+    let result = execute("unknown", "cobol", "DISPLAY 'HELLO'.").await;
+
+    assert!(!result.is_success());
+    assert_eq!(result.output().stderr, "unsupported language: cobol");
+}
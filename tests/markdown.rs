@@ -0,0 +1,202 @@
+use autogen::text_chat::collaborative_agent::{CollaborativeAgentResponse, ResponseSegment};
+use autogen::text_chat::markdown::{
+    parse_markdown, MarkdownCodeBlock, MarkdownResponseParser, MarkdownSegment,
+};
+
+/// Language, code and whether execution is requested, for every code block of the response.
+fn code_blocks(response: &CollaborativeAgentResponse) -> Vec<(String, String, bool)> {
+    match response {
+        CollaborativeAgentResponse::CommentedCodeBlock(commented_code_block) => vec![(
+            commented_code_block.code_block.language.clone(),
+            commented_code_block.code_block.code.clone(),
+            commented_code_block.request_execution,
+        )],
+        CollaborativeAgentResponse::Segments(segments) => segments
+            .iter()
+            .filter_map(|segment| match segment {
+                ResponseSegment::CodeBlock {
+                    code_block,
+                    request_execution,
+                } => Some((
+                    code_block.language.clone(),
+                    code_block.code.clone(),
+                    *request_execution,
+                )),
+                ResponseSegment::Text(_) => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn block(language: &str, code: &str, request_execution: bool) -> (String, String, bool) {
+    (language.to_string(), code.to_string(), request_execution)
+}
+
+#[test]
+fn prose_only_is_text() {
+    let response = MarkdownResponseParser::new()
+        .parse("  Sure, `print` prints.\n\n- first\n- second\n\nUse ``` for code.  ");
+
+    match response {
+        CollaborativeAgentResponse::Text(text) => assert_eq!(
+            text,
+            "Sure, `print` prints.\n\n- first\n- second\n\nUse ``` for code."
+        ),
+        response => panic!("unexpected response: {:?}", response),
+    }
+}
+
+#[test]
+fn single_code_block_is_commented() {
+    let response = MarkdownResponseParser::new()
+        .parse("Here it is:\n\n```python execute\nprint('hi')\n```\n\nRun it.");
+
+    match &response {
+        CollaborativeAgentResponse::CommentedCodeBlock(commented_code_block) => {
+            assert_eq!(commented_code_block.comment, "Here it is:\n\nRun it.")
+        }
+        response => panic!("unexpected response: {:?}", response),
+    }
+    assert_eq!(
+        code_blocks(&response),
+        [block("python", "print('hi')", true)]
+    );
+}
+
+#[test]
+fn execution_is_requested_with_the_marker() {
+    let text = "```python\nshown()\n```\n\n```python execute\nrun()\n```";
+
+    assert_eq!(
+        code_blocks(&MarkdownResponseParser::new().parse(text)),
+        [
+            block("python", "shown()", false),
+            block("python", "run()", true)
+        ]
+    );
+    assert_eq!(
+        code_blocks(&MarkdownResponseParser::new().execute_all().parse(text)),
+        [
+            block("python", "shown()", true),
+            block("python", "run()", true)
+        ]
+    );
+    assert_eq!(
+        code_blocks(
+            &MarkdownResponseParser::new()
+                .with_execution_marker("run")
+                .parse("```{.python run}\nrun()\n```")
+        ),
+        [block("python", "run()", true)]
+    );
+}
+
+#[test]
+fn tilde_fences() {
+    let response = MarkdownResponseParser::new().parse("~~~sh execute\necho ```\n~~~");
+
+    assert_eq!(code_blocks(&response), [block("sh", "echo ```", true)]);
+}
+
+#[test]
+fn longer_fences_contain_nested_backticks() {
+    let response = MarkdownResponseParser::new()
+        .parse("````markdown\nExample:\n```python\nprint('hi')\n```\n````\nDone.");
+
+    assert_eq!(
+        code_blocks(&response),
+        [block(
+            "markdown",
+            "Example:\n```python\nprint('hi')\n```",
+            false
+        )]
+    );
+}
+
+#[test]
+fn unlabeled_blocks() {
+    let text = "```\nls -la\n```";
+
+    assert_eq!(
+        code_blocks(&MarkdownResponseParser::new().parse(text)),
+        [block("", "ls -la", false)]
+    );
+    assert_eq!(
+        code_blocks(
+            &MarkdownResponseParser::new()
+                .execute_all()
+                .with_default_language("sh")
+                .parse(text)
+        ),
+        [block("sh", "ls -la", true)]
+    );
+}
+
+#[test]
+fn indented_blocks() {
+    let text = "Run this:\n\n    x = 1\n\n    print(x)\n\nThen this.\n    not code, continues the paragraph";
+
+    let segments = parse_markdown(text);
+
+    assert_eq!(segments.len(), 3);
+    match &segments[1] {
+        MarkdownSegment::Code(code_block) => {
+            assert_eq!(code_block.info, "");
+            assert_eq!(code_block.code, "x = 1\n\nprint(x)");
+            assert_eq!(code_block.raw, "    x = 1\n\n    print(x)\n");
+        }
+        segment => panic!("unexpected segment: {:?}", segment),
+    }
+    assert_eq!(
+        segments[2],
+        MarkdownSegment::Text("\nThen this.\n    not code, continues the paragraph".to_string())
+    );
+}
+
+#[test]
+fn list_embedded_blocks() {
+    let text = "1. Install it:\n\n       ```sh execute\n       pip install numpy\n       ```\n\n2. Continuation of the item:\n\n    indented text, not code\n";
+
+    let response = MarkdownResponseParser::new().parse(text);
+
+    assert_eq!(
+        code_blocks(&response),
+        [block("sh", "pip install numpy", true)]
+    );
+    match &response {
+        CollaborativeAgentResponse::CommentedCodeBlock(commented_code_block) => assert_eq!(
+            commented_code_block.comment,
+            "1. Install it:\n\n2. Continuation of the item:\n\n    indented text, not code"
+        ),
+        response => panic!("unexpected response: {:?}", response),
+    }
+}
+
+#[test]
+fn unterminated_fences_run_until_the_end() {
+    let response =
+        MarkdownResponseParser::new().parse("Almost done:\n```python execute\nprint('cut')\n");
+
+    assert_eq!(
+        code_blocks(&response),
+        [block("python", "print('cut')", true)]
+    );
+}
+
+#[test]
+fn raw_keeps_the_fences() {
+    let segments = parse_markdown("text\n```rust\nfn main() {}\n```\n");
+
+    assert_eq!(
+        segments,
+        [
+            MarkdownSegment::Text("text\n".to_string()),
+            MarkdownSegment::Code(MarkdownCodeBlock {
+                info: "rust".to_string(),
+                code: "fn main() {}".to_string(),
+                raw: "```rust\nfn main() {}\n```\n".to_string(),
+            }),
+        ]
+    );
+}
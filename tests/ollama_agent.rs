@@ -0,0 +1,191 @@
+//! Runs the Ollama agent against a local mock of the `/api/chat` endpoint.
+
+use std::time::Duration;
+
+use autogen::agent_traits::{ProducerAgent, TokenUsage};
+use autogen::text_chat::code::CodeBlock;
+use autogen::text_chat::collaborative_agent::{CollaborativeAgent, CollaborativeAgentResponse};
+use autogen::text_chat::collaborative_agent_error::CollaborativeAgentError;
+use autogen::text_chat::llm::ollama_agent::{OllamaAgent, OllamaOptions};
+use autogen::text_chat::llm::ollama_agent_error::OllamaAgentError;
+
+use serde_json::{json, Value};
+
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn chat_response(content: &str) -> ResponseTemplate {
+    ResponseTemplate::new(200).set_body_json(json!({
+        "model": "llama3",
+        "created_at": "2024-01-01T12:00:00.000000Z",
+        "message": { "role": "assistant", "content": content },
+        "done_reason": "stop",
+        "done": true,
+        "total_duration": 1000,
+        "prompt_eval_count": 20,
+        "eval_count": 10,
+    }))
+}
+
+async fn mock_server(response: ResponseTemplate) -> MockServer {
+    let server = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .and(path("/api/chat"))
+        .respond_with(response)
+        .mount(&server)
+        .await;
+
+    server
+}
+
+async fn request_bodies(server: &MockServer) -> Vec<Value> {
+    server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|request| request.body_json().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn sends_options_and_parses_the_reply() {
+    let server = mock_server(chat_response(
+        "Here you go:\n\n```bash execute\necho hello\n```\n",
+    ))
+    .await;
+
+    let mut agent = OllamaAgent::new(server.uri(), "llama3")
+        .with_system_prompt("You write code.")
+        .with_temperature(0.25)
+        .with_num_ctx(8192)
+        .with_keep_alive(Duration::from_secs(600));
+
+    let reply = agent
+        .receive_and_reply("user".to_string(), "Say hello".to_string())
+        .await
+        .unwrap();
+
+    match reply {
+        CollaborativeAgentResponse::CommentedCodeBlock(commented_code_block) => {
+            assert_eq!(commented_code_block.comment, "Here you go:");
+            assert_eq!(commented_code_block.code_block.language, "bash");
+            assert_eq!(commented_code_block.code_block.code, "echo hello");
+            assert!(commented_code_block.request_execution);
+        }
+        reply => panic!("unexpected reply: {:?}", reply),
+    }
+
+    assert_eq!(
+        request_bodies(&server).await,
+        vec![json!({
+            "model": "llama3",
+            "stream": false,
+            "options": { "temperature": 0.25, "num_ctx": 8192 },
+            "keep_alive": 600,
+            "messages": [
+                { "role": "system", "content": "You write code." },
+                { "role": "user", "content": "Say hello" },
+            ],
+        })]
+    );
+}
+
+#[tokio::test]
+async fn sends_the_whole_history() {
+    let server = mock_server(chat_response("Fine, no code then.")).await;
+
+    let mut agent = OllamaAgent::new(format!("{}/", server.uri()), "llama3")
+        .with_options(OllamaOptions {
+            seed: Some(42),
+            stop: vec!["</answer>".to_string()],
+            ..Default::default()
+        })
+        .with_keep_alive_forever();
+
+    agent
+        .receive_and_reply("user".to_string(), "Delete my files".to_string())
+        .await
+        .unwrap();
+
+    let code_block = CodeBlock {
+        language: "bash".to_string(),
+        code: "rm -rf ~".to_string(),
+    };
+
+    agent
+        .deny_code_block_execution(code_block, "Too dangerous".to_string())
+        .await
+        .unwrap();
+
+    assert_eq!(agent.history().len(), 4);
+    assert_eq!(agent.history().entries()[3].tokens, Some(10));
+    assert_eq!(
+        agent.usage(),
+        Some(TokenUsage {
+            prompt_tokens: 40,
+            completion_tokens: 20,
+        })
+    );
+
+    let bodies = request_bodies(&server).await;
+    assert_eq!(bodies.len(), 2);
+    assert_eq!(
+        bodies[1]["options"],
+        json!({ "seed": 42, "stop": ["</answer>"] })
+    );
+    assert_eq!(bodies[1]["keep_alive"], json!(-1));
+    assert_eq!(
+        bodies[1]["messages"],
+        json!([
+            { "role": "user", "content": "Delete my files" },
+            { "role": "assistant", "content": "Fine, no code then." },
+            {
+                "role": "user",
+                "content": "Execution of the code was denied:\n```bash\nrm -rf ~\n```\n\nReason: Too dangerous",
+            },
+        ])
+    );
+}
+
+#[tokio::test]
+async fn error_status_is_reported() {
+    let server = mock_server(
+        ResponseTemplate::new(404).set_body_json(json!({ "error": "model \"llama3\" not found" })),
+    )
+    .await;
+
+    let mut agent = OllamaAgent::new(server.uri(), "llama3");
+
+    let error = agent
+        .receive_and_reply("user".to_string(), "hi".to_string())
+        .await
+        .unwrap_err();
+
+    match error {
+        CollaborativeAgentError::Sending(OllamaAgentError::Status { status, body }) => {
+            assert_eq!(status, 404);
+            assert!(body.contains("not found"));
+        }
+        error => panic!("unexpected error: {:?}", error),
+    }
+}
+
+#[tokio::test]
+async fn reply_without_message_is_invalid() {
+    let server =
+        mock_server(ResponseTemplate::new(200).set_body_json(json!({ "done": true }))).await;
+
+    let mut agent = OllamaAgent::new(server.uri(), "llama3");
+
+    let error = agent
+        .receive_and_reply("user".to_string(), "hi".to_string())
+        .await
+        .unwrap_err();
+
+    assert!(matches!(
+        error,
+        CollaborativeAgentError::Sending(OllamaAgentError::InvalidResponse(_))
+    ));
+}
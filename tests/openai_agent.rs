@@ -0,0 +1,438 @@
+//! Runs the OpenAI agent against a local mock of the chat completions endpoint.
+
+use autogen::agent_traits::{ConsumerAgent, ProducerAgent, StreamingProducerAgent, TokenUsage};
+use autogen::text_chat::code::{CodeBlockExecutionOutput, CodeBlockExecutionResult};
+use autogen::text_chat::collaborative_agent::{
+    CollaborativeAgent, CollaborativeAgentResponse, Message,
+};
+use autogen::text_chat::collaborative_agent_error::CollaborativeAgentError;
+use autogen::text_chat::conversation_history::{ConversationHistory, Role, SlidingWindow};
+use autogen::text_chat::llm::openai_agent::OpenAiAgent;
+use autogen::text_chat::llm::openai_agent_error::OpenAiAgentError;
+use autogen::text_chat::tool::{
+    Tool, ToolCall, ToolCallOutcome, ToolCallReport, ToolDefinition, ToolRegistry,
+};
+
+use futures::TryStreamExt;
+
+use serde_json::{json, Value};
+
+use wiremock::matchers::{header, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn completion(content: &str) -> ResponseTemplate {
+    ResponseTemplate::new(200).set_body_json(json!({
+        "id": "chatcmpl-1",
+        "object": "chat.completion",
+        "choices": [{
+            "index": 0,
+            "message": { "role": "assistant", "content": content },
+            "finish_reason": "stop",
+        }],
+    }))
+}
+
+async fn mock_server(content: &str) -> MockServer {
+    let server = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .respond_with(completion(content))
+        .mount(&server)
+        .await;
+
+    server
+}
+
+async fn request_bodies(server: &MockServer) -> Vec<Value> {
+    server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|request| request.body_json().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn sends_role_tagged_messages_and_parses_the_reply() {
+    let server = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .and(header("authorization", "Bearer secret"))
+        .respond_with(completion(
+            "Sure:\n```python execute\nprint(\"Hello World\")\n```",
+        ))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let mut agent = OpenAiAgent::new(format!("{}/v1", server.uri()), "gpt-test")
+        .with_api_key("secret")
+        .with_system_prompt("You write code.")
+        .with_temperature(0.5);
+
+    let reply = agent
+        .receive_and_reply(
+            "Jan Kowalski".to_string(),
+            "Write Hello World in Python".to_string(),
+        )
+        .await
+        .unwrap();
+
+    match reply {
+        CollaborativeAgentResponse::CommentedCodeBlock(commented_code_block) => {
+            assert_eq!(commented_code_block.comment, "Sure:");
+            assert_eq!(commented_code_block.code_block.language, "python");
+            assert_eq!(
+                commented_code_block.code_block.code,
+                "print(\"Hello World\")"
+            );
+            assert!(commented_code_block.request_execution);
+        }
+        reply => panic!("unexpected reply: {:?}", reply),
+    }
+
+    assert_eq!(
+        request_bodies(&server).await,
+        vec![json!({
+            "model": "gpt-test",
+            "temperature": 0.5,
+            "messages": [
+                { "role": "system", "content": "You write code." },
+                { "role": "user", "content": "Write Hello World in Python", "name": "Jan_Kowalski" },
+            ],
+        })]
+    );
+}
+
+#[tokio::test]
+async fn keeps_the_conversation_history() {
+    let server = mock_server("Done.").await;
+
+    let mut agent = OpenAiAgent::new(format!("{}/v1/", server.uri()), "gpt-test");
+
+    agent
+        .receive_and_reply("user".to_string(), "Run it".to_string())
+        .await
+        .unwrap();
+
+    let result = CodeBlockExecutionResult::Success(CodeBlockExecutionOutput {
+        exit_code: Some(0),
+        stdout: "Hello World\n".to_string(),
+        ..Default::default()
+    });
+
+    agent
+        .receive_code_and_reply_to_execution_result(result)
+        .await
+        .unwrap();
+
+    let roles: Vec<Role> = agent
+        .history()
+        .entries()
+        .iter()
+        .map(|message| message.role)
+        .collect();
+    assert_eq!(
+        roles,
+        vec![Role::User, Role::Assistant, Role::User, Role::Assistant]
+    );
+
+    let bodies = request_bodies(&server).await;
+    assert_eq!(bodies.len(), 2);
+    assert_eq!(
+        bodies[1]["messages"],
+        json!([
+            { "role": "user", "content": "Run it", "name": "user" },
+            { "role": "assistant", "content": "Done." },
+            {
+                "role": "user",
+                "content": "The code was executed successfully.\nExit code: 0\nstdout:\n```\nHello World\n```",
+            },
+        ])
+    );
+}
+
+#[tokio::test]
+async fn sends_the_truncated_history() {
+    let server = mock_server("Done.").await;
+
+    let mut agent = OpenAiAgent::new(format!("{}/v1", server.uri()), "gpt-test")
+        .with_system_prompt("You write code.")
+        .with_history(ConversationHistory::new().with_truncation(SlidingWindow { max_entries: 1 }));
+
+    for message in ["first", "second"] {
+        agent
+            .receive_and_reply("user".to_string(), message.to_string())
+            .await
+            .unwrap();
+    }
+
+    assert_eq!(agent.history().len(), 4);
+
+    let bodies = request_bodies(&server).await;
+    assert_eq!(
+        bodies[1]["messages"],
+        json!([
+            { "role": "system", "content": "You write code." },
+            { "role": "user", "content": "second", "name": "user" },
+        ])
+    );
+}
+
+#[tokio::test]
+async fn tool_calls_round_trip() {
+    let server = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "choices": [{
+                "index": 0,
+                "message": {
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [{
+                        "id": "call_1",
+                        "type": "function",
+                        "function": { "name": "add", "arguments": "{\"a\": 2, \"b\": 3}" },
+                    }],
+                },
+                "finish_reason": "tool_calls",
+            }],
+        })))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .respond_with(completion("It is 5."))
+        .mount(&server)
+        .await;
+
+    let tools = ToolRegistry::new().with_tool(Tool::new(
+        ToolDefinition::new(
+            "add",
+            "Adds two integers.",
+            json!({
+                "type": "object",
+                "properties": { "a": { "type": "integer" }, "b": { "type": "integer" } },
+            }),
+        ),
+        |arguments: Value| async move {
+            Ok::<_, String>(arguments["a"].as_i64().unwrap() + arguments["b"].as_i64().unwrap())
+        },
+    ));
+
+    let mut agent = OpenAiAgent::new(format!("{}/v1", server.uri()), "gpt-test")
+        .with_tools(tools.definitions());
+
+    let reply = agent
+        .receive_and_reply("user".to_string(), "What is 2 + 3?".to_string())
+        .await
+        .unwrap();
+
+    let tool_calls = match reply {
+        CollaborativeAgentResponse::ToolCalls {
+            comment,
+            tool_calls,
+        } => {
+            assert_eq!(comment, "");
+            tool_calls
+        }
+        reply => panic!("unexpected reply: {:?}", reply),
+    };
+    assert_eq!(
+        tool_calls,
+        [ToolCall {
+            id: "call_1".to_string(),
+            name: "add".to_string(),
+            arguments: json!({ "a": 2, "b": 3 }),
+        }]
+    );
+
+    let mut reports = Vec::new();
+    for tool_call in tool_calls {
+        let outcome = ToolCallOutcome::Returned(tools.call(&tool_call).await.unwrap());
+        reports.push(ToolCallReport { tool_call, outcome });
+    }
+
+    match agent
+        .receive_and_reply_to_tool_call_results(reports)
+        .await
+        .unwrap()
+    {
+        CollaborativeAgentResponse::Text(text) => assert_eq!(text, "It is 5."),
+        reply => panic!("unexpected reply: {:?}", reply),
+    }
+
+    let bodies = request_bodies(&server).await;
+    assert_eq!(bodies.len(), 2);
+    assert_eq!(
+        bodies[0]["tools"],
+        json!([{
+            "type": "function",
+            "function": {
+                "name": "add",
+                "description": "Adds two integers.",
+                "parameters": {
+                    "type": "object",
+                    "properties": { "a": { "type": "integer" }, "b": { "type": "integer" } },
+                },
+            },
+        }])
+    );
+    assert_eq!(
+        bodies[1]["messages"],
+        json!([
+            { "role": "user", "content": "What is 2 + 3?", "name": "user" },
+            {
+                "role": "assistant",
+                "content": null,
+                "tool_calls": [{
+                    "id": "call_1",
+                    "type": "function",
+                    "function": { "name": "add", "arguments": "{\"a\":2,\"b\":3}" },
+                }],
+            },
+            { "role": "tool", "content": "5", "tool_call_id": "call_1" },
+        ])
+    );
+}
+
+#[tokio::test]
+async fn error_status_is_reported() {
+    let server = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(429).set_body_string("rate limited"))
+        .mount(&server)
+        .await;
+
+    let mut agent = OpenAiAgent::new(format!("{}/v1", server.uri()), "gpt-test");
+
+    let error = agent
+        .receive_and_reply("user".to_string(), "hi".to_string())
+        .await
+        .unwrap_err();
+
+    match error {
+        CollaborativeAgentError::Sending(OpenAiAgentError::Status { status, body }) => {
+            assert_eq!(status, 429);
+            assert_eq!(body, "rate limited");
+        }
+        error => panic!("unexpected error: {:?}", error),
+    }
+}
+
+#[tokio::test]
+async fn reply_without_content_is_invalid() {
+    let server = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "choices": [] })))
+        .mount(&server)
+        .await;
+
+    let mut agent = OpenAiAgent::new(format!("{}/v1", server.uri()), "gpt-test");
+
+    let error = agent
+        .receive_and_reply("user".to_string(), "hi".to_string())
+        .await
+        .unwrap_err();
+
+    assert!(matches!(
+        error,
+        CollaborativeAgentError::Sending(OpenAiAgentError::InvalidResponse(_))
+    ));
+}
+
+#[tokio::test]
+async fn streams_the_reply_in_deltas() {
+    let server = MockServer::start().await;
+
+    let chunks = ["Sure:\n```bash ", "execute\necho ", "zażółć\n```"];
+    let events: String = chunks
+        .iter()
+        .map(|chunk| {
+            let event = json!({ "choices": [{ "index": 0, "delta": { "content": chunk } }] });
+            format!("data: {}\n\n", event)
+        })
+        .chain([
+            format!(
+                "data: {}\n\n",
+                json!({ "choices": [], "usage": { "prompt_tokens": 12, "completion_tokens": 9 } })
+            ),
+            "data: [DONE]\n\n".to_string(),
+        ])
+        .collect();
+
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(events, "text/event-stream"))
+        .mount(&server)
+        .await;
+
+    let mut agent = OpenAiAgent::new(format!("{}/v1", server.uri()), "gpt-test");
+
+    agent
+        .receive_message(Message::Text {
+            sender: "user".to_string(),
+            message: "Say it in Polish".to_string(),
+        })
+        .await
+        .unwrap();
+
+    let deltas: Vec<String> = agent.send_message_stream().try_collect().await.unwrap();
+    assert_eq!(deltas, chunks);
+
+    match agent.assemble_message(deltas).unwrap() {
+        CollaborativeAgentResponse::CommentedCodeBlock(commented_code_block) => {
+            assert_eq!(commented_code_block.code_block.code, "echo zażółć");
+            assert!(commented_code_block.request_execution);
+        }
+        reply => panic!("unexpected reply: {:?}", reply),
+    }
+
+    assert_eq!(agent.history().len(), 2);
+    assert_eq!(agent.history().entries()[1].content, chunks.concat());
+    assert_eq!(agent.history().entries()[1].tokens, Some(9));
+
+    assert_eq!(
+        agent.usage(),
+        Some(TokenUsage {
+            prompt_tokens: 12,
+            completion_tokens: 9,
+        })
+    );
+
+    let body = &request_bodies(&server).await[0];
+    assert_eq!(body["stream"], json!(true));
+    assert_eq!(body["stream_options"], json!({ "include_usage": true }));
+}
+
+#[tokio::test]
+async fn streaming_error_status_is_reported() {
+    let server = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(500).set_body_string("oops"))
+        .mount(&server)
+        .await;
+
+    let mut agent = OpenAiAgent::new(format!("{}/v1", server.uri()), "gpt-test");
+
+    let error = agent
+        .send_message_stream()
+        .try_collect::<Vec<String>>()
+        .await
+        .unwrap_err();
+
+    assert!(matches!(
+        error,
+        OpenAiAgentError::Status { status: 500, .. }
+    ));
+}
@@ -0,0 +1,140 @@
+use autogen::text_chat::code::repl_code_executor::{ReplCodeExecutor, ReplLanguage};
+use autogen::text_chat::code::{CodeBlock, CodeBlockExecutionResult, CodeExecutor};
+
+use std::path::PathBuf;
+
+/// Fresh working directory for every test.
+fn work_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "autogen_repl_code_executor_{}_{}",
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    dir
+}
+
+async fn execute(
+    executor: &ReplCodeExecutor,
+    language: &str,
+    code: &str,
+) -> CodeBlockExecutionResult {
+    executor
+        .execute_code_block(&CodeBlock {
+            language: language.to_string(),
+            code: code.to_string(),
+        })
+        .await
+        .expect("execution failed")
+}
+
+#[tokio::test]
+async fn python_state_is_kept_between_code_blocks() {
+    let executor = ReplCodeExecutor::new(work_dir("python_state"));
+
+    let result = execute(&executor, "python", "x = 41\ndef inc(v):\n    return v + 1").await;
+    assert!(result.is_success());
+    assert_eq!(result.output().stdout, "");
+
+    let result = execute(&executor, "python", "print(inc(x))").await;
+    assert!(result.is_success());
+    assert_eq!(result.output().stdout, "42\n");
+
+    // The value of a trailing expression is printed.
+    let result = execute(&executor, "python", "inc(x) * 2").await;
+    assert_eq!(result.output().stdout, "84\n");
+}
+
+#[tokio::test]
+async fn python_errors_keep_the_state() {
+    let executor = ReplCodeExecutor::new(work_dir("python_errors"));
+
+    let result = execute(&executor, "python", "y = 1\nraise ValueError('boom')").await;
+    assert!(!result.is_success());
+    assert_eq!(result.output().exit_code, Some(1));
+    assert!(result.output().stderr.contains("ValueError: boom"));
+
+    let result = execute(&executor, "python", "print(y)").await;
+    assert!(result.is_success());
+    assert_eq!(result.output().stdout, "1\n");
+}
+
+#[tokio::test]
+async fn bash_environment_and_current_dir_are_kept_between_code_blocks() {
+    let dir = work_dir("bash_state");
+    let executor = ReplCodeExecutor::new(&dir);
+
+    let result = execute(&executor, "bash", "export GREETING=hi\nmkdir sub\ncd sub").await;
+    assert!(result.is_success());
+
+    let result = execute(&executor, "bash", "echo $GREETING\npwd").await;
+    assert!(result.is_success());
+    assert_eq!(
+        result.output().stdout,
+        format!(
+            "hi\n{}\n",
+            dir.canonicalize().unwrap().join("sub").display()
+        )
+    );
+}
+
+#[tokio::test]
+async fn session_is_restarted_after_a_crash() {
+    let executor = ReplCodeExecutor::new(work_dir("crash"));
+
+    execute(&executor, "python", "x = 1").await;
+
+    let result = execute(&executor, "python", "import os\nos._exit(3)").await;
+    assert!(!result.is_success());
+    assert_eq!(result.output().exit_code, Some(3));
+
+    // Fresh session, the state is gone.
+    let result = execute(&executor, "python", "print('x' in globals())").await;
+    assert!(result.is_success());
+    assert_eq!(result.output().stdout, "False\n");
+
+    let result = execute(&executor, "bash", "exit 4").await;
+    assert_eq!(result.output().exit_code, Some(4));
+
+    let result = execute(&executor, "bash", "echo alive").await;
+    assert_eq!(result.output().stdout, "alive\n");
+}
+
+#[tokio::test]
+async fn reset_clears_the_state() {
+    let executor = ReplCodeExecutor::new(work_dir("reset"));
+
+    execute(&executor, "python", "x = 1").await;
+    executor.reset(ReplLanguage::Python).await;
+
+    let result = execute(&executor, "python", "print('x' in globals())").await;
+    assert_eq!(result.output().stdout, "False\n");
+}
+
+#[tokio::test]
+async fn output_around_the_markers_is_kept_intact() {
+    let executor = ReplCodeExecutor::new(work_dir("markers"));
+
+    let result = execute(
+        &executor,
+        "python",
+        "import sys\nprint('out', end='')\n_ = sys.stderr.write('err')",
+    )
+    .await;
+    assert_eq!(result.output().stdout, "out");
+    assert_eq!(result.output().stderr, "err");
+
+    let result = execute(&executor, "python", "print('a\\n\\nb\\n')").await;
+    assert_eq!(result.output().stdout, "a\n\nb\n\n");
+    assert_eq!(result.output().stderr, "");
+
+    let result = execute(&executor, "bash", "printf 'no newline'\necho err >&2").await;
+    assert_eq!(result.output().stdout, "no newline");
+    assert_eq!(result.output().stderr, "err\n");
+
+    let result = execute(&executor, "bash", "true").await;
+    assert!(result.is_success());
+    assert_eq!(result.output().stdout, "");
+    assert_eq!(result.output().stderr, "");
+}
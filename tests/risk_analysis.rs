@@ -0,0 +1,328 @@
+use autogen::text_chat::code::risk_analysis::{RiskAnalyzer, RiskKind, RiskReport};
+use autogen::text_chat::code::CodeBlock;
+
+use RiskKind::*;
+
+fn analyze(language: &str, code: &str) -> RiskReport {
+    RiskAnalyzer::new().analyze(&CodeBlock {
+        language: language.to_string(),
+        code: code.to_string(),
+    })
+}
+
+/// Kinds with the lines they were found on.
+fn risks(language: &str, code: &str) -> Vec<(usize, RiskKind)> {
+    analyze(language, code)
+        .risks
+        .into_iter()
+        .map(|risk| (risk.line, risk.kind))
+        .collect()
+}
+
+#[test]
+fn other_languages_are_not_analyzed() {
+    let report = analyze("rust", "fn main() {}");
+
+    assert!(!report.analyzed);
+    assert_eq!(
+        report.to_string(),
+        "Risks: unknown, the language is not analyzed."
+    );
+}
+
+#[test]
+fn harmless_code_has_no_risks() {
+    assert_eq!(
+        analyze("python", "print('hello')").to_string(),
+        "Risks: none found."
+    );
+    assert_eq!(risks("sh", "echo hello > out.txt\ncat out.txt | wc -l"), []);
+}
+
+#[test]
+fn report_lists_the_risks_with_their_lines() {
+    let report = analyze("sh", "ls\n  curl example.com\n");
+
+    assert_eq!(
+        report.to_string(),
+        "Risks:\n- line 2, network access: `curl example.com`"
+    );
+}
+
+#[test]
+fn shell_network_access() {
+    assert_eq!(
+        risks(
+            "bash",
+            "curl -s example.com\nwget example.com\ngit clone https://example.com/repo\ngit status"
+        ),
+        [(1, NetworkAccess), (2, NetworkAccess), (3, NetworkAccess)]
+    );
+    assert_eq!(
+        risks("sh", "echo hi > /dev/tcp/example.com/80"),
+        [(1, FileWriteOutsideWorkdir), (1, NetworkAccess)]
+    );
+}
+
+#[test]
+fn shell_file_writes_outside_the_workdir() {
+    assert_eq!(
+        risks(
+            "sh",
+            "echo a > /etc/hosts\necho b >> ~/.bashrc\nrm -rf ../data\ncp a.txt /tmp/\ntouch ok.txt\necho c 2>&1 > /dev/null"
+        ),
+        [
+            (1, FileWriteOutsideWorkdir),
+            (2, FileWriteOutsideWorkdir),
+            (3, FileWriteOutsideWorkdir),
+            (4, FileWriteOutsideWorkdir),
+        ]
+    );
+}
+
+#[test]
+fn shell_writes_to_paths_with_variables() {
+    assert_eq!(
+        risks(
+            "sh",
+            "for f in *; do rm -rf /etc/$f; done\nrm -rf $TARGET\nrm -rf \"${DIR}/data\"\nrm -rf out/$f\necho a > \"$PWD/notes\"\necho b > $PWD/../notes"
+        ),
+        [
+            (1, FileWriteOutsideWorkdir),
+            (2, FileWriteOutsideWorkdir),
+            (3, FileWriteOutsideWorkdir),
+            (6, FileWriteOutsideWorkdir),
+        ]
+    );
+}
+
+#[test]
+fn shell_wrapper_options_are_skipped() {
+    assert_eq!(
+        risks(
+            "sh",
+            "sudo -u root rm -rf /etc\nenv -u VAR rm -rf /etc\nnice -n 10 rm -rf /etc\ntimeout -s KILL 5 rm -rf /etc\nls | xargs -I {} rm -rf /etc/{}"
+        ),
+        [
+            (1, FileWriteOutsideWorkdir),
+            (2, FileWriteOutsideWorkdir),
+            (3, FileWriteOutsideWorkdir),
+            (4, FileWriteOutsideWorkdir),
+            (5, FileWriteOutsideWorkdir),
+            (5, ProcessSpawn),
+        ]
+    );
+}
+
+#[test]
+fn workdir_is_not_outside() {
+    let analyzer = RiskAnalyzer::new().with_workdir("/work");
+    let report = analyzer.analyze(&CodeBlock {
+        language: "sh".to_string(),
+        code: "echo a > /work/out.txt\necho b > ../work/out.txt\necho c > /tmp/out.txt".to_string(),
+    });
+
+    assert_eq!(
+        report
+            .risks
+            .iter()
+            .map(|risk| (risk.line, risk.kind))
+            .collect::<Vec<_>>(),
+        [(3, FileWriteOutsideWorkdir)]
+    );
+}
+
+#[test]
+fn shell_relative_writes_follow_the_directory_changes() {
+    let risks = |code: &str| -> Vec<(usize, RiskKind)> {
+        RiskAnalyzer::new()
+            .with_workdir("/work")
+            .analyze(&CodeBlock {
+                language: "sh".to_string(),
+                code: code.to_string(),
+            })
+            .risks
+            .into_iter()
+            .map(|risk| (risk.line, risk.kind))
+            .collect()
+    };
+
+    assert_eq!(risks("cd /; rm -rf *"), [(1, FileWriteOutsideWorkdir)]);
+    assert_eq!(
+        risks("cd sub\ntouch a\ncd ../..\ntouch b\necho c > c.txt\ncd -\ntouch d"),
+        [(4, FileWriteOutsideWorkdir), (5, FileWriteOutsideWorkdir)]
+    );
+    assert_eq!(
+        risks("pushd /tmp\ntouch a\npopd\ntouch b"),
+        [(2, FileWriteOutsideWorkdir)]
+    );
+    assert_eq!(
+        risks("cd\necho a > notes\ncd $DIR\ntouch b"),
+        [(2, FileWriteOutsideWorkdir), (4, FileWriteOutsideWorkdir)]
+    );
+    assert_eq!(
+        risks("cd /tmp &\ntouch a\ncd /work/sub\ntouch b\ncd /etc\nbash -c 'rm hosts'"),
+        [
+            (1, ProcessSpawn),
+            (6, FileWriteOutsideWorkdir),
+            (6, ProcessSpawn)
+        ]
+    );
+}
+
+#[test]
+fn shell_process_spawning() {
+    assert_eq!(
+        risks(
+            "sh",
+            "sleep 100 &\nnohup ./server\nbash -c 'curl example.com'\nls | xargs rm\npython3 -c 'print(1)'\npython -u -c \"import urllib.request; urllib.request.urlopen('http://x')\"\npython script.py"
+        ),
+        [
+            (1, ProcessSpawn),
+            (2, ProcessSpawn),
+            (3, NetworkAccess),
+            (3, ProcessSpawn),
+            (4, ProcessSpawn),
+            (5, ProcessSpawn),
+            (6, NetworkAccess),
+            (6, ProcessSpawn),
+        ]
+    );
+}
+
+#[test]
+fn shell_package_installation() {
+    assert_eq!(
+        risks(
+            "sh",
+            "pip install numpy\nsudo apt-get install -y curl\npython -m pip install requests\nnpm ls"
+        ),
+        [(1, PackageInstall), (2, PackageInstall), (3, PackageInstall)]
+    );
+}
+
+#[test]
+fn shell_infinite_loops() {
+    assert_eq!(
+        risks(
+            "sh",
+            "while true; do\n  echo hi\ndone\nwhile :; do\n  [ -f done ] && break\ndone\nfor ((;;)); do echo hi; done\nwhile read line; do echo $line; done < input"
+        ),
+        [(1, InfiniteLoop), (7, InfiniteLoop)]
+    );
+}
+
+#[test]
+fn shell_comments_strings_and_heredocs_are_not_commands() {
+    assert_eq!(
+        risks(
+            "sh",
+            "# curl example.com\necho 'wget example.com'\ncat <<EOF\ncurl example.com\nEOF\nls"
+        ),
+        []
+    );
+}
+
+#[test]
+fn shell_command_substitution_is_analyzed() {
+    assert_eq!(
+        risks("sh", "ip=$(curl -s example.com)\necho $ip"),
+        [(1, NetworkAccess)]
+    );
+}
+
+#[test]
+fn python_process_spawning() {
+    assert_eq!(
+        risks(
+            "python",
+            "import subprocess as sp\nimport os\nsp.run(['ls', '-la'])\nos.system('echo hi')\nfrom subprocess import Popen\nPopen('ls')"
+        ),
+        [(3, ProcessSpawn), (4, ProcessSpawn), (6, ProcessSpawn)]
+    );
+}
+
+#[test]
+fn python_commands_are_analyzed_as_shell() {
+    assert_eq!(
+        risks(
+            "python",
+            "import subprocess, sys\nsubprocess.check_call([sys.executable, '-m', 'pip', 'install', 'numpy'])\nsubprocess.run('curl example.com > /etc/hosts', shell=True)"
+        ),
+        [
+            (2, ProcessSpawn),
+            (2, PackageInstall),
+            (3, FileWriteOutsideWorkdir),
+            (3, NetworkAccess),
+            (3, ProcessSpawn),
+        ]
+    );
+}
+
+#[test]
+fn python_network_access() {
+    assert_eq!(
+        risks(
+            "python",
+            "import requests\nfrom urllib.request import urlopen\nimport urllib.parse\nrequests.get('https://example.com')\nurlopen('https://example.com')\nurllib.parse.quote('a b')\nimport pandas as pd\npd.read_csv('https://example.com/data.csv')"
+        ),
+        [(4, NetworkAccess), (5, NetworkAccess), (8, NetworkAccess)]
+    );
+}
+
+#[test]
+fn python_file_writes_outside_the_workdir() {
+    assert_eq!(
+        risks(
+            "python",
+            "open('/etc/hosts', 'a').write('x')\nopen('/etc/hosts').read()\nopen('out.txt', 'w')\nimport shutil\nshutil.rmtree('/var/data')\nshutil.copy('/etc/hosts', 'hosts')\nfrom pathlib import Path\nPath('~/.bashrc').write_text('x')\nwith open(\n    '../secret',\n    mode='w',\n) as f:\n    pass"
+        ),
+        [
+            (1, FileWriteOutsideWorkdir),
+            (5, FileWriteOutsideWorkdir),
+            (8, FileWriteOutsideWorkdir),
+            (9, FileWriteOutsideWorkdir),
+        ]
+    );
+}
+
+#[test]
+fn python_package_installation() {
+    assert_eq!(
+        risks(
+            "python",
+            "!pip install numpy\n%pip install pandas\nimport numpy"
+        ),
+        [(1, ProcessSpawn), (1, PackageInstall), (2, PackageInstall)]
+    );
+}
+
+#[test]
+fn python_infinite_loops() {
+    assert_eq!(
+        risks(
+            "python",
+            "while True:\n    print('hi')\nwhile True:\n    if done():\n        break\nwhile True:\n    for x in y:\n        break\nwhile 1: pass\nimport itertools\nfor i in itertools.count():\n    print(i)"
+        ),
+        [(1, InfiniteLoop), (6, InfiniteLoop), (9, InfiniteLoop), (11, InfiniteLoop)]
+    );
+}
+
+#[test]
+fn python_comments_and_strings_are_not_code() {
+    assert_eq!(
+        risks(
+            "python",
+            "# os.system('rm -rf /')\nprint('os.system(\"ls\")')\n\"\"\"\nwhile True:\n    pass\n\"\"\""
+        ),
+        []
+    );
+}
+
+#[test]
+fn jupyter_shell_cells_are_analyzed_as_shell() {
+    assert_eq!(
+        risks("python", "%%bash\nls\ncurl example.com"),
+        [(3, NetworkAccess)]
+    );
+}
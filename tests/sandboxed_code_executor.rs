@@ -0,0 +1,170 @@
+#![cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+
+use autogen::text_chat::code::sandboxed_code_executor::SandboxedCodeExecutor;
+use autogen::text_chat::code::{CodeBlock, CodeBlockExecutionResult, CodeExecutor};
+
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+
+fn work_dir(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "autogen_sandboxed_code_executor_{}_{}",
+        name,
+        std::process::id()
+    ))
+}
+
+/// Whether the namespaces used by the sandbox can be created here. Unprivileged user namespaces
+/// are disabled on some systems.
+fn namespaces_available() -> bool {
+    let mut command = std::process::Command::new("true");
+
+    // SAFETY: unshare is a raw syscall.
+    unsafe {
+        command.pre_exec(|| {
+            match libc::unshare(
+                libc::CLONE_NEWUSER
+                    | libc::CLONE_NEWNS
+                    | libc::CLONE_NEWNET
+                    | libc::CLONE_NEWPID
+                    | libc::CLONE_NEWIPC
+                    | libc::CLONE_NEWUTS,
+            ) {
+                -1 => Err(std::io::Error::last_os_error()),
+                _ => Ok(()),
+            }
+        });
+    }
+
+    command.status().is_ok_and(|status| status.success())
+}
+
+/// Runs the shell code in a fresh working directory. None if the namespaces are not available,
+/// any other error fails the test.
+async fn execute(name: &str, code: &str) -> Option<CodeBlockExecutionResult> {
+    if !namespaces_available() {
+        eprintln!("skipping, user namespaces are not available");
+        return None;
+    }
+
+    let _ = std::fs::remove_dir_all(work_dir(name));
+
+    let result = SandboxedCodeExecutor::new(work_dir(name))
+        .execute_code_block(&CodeBlock {
+            language: "sh".to_string(),
+            code: code.to_string(),
+        })
+        .await
+        .expect("sandboxed execution failed");
+
+    Some(result)
+}
+
+/// Python code printing the result of the raw syscall and errno.
+fn syscall(number: libc::c_long, arguments: &str) -> String {
+    format!(
+        "python3 -c \"import ctypes; libc = ctypes.CDLL(None, use_errno=True); \
+         buffer = ctypes.create_string_buffer(128); \
+         print(libc.syscall({}, {}), ctypes.get_errno())\"",
+        number, arguments
+    )
+}
+
+#[tokio::test]
+async fn work_dir_is_writable() {
+    let Some(result) = execute("work_dir", "echo hello > out.txt\ncat out.txt").await else {
+        return;
+    };
+
+    assert!(result.is_success());
+    assert_eq!(result.output().stdout, "hello\n");
+}
+
+#[tokio::test]
+async fn writes_outside_of_the_work_dir_fail() {
+    let outside = std::env::temp_dir().join(format!(
+        "autogen_sandboxed_code_executor_escaped_{}.txt",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&outside);
+
+    let code = format!("echo escaped > {}", outside.display());
+    let Some(result) = execute("outside", &code).await else {
+        return;
+    };
+
+    assert!(!result.is_success());
+    assert!(result.output().stderr.contains("Read-only file system"));
+    assert!(!outside.exists());
+}
+
+#[tokio::test]
+async fn network_is_not_available() {
+    // Not even the loopback is up, so connecting anywhere fails without waiting for a timeout.
+    let code =
+        "python3 -c \"import socket; socket.create_connection(('127.0.0.1', 80), timeout=5)\"";
+    let Some(result) = execute("network", code).await else {
+        return;
+    };
+
+    assert!(!result.is_success());
+    assert!(result.output().stderr.contains("Network is unreachable"));
+}
+
+#[tokio::test]
+async fn denied_syscalls_fail() {
+    let code = [
+        syscall(libc::SYS_io_uring_setup, "1, buffer"),
+        syscall(libc::SYS_clone3, "buffer, 88"),
+        syscall(
+            libc::SYS_clone,
+            &format!("{}, 0, 0, 0, 0", libc::CLONE_NEWUSER | libc::SIGCHLD),
+        ),
+        syscall(libc::SYS_unshare, &libc::CLONE_NEWNET.to_string()),
+    ]
+    .join("\n");
+    let Some(result) = execute("denied_syscalls", &code).await else {
+        return;
+    };
+
+    assert!(result.is_success());
+    assert_eq!(
+        result.output().stdout,
+        format!(
+            "-1 {eperm}\n-1 {enosys}\n-1 {eperm}\n-1 {eperm}\n",
+            eperm = libc::EPERM,
+            enosys = libc::ENOSYS
+        )
+    );
+}
+
+#[tokio::test]
+async fn forking_still_works() {
+    let Some(result) = execute("forking", "(echo child) && echo parent").await else {
+        return;
+    };
+
+    assert!(result.is_success());
+    assert_eq!(result.output().stdout, "child\nparent\n");
+}
+
+#[tokio::test]
+async fn host_processes_are_hidden() {
+    let code = format!(
+        "echo $$\ntest -e /proc/{} && echo visible\nls -d /proc/[0-9]*",
+        std::process::id()
+    );
+    let Some(result) = execute("pid_namespace", &code).await else {
+        return;
+    };
+
+    // The shell is the init of the namespace, the only other process is ls - unless the shell
+    // replaced itself by it.
+    let stdout = &result.output().stdout;
+    assert!(stdout.starts_with("1\n/proc/1\n"));
+    assert!(!stdout.contains("visible"));
+    assert!(stdout.lines().count() <= 3);
+}
@@ -0,0 +1,238 @@
+//! Tools generated with `#[autogen::tool]` and schemas derived with `autogen::ToolSchema`.
+
+use autogen::text_chat::tool::{ToolCall, ToolRegistry};
+use autogen::text_chat::tool_error::ToolError;
+use autogen::text_chat::tool_schema::ToolSchema;
+
+use serde::Deserialize;
+use serde_json::json;
+
+/// Unit of the temperature.
+#[derive(Debug, Deserialize, autogen::ToolSchema)]
+enum Unit {
+    Celsius,
+    Fahrenheit,
+}
+
+/// Where to look.
+#[derive(Debug, Deserialize, autogen::ToolSchema)]
+struct Location {
+    /// Name of the city in English.
+    city: String,
+    country: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, autogen::ToolSchema)]
+#[serde(rename_all = "snake_case")]
+enum Precipitation {
+    LightRain,
+    #[serde(rename = "snow")]
+    HeavySnow,
+    #[serde(skip)]
+    #[allow(dead_code)]
+    Unknown,
+}
+
+#[derive(Debug, PartialEq, Deserialize, autogen::ToolSchema)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct Forecast {
+    days_ahead: u32,
+    #[serde(rename = "kind")]
+    precipitation: Precipitation,
+    #[serde(default)]
+    with_wind: bool,
+    #[serde(skip)]
+    cached: bool,
+}
+
+/// Current temperature at the location.
+///
+/// Fails for unknown cities.
+#[autogen::tool]
+async fn temperature(
+    location: Location,
+    /// Defaults to Celsius.
+    unit: Option<Unit>,
+) -> Result<f64, String> {
+    match (location.city.as_str(), location.country.as_deref(), unit) {
+        ("Warsaw", None | Some("Poland"), Some(Unit::Fahrenheit)) => Ok(68.0),
+        ("Warsaw", None | Some("Poland"), _) => Ok(20.0),
+        (city, _, _) => Err(format!("no station in {}", city)),
+    }
+}
+
+/// Adds two integers.
+#[autogen::tool]
+pub async fn add(
+    /// First summand.
+    a: i64,
+    /// Second summand.
+    r#b: i64,
+) -> i64 {
+    a + b
+}
+
+fn tool_call(name: &str, arguments: serde_json::Value) -> ToolCall {
+    ToolCall {
+        id: "1".to_string(),
+        name: name.to_string(),
+        arguments,
+    }
+}
+
+#[test]
+fn derived_schemas_describe_the_types() {
+    assert_eq!(
+        Unit::schema(),
+        json!({
+            "type": "string",
+            "enum": ["Celsius", "Fahrenheit"],
+            "description": "Unit of the temperature.",
+        })
+    );
+
+    assert_eq!(
+        Location::schema(),
+        json!({
+            "type": "object",
+            "description": "Where to look.",
+            "properties": {
+                "city": { "type": "string", "description": "Name of the city in English." },
+                "country": { "type": "string" },
+            },
+            "required": ["city"],
+            "additionalProperties": false,
+        })
+    );
+}
+
+#[test]
+fn derived_schemas_follow_the_serde_attributes() {
+    assert_eq!(
+        Precipitation::schema(),
+        json!({ "type": "string", "enum": ["light_rain", "snow"] })
+    );
+
+    assert_eq!(
+        Forecast::schema(),
+        json!({
+            "type": "object",
+            "properties": {
+                "daysAhead": { "type": "integer", "minimum": 0 },
+                "kind": Precipitation::schema(),
+                "withWind": { "type": "boolean" },
+            },
+            "required": ["daysAhead", "kind"],
+            "additionalProperties": false,
+        })
+    );
+
+    // What the schema describes is accepted by the deserializer.
+    let forecast: Forecast =
+        serde_json::from_value(json!({ "daysAhead": 2, "kind": "snow" })).unwrap();
+    assert_eq!(
+        forecast,
+        Forecast {
+            days_ahead: 2,
+            precipitation: Precipitation::HeavySnow,
+            with_wind: false,
+            cached: false,
+        }
+    );
+}
+
+#[test]
+fn tool_definition_is_generated_from_the_function() {
+    let tool = temperature_tool();
+    let definition = tool.definition();
+
+    assert_eq!(definition.name, "temperature");
+    assert_eq!(
+        definition.description,
+        "Current temperature at the location.\n\nFails for unknown cities."
+    );
+    assert_eq!(
+        definition.parameters,
+        json!({
+            "type": "object",
+            "properties": {
+                "location": Location::schema(),
+                "unit": {
+                    "type": "string",
+                    "enum": ["Celsius", "Fahrenheit"],
+                    "description": "Defaults to Celsius.",
+                },
+            },
+            "required": ["location"],
+            "additionalProperties": false,
+        })
+    );
+
+    assert_eq!(
+        add_tool().definition().parameters,
+        json!({
+            "type": "object",
+            "properties": {
+                "a": { "type": "integer", "description": "First summand." },
+                "b": { "type": "integer", "description": "Second summand." },
+            },
+            "required": ["a", "b"],
+            "additionalProperties": false,
+        })
+    );
+}
+
+#[tokio::test]
+async fn generated_tools_are_called_through_the_registry() {
+    let tools = ToolRegistry::new()
+        .with_tool(temperature_tool())
+        .with_tool(add_tool());
+
+    assert_eq!(
+        tools
+            .call(&tool_call("add", json!({ "a": 2, "b": 3 })))
+            .await
+            .unwrap(),
+        json!(5)
+    );
+
+    assert_eq!(
+        tools
+            .call(&tool_call(
+                "temperature",
+                json!({ "location": { "city": "Warsaw" }, "unit": "Fahrenheit" }),
+            ))
+            .await
+            .unwrap(),
+        json!(68.0)
+    );
+
+    // The function itself is left intact.
+    assert_eq!(add(1, 1).await, 2);
+}
+
+#[tokio::test]
+async fn invalid_calls_are_reported() {
+    let tools = ToolRegistry::new()
+        .with_tool(temperature_tool())
+        .with_tool(add_tool());
+
+    let missing = tools.call(&tool_call("add", json!({ "a": 2 }))).await;
+    assert!(matches!(missing, Err(ToolError::InvalidArguments(_))));
+
+    let unknown_field = tools
+        .call(&tool_call("add", json!({ "a": 2, "b": 3, "c": 4 })))
+        .await;
+    assert!(matches!(unknown_field, Err(ToolError::InvalidArguments(_))));
+
+    let failed = tools
+        .call(&tool_call(
+            "temperature",
+            json!({ "location": { "city": "Atlantis" } }),
+        ))
+        .await;
+    match failed {
+        Err(ToolError::Failed(error)) => assert_eq!(error, "no station in Atlantis"),
+        result => panic!("unexpected result: {:?}", result),
+    }
+}
@@ -0,0 +1,149 @@
+use autogen::text_chat::code::execution_limits::{ExecutionLimit, ExecutionLimits};
+use autogen::text_chat::code::wasm_code_executor::WasmCodeExecutor;
+use autogen::text_chat::code::{CodeBlock, CodeBlockExecutionResult, CodeExecutor};
+
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// Writes `hello\n` to the given file descriptors, forever if `forever` is set.
+fn hello_module(fds: &[u32], forever: bool) -> String {
+    let writes: String = fds
+        .iter()
+        .map(|fd| {
+            format!(
+                "(drop (call $fd_write (i32.const {}) (i32.const 0) (i32.const 1) (i32.const 8)))",
+                fd
+            )
+        })
+        .collect();
+
+    let body = match forever {
+        true => format!("(loop {} (br 0))", writes),
+        false => writes,
+    };
+
+    format!(
+        r#"(module
+            (import "wasi_snapshot_preview1" "fd_write"
+                (func $fd_write (param i32 i32 i32 i32) (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 16) "hello\n")
+            (func (export "_start")
+                (i32.store (i32.const 0) (i32.const 16))
+                (i32.store (i32.const 4) (i32.const 6))
+                {}))"#,
+        body
+    )
+}
+
+const BUSY_LOOP: &str = r#"(module (func (export "_start") (loop (br 0))))"#;
+
+fn work_dir(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "autogen_wasm_code_executor_{}_{}",
+        name,
+        std::process::id()
+    ))
+}
+
+async fn execute(executor: WasmCodeExecutor, code: &str) -> CodeBlockExecutionResult {
+    executor
+        .execute_code_block(&CodeBlock {
+            language: "wat".to_string(),
+            code: code.to_string(),
+        })
+        .await
+        .expect("execution failed")
+}
+
+#[tokio::test]
+async fn wat_is_executed() {
+    let executor = WasmCodeExecutor::new(work_dir("wat")).unwrap();
+
+    let result = execute(executor, &hello_module(&[1, 2], false)).await;
+
+    assert!(result.is_success());
+    assert_eq!(result.output().stdout, "hello\n");
+    assert_eq!(result.output().stderr, "hello\n");
+    assert_eq!(result.output().exit_code, Some(0));
+    assert!(!result.output().truncated);
+}
+
+#[tokio::test]
+async fn fuel_stops_busy_loops() {
+    let executor = WasmCodeExecutor::new(work_dir("fuel"))
+        .unwrap()
+        .with_fuel(100_000);
+
+    let result = execute(executor, BUSY_LOOP).await;
+
+    assert!(matches!(
+        result,
+        CodeBlockExecutionResult::LimitExceeded {
+            limit: ExecutionLimit::Fuel(100_000),
+            ..
+        }
+    ));
+}
+
+#[tokio::test]
+async fn timeout_interrupts_the_execution() {
+    let executor = WasmCodeExecutor::new(work_dir("timeout"))
+        .unwrap()
+        .with_limits(ExecutionLimits {
+            timeout: Some(Duration::from_millis(100)),
+            ..Default::default()
+        });
+
+    let start = Instant::now();
+    let result = execute(executor, BUSY_LOOP).await;
+
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert!(matches!(
+        result,
+        CodeBlockExecutionResult::LimitExceeded {
+            limit: ExecutionLimit::Timeout(_),
+            ..
+        }
+    ));
+}
+
+#[tokio::test]
+async fn output_size_is_shared_by_stdout_and_stderr() {
+    let executor = WasmCodeExecutor::new(work_dir("output_size"))
+        .unwrap()
+        .with_limits(ExecutionLimits {
+            max_output_bytes: Some(100),
+            ..Default::default()
+        });
+
+    let result = execute(executor, &hello_module(&[1, 2], true)).await;
+
+    let output = result.output();
+    assert!(matches!(
+        result,
+        CodeBlockExecutionResult::LimitExceeded {
+            limit: ExecutionLimit::OutputSize(100),
+            ..
+        }
+    ));
+    assert!(output.truncated);
+    assert_eq!(output.stdout.len() + output.stderr.len(), 100);
+    assert!(output.stdout.starts_with("hello\nhello\n"));
+    assert!(output.stderr.starts_with("hello\nhello\n"));
+}
+
+#[tokio::test]
+async fn output_within_the_limit_is_not_truncated() {
+    let executor = WasmCodeExecutor::new(work_dir("output_within"))
+        .unwrap()
+        .with_limits(ExecutionLimits {
+            max_output_bytes: Some(12),
+            ..Default::default()
+        });
+
+    let result = execute(executor, &hello_module(&[1, 2], false)).await;
+
+    assert!(result.is_success());
+    assert!(!result.output().truncated);
+}